@@ -1,10 +1,15 @@
 // Copyright © ByteHeed.  All rights reserved.
 
+#[cfg(windows)]
 use super::winapi::um::winsvc::{SC_HANDLE};
+#[cfg(windows)]
 use super::winapi::shared::ntdef::{PVOID, ULONG, PULONG, NTSTATUS};
+#[cfg(windows)]
 use super::winapi::shared::minwindef::{BOOL, DWORD};
+#[cfg(windows)]
 use super::winapi::um::winnt::{LPCWSTR};
 
+#[cfg(windows)]
 #[link(name = "advapi32")]
 extern "stdcall" {
     pub fn StartServiceW(
@@ -19,6 +24,7 @@ pub enum SystemInformationClass {
     SystemModuleInformationEx = 11,
 }
 
+#[cfg(windows)]
 #[link(name = "ntdll")]
 extern "stdcall" {
     pub fn NtQuerySystemInformation(
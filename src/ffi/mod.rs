@@ -1,6 +1,7 @@
 // Copyright © ByteHeed.  All rights reserved.
 extern crate winapi;
 
+#[cfg(windows)]
 pub mod traits;
 
 #[macro_use]
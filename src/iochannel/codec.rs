@@ -0,0 +1,239 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::error::CodecError;
+use super::IoCtl;
+
+use sentry::io::{CONTROLS, IOCTL_SENTRY_TYPE};
+
+use super::{METHOD_BUFFERED, FILE_READ_ACCESS, FILE_WRITE_ACCESS};
+
+use std::str::FromStr;
+
+const FILE_ACCESS_RW: u32 = FILE_READ_ACCESS | FILE_WRITE_ACCESS;
+
+// well-known FILE_DEVICE_* types, plus the vendor types this tool talks to
+pub const DEVICE_TYPES: &[(&str, u32)] = &[
+    ("BEEP",                0x0001),
+    ("CD_ROM",              0x0002),
+    ("CD_ROM_FILE_SYSTEM",  0x0003),
+    ("CONTROLLER",          0x0004),
+    ("DATALINK",            0x0005),
+    ("DFS",                 0x0006),
+    ("DISK",                0x0007),
+    ("DISK_FILE_SYSTEM",    0x0008),
+    ("FILE_SYSTEM",         0x0009),
+    ("INPORT_PORT",         0x000A),
+    ("KEYBOARD",            0x000B),
+    ("MAILSLOT",            0x000C),
+    ("MIDI_IN",             0x000D),
+    ("MIDI_OUT",            0x000E),
+    ("MOUSE",               0x000F),
+    ("MULTI_UNC_PROVIDER",  0x0010),
+    ("NAMED_PIPE",          0x0011),
+    ("NETWORK",             0x0012),
+    ("NETWORK_BROWSER",     0x0013),
+    ("NETWORK_FILE_SYSTEM", 0x0014),
+    ("NULL",                0x0015),
+    ("PARALLEL_PORT",       0x0016),
+    ("PHYSICAL_NETCARD",    0x0017),
+    ("PRINTER",             0x0018),
+    ("SCANNER",             0x0019),
+    ("SERIAL_MOUSE_PORT",   0x001A),
+    ("SERIAL_PORT",         0x001B),
+    ("SCREEN",              0x001C),
+    ("SOUND",               0x001D),
+    ("STREAMS",             0x001E),
+    ("TAPE",                0x001F),
+    ("TAPE_FILE_SYSTEM",    0x0020),
+    ("TRANSPORT",           0x0021),
+    ("UNKNOWN",             0x0022),
+    ("VIDEO",               0x0023),
+    ("VIRTUAL_DISK",        0x0024),
+    ("WAVE_IN",             0x0025),
+    ("WAVE_OUT",            0x0026),
+    ("8042_PORT",           0x0027),
+    ("NETWORK_REDIRECTOR",  0x0028),
+    ("BATTERY",             0x0029),
+    ("BUS_EXTENDER",        0x002A),
+    ("MODEM",               0x002B),
+    ("VDM",                 0x002C),
+    ("MASS_STORAGE",        0x002D),
+    ("SMB",                 0x002E),
+    ("KS",                  0x002F),
+    ("CHANGER",             0x0030),
+    ("SMARTCARD",           0x0031),
+    ("ACPI",                0x0032),
+    ("DVD",                 0x0033),
+    ("FULLSCREEN_VIDEO",    0x0034),
+    ("DFS_FILE_SYSTEM",     0x0035),
+    ("DFS_VOLUME",          0x0036),
+    ("SERENUM",             0x0037),
+    ("TERMSRV",             0x0038),
+    ("KSEC",                0x0039),
+    ("FIPS",                0x003A),
+    ("INFINIBAND",          0x003B),
+    ("SENTRY",              IOCTL_SENTRY_TYPE),
+];
+
+pub const METHODS: &[(&str, u32)] = &[
+    ("BUFFERED",   0),
+    ("IN_DIRECT",  1),
+    ("OUT_DIRECT", 2),
+    ("NEITHER",    3),
+];
+
+pub const ACCESS: &[(&str, u32)] = &[
+    ("ANY", 0),
+    ("R",   1),
+    ("W",   2),
+    ("RW",  3),
+];
+
+fn by_name(table: &[(&'static str, u32)], name: &str) -> Option<u32> {
+    table.iter()
+         .find(|&&(entry, _)| entry.eq_ignore_ascii_case(name))
+         .map(|&(_, value)| value)
+}
+
+fn by_value(table: &[(&'static str, u32)], value: u32) -> Option<&'static str> {
+    table.iter()
+         .find(|&&(_, entry)| entry == value)
+         .map(|&(name, _)| name)
+}
+
+pub fn device_type_name(device_type: u32) -> Option<&'static str> {
+    by_value(DEVICE_TYPES, device_type)
+}
+
+pub fn method_name(method: u32) -> &'static str {
+    match method & 3 {
+        0 => "METHOD_BUFFERED",
+        1 => "METHOD_IN_DIRECT",
+        2 => "METHOD_OUT_DIRECT",
+        _ => "METHOD_NEITHER",
+    }
+}
+
+pub fn access_name(access: u32) -> &'static str {
+    match access & 3 {
+        0 => "FILE_ANY_ACCESS",
+        1 => "FILE_READ_ACCESS",
+        2 => "FILE_WRITE_ACCESS",
+        _ => "FILE_READ_ACCESS | FILE_WRITE_ACCESS",
+    }
+}
+
+// symbolic name of a known control code
+pub fn control_name(code: u32) -> Option<&'static str> {
+    CONTROLS.iter()
+            .find(|&&(_, function)| super::encode(IOCTL_SENTRY_TYPE, function, METHOD_BUFFERED, FILE_ACCESS_RW) == code)
+            .map(|&(name, _)| name)
+}
+
+// every known control, with its default method and access
+pub fn registry() -> Vec<IoCtl> {
+    CONTROLS.iter()
+            .map(|&(name, function)| IoCtl::new(Some(name), IOCTL_SENTRY_TYPE, function, None, None))
+            .collect()
+}
+
+fn number(value: &str) -> Result<u32, CodecError> {
+    let value = value.trim();
+
+    let parsed = if value.starts_with("0x") || value.starts_with("0X") {
+        u32::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse::<u32>()
+    };
+
+    parsed.map_err(|_| CodecError::Number(value.to_string()))
+}
+
+fn field(table: &[(&'static str, u32)], value: &str, limit: u32) -> Option<u32> {
+    by_name(table, value).or_else(|| number(value).ok())
+                         .and_then(|value| if value <= limit { Some(value) } else { None })
+}
+
+//
+// Accepts a raw code (`0xB080E800`), a symbolic name (`SE_IOCTL_CREATE_PARTITION`) or
+// `TYPE:FUNCTION[:METHOD[:ACCESS]]`, e.g. `SENTRY:0xA00:BUFFERED:RW`.
+//
+pub fn parse(value: &str) -> Result<IoCtl, CodecError> {
+    let value = value.trim();
+
+    if let Some(control) = registry().into_iter().find(|control| control.to_string().eq_ignore_ascii_case(value)) {
+        return Ok(control)
+    }
+
+    let parts: Vec<&str> = value.split(':').collect();
+
+    if parts.len() == 1 {
+        return Ok(IoCtl::from(number(value)?))
+    }
+
+    if parts.len() > 4 {
+        return Err(CodecError::Malformed(value.to_string()))
+    }
+
+    let device_type = field(DEVICE_TYPES, parts[0], 0xFFFF)
+                        .ok_or_else(|| CodecError::DeviceType(parts[0].to_string()))?;
+
+    let function = number(parts[1])?;
+
+    if function > 0xFFF {
+        return Err(CodecError::Function(function))
+    }
+
+    let method = match parts.get(2) {
+        Some(method) => Some(field(METHODS, method.trim_left_matches("METHOD_"), 3)
+                                .ok_or_else(|| CodecError::Method(method.to_string()))?),
+        None => None
+    };
+
+    let access = match parts.get(3) {
+        Some(access) => Some(field(ACCESS, access, 3)
+                                .ok_or_else(|| CodecError::Access(access.to_string()))?),
+        None => None
+    };
+
+    let control = IoCtl::new(None, device_type, function, method, access);
+
+    Ok(IoCtl::from(control.code()))
+}
+
+impl FromStr for IoCtl {
+    type Err = CodecError;
+
+    fn from_str(value: &str) -> Result<IoCtl, CodecError> {
+        parse(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_raw_code() {
+        let control = parse("0xB080E800").unwrap();
+        assert_eq!(control.device_type, 0xB080);
+        assert_eq!(control.function, 0x0A00);
+        assert_eq!(control.to_string(), "SE_IOCTL_CREATE_PARTITION");
+    }
+
+    #[test]
+    fn test_parse_symbolic_forms() {
+        assert_eq!(parse("SENTRY:0xA00:BUFFERED:RW").unwrap().code(), 0xB080_E800);
+        assert_eq!(parse("sentry:0xA51").unwrap().code(), 0xB080_E944);
+        assert_eq!(parse("SE_IOCTL_CREATE_PARTITION").unwrap().code(), 0xB080_E800);
+        assert_eq!(parse("DISK:0x1:METHOD_NEITHER:ANY").unwrap().code(), 0x0007_0007);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("NOPE:0x1").is_err());
+        assert!(parse("SENTRY:0x1000").is_err());
+        assert!(parse("SENTRY:0x1:SIDEWAYS").is_err());
+        assert!(parse("0xZZ").is_err());
+    }
+}
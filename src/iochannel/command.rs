@@ -1,7 +1,23 @@
 use super::clap::{App, Arg, ArgMatches, SubCommand};
-use super::Device;
+#[cfg(windows)]
+use super::{Device, Transport};
+#[cfg(windows)]
+use super::dump;
+use super::{IoCtl, codec, record};
+#[cfg(windows)]
+use super::error::DumpError;
+use super::metrics::Metrics;
 use super::failure::Error;
 
+#[cfg(windows)]
+use sentry::io::LAYOUTS;
+use sentry::io::SE_NT_DEVICE_NAME;
+use sentry::session::SessionOptions;
+
+use std::fs;
+#[cfg(windows)]
+use std::time::Duration;
+
 
 use std::sync::mpsc::{Sender};
 use super::cli::output::{MessageType, ShellMessage};
@@ -24,20 +40,223 @@ pub fn bind() -> App<'static, 'static> {
             ),
         )
         .subcommand(
-            SubCommand::with_name("call").arg(
-                Arg::with_name("ctl")
-                    .short("c")
-                    .required(true)
-                    .value_name("IOCTL")
-                    .help("specifies any IOCTL code"),
-            ),
+            SubCommand::with_name("call")
+                .about("sends an arbitrary buffer through any IOCTL")
+                .arg(
+                    Arg::with_name("name")
+                        .short("n")
+                        .default_value(SE_NT_DEVICE_NAME)
+                        .value_name("DEVICENAME")
+                        .help("name of target device"),
+                )
+                .arg(
+                    Arg::with_name("ctl")
+                        .short("c")
+                        .required(true)
+                        .value_name("IOCTL")
+                        .help("specifies any IOCTL code, by number, name or TYPE:FUNCTION[:METHOD[:ACCESS]]"),
+                )
+                .arg(
+                    Arg::with_name("in-hex")
+                        .long("in-hex")
+                        .value_name("HEX")
+                        .conflicts_with("in-file")
+                        .help("input buffer as hex bytes (\"0a 0b\", \"0x0a,0x0b\" or \"0a0b\")"),
+                )
+                .arg(
+                    Arg::with_name("in-file")
+                        .long("in-file")
+                        .value_name("FILE")
+                        .help("reads the input buffer from a file"),
+                )
+                .arg(
+                    Arg::with_name("out-size")
+                        .long("out-size")
+                        .value_name("SIZE")
+                        .default_value("0")
+                        .help("size of the output buffer"),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .short("t")
+                        .long("timeout")
+                        .value_name("MILLISECONDS")
+                        .help("cancels the request if the driver doesn't complete it in time"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .default_value("hex")
+                        .help("output rendering: hex, u64 or a layout name (Channel, RegionInfo, PatchInfo, Id)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("decode")
+                .about("decodes or encodes I/O control codes")
+                .arg(
+                    Arg::with_name("ioctl")
+                        .multiple(true)
+                        .required_unless("list")
+                        .value_name("IOCTL")
+                        .help("raw code (0xB080E800), name (SE_IOCTL_CREATE_PARTITION) or TYPE:FUNCTION[:METHOD[:ACCESS]]"),
+                )
+                .arg(
+                    Arg::with_name("list")
+                        .short("l")
+                        .long("list")
+                        .help("lists every known I/O control"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("reports the latency and error metrics saved by `conveyor --metrics FILE`")
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .value_name("FILE")
+                        .help("metrics file"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["text", "json"])
+                        .default_value("text")
+                        .help("report format"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("writes the report to a file instead of the console"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("trace")
+                .about("prints every IOCTL stored in a recording")
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .required(true)
+                        .value_name("FILE")
+                        .help("recording file"),
+                ),
         )
 }
 
-fn device_call(_matches: &ArgMatches, _messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    unimplemented!()
+#[cfg(windows)]
+fn render(format: &str, data: &[u8]) -> Result<String, DumpError> {
+    match format.to_lowercase().as_str() {
+        "hex" => Ok(dump::hexdump(data)),
+        "u64" => Ok(dump::words(data)),
+        _ => {
+            LAYOUTS.iter()
+                   .find(|layout| layout.name.eq_ignore_ascii_case(format))
+                   .ok_or_else(|| DumpError::Format(format.to_string()))?
+                   .render_all(data)
+        }
+    }
 }
 
+#[cfg(windows)]
+fn device_call(matches: &ArgMatches, options: &SessionOptions, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    let name = matches
+        .value_of("name")
+        .expect("argument `name` is not present");
+
+    let control = codec::parse(matches.value_of("ctl").expect("argument `ctl` is not present"))?;
+
+    let format = matches
+        .value_of("format")
+        .expect("argument `format` is not present");
+
+    let out_size = matches
+        .value_of("out-size")
+        .expect("argument `out-size` is not present")
+        .parse::<usize>()?;
+
+    let input = match (matches.value_of("in-hex"), matches.value_of("in-file")) {
+        (Some(hex), _) => Some(dump::parse_hex(hex)?),
+        (_, Some(file)) => Some(fs::read(file)?),
+        _ => None,
+    };
+
+    // fail early on unknown formats, before touching the device
+    render(format, &[])?;
+
+    // recordings only hold plain calls
+    if let (Some(_), Some(path)) = (matches.value_of("timeout"), options.recording()) {
+        return Err(format_err!("--timeout calls can't be recorded to {}", path.display()));
+    }
+
+    ShellMessage::send(
+        messenger,
+        format!("Calling {} on {} ({} bytes in, {} bytes out)...",
+                describe(&control),
+                style(name).underlined().blue(),
+                input.as_ref().map_or(0, |input| input.len()),
+                out_size),
+        MessageType::Close,
+        0,
+    );
+
+    let device = options.account(Device::new(name)?);
+
+    let output = match out_size {
+        0 => None,
+        size => Some(Vec::with_capacity(size)),
+    };
+
+    let cursor = match matches.value_of("timeout") {
+        Some(timeout) => device.call_timeout(control, input, output, Duration::from_millis(timeout.parse()?))?,
+        None => options.session(device)?.call(control, input, output)?,
+    };
+    let data = cursor.into_inner();
+
+    ShellMessage::send(
+        messenger,
+        format!("{} bytes returned", style(data.len()).cyan()),
+        MessageType::Close,
+        1,
+    );
+
+    if !data.is_empty() {
+        ShellMessage::send(messenger, render(format, &data)?, MessageType::Close, 1);
+    }
+
+    Ok(())
+}
+
+fn device_stats(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    let file = matches
+        .value_of("file")
+        .expect("argument `file` is not present");
+
+    let metrics = Metrics::load(file)?;
+
+    let report = match matches.value_of("format") {
+        Some("json") => metrics.to_json(),
+        _ => metrics.to_text(),
+    };
+
+    match matches.value_of("output") {
+        Some(file) => {
+            fs::write(file, report)?;
+            ShellMessage::send(messenger, format!("Report written to {}", style(file).underlined()), MessageType::Close, 1);
+        },
+        None => {
+            ShellMessage::send(messenger, report, MessageType::Close, 1);
+        },
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
 pub fn device_open(
     matches: &ArgMatches,
     messenger: &Sender<ShellMessage>,
@@ -67,10 +286,76 @@ pub fn device_open(
     Ok(())
 }
 
-pub fn parse(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+fn describe(control: &IoCtl) -> String {
+    format!("{} {} device: {} (0x{:04X}) function: 0x{:03X} method: {} access: {}",
+            style(format!("0x{:08X}", control.code())).cyan(),
+            style(control.name()).magenta(),
+            control.device_type_name().unwrap_or("?"),
+            control.device_type,
+            control.function,
+            control.method_name(),
+            control.access_name())
+}
+
+pub fn device_decode(
+    matches: &ArgMatches,
+    messenger: &Sender<ShellMessage>,
+) -> Result<(), Error> {
+    let controls = if matches.is_present("list") {
+        codec::registry()
+    } else {
+        matches
+            .values_of("ioctl")
+            .expect("argument `ioctl` is not present")
+            .map(codec::parse)
+            .collect::<Result<Vec<IoCtl>, _>>()?
+    };
+
+    controls.iter().for_each(|control| {
+        ShellMessage::send(messenger, describe(control), MessageType::Close, 0);
+    });
+
+    Ok(())
+}
+
+pub fn device_trace(
+    matches: &ArgMatches,
+    messenger: &Sender<ShellMessage>,
+) -> Result<(), Error> {
+    let file = matches
+        .value_of("file")
+        .expect("argument `file` is not present");
+
+    let entries = record::load(file)?;
+
+    entries.iter().enumerate().for_each(|(index, entry)| {
+        let line = format!("#{:<5} {}", index, entry);
+
+        ShellMessage::send(
+            messenger,
+            match entry.error {
+                Some(_) => format!("{}", style(line).red()),
+                None => line,
+            },
+            MessageType::Close,
+            0,
+        );
+    });
+
+    Ok(())
+}
+
+// only the device calls, which need Windows, open sessions
+#[cfg_attr(not(windows), allow(unused_variables))]
+pub fn parse(matches: &ArgMatches, session: &SessionOptions, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
     match matches.subcommand() {
+        #[cfg(windows)]
         ("open", Some(matches)) => device_open(matches, messenger),
-        ("call", Some(matches)) => device_call(matches, messenger),
+        #[cfg(windows)]
+        ("call", Some(matches)) => device_call(matches, session, messenger),
+        ("decode", Some(matches)) => device_decode(matches, messenger),
+        ("trace", Some(matches)) => device_trace(matches, messenger),
+        ("stats", Some(matches)) => device_stats(matches, messenger),
         _ => Ok(println!("{}", matches.usage())),
     }
 }
@@ -0,0 +1,178 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::byteorder::{LittleEndian, ReadBytesExt};
+use super::error::DumpError;
+
+use std::io::Cursor;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Width {
+    U8,
+    U16,
+    U32,
+    U64,
+    // explicit padding, skipped while rendering
+    Pad(usize),
+    // raw bytes, rendered as they are laid out
+    Bytes(usize),
+}
+
+impl Width {
+    pub fn size(&self) -> usize {
+        match *self {
+            Width::U8 => 1,
+            Width::U16 => 2,
+            Width::U32 => 4,
+            Width::U64 => 8,
+            Width::Pad(size) | Width::Bytes(size) => size,
+        }
+    }
+}
+
+// describes how a driver buffer is laid out, field by field
+#[derive(Debug)]
+pub struct Layout {
+    pub name: &'static str,
+    pub fields: &'static [(&'static str, Width)],
+}
+
+impl Layout {
+    pub fn size(&self) -> usize {
+        self.fields.iter().map(|&(_, width)| width.size()).sum()
+    }
+
+    // byte offset of a field from the start of the buffer
+    pub fn offset(&self, field: &str) -> Option<usize> {
+        let index = self.fields.iter().position(|&(name, _)| name == field)?;
+
+        Some(self.fields[..index].iter().map(|&(_, width)| width.size()).sum())
+    }
+
+    pub fn render(&self, data: &[u8]) -> Result<String, DumpError> {
+        if data.len() < self.size() {
+            return Err(DumpError::Truncated(self.name.to_string(), self.size(), data.len()))
+        }
+
+        let mut cursor = Cursor::new(data);
+        let padding = self.fields.iter().map(|&(name, _)| name.len()).max().unwrap_or(0);
+
+        let lines = self.fields.iter().filter_map(|&(name, width)| {
+            let value = match width {
+                Width::U8 => u64::from(cursor.read_u8().expect("size validated")),
+                Width::U16 => u64::from(cursor.read_u16::<LittleEndian>().expect("size validated")),
+                Width::U32 => u64::from(cursor.read_u32::<LittleEndian>().expect("size validated")),
+                Width::U64 => cursor.read_u64::<LittleEndian>().expect("size validated"),
+                Width::Pad(size) => {
+                    cursor.set_position(cursor.position() + size as u64);
+                    return None
+                },
+                Width::Bytes(size) => {
+                    let start = cursor.position() as usize;
+                    cursor.set_position((start + size) as u64);
+
+                    let bytes = data[start..start + size].iter()
+                                                          .map(|byte| format!("{:02X}", byte))
+                                                          .collect::<Vec<String>>();
+
+                    return Some(format!("{:>width$}: {}", name, bytes.join(" "), width = padding))
+                },
+            };
+
+            Some(format!("{:>width$}: 0x{:0digits$X}", name, value,
+                         width = padding, digits = width.size() * 2))
+        }).collect::<Vec<String>>();
+
+        Ok(format!("{} {{\n  {}\n}}", self.name, lines.join("\n  ")))
+    }
+
+    // enumerations chain several records of the same layout
+    pub fn render_all(&self, data: &[u8]) -> Result<String, DumpError> {
+        if self.size() == 0 {
+            return Err(DumpError::Empty(self.name.to_string()))
+        }
+
+        let rendered = data.chunks(self.size())
+                           .map(|chunk| self.render(chunk))
+                           .collect::<Result<Vec<String>, DumpError>>()?;
+
+        Ok(rendered.join("\n"))
+    }
+}
+
+pub fn hexdump(data: &[u8]) -> String {
+    data.chunks(16).enumerate().map(|(index, chunk)| {
+        let hex = chunk.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" ");
+        let ascii = chunk.iter()
+                         .map(|&b| if b >= 0x20 && b < 0x7F { char::from(b) } else { '.' })
+                         .collect::<String>();
+
+        format!("{:08X}  {:<47}  |{}|", index * 16, hex, ascii)
+    }).collect::<Vec<String>>().join("\n")
+}
+
+pub fn words(data: &[u8]) -> String {
+    data.chunks(8).enumerate().map(|(index, chunk)| {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+
+        let value = Cursor::new(&word[..]).read_u64::<LittleEndian>().expect("8 bytes");
+        format!("{:08X}  0x{:016X}", index * 8, value)
+    }).collect::<Vec<String>>().join("\n")
+}
+
+// parses "0a 0B ff", "0x0a,0x0b" or "0a0bff"
+pub fn parse_hex(value: &str) -> Result<Vec<u8>, DumpError> {
+    let digits = value.split(|c: char| c.is_whitespace() || c == ',')
+                      .map(|chunk| chunk.trim_left_matches("0x").trim_left_matches("0X"))
+                      .collect::<String>();
+
+    if !digits.is_ascii() || digits.len() % 2 != 0 {
+        return Err(DumpError::Hex(value.to_string()))
+    }
+
+    (0..digits.len()).step_by(2)
+                     .map(|index| u8::from_str_radix(&digits[index..index + 2], 16)
+                                        .map_err(|_| DumpError::Hex(value.to_string())))
+                     .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: Layout = Layout {
+        name: "Sample",
+        fields: &[("id", Width::U64), ("size", Width::U32), ("", Width::Pad(4))],
+    };
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("0a 0B,ff").unwrap(), vec![0x0A, 0x0B, 0xFF]);
+        assert_eq!(parse_hex("0x01 0x02").unwrap(), vec![0x01, 0x02]);
+        assert_eq!(parse_hex("deadbeef").unwrap(), vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        assert!(parse_hex("abc").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_layout_render() {
+        let data = [1, 0, 0, 0, 0, 0, 0, 0, 0x10, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(SAMPLE.size(), 16);
+        assert_eq!(SAMPLE.render(&data).unwrap(),
+                   "Sample {\n    id: 0x0000000000000001\n  size: 0x00000010\n}");
+        assert!(SAMPLE.render(&data[..8]).is_err());
+        assert_eq!(SAMPLE.offset("size"), Some(8));
+        assert_eq!(SAMPLE.offset("missing"), None);
+    }
+
+    #[test]
+    fn test_layout_render_all() {
+        let data = [1, 0, 0, 0, 0, 0, 0, 0, 0x10, 0, 0, 0, 0, 0, 0, 0];
+        let chained = [&data[..], &data[..]].concat();
+        assert_eq!(SAMPLE.render_all(&chained).unwrap().matches("Sample").count(), 2);
+        assert!(SAMPLE.render_all(&chained[..24]).is_err());
+
+        let empty = Layout { name: "Empty", fields: &[] };
+        assert!(empty.render_all(&data).is_err());
+    }
+}
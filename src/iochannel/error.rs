@@ -1,13 +1,220 @@
 use std::io::Error;
+use std::time::Duration;
+use std::fmt;
 use super::IoCtl;
 
+const ERROR_FILE_NOT_FOUND: u32 = 2;
+const ERROR_PATH_NOT_FOUND: u32 = 3;
+const ERROR_ACCESS_DENIED: u32 = 5;
+const ERROR_INVALID_FUNCTION: u32 = 1;
+const ERROR_INVALID_PARAMETER: u32 = 87;
+const ERROR_INSUFFICIENT_BUFFER: u32 = 122;
+const ERROR_BUFFER_OVERFLOW: u32 = 111;
+const ERROR_MORE_DATA: u32 = 234;
+const ERROR_PROC_NOT_FOUND: u32 = 127;
+const ERROR_INVALID_ADDRESS: u32 = 487;
+const ERROR_NOACCESS: u32 = 998;
+const ERROR_DEVICE_NOT_CONNECTED: u32 = 1167;
+const ERROR_NOT_FOUND: u32 = 1168;
+const ERROR_SERVICE_NOT_ACTIVE: u32 = 1062;
+
+// what went wrong, independently of the exact code reported by the driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorKind {
+    NotFound,
+    AccessDenied,
+    InvalidParameter,
+    BufferTooSmall,
+    DriverNotLoaded,
+    TimedOut,
+    Other,
+}
+
+impl ErrorKind {
+    // the driver reports missing partitions as ERROR_DEVICE_NOT_CONNECTED
+    pub fn from_win32(code: u32) -> ErrorKind {
+        match code {
+            ERROR_NOT_FOUND | ERROR_DEVICE_NOT_CONNECTED | ERROR_PROC_NOT_FOUND => ErrorKind::NotFound,
+            ERROR_ACCESS_DENIED | ERROR_NOACCESS => ErrorKind::AccessDenied,
+            ERROR_INVALID_PARAMETER | ERROR_INVALID_FUNCTION | ERROR_INVALID_ADDRESS => ErrorKind::InvalidParameter,
+            ERROR_INSUFFICIENT_BUFFER | ERROR_MORE_DATA | ERROR_BUFFER_OVERFLOW => ErrorKind::BufferTooSmall,
+            ERROR_SERVICE_NOT_ACTIVE => ErrorKind::DriverNotLoaded,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// what the caller was doing when the request failed, e.g. "partition 4"
+#[derive(Debug, Clone, Default)]
+pub struct Context(Option<String>);
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(ref context) => write!(f, " ({})", context),
+            None => Ok(()),
+        }
+    }
+}
+
 #[derive(Fail, Debug)]
 pub enum DeviceError {
-    #[fail(display = "Error opening {:?}: {}", _0, _1)]
-    Open(String, String),
-
-    #[fail(display = "I/O error calling {}: {}", _0, _1)]
-    IoCall(IoCtl, String, #[cause] Error),
-    // #[fail(display = "{}", _0)]
-    // Io(#[cause] io::Error),
-}
\ No newline at end of file
+    #[fail(display = "Error opening {:?}: {} [{}]", name, error, kind)]
+    Open {
+        name: String,
+        kind: ErrorKind,
+        #[cause] error: Error,
+    },
+
+    #[fail(display = "I/O error calling {}{}: {} [{}]", control, context, error, kind)]
+    IoCall {
+        control: IoCtl,
+        // Win32 code as reported by GetLastError, and the raw NTSTATUS when it was available
+        code: u32,
+        status: Option<u32>,
+        kind: ErrorKind,
+        context: Context,
+        // output size the driver asked for, when it reported one
+        required: Option<usize>,
+        #[cause] error: Error,
+    },
+
+    #[fail(display = "{} timed out after {:?}", _0, _1)]
+    Timeout(IoCtl, Duration),
+
+    #[fail(display = "Output of {} doesn't fit in {} bytes", _0, _1)]
+    BufferLimit(IoCtl, usize),
+
+    #[fail(display = "Replay error: {}", _0)]
+    Replay(String),
+}
+
+impl DeviceError {
+    pub fn open(name: &str, error: Error) -> DeviceError {
+        let kind = match error.raw_os_error().map(|code| code as u32) {
+            // no driver behind the symbolic link
+            Some(ERROR_FILE_NOT_FOUND) | Some(ERROR_PATH_NOT_FOUND) => ErrorKind::DriverNotLoaded,
+            Some(code) => ErrorKind::from_win32(code),
+            None => ErrorKind::Other,
+        };
+
+        DeviceError::Open { name: name.to_string(), kind: kind, error: error }
+    }
+
+    pub fn io(control: IoCtl, code: u32, status: Option<u32>) -> DeviceError {
+        DeviceError::IoCall {
+            control: control,
+            code: code,
+            status: status,
+            kind: ErrorKind::from_win32(code),
+            context: Context::default(),
+            required: None,
+            error: Error::from_raw_os_error(code as i32),
+        }
+    }
+
+    // captures GetLastError() right after a failed call
+    pub fn last_os_error(control: IoCtl) -> DeviceError {
+        let code = Error::last_os_error().raw_os_error().unwrap_or(0);
+        DeviceError::io(control, code as u32, None)
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            DeviceError::Open { kind, .. } | DeviceError::IoCall { kind, .. } => kind,
+            DeviceError::Timeout(..) => ErrorKind::TimedOut,
+            DeviceError::BufferLimit(..) => ErrorKind::BufferTooSmall,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    pub fn code(&self) -> Option<u32> {
+        match *self {
+            DeviceError::IoCall { code, .. } => Some(code),
+            DeviceError::Open { ref error, .. } => error.raw_os_error().map(|code| code as u32),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> Option<u32> {
+        match *self {
+            DeviceError::IoCall { status, .. } => status,
+            _ => None,
+        }
+    }
+
+    pub fn control(&self) -> Option<&IoCtl> {
+        match *self {
+            DeviceError::IoCall { ref control, .. } |
+            DeviceError::Timeout(ref control, _) |
+            DeviceError::BufferLimit(ref control, _) => Some(control),
+            _ => None,
+        }
+    }
+
+    pub fn required_size(&self) -> Option<usize> {
+        match *self {
+            DeviceError::IoCall { required, .. } => required,
+            _ => None,
+        }
+    }
+
+    pub fn requiring(mut self, size: usize) -> DeviceError {
+        if let DeviceError::IoCall { ref mut required, .. } = self {
+            *required = Some(size);
+        }
+
+        self
+    }
+
+    // attaches what the request was about, e.g. `.within(format!("region {}", id))`
+    pub fn within<S: Into<String>>(mut self, operation: S) -> DeviceError {
+        if let DeviceError::IoCall { ref mut context, .. } = self {
+            *context = Context(Some(operation.into()));
+        }
+
+        self
+    }
+}
+
+#[derive(Fail, Debug)]
+pub enum CodecError {
+    #[fail(display = "Invalid number {:?}", _0)]
+    Number(String),
+
+    #[fail(display = "Unknown device type {:?}", _0)]
+    DeviceType(String),
+
+    #[fail(display = "Function 0x{:X} doesn't fit in 12 bits", _0)]
+    Function(u32),
+
+    #[fail(display = "Unknown transfer method {:?}", _0)]
+    Method(String),
+
+    #[fail(display = "Unknown access {:?}", _0)]
+    Access(String),
+
+    #[fail(display = "Malformed I/O control {:?}, expected TYPE:FUNCTION[:METHOD[:ACCESS]]", _0)]
+    Malformed(String),
+}
+
+#[derive(Fail, Debug)]
+pub enum DumpError {
+    #[fail(display = "Invalid hex buffer {:?}", _0)]
+    Hex(String),
+
+    #[fail(display = "{} requires {} bytes but only {} were returned", _0, _1, _2)]
+    Truncated(String, usize, usize),
+
+    #[fail(display = "Unknown output format {:?}", _0)]
+    Format(String),
+
+    #[fail(display = "{} has no fields to render", _0)]
+    Empty(String),
+}
@@ -0,0 +1,476 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::error::{DeviceError, ErrorKind};
+use super::IoCtl;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::fmt;
+
+//
+// Saved metrics are text, one line per control followed by one per failure:
+//
+//   control <code> <calls> <bytes in> <bytes out> <total us> <max us> <bucket counts..> <name>
+//   failure <code> <Win32 code or error kind> <count>
+//
+pub const METRICS_HEADER: &str = "# conveyor metrics v1";
+
+const KINDS: &[ErrorKind] = &[ErrorKind::NotFound, ErrorKind::AccessDenied, ErrorKind::InvalidParameter,
+                              ErrorKind::BufferTooSmall, ErrorKind::DriverNotLoaded, ErrorKind::TimedOut,
+                              ErrorKind::Other];
+
+// upper bound of every latency bucket in microseconds, slower calls land in the last one
+pub const BUCKETS: &[u64] = &[10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 1_000_000];
+
+fn micros(elapsed: Duration) -> u64 {
+    elapsed.as_secs()
+           .saturating_mul(1_000_000)
+           .saturating_add(u64::from(elapsed.subsec_nanos() / 1_000))
+}
+
+// why a call failed: the Win32 code when the driver answered, the kind otherwise (e.g. timeouts)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Failure {
+    Code(u32),
+    Kind(ErrorKind),
+}
+
+impl<'a> From<&'a DeviceError> for Failure {
+    fn from(err: &'a DeviceError) -> Failure {
+        match err.code() {
+            Some(code) => Failure::Code(code),
+            None => Failure::Kind(err.kind()),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Failure::Code(code) => write!(f, "{}", code),
+            Failure::Kind(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+impl Failure {
+    // reads back what `Display` wrote
+    fn parse(value: &str) -> Option<Failure> {
+        match value.parse::<u32>() {
+            Ok(code) => Some(Failure::Code(code)),
+            Err(_) => KINDS.iter().find(|kind| kind.to_string() == value).map(|&kind| Failure::Kind(kind)),
+        }
+    }
+}
+
+fn invalid(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid metrics line {:?}", line))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    // one counter per entry in BUCKETS, plus the overflow bucket
+    pub counts: Vec<u64>,
+    pub total: u64,
+    pub max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            counts: vec![0; BUCKETS.len() + 1],
+            total: 0,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn add(&mut self, micros: u64) {
+        let bucket = BUCKETS.iter().position(|&bound| micros <= bound).unwrap_or(BUCKETS.len());
+
+        self.counts[bucket] += 1;
+        self.total = self.total.saturating_add(micros);
+        self.max = self.max.max(micros);
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn mean(&self) -> u64 {
+        match self.samples() {
+            0 => 0,
+            samples => self.total / samples,
+        }
+    }
+
+    // upper bound of the bucket holding the given percentile, `max` for the overflow bucket
+    pub fn percentile(&self, percentile: u64) -> u64 {
+        let samples = self.samples();
+        let rank = (samples * percentile + 99) / 100;
+        let mut seen = 0;
+
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+
+            if seen >= rank && *count > 0 {
+                return BUCKETS.get(index).map_or(self.max, |&bound| bound.min(self.max))
+            }
+        }
+
+        0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlStats {
+    pub name: String,
+    pub code: u32,
+    pub calls: u64,
+    pub failures: BTreeMap<Failure, u64>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub latency: Histogram,
+}
+
+impl ControlStats {
+    fn new(control: &IoCtl) -> ControlStats {
+        ControlStats {
+            name: control.to_string(),
+            code: control.code(),
+            calls: 0,
+            failures: BTreeMap::new(),
+            bytes_in: 0,
+            bytes_out: 0,
+            latency: Histogram::default(),
+        }
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.failures.values().sum()
+    }
+}
+
+/// Per-IoCtl counters shared by every request issued through a `Device`.
+///
+/// Each driver round trip is accounted once it completes: calls, failures by
+/// error code, bytes sent and returned, and a latency histogram. Requests
+/// cancelled through `Pending::cancel` are not accounted.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    controls: Mutex<BTreeMap<u32, ControlStats>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    // `outcome` carries the number of bytes returned by a successful call
+    pub fn record(&self, control: &IoCtl, bytes_in: usize, outcome: Result<usize, &DeviceError>, elapsed: Duration) {
+        let mut controls = self.controls.lock().expect("metrics lock poisoned");
+
+        let stats = controls.entry(control.code())
+                            .or_insert_with(|| ControlStats::new(control));
+
+        stats.calls += 1;
+        stats.bytes_in += bytes_in as u64;
+        stats.latency.add(micros(elapsed));
+
+        match outcome {
+            Ok(bytes_out) => stats.bytes_out += bytes_out as u64,
+            Err(err) => *stats.failures.entry(Failure::from(err)).or_insert(0) += 1,
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<ControlStats> {
+        self.controls.lock().expect("metrics lock poisoned").values().cloned().collect()
+    }
+
+    pub fn get(&self, control: &IoCtl) -> Option<ControlStats> {
+        self.controls.lock().expect("metrics lock poisoned").get(&control.code()).cloned()
+    }
+
+    pub fn reset(&self) {
+        self.controls.lock().expect("metrics lock poisoned").clear();
+    }
+
+    // adds `other` to the counters of the same control
+    pub fn merge(&self, other: &ControlStats) {
+        let mut controls = self.controls.lock().expect("metrics lock poisoned");
+
+        let stats = controls.entry(other.code).or_insert_with(|| ControlStats {
+            calls: 0,
+            failures: BTreeMap::new(),
+            bytes_in: 0,
+            bytes_out: 0,
+            latency: Histogram::default(),
+            ..other.clone()
+        });
+
+        stats.calls += other.calls;
+        stats.bytes_in += other.bytes_in;
+        stats.bytes_out += other.bytes_out;
+
+        for (count, other) in stats.latency.counts.iter_mut().zip(&other.latency.counts) {
+            *count += other;
+        }
+
+        stats.latency.total = stats.latency.total.saturating_add(other.latency.total);
+        stats.latency.max = stats.latency.max.max(other.latency.max);
+
+        for (&failure, &count) in &other.failures {
+            *stats.failures.entry(failure).or_insert(0) += count;
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", METRICS_HEADER)?;
+
+        for stats in self.snapshot() {
+            let counts = stats.latency.counts.iter().map(|count| count.to_string()).collect::<Vec<String>>();
+
+            writeln!(writer, "control {} {} {} {} {} {} {} {}",
+                     stats.code,
+                     stats.calls,
+                     stats.bytes_in,
+                     stats.bytes_out,
+                     stats.latency.total,
+                     stats.latency.max,
+                     counts.join(" "),
+                     stats.name)?;
+
+            for (failure, count) in &stats.failures {
+                writeln!(writer, "failure {} {} {}", stats.code, failure, count)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: BufRead>(reader: R) -> io::Result<Metrics> {
+        let metrics = Metrics::new();
+        let mut lines = reader.lines();
+
+        match lines.next() {
+            Some(Ok(ref header)) if header == METRICS_HEADER => (),
+            Some(Err(err)) => return Err(err),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a conveyor metrics file")),
+        }
+
+        let mut stats: Option<ControlStats> = None;
+
+        for line in lines {
+            let line = line?;
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            let number = |index: usize| fields.get(index).and_then(|field| field.parse::<u64>().ok()).ok_or_else(|| invalid(&line));
+
+            match fields.first() {
+                Some(&"control") => {
+                    if let Some(ref stats) = stats {
+                        metrics.merge(stats);
+                    }
+
+                    let buckets = BUCKETS.len() + 1;
+                    let name = fields.get(7 + buckets).ok_or_else(|| invalid(&line))?;
+
+                    stats = Some(ControlStats {
+                        name: name.to_string(),
+                        code: number(1)? as u32,
+                        calls: number(2)?,
+                        failures: BTreeMap::new(),
+                        bytes_in: number(3)?,
+                        bytes_out: number(4)?,
+                        latency: Histogram {
+                            counts: (7..7 + buckets).map(&number).collect::<io::Result<Vec<u64>>>()?,
+                            total: number(5)?,
+                            max: number(6)?,
+                        },
+                    });
+                },
+                Some(&"failure") => {
+                    let stats = match stats {
+                        Some(ref mut stats) if u64::from(stats.code) == number(1)? => stats,
+                        _ => return Err(invalid(&line)),
+                    };
+
+                    let failure = fields.get(2).and_then(|field| Failure::parse(field)).ok_or_else(|| invalid(&line))?;
+                    stats.failures.insert(failure, number(3)?);
+                },
+                None => (),
+                Some(_) => return Err(invalid(&line)),
+            }
+        }
+
+        if let Some(ref stats) = stats {
+            metrics.merge(stats);
+        }
+
+        Ok(metrics)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Metrics> {
+        Metrics::read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Adds these counters to those already saved in `path`, e.g. by earlier sessions.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let saved = match Metrics::load(&path) {
+            Ok(saved) => saved,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Metrics::new(),
+            Err(err) => return Err(err),
+        };
+
+        for stats in self.snapshot() {
+            saved.merge(&stats);
+        }
+
+        saved.write_to(&mut File::create(path)?)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("{:<36} {:>8} {:>8} {:>12} {:>12} {:>10} {:>10} {:>10} {:>10}",
+                    "IOCTL", "calls", "failed", "bytes in", "bytes out", "mean us", "p50 us", "p99 us", "max us")
+        ];
+
+        for stats in self.snapshot() {
+            lines.push(format!("{:<36} {:>8} {:>8} {:>12} {:>12} {:>10} {:>10} {:>10} {:>10}",
+                               stats.name,
+                               stats.calls,
+                               stats.failed(),
+                               stats.bytes_in,
+                               stats.bytes_out,
+                               stats.latency.mean(),
+                               stats.latency.percentile(50),
+                               stats.latency.percentile(99),
+                               stats.latency.max));
+
+            for (failure, count) in &stats.failures {
+                lines.push(format!("  error {:<29} {:>17}", failure, count));
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        let controls = self.snapshot().iter().map(|stats| {
+            let failures = stats.failures.iter()
+                                         .map(|(failure, count)| format!("{}: {}", quote(&failure.to_string()), count))
+                                         .collect::<Vec<String>>();
+
+            let buckets = BUCKETS.iter()
+                                 .map(|bound| quote(&bound.to_string()))
+                                 .chain(Some(quote("inf")))
+                                 .zip(stats.latency.counts.iter())
+                                 .map(|(bound, count)| format!("{}: {}", bound, count))
+                                 .collect::<Vec<String>>();
+
+            format!("{{\"name\": {}, \"code\": {}, \"calls\": {}, \"failures\": {{{}}}, \
+                     \"bytes_in\": {}, \"bytes_out\": {}, \
+                     \"latency_us\": {{\"mean\": {}, \"p50\": {}, \"p99\": {}, \"max\": {}, \"buckets\": {{{}}}}}}}",
+                    quote(&stats.name),
+                    stats.code,
+                    stats.calls,
+                    failures.join(", "),
+                    stats.bytes_in,
+                    stats.bytes_out,
+                    stats.latency.mean(),
+                    stats.latency.percentile(50),
+                    stats.latency.percentile(99),
+                    stats.latency.max,
+                    buckets.join(", "))
+        }).collect::<Vec<String>>();
+
+        format!("{{\"controls\": [{}]}}", controls.join(", "))
+    }
+}
+
+fn quote(value: &str) -> String {
+    let escaped = value.chars().flat_map(|c| match c {
+        '"' | '\\' => vec!['\\', c],
+        c => vec![c],
+    }).collect::<String>();
+
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_aggregates_per_control() {
+        let metrics = Metrics::new();
+        let create = IoCtl::from(0xB080_E800);
+        let delete = IoCtl::from(0xB080_E804);
+        let missing = DeviceError::io(delete.clone(), 1167, None);
+
+        metrics.record(&create, 0, Ok(24), Duration::from_millis(2));
+        metrics.record(&create, 0, Ok(24), Duration::from_micros(40));
+        metrics.record(&delete, 8, Err(&missing), Duration::from_micros(5));
+        metrics.record(&delete, 8, Err(&DeviceError::Timeout(delete.clone(), Duration::from_secs(1))), Duration::from_secs(1));
+
+        let stats = metrics.get(&create).unwrap();
+        assert_eq!((stats.calls, stats.failed(), stats.bytes_out), (2, 0, 48));
+        assert_eq!(stats.latency.percentile(50), 50);
+        assert_eq!(stats.latency.max, 2_000);
+
+        let stats = metrics.get(&delete).unwrap();
+        assert_eq!((stats.calls, stats.failed(), stats.bytes_in), (2, 2, 16));
+        assert_eq!(stats.failures[&Failure::Code(1167)], 1);
+        assert_eq!(stats.failures[&Failure::Kind(ErrorKind::TimedOut)], 1);
+
+        metrics.reset();
+        assert!(metrics.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_json_report() {
+        let metrics = Metrics::new();
+        let control = IoCtl::from(0xB080_E800);
+
+        metrics.record(&control, 4, Ok(24), Duration::from_micros(7));
+
+        let json = metrics.to_json();
+        assert!(json.starts_with("{\"controls\": [{\"name\": \"SE_IOCTL_CREATE_PARTITION\", \"code\": 2961238016"));
+        assert!(json.contains("\"failures\": {}"));
+        assert!(json.contains("\"buckets\": {\"10\": 1, \"25\": 0"));
+        assert!(json.ends_with("\"inf\": 0}}}]}"));
+    }
+
+    #[test]
+    fn test_saved_metrics_merge() {
+        let metrics = Metrics::new();
+        let control = IoCtl::from(0xB080_E800);
+        let timeout = DeviceError::Timeout(control.clone(), Duration::from_secs(1));
+
+        metrics.record(&control, 4, Ok(24), Duration::from_micros(7));
+        metrics.record(&control, 4, Err(&DeviceError::io(control.clone(), 1167, None)), Duration::from_millis(3));
+        metrics.record(&control, 4, Err(&timeout), Duration::from_secs(1));
+
+        let mut saved = vec![];
+        metrics.write_to(&mut saved).unwrap();
+
+        let loaded = Metrics::read_from(&saved[..]).unwrap();
+        assert_eq!(loaded.snapshot(), metrics.snapshot());
+
+        // a second session adds to the first
+        for stats in metrics.snapshot() {
+            loaded.merge(&stats);
+        }
+
+        let stats = loaded.get(&control).unwrap();
+        assert_eq!((stats.calls, stats.failed(), stats.bytes_out), (6, 4, 48));
+        assert_eq!(stats.failures[&Failure::Kind(ErrorKind::TimedOut)], 2);
+        assert_eq!(stats.latency.max, 1_000_000);
+
+        assert!(Metrics::read_from(&b"control 1 2"[..]).is_err());
+    }
+}
@@ -5,30 +5,55 @@ extern crate clap;
 extern crate slog;
 extern crate winapi;
 extern crate console;
+extern crate byteorder;
 
 pub mod command;
 pub mod error;
+pub mod record;
+pub mod codec;
+pub mod dump;
+#[cfg(windows)]
+pub mod overlapped;
+pub mod metrics;
 
 use std::fmt;
-use self::winapi::um::{ioapiset, fileapi, handleapi, winioctl};
+#[cfg(windows)]
+use self::winapi::um::{fileapi, handleapi};
 
+#[cfg(windows)]
 use std::ptr::{null_mut};
 
-use std::io::{Cursor, Error};
-
-use self::error::DeviceError;
-
-use std::mem::{zeroed};
-
+use std::io::Cursor;
+#[cfg(windows)]
+use std::io::Error;
+use std::os::raw::c_void;
+#[cfg(windows)]
+use std::sync::Arc;
+#[cfg(windows)]
+use std::time::Duration;
+
+use self::error::{DeviceError, ErrorKind};
+#[cfg(windows)]
+use self::metrics::Metrics;
+#[cfg(windows)]
+use self::overlapped::{Handle, Pending};
+
+#[cfg(windows)]
 use self::winapi::shared::minwindef::LPVOID;
 
-use self::winapi::um::minwinbase::{OVERLAPPED};
-use self::winapi::um::winnt;
+#[cfg(windows)]
+use self::winapi::um::{winbase, winnt};
 
 use super::cli;
 
+#[cfg(windows)]
 use ffi::traits::EncodeUtf16;
 
+// winioctl.h, kept here so that controls can be encoded off Windows
+pub const METHOD_BUFFERED: u32 = 0;
+pub const FILE_READ_ACCESS: u32 = 0x0001;
+pub const FILE_WRITE_ACCESS: u32 = 0x0002;
+
 #[derive(Clone)]
 pub struct IoCtl {
     name: String,
@@ -38,22 +63,52 @@ pub struct IoCtl {
     pub access: u32
 }
 
+fn encode(device_type: u32, function: u32, method: u32, access: u32) -> u32 {
+    (device_type << 16) |
+    (access      << 14) |
+    (function    <<  2) |
+     method
+}
+
+// known controls are named after the registry, anything else after its function
+fn default_name(code: u32, function: u32) -> String {
+    codec::control_name(code).map(String::from)
+                             .unwrap_or_else(|| format!("0x{:03X}", function))
+}
+
 impl IoCtl {
     pub fn new(name: Option<&str>, device_type: u32, function: u32, method: Option<u32>, access: Option<u32>) -> IoCtl {
+        let method = method.unwrap_or(METHOD_BUFFERED);
+        let access = access.unwrap_or(FILE_READ_ACCESS | FILE_WRITE_ACCESS);
+        let code = encode(device_type, function, method, access);
+
         IoCtl {
-            name: name.unwrap_or(&format!("0x{:03X}", function)).to_string(),
+            name: name.map(String::from).unwrap_or_else(|| default_name(code, function)),
             device_type: device_type,
             function: function,
-            method: method.unwrap_or(winioctl::METHOD_BUFFERED),
-            access: access.unwrap_or(winioctl::FILE_READ_ACCESS | winioctl::FILE_WRITE_ACCESS)
+            method: method,
+            access: access
         }
     }
 
     pub fn code(&self) -> u32 {
-        (self.device_type << 16) |
-        (self.access      << 14) |
-        (self.function    <<  2) |
-         self.method
+        encode(self.device_type, self.function, self.method, self.access)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn device_type_name(&self) -> Option<&'static str> {
+        codec::device_type_name(self.device_type)
+    }
+
+    pub fn method_name(&self) -> &'static str {
+        codec::method_name(self.method)
+    }
+
+    pub fn access_name(&self) -> &'static str {
+        codec::access_name(self.access)
     }
 }
 
@@ -68,7 +123,7 @@ impl From<u32> for IoCtl {
         let function = (number >> 2) & ((1 << 12) - 1);
 
         IoCtl {
-            name: format!("0x{:03X}", function),
+            name: default_name(number, function),
             device_type: (number & 0xFFFF_0000) >> 16,
             function: function,
             access: (number >> 14) & 3,
@@ -85,16 +140,68 @@ impl fmt::Display for IoCtl {
 
 impl fmt::Debug for IoCtl {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "IoCtl{{ name: {}, device: 0x{:X}, function: 0x{:03X} }}", self.name, self.device_type, self.function)
+        write!(f, "IoCtl{{ name: {}, device: {} (0x{:X}), function: 0x{:03X}, method: {}, access: {} }}",
+                    self.name,
+                    self.device_type_name().unwrap_or("?"),
+                    self.device_type,
+                    self.function,
+                    self.method_name(),
+                    self.access_name())
+    }
+}
+
+/// Anything able to answer Sentry I/O control requests.
+///
+/// `Device` talks to the real driver through `DeviceIoControl`, while other
+/// implementations (see `sentry::simulator`) allow exercising the sentry layer without it.
+/// Transports are shared between threads through `sentry::session::SentrySession`.
+pub trait Transport: fmt::Debug + Send + Sync {
+    fn call(&self, control: IoCtl, input: Option<Vec<u8>>, output: Option<Vec<u8>>) -> Result<Cursor<Vec<u8>>, DeviceError>;
+    fn raw_call(&self, control: IoCtl, ptr: *mut c_void, len: usize) -> Result<(), DeviceError>;
+
+    /// Calls `control` with a `size` bytes output buffer, growing it while the driver
+    /// reports it as too small, up to `limit` bytes.
+    fn call_sized(&self, control: IoCtl, input: Option<Vec<u8>>, size: usize, limit: usize) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        grow(&control, size, limit, |output| self.call(control.clone(), input.clone(), Some(output)))
+    }
+}
+
+//
+// Re-issues a request with a larger output buffer each time it fails with ERROR_MORE_DATA or
+// ERROR_INSUFFICIENT_BUFFER. The size the driver asked for is honored when it reported one,
+// otherwise the buffer doubles; past `limit` the request fails rather than being truncated.
+//
+fn grow<F>(control: &IoCtl, size: usize, limit: usize, mut call: F) -> Result<Cursor<Vec<u8>>, DeviceError>
+    where F: FnMut(Vec<u8>) -> Result<Cursor<Vec<u8>>, DeviceError> {
+    let mut size = size.max(1).min(limit);
+
+    loop {
+        match call(Vec::with_capacity(size)) {
+            Err(ref err) if err.kind() == ErrorKind::BufferTooSmall => {
+                if size >= limit {
+                    return Err(DeviceError::BufferLimit(control.clone(), limit))
+                }
+
+                size = err.required_size()
+                          .filter(|&required| required > size)
+                          .unwrap_or_else(|| size.saturating_mul(2))
+                          .min(limit);
+            },
+            result => return result,
+        }
     }
 }
 
+#[cfg(windows)]
 #[derive(Debug)]
 pub struct Device {
     name: String,
-    device: winnt::HANDLE
+    handle: Arc<Handle>,
+    growth: Option<usize>,
+    metrics: Option<Arc<Metrics>>
 }
 
+#[cfg(windows)]
 impl Device {
     pub fn new(name: &str) -> Result<Device, DeviceError> {
         let device = Device::open(name)?;
@@ -102,10 +209,31 @@ impl Device {
         Ok(
             Device {
             name: name.to_string(),
-            device: device
+            handle: Arc::new(Handle(device)),
+            growth: None,
+            metrics: None
         })
     }
 
+    /// Lets every `call` grow its output buffer up to `limit` bytes when the driver
+    /// reports it as too small, instead of failing on the first attempt.
+    pub fn grow_up_to(mut self, limit: usize) -> Device {
+        self.growth = Some(limit);
+        self
+    }
+
+    /// Accounts every request issued through this device in `metrics`, which
+    /// may be shared with other devices to aggregate their counters.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Device {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn metrics(&self) -> Option<&Arc<Metrics>> {
+        self.metrics.as_ref()
+    }
+
+    // handles are always overlapped, synchronous calls just wait for their request
     pub fn open(name: &str) -> Result<winnt::HANDLE, DeviceError> {
         let handle = unsafe {
             fileapi::CreateFileW(name.encode_utf16_null().as_ptr(),
@@ -113,88 +241,50 @@ impl Device {
                         winnt::FILE_SHARE_READ | winnt::FILE_SHARE_WRITE,
                         null_mut(),
                         fileapi::OPEN_ALWAYS,
-                        0,
+                        winbase::FILE_FLAG_OVERLAPPED,
                         handleapi::INVALID_HANDLE_VALUE)
         };
 
         if handle == handleapi::INVALID_HANDLE_VALUE {
-            return Err(DeviceError::Open(name.to_string(), Error::last_os_error().to_string()))
+            return Err(DeviceError::open(name, Error::last_os_error()))
         }
 
         Ok( handle )
     }
 
-    pub fn raw_call(&self, control: IoCtl, ptr: LPVOID, len: usize) -> Result<(), DeviceError> {
+    /// Issues `control` without waiting for the driver to complete it.
+    ///
+    /// Several requests may be in flight on the same device at once, e.g. a monitor
+    /// wait alongside bulk memory reads. The returned `Pending` can be waited on,
+    /// with or without a timeout, cancelled or handed a completion callback.
+    pub fn submit(&self, control: IoCtl, input: Option<Vec<u8>>, output: Option<Vec<u8>>) -> Result<Pending, DeviceError> {
+        overlapped::submit(&self.handle, self.metrics.as_ref(), control, input, output)
+    }
 
-        let mut bytes = 0;
-        let mut overlapped: OVERLAPPED = unsafe { zeroed() };
+    pub fn call_timeout(&self, control: IoCtl, input: Option<Vec<u8>>, output: Option<Vec<u8>>, timeout: Duration) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        self.submit(control, input, output)?.wait_timeout(timeout)
+    }
+}
 
-        let success = unsafe {
-            ioapiset::DeviceIoControl(
-                self.device,
-                control.code(),
-                ptr,
-                len as u32,
-                ptr,
-                len as u32,
-                &mut bytes,
-                &mut overlapped) != 0
-        };
+#[cfg(windows)]
+impl Transport for Device {
+    fn raw_call(&self, control: IoCtl, ptr: *mut c_void, len: usize) -> Result<(), DeviceError> {
+        // waiting right away keeps `ptr` borrowed for the whole request
+        let pending = unsafe { overlapped::submit_raw(&self.handle, self.metrics.as_ref(), control, ptr as LPVOID, len)? };
 
-        if !success { return Err(DeviceError::IoCall(control,
-                                                     Error::last_os_error().to_string(),
-                                                     Error::last_os_error()))};
+        pending.wait()?;
 
         Ok(())
     }
 
-    pub fn call(&self, control: IoCtl, input: Option<Vec<u8>>, output: Option<Vec<u8>>) -> Result<Cursor<Vec<u8>>, DeviceError> {
-
-        let mut bytes = 0;
-        let mut overlapped: OVERLAPPED = unsafe { zeroed() };
-
-        // if there is no input, just put a null pointer and 0 as size
-        // a little "hack" is that we should remain a reference of input to avoid release the buffer
-        // probably it would require some lifetime specification
-        let (input_ptr, input_size, _input) = match input {
-            Some(mut buffer) => (buffer.as_mut_ptr() as LPVOID, buffer.len() as u32, buffer),
-            None => (null_mut(), 0u32, vec![])
-        };
-
-        // I don't like this at all, but this is what I've went on so far.
-        let (output_ptr, output_size, mut output) = match output {
-            Some(mut buffer) => (buffer.as_mut_ptr() as LPVOID, buffer.capacity() as u32, buffer),
-            None => (null_mut(), 0u32, vec![])
-        };
-
-        let success = unsafe {
-            ioapiset::DeviceIoControl(
-                self.device,
-                control.code(),
-                input_ptr,
-                input_size,
-                output_ptr,
-                output_size,
-                &mut bytes,
-                &mut overlapped) != 0
-        };
-
-
-        if !success { return Err(DeviceError::IoCall(control,
-                                                     Error::last_os_error().to_string(),
-                                                     Error::last_os_error()))};
-
-        unsafe { output.set_len(bytes as usize) };
-        output.shrink_to_fit();
-
-        Ok(Cursor::new(output))
+    fn call(&self, control: IoCtl, input: Option<Vec<u8>>, output: Option<Vec<u8>>) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        match (self.growth, output) {
+            (Some(limit), Some(output)) => self.call_sized(control, input, output.capacity(), limit),
+            (_, output) => self.submit(control, input, output)?.wait(),
+        }
     }
-}
 
-impl Drop for Device {
-    fn drop(&mut self) {
-        unsafe {
-            handleapi::CloseHandle(self.device);
-        }
+    fn call_sized(&self, control: IoCtl, input: Option<Vec<u8>>, size: usize, limit: usize) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        grow(&control, size, limit, |output| self.submit(control.clone(), input.clone(), Some(output))?.wait())
     }
 }
@@ -0,0 +1,262 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::winapi::um::{ioapiset, synchapi, handleapi, winbase};
+use super::winapi::um::minwinbase::OVERLAPPED;
+use super::winapi::um::winnt::HANDLE;
+use super::winapi::shared::minwindef::{DWORD, LPVOID, TRUE, FALSE};
+use super::winapi::shared::winerror::{ERROR_IO_PENDING, ERROR_MORE_DATA, WAIT_TIMEOUT};
+use super::byteorder::{LittleEndian, ReadBytesExt};
+
+use super::error::DeviceError;
+use super::metrics::Metrics;
+use super::IoCtl;
+
+use std::io::{Cursor, Error};
+use std::ptr::{null, null_mut};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{mem, thread};
+
+// device handle shared between a `Device` and its in-flight requests,
+// so that the handle outlives every request issued through it
+#[derive(Debug)]
+pub struct Handle(pub HANDLE);
+
+unsafe impl Send for Handle {}
+unsafe impl Sync for Handle {}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe {
+            handleapi::CloseHandle(self.0);
+        }
+    }
+}
+
+//
+// An I/O control issued on an overlapped handle which may still be running in the driver.
+//
+// The request owns its buffers and its OVERLAPPED block until it completes; dropping
+// an unfinished request cancels it and waits for the driver to let go of them.
+//
+pub struct Pending {
+    handle: Arc<Handle>,
+    control: IoCtl,
+    overlapped: Box<OVERLAPPED>,
+    _input: Vec<u8>,
+    output: Vec<u8>,
+    done: bool,
+    metrics: Option<Arc<Metrics>>,
+    sent: usize,
+    returned: usize,
+    started: Instant,
+}
+
+unsafe impl Send for Pending {}
+
+pub fn submit(handle: &Arc<Handle>,
+              metrics: Option<&Arc<Metrics>>,
+              control: IoCtl,
+              input: Option<Vec<u8>>,
+              output: Option<Vec<u8>>) -> Result<Pending, DeviceError> {
+    let mut input = input.unwrap_or_default();
+    let mut output = output.unwrap_or_default();
+
+    let (input_ptr, input_size) = match input.len() {
+        0 => (null_mut(), 0),
+        size => (input.as_mut_ptr() as LPVOID, size),
+    };
+
+    let (output_ptr, output_size) = match output.capacity() {
+        0 => (null_mut(), 0),
+        size => (output.as_mut_ptr() as LPVOID, size),
+    };
+
+    Pending::issue(handle, metrics, control, (input_ptr, input_size), (output_ptr, output_size), input, output)
+}
+
+// the caller keeps `ptr` alive until the request completes
+pub unsafe fn submit_raw(handle: &Arc<Handle>,
+                         metrics: Option<&Arc<Metrics>>,
+                         control: IoCtl,
+                         ptr: LPVOID,
+                         len: usize) -> Result<Pending, DeviceError> {
+    Pending::issue(handle, metrics, control, (ptr, len), (ptr, len), vec![], vec![])
+}
+
+fn millis(timeout: Duration) -> DWORD {
+    let millis = timeout.as_secs()
+                        .saturating_mul(1000)
+                        .saturating_add(u64::from(timeout.subsec_nanos() / 1_000_000));
+
+    // INFINITE is reserved for `wait`
+    millis.min(u64::from(winbase::INFINITE - 1)) as DWORD
+}
+
+impl Pending {
+    fn issue(handle: &Arc<Handle>,
+             metrics: Option<&Arc<Metrics>>,
+             control: IoCtl,
+             (input_ptr, input_size): (LPVOID, usize),
+             (output_ptr, output_size): (LPVOID, usize),
+             input: Vec<u8>,
+             output: Vec<u8>) -> Result<Pending, DeviceError> {
+
+        let mut overlapped: Box<OVERLAPPED> = Box::new(unsafe { mem::zeroed() });
+
+        // a manual reset event, as required by GetOverlappedResult
+        overlapped.hEvent = unsafe { synchapi::CreateEventW(null_mut(), TRUE, FALSE, null()) };
+
+        if overlapped.hEvent.is_null() {
+            return Err(DeviceError::last_os_error(control))
+        }
+
+        let mut pending = Pending {
+            handle: Arc::clone(handle),
+            control: control,
+            overlapped: overlapped,
+            _input: input,
+            output: output,
+            done: false,
+            metrics: metrics.cloned(),
+            sent: input_size,
+            returned: 0,
+            started: Instant::now(),
+        };
+
+        let success = unsafe {
+            ioapiset::DeviceIoControl(
+                pending.handle.0,
+                pending.control.code(),
+                input_ptr,
+                input_size as u32,
+                output_ptr,
+                output_size as u32,
+                null_mut(),
+                &mut *pending.overlapped) != 0
+        };
+
+        if !success {
+            let code = Error::last_os_error().raw_os_error().unwrap_or(0) as u32;
+
+            if code != ERROR_IO_PENDING {
+                // nothing was queued, there is nothing to cancel on drop
+                pending.done = true;
+                let err = DeviceError::io(pending.control.clone(), code, None);
+                pending.account(Err(&err));
+                return Err(err)
+            }
+        }
+
+        Ok(pending)
+    }
+
+    pub fn control(&self) -> &IoCtl {
+        &self.control
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.done || unsafe { synchapi::WaitForSingleObject(self.overlapped.hEvent, 0) } != WAIT_TIMEOUT
+    }
+
+    // blocks until the driver completes the request
+    pub fn wait(mut self) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        self.complete()
+    }
+
+    // blocks up to `timeout`, cancelling the request once it expires
+    pub fn wait_timeout(mut self, timeout: Duration) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        if unsafe { synchapi::WaitForSingleObject(self.overlapped.hEvent, millis(timeout)) } == WAIT_TIMEOUT {
+            self.abort();
+            let err = DeviceError::Timeout(self.control.clone(), timeout);
+            self.account(Err(&err));
+            return Err(err)
+        }
+
+        self.complete()
+    }
+
+    pub fn cancel(mut self) {
+        self.abort();
+    }
+
+    // waits on a separate thread and hands the result over to `callback`
+    pub fn then<F>(self, callback: F) -> thread::JoinHandle<()>
+        where F: FnOnce(Result<Cursor<Vec<u8>>, DeviceError>) + Send + 'static {
+        thread::spawn(move || callback(self.wait()))
+    }
+
+    // called once per request, when it completes, fails or times out
+    fn account(&self, outcome: Result<usize, &DeviceError>) {
+        if let Some(ref metrics) = self.metrics {
+            metrics.record(&self.control, self.sent, outcome, self.started.elapsed());
+        }
+    }
+
+    fn complete(&mut self) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        let result = self.collect();
+        self.account(result.as_ref().map(|_| self.returned));
+        result
+    }
+
+    fn collect(&mut self) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        let mut bytes = 0;
+
+        let success = unsafe {
+            ioapiset::GetOverlappedResult(self.handle.0, &mut *self.overlapped, &mut bytes, TRUE) != 0
+        };
+
+        self.done = true;
+        self.returned = bytes as usize;
+
+        if !success {
+            let code = Error::last_os_error().raw_os_error().unwrap_or(0) as u32;
+
+            // the I/O manager leaves the driver's NTSTATUS in the OVERLAPPED block
+            let error = DeviceError::io(self.control.clone(), code, Some(self.overlapped.Internal as u32));
+
+            // on STATUS_BUFFER_OVERFLOW the driver writes back the size it needs
+            if code == ERROR_MORE_DATA && bytes >= 8 {
+                unsafe { self.output.set_len(8) };
+                let required = self.output.as_slice().read_u64::<LittleEndian>().unwrap_or(0);
+                return Err(error.requiring(required as usize))
+            }
+
+            return Err(error)
+        }
+
+        let mut output = mem::replace(&mut self.output, vec![]);
+
+        unsafe { output.set_len((bytes as usize).min(output.capacity())) };
+        output.shrink_to_fit();
+
+        Ok(Cursor::new(output))
+    }
+
+    fn abort(&mut self) {
+        if self.done {
+            return
+        }
+
+        let mut bytes = 0;
+
+        unsafe {
+            ioapiset::CancelIoEx(self.handle.0, &mut *self.overlapped);
+
+            // the driver owns the buffers until the cancellation is acknowledged
+            ioapiset::GetOverlappedResult(self.handle.0, &mut *self.overlapped, &mut bytes, TRUE);
+        }
+
+        self.done = true;
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.abort();
+
+        unsafe {
+            handleapi::CloseHandle(self.overlapped.hEvent);
+        }
+    }
+}
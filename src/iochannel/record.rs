@@ -0,0 +1,466 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use super::error::DeviceError;
+use super::{IoCtl, Transport};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::os::raw::c_void;
+use std::{fmt, slice};
+
+//
+// Recording layout (little-endian):
+//
+//   header: magic "CNVYREC\0", version u16
+//   entry:  kind u8, code u32, timestamp u64 (ms since epoch),
+//           name (u16 len + utf8), input (u32 len + bytes), output (u32 len + bytes),
+//           error u32 (0 when the call succeeded)
+//
+// For raw calls `input` is the struct before the call and `output` the struct after it,
+// memory written by the driver through pointers embedded in the struct is not captured.
+// Those structs hold pointers valid only in the recorded process, e.g. mapped memory or
+// allocations, so replays never copy them back: raw calls the driver answered by
+// changing the struct fail instead.
+//
+pub const RECORD_MAGIC: &[u8; 8] = b"CNVYREC\0";
+pub const RECORD_VERSION: u16 = 1;
+
+// stored when the failure doesn't carry an OS error code
+const UNKNOWN_ERROR: u32 = 0xFFFF_FFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallKind {
+    Buffered,
+    Raw
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub kind: CallKind,
+    pub name: String,
+    pub code: u32,
+    pub timestamp: u64,
+    pub input: Vec<u8>,
+    pub output: Vec<u8>,
+    pub error: Option<u32>,
+}
+
+impl Entry {
+    fn new(kind: CallKind, control: &IoCtl, input: Vec<u8>) -> Entry {
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        Entry {
+            kind: kind,
+            name: control.to_string(),
+            code: control.code(),
+            timestamp: elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos() / 1_000_000),
+            input: input,
+            output: vec![],
+            error: None,
+        }
+    }
+
+    fn set_result<T>(&mut self, result: &Result<T, DeviceError>) {
+        if let Err(ref err) = *result {
+            self.error = Some(match *err {
+                DeviceError::IoCall { code, .. } => code,
+                _ => UNKNOWN_ERROR,
+            });
+        }
+    }
+
+    fn replay_error(&self, control: IoCtl) -> Option<DeviceError> {
+        self.error.map(|code| match code {
+            UNKNOWN_ERROR => DeviceError::Replay(format!("{} failed with an unrecorded error", control)),
+            code => DeviceError::io(control, code, None),
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u8(match self.kind { CallKind::Buffered => 0, CallKind::Raw => 1 })?;
+        writer.write_u32::<LittleEndian>(self.code)?;
+        writer.write_u64::<LittleEndian>(self.timestamp)?;
+        writer.write_u16::<LittleEndian>(self.name.len() as u16)?;
+        writer.write_all(self.name.as_bytes())?;
+        writer.write_u32::<LittleEndian>(self.input.len() as u32)?;
+        writer.write_all(&self.input)?;
+        writer.write_u32::<LittleEndian>(self.output.len() as u32)?;
+        writer.write_all(&self.output)?;
+        writer.write_u32::<LittleEndian>(self.error.unwrap_or(0))?;
+
+        Ok(())
+    }
+
+    // returns None at a clean end of stream
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Entry>> {
+        let kind = match reader.read_u8() {
+            Ok(0) => CallKind::Buffered,
+            Ok(1) => CallKind::Raw,
+            Ok(other) => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                   format!("unknown call kind {}", other))),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let code = reader.read_u32::<LittleEndian>()?;
+        let timestamp = reader.read_u64::<LittleEndian>()?;
+
+        let size = reader.read_u16::<LittleEndian>()? as usize;
+        let name = read_block(reader, size)?;
+        let name = String::from_utf8(name)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let size = reader.read_u32::<LittleEndian>()? as usize;
+        let input = read_block(reader, size)?;
+        let size = reader.read_u32::<LittleEndian>()? as usize;
+        let output = read_block(reader, size)?;
+
+        let error = match reader.read_u32::<LittleEndian>()? {
+            0 => None,
+            code => Some(code)
+        };
+
+        Ok(Some(Entry {
+            kind: kind,
+            name: name,
+            code: code,
+            timestamp: timestamp,
+            input: input,
+            output: output,
+            error: error,
+        }))
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {} (0x{:08X}) {:?} in: {} bytes, out: {} bytes",
+                    self.timestamp,
+                    self.name,
+                    self.code,
+                    self.kind,
+                    self.input.len(),
+                    self.output.len())?;
+
+        match self.error {
+            Some(code) => write!(f, " -> error {}", code),
+            None => write!(f, " -> ok")
+        }
+    }
+}
+
+fn read_block<R: Read>(reader: &mut R, size: usize) -> io::Result<Vec<u8>> {
+    let mut buffer = vec![0; size];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+pub fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(RECORD_MAGIC)?;
+    writer.write_u16::<LittleEndian>(RECORD_VERSION)
+}
+
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != RECORD_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a conveyor recording"))
+    }
+
+    let version = reader.read_u16::<LittleEndian>()?;
+
+    if version > RECORD_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("unsupported recording version {}", version)))
+    }
+
+    Ok(version)
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<Entry>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    read_header(&mut reader)?;
+
+    let mut entries = vec![];
+
+    while let Some(entry) = Entry::read_from(&mut reader)? {
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Wraps a transport and appends every exchanged IOCTL to a recording file.
+///
+/// Calls return what the driver answered even when storing them fails, e.g. a created
+/// partition must reach its owner to be deleted again; see `lost` for how many are
+/// missing from the recording.
+pub struct Recorder<T: Transport> {
+    transport: T,
+    writer: Mutex<BufWriter<File>>,
+    lost: Arc<AtomicUsize>,
+}
+
+impl<T: Transport> Recorder<T> {
+    pub fn create<P: AsRef<Path>>(transport: T, path: P) -> io::Result<Recorder<T>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        write_header(&mut writer)?;
+        writer.flush()?;
+
+        Ok(Recorder {
+            transport: transport,
+            writer: Mutex::new(writer),
+            lost: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    // keeps what earlier sessions recorded, e.g. when a command opens several of them
+    pub fn append<P: AsRef<Path>>(transport: T, path: P) -> io::Result<Recorder<T>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let empty = file.metadata()?.len() == 0;
+
+        let mut writer = BufWriter::new(file);
+
+        if empty {
+            write_header(&mut writer)?;
+            writer.flush()?;
+        }
+
+        Ok(Recorder {
+            transport: transport,
+            writer: Mutex::new(writer),
+            lost: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    // counts the entries it fails to store in `lost`, e.g. shared by several recorders
+    pub fn counting_losses(mut self, lost: Arc<AtomicUsize>) -> Recorder<T> {
+        self.lost = lost;
+        self
+    }
+
+    // calls missing from the recording
+    pub fn lost(&self) -> usize {
+        self.lost.load(Ordering::SeqCst)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    // entries are flushed one by one, a crashing session still leaves a usable trace
+    fn store(&self, entry: &Entry) {
+        let mut writer = self.writer.lock().expect("recording lock poisoned");
+
+        if entry.write_to(&mut *writer).and_then(|_| writer.flush()).is_err() {
+            self.lost.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<T: Transport> fmt::Debug for Recorder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Recorder({:?})", self.transport)
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn call(&self, control: IoCtl, input: Option<Vec<u8>>, output: Option<Vec<u8>>) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        let mut entry = Entry::new(CallKind::Buffered, &control, input.clone().unwrap_or_default());
+
+        let result = self.transport.call(control, input, output);
+
+        entry.set_result(&result);
+
+        if let Ok(ref cursor) = result {
+            entry.output = cursor.get_ref().clone();
+        }
+
+        self.store(&entry);
+
+        result
+    }
+
+    fn raw_call(&self, control: IoCtl, ptr: *mut c_void, len: usize) -> Result<(), DeviceError> {
+        let snapshot = || if ptr.is_null() { vec![] } else {
+            unsafe { slice::from_raw_parts(ptr as *const u8, len).to_vec() }
+        };
+
+        let mut entry = Entry::new(CallKind::Raw, &control, snapshot());
+
+        let result = self.transport.raw_call(control, ptr, len);
+
+        entry.set_result(&result);
+        entry.output = snapshot();
+
+        self.store(&entry);
+
+        result
+    }
+}
+
+/// Answers I/O controls from a recording, in the same order they were captured.
+///
+/// Calls whose code doesn't match the recording fail, input mismatches are collected
+/// and can be inspected through `divergences()`.
+pub struct Replay {
+    entries: Mutex<VecDeque<Entry>>,
+    position: Mutex<usize>,
+    divergences: Mutex<Vec<String>>,
+}
+
+impl Replay {
+    pub fn new(entries: Vec<Entry>) -> Replay {
+        Replay {
+            entries: Mutex::new(entries.into_iter().collect()),
+            position: Mutex::new(0),
+            divergences: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Replay> {
+        Ok(Replay::new(load(path)?))
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.entries.lock().expect("replay lock poisoned").len()
+    }
+
+    pub fn divergences(&self) -> Vec<String> {
+        self.divergences.lock().expect("replay lock poisoned").clone()
+    }
+
+    fn next(&self, kind: CallKind, control: &IoCtl, input: &[u8]) -> Result<Entry, DeviceError> {
+        let index = {
+            let mut position = self.position.lock().expect("replay lock poisoned");
+            *position += 1;
+            *position
+        };
+
+        let entry = self.entries.lock().expect("replay lock poisoned").pop_front().ok_or_else(|| {
+            DeviceError::Replay(format!("#{} {} issued after the end of the recording", index, control))
+        })?;
+
+        if entry.code != control.code() || entry.kind != kind {
+            return Err(DeviceError::Replay(format!("#{} expected {} (0x{:08X}) but got {} (0x{:08X})",
+                                                   index, entry.name, entry.code, control, control.code())))
+        }
+
+        if entry.input != input {
+            self.divergences.lock().expect("replay lock poisoned").push(format!("#{} {}: input differs from the recording", index, control));
+        }
+
+        Ok(entry)
+    }
+}
+
+impl fmt::Debug for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Replay(remaining: {})", self.remaining())
+    }
+}
+
+impl Transport for Replay {
+    fn call(&self, control: IoCtl, input: Option<Vec<u8>>, _output: Option<Vec<u8>>) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        let entry = self.next(CallKind::Buffered, &control, &input.unwrap_or_default())?;
+
+        match entry.replay_error(control) {
+            Some(err) => Err(err),
+            None => Ok(Cursor::new(entry.output))
+        }
+    }
+
+    fn raw_call(&self, control: IoCtl, ptr: *mut c_void, len: usize) -> Result<(), DeviceError> {
+        let buffer: &[u8] = if ptr.is_null() { &[] } else {
+            unsafe { slice::from_raw_parts(ptr as *const u8, len) }
+        };
+
+        let entry = self.next(CallKind::Raw, &control, buffer)?;
+
+        if let Some(err) = entry.replay_error(control) {
+            return Err(err)
+        }
+
+        if entry.output.len() != buffer.len() {
+            return Err(DeviceError::Replay(format!("{}: recorded {} bytes but buffer has {}",
+                                                   entry.name, entry.output.len(), buffer.len())))
+        }
+
+        // whatever the driver wrote back may point into the recorded process
+        if entry.output != entry.input {
+            return Err(DeviceError::Replay(format!("{}: the recorded answer holds pointers of another process and can't be replayed",
+                                                   entry.name)))
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_roundtrip() {
+        let entry = Entry {
+            kind: CallKind::Buffered,
+            name: "SE_IOCTL_CREATE_PARTITION".to_string(),
+            code: 0xB080_E800,
+            timestamp: 1_500_000_000_000,
+            input: vec![1, 2, 3],
+            output: vec![4, 5],
+            error: Some(1167),
+        };
+
+        let mut buffer = vec![];
+        write_header(&mut buffer).unwrap();
+        entry.write_to(&mut buffer).unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_header(&mut reader).unwrap(), RECORD_VERSION);
+        assert_eq!(Entry::read_from(&mut reader).unwrap(), Some(entry));
+        assert_eq!(Entry::read_from(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_replay_detects_divergence() {
+        let control = IoCtl::from(0xB080_E800);
+        let mut entry = Entry::new(CallKind::Buffered, &control, vec![]);
+        entry.output = vec![0xAA];
+
+        let replay = Replay::new(vec![entry]);
+
+        assert!(replay.call(IoCtl::from(0xB080_E804), None, None).is_err());
+        assert_eq!(replay.remaining(), 0);
+        assert!(replay.call(control, None, None).is_err());
+    }
+
+    #[test]
+    fn test_replay_never_copies_raw_answers() {
+        let control = IoCtl::from(0xB080_E800);
+
+        let mut unchanged = Entry::new(CallKind::Raw, &control, vec![1, 2, 3, 4]);
+        unchanged.output = unchanged.input.clone();
+
+        // e.g. a mapping, the driver wrote a pointer into the struct
+        let mut mapped = Entry::new(CallKind::Raw, &control, vec![1, 2, 3, 4]);
+        mapped.output = vec![0x00, 0x10, 0x46, 0x9e];
+
+        let replay = Replay::new(vec![unchanged, mapped]);
+
+        let mut buffer = vec![1u8, 2, 3, 4];
+        let ptr = buffer.as_mut_ptr() as *mut c_void;
+
+        assert!(replay.raw_call(control.clone(), ptr, 4).is_ok());
+        assert!(replay.raw_call(control, ptr, 4).is_err());
+        assert_eq!(buffer, vec![1, 2, 3, 4]);
+    }
+}
@@ -12,6 +12,8 @@ extern crate indicatif;
 pub mod cli;
 pub mod symbols;
 pub mod iochannel;
+#[cfg(windows)]
 pub mod service;
+#[cfg(windows)]
 pub mod tests;
 pub mod sentry;
@@ -1,5 +1,7 @@
 // Copyright © ByteHeed.  All rights reserved.
-use conveyor::{iochannel, sentry, service, symbols, tests};
+use conveyor::{iochannel, sentry, symbols};
+#[cfg(windows)]
+use conveyor::{service, tests};
 
 extern crate clap;
 extern crate conveyor;
@@ -10,14 +12,18 @@ extern crate termcolor;
 use failure::Error;
 
 use std::process;
-use clap::{App, Arg, ArgMatches, SubCommand};
+use clap::{App, Arg, ArgMatches};
+#[cfg(windows)]
+use clap::SubCommand;
 use std::sync::mpsc::channel;
 use std::sync::mpsc::{Sender};
 use conveyor::cli::output::{create_messenger, MessageType, ShellMessage};
+use conveyor::sentry::session::SessionOptions;
 
-fn run(app: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+fn run(app: &ArgMatches, session: &SessionOptions, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
     match app.subcommand() {
-        ("device", Some(matches)) => iochannel::command::parse(matches, &messenger),
+        ("device", Some(matches)) => iochannel::command::parse(matches, session, &messenger),
+        #[cfg(windows)]
         ("load", Some(matches)) => {
             let target = matches.value_of("target")
                              .expect("can't extract TARGET from arguments");
@@ -27,6 +33,7 @@ fn run(app: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error>
 
             Ok(())
         },
+        #[cfg(windows)]
         ("unload", Some(matches)) => {
             let target = matches.value_of("target")
                              .expect("can't extract TARGET from arguments");
@@ -37,12 +44,19 @@ fn run(app: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error>
             Ok(())
         },
         ("pdb", Some(matches)) => symbols::command::parse(matches, &messenger),
+        #[cfg(windows)]
         ("services", Some(matches)) => service::command::parse(matches, &messenger),
+        #[cfg(windows)]
         ("tests", Some(matches)) => tests::command::parse(matches, &messenger),
+        #[cfg(windows)]
         ("monitor", Some(matches)) => conveyor::tests::monitor::parse(matches, &messenger),
+        #[cfg(windows)]
         ("patch", Some(matches)) => conveyor::tests::patches::parse(matches, &messenger),
+        #[cfg(windows)]
         ("token", Some(matches)) => conveyor::tests::token::parse(matches, &messenger),
         ("sentry", Some(matches)) => sentry::command::parse(matches, &messenger),
+        ("memguard", Some(matches)) => sentry::memguard::command::parse(matches, session, &messenger),
+        ("policy", Some(matches)) => sentry::policy::command::parse(matches, session, &messenger),
         _ => Ok(println!("{}", app.usage())),
     }
 }
@@ -70,36 +84,60 @@ Sherab G. <sherab.giovannini@byteheed.com>
 A gate between humans and dragons.
 ___________________________________________________________________________\n\n"
     );
-    let target = Arg::with_name("target").short("t")
-                            .required(true)
-                            .value_name("TARGET")
-                            .help("service target");
-
-
-
-    let matches = App::new("conveyor")
+    let app = App::new("conveyor")
         .about("A gate between humans and dragons.")
         .version("1.0")
         .author("Sherab G. <sherab.giovannini@byteheed.com>")
         .arg(Arg::with_name("v") .short("v") .multiple(true) .help("Sets the level of verbosity"))
-        .subcommand(conveyor::service::command::bind())
+        .arg(Arg::with_name("record") .long("record") .value_name("FILE")
+                                      .help("Appends every IOCTL the device, memguard and policy commands send to FILE, see `device trace`"))
+        .arg(Arg::with_name("metrics") .long("metrics") .value_name("FILE")
+                                       .help("Adds the latency and errors of every IOCTL to FILE, see `device stats`"))
         .subcommand(conveyor::iochannel::command::bind())
-        .subcommand(conveyor::tests::command::bind())
         // .subcommand(conveyor::sentry::command::bind())
         .subcommand(conveyor::symbols::command::bind())
-        .subcommand(conveyor::tests::patches::bind())
-        .subcommand(conveyor::tests::token::bind())
-        .subcommand(SubCommand::with_name("load")
-                                .arg(target.clone()))
-        .subcommand(SubCommand::with_name("unload")
-                                .arg(target.clone()))
-        .subcommand(conveyor::tests::monitor::bind())
-        .get_matches();
+        .subcommand(conveyor::sentry::memguard::command::bind())
+        .subcommand(conveyor::sentry::policy::command::bind());
+
+    // services and driver tests need the Windows APIs
+    #[cfg(windows)]
+    let app = {
+        let target = Arg::with_name("target").short("t")
+                                .required(true)
+                                .value_name("TARGET")
+                                .help("service target");
+
+        app.subcommand(conveyor::service::command::bind())
+           .subcommand(conveyor::tests::command::bind())
+           .subcommand(conveyor::tests::patches::bind())
+           .subcommand(conveyor::tests::token::bind())
+           .subcommand(SubCommand::with_name("load")
+                                   .arg(target.clone()))
+           .subcommand(SubCommand::with_name("unload")
+                                   .arg(target.clone()))
+           .subcommand(conveyor::tests::monitor::bind())
+    };
+
+    let matches = app.get_matches();
+
+    let mut session = SessionOptions::new();
+
+    if let Some(file) = matches.value_of("record") {
+        session = session.record(file);
+    }
+
+    if let Some(file) = matches.value_of("metrics") {
+        session = session.metrics(file);
+    }
 
     let (messenger, receiver) = channel();
     let printer = create_messenger(receiver, None, 20);
 
-    if let Err(e) = run(&matches, &messenger) {
+    let result = run(&matches, &session, &messenger);
+    // reported even when the command failed
+    let finished = session.finish();
+
+    if let Err(e) = result.and(finished) {
         ShellMessage::send( &messenger,
                     format!("Application Error: {}", e), MessageType::Exit, 0, );
 
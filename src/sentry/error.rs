@@ -25,4 +25,17 @@ pub enum MiscError {
     LoadLibrary(String),
     #[fail(display = "Unable to get procedure: {}", _0)]
     GetProcedure(String),
-}
\ No newline at end of file
+    #[fail(display = "Process {} not found", _0)]
+    ProcessNotFound(u64),
+    #[fail(display = "Symbol {} not found in the kernel PDB", _0)]
+    SymbolNotFound(String),
+    #[fail(display = "Unable to list kernel modules ({} bytes of module information)", _0)]
+    ModuleInformation(usize),
+}
+#[derive(Fail, Debug)]
+pub enum WireError {
+    #[fail(display = "{} requires {} bytes but only {} were received", _0, _1, _2)]
+    Truncated(&'static str, usize, usize),
+    #[fail(display = "{} at offset {} links to an entry inside itself or beyond any buffer ({} bytes ahead)", _0, _1, _2)]
+    Chain(&'static str, usize, u64),
+}
@@ -1,85 +1,117 @@
 // Copyright © ByteHeed.  All rights reserved.
 
-use super::iochannel::{ Device, IoCtl };
+use super::iochannel::{ Transport, IoCtl };
+use super::iochannel::dump::Layout;
 
-use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use super::memguard::{Access, Action, Range, GuardFlags, ControlGuard, RegionFlags, RegionStatus, Filter};
+use super::wire::{self, Message};
 
 use super::misc;
-use super::iochannel::error::DeviceError;
+use super::session::SentrySession;
+use super::iochannel::error::{DeviceError, ErrorKind};
 use super::error::PartitionError;
 use super::failure::Error;
 use std::io::Cursor;
 
 use self::misc::Process;
 
-use std::{mem, fmt};
 
 pub const IOCTL_SENTRY_TYPE: u32 = 0xB080;
 pub const SE_NT_DEVICE_NAME: &str = "\\\\.\\Sentry";
 
-enum_from_primitive! {
-    #[derive(Debug, Clone)]
-    enum PartitionOption {
-        None = 0,
-        TraceDebugEvents,
-        TraceToDisk,
-        CoalesceNotifications,
-        CollectStats,
-        SecureMode,
-    }
-}
-
-#[repr(C)]
-pub struct Channel {
-    pub id: u64,
-    pub address: u64,
-    pub size: u32
-}
-
-impl fmt::Debug for Channel {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Channel(id: 0x{:016X}, address: 0x{:016x}, size: 0x{:016x}",
-                        self.id,
-                        self.address,
-                        self.size)
-    }
-}
-
-impl Channel {
-    pub unsafe fn from_raw(ptr: *const u8) -> Channel {
-        mem::transmute_copy(&*ptr)
-    }
-}
-
-pub fn start_monitor(device: &Device, id: u64) -> Result<(), Error> {
+// enumerations start with room for this many entries and grow up to ENUMERATION_LIMIT bytes
+const ENUMERATION_HINT: usize = 64;
+const ENUMERATION_LIMIT: usize = 16 * 1024 * 1024;
+
+// every function exposed by the Sentry driver
+pub const CONTROLS: &[(&str, u32)] = &[
+    ("SE_IOCTL_CREATE_PARTITION",      0x0A00),
+    ("SE_IOCTL_DELETE_PARTITION",      0x0A01),
+    ("SE_IOCTL_GET_PARTITION_OPTION",  0x0A02),
+    ("SE_IOCTL_SET_PARTITION_OPTION",  0x0A03),
+    ("SE_IOCTL_REGISTER_GUARD",        0x0A10),
+    ("SE_IOCTL_UNREGISTER_GUARD",      0x0A11),
+    ("SE_IOCTL_CONTROL_GUARD",         0x0A12),
+    ("SE_IOCTL_CREATE_REGION",         0x0A20),
+    ("SE_IOCTL_DELETE_REGION",         0x0A21),
+    ("SE_IOCTL_ADD_REGION",            0x0A22),
+    ("SE_IOCTL_REMOVE_REGION",         0x0A23),
+    ("SE_IOCTL_SET_STATE_REGION",      0x0A24),
+    ("SE_IOCTL_GET_INFO_REGION",       0x0A25),
+    ("SE_IOCTL_ENUMERATE_REGION",      0x0A26),
+    ("SE_IOCTL_CREATE_PATCH",          0x0A40),
+    ("SE_IOCTL_DELETE_PATCH",          0x0A41),
+    ("SE_IOCTL_ADD_PATCH",             0x0A42),
+    ("SE_IOCTL_REMOVE_PATCH",          0x0A43),
+    ("SE_IOCTL_ENABLE_PATCH",          0x0A44),
+    ("SE_IOCTL_DISABLE_PATCH",         0x0A45),
+    ("SE_IOCTL_GET_INFO_PATCH",        0x0A46),
+    ("SE_IOCTL_ENUMERATE_PATCH",       0x0A47),
+    ("SE_ALLOC_VIRTUAL_MEMORY",        0x0A50),
+    ("SE_FREE_VIRTUAL_MEMORY",         0x0A51),
+    ("SE_COPY_VIRTUAL_MEMORY",         0x0A52),
+    ("SE_SECURE_VIRTUAL_MEMORY",       0x0A53),
+    ("SE_UNSECURE_VIRTUAL_MEMORY",     0x0A54),
+    ("SE_MAP_VIRTUAL_MEMORY",          0x0A55),
+    ("SE_UNMAP_VIRTUAL_MEMORY",        0x0A56),
+    ("SE_READ_VIRTUAL_MEMORY",         0x0A57),
+    ("SE_WRITE_VIRTUAL_MEMORY",        0x0A58),
+    ("SE_ALLOC_PROCESS_MEMORY",        0x0A59),
+    ("SE_FREE_PROCESS_MEMORY",         0x0A5A),
+    ("SE_READ_PROCESS_MEMORY",         0x0A5B),
+    ("SE_WRITE_PROCESS_MEMORY",        0x0A5C),
+    ("SE_STEAL_TOKEN",                 0x0A60),
+    ("SE_GET_EXPORT_ADDRESS",          0x0A62),
+    ("SE_RUN_TEST",                    0x0A63),
+    ("SE_CREATE_MONITOR",              0x0A70),
+    ("SE_DESTROY_MONITOR",             0x0A71),
+    ("SE_START_MONITOR",               0x0A72),
+    ("SE_STOP_MONITOR",                0x0A73),
+];
+
+// buffers returned by the driver, used to render raw `device call` output
+pub const LAYOUTS: &[Layout] = &[
+    wire::Id::LAYOUT,
+    wire::Channel::LAYOUT,
+    wire::RegionInfo::LAYOUT,
+    wire::PatchInfo::LAYOUT,
+];
+
+pub use super::wire::Channel;
+
+// tags driver failures with the objects the request was about
+fn about(context: String) -> impl FnOnce(DeviceError) -> DeviceError {
+    move |err| err.within(context)
+}
+
+fn id(cursor: Cursor<Vec<u8>>) -> Result<u64, Error> {
+    Ok(wire::Id::decode(cursor.get_ref())?.id)
+}
+
+pub fn start_monitor(device: &dyn Transport, id: u64) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_START_MONITOR"), IOCTL_SENTRY_TYPE, 0x0A72, None, None);
 
-    let mut input = vec![];
+    let input = wire::Id { id: id }.encode();
     let output: Vec<u8> = Vec::with_capacity(1000);
 
-    input.write_u64::<LittleEndian>(id)?;
-
-    let _ = device.call(control, Some(input), Some(output))?;
+    let _ = device.call(control, Some(input), Some(output)).map_err(about(format!("monitor {}", id)))?;
 
     Ok(())
 }
 
-pub fn stop_monitor(device: &Device, id: u64) -> Result<(), Error> {
+pub fn stop_monitor(device: &dyn Transport, id: u64) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_STOP_MONITOR"), IOCTL_SENTRY_TYPE, 0x0A73, None, None);
 
-    let mut input = vec![];
+    let input = wire::Id { id: id }.encode();
     let output: Vec<u8> = Vec::with_capacity(1000);
 
-    input.write_u64::<LittleEndian>(id)?;
-
-    let _ = device.call(control, Some(input), Some(output))?;
+    let _ = device.call(control, Some(input), Some(output)).map_err(about(format!("monitor {}", id)))?;
 
     Ok(())
 }
 
 
-pub fn create_monitor(device: &Device) -> Result<Channel, Error> {
+pub fn create_monitor(device: &dyn Transport) -> Result<Channel, Error> {
     let control = IoCtl::new(Some("SE_CREATE_MONITOR"), IOCTL_SENTRY_TYPE, 0x0A70, None, None);
 
 
@@ -89,22 +121,20 @@ pub fn create_monitor(device: &Device) -> Result<Channel, Error> {
 
     let cursor = device.call(control, Some(input), Some(output))?;
 
-    Ok(unsafe { Channel::from_raw(cursor.into_inner().as_ptr()) })
+    Ok(Channel::decode(cursor.get_ref())?)
 }
 
-pub fn destroy_monitor(device: &Device, id: u64) -> Result<(), Error> {
+pub fn destroy_monitor(device: &dyn Transport, id: u64) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_DESTROY_MONITOR"), IOCTL_SENTRY_TYPE, 0x0A71, None, None);
 
-    let mut input = vec![];
-
-    input.write_u64::<LittleEndian>(id)?;
+    let input = wire::Id { id: id }.encode();
 
-    device.call(control, Some(input), Some(vec![0; 1024]))?;
+    device.call(control, Some(input), Some(vec![0; 1024])).map_err(about(format!("monitor {}", id)))?;
 
     Ok(())
 }
 
-pub fn create_partition(device: &Device) -> Result<Channel, Error> {
+pub fn create_partition(device: &dyn Transport) -> Result<Channel, Error> {
     let control = IoCtl::new(Some("SE_IOCTL_CREATE_PARTITION"), IOCTL_SENTRY_TYPE, 0x0A00, None, None);
 
 
@@ -114,74 +144,55 @@ pub fn create_partition(device: &Device) -> Result<Channel, Error> {
 
     let cursor = device.call(control, Some(input), Some(output))?;
 
-    Ok(unsafe { Channel::from_raw(cursor.into_inner().as_ptr()) })
+    Ok(Channel::decode(cursor.get_ref())?)
 }
 
 
-pub fn delete_partition(device: &Device, id: u64) -> Result<(), Error> {
+pub fn delete_partition(device: &dyn Transport, id: u64) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_IOCTL_DELETE_PARTITION"), IOCTL_SENTRY_TYPE, 0x0A01, None, None);
 
-    let mut input = vec![];
+    let input = wire::Id { id: id }.encode();
 
-    input.write_u64::<LittleEndian>(id)?;
-
-    device.call(control, Some(input), Some(vec![0; 1024]))?;
+    device.call(control, Some(input), Some(vec![0; 1024])).map_err(about(format!("partition {}", id)))?;
 
     Ok(())
 }
 
 fn partition_result(id: u64, result: Result<Cursor<Vec<u8>>, DeviceError>) -> Result<Cursor<Vec<u8>>, PartitionError> {
-    match result {
-        Err(err) => {
-            if let DeviceError::IoCall(n, s, io_err) = err {
-                if let Some(1167) = io_err.raw_os_error() {
-                    return Err(PartitionError::NotExists(id))
-                } else {
-                    return Err(PartitionError::UnknownError(DeviceError::IoCall(n, s, io_err)));
-                }
-            }  else {
-                return Err(PartitionError::UnknownError(err));
-            }
-
-        },
-        Ok(cursor) => Ok(cursor)
-    }
+    result.map_err(|err| match err.kind() {
+        ErrorKind::NotFound => PartitionError::NotExists(id),
+        _ => PartitionError::UnknownError(err),
+    })
 }
 
-#[allow(dead_code)]
-pub fn get_partition_option(device: &Device, id: u64, option: u64) -> Result<u64, Error> {
+pub fn get_partition_option(device: &dyn Transport, id: u64, option: u64) -> Result<u64, Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A02, None, None);
 
 
-    let mut input = vec![];
+    let input = wire::GetPartitionOption { partition_id: id, option: option }.encode();
     let output: Vec<u8> = Vec::with_capacity(1000);
 
-    input.write_u64::<LittleEndian>(id)?;
-    input.write_u64::<LittleEndian>(option)?;
+    let result = device.call(control, Some(input), Some(output))
+                       .map_err(about(format!("partition {}, option {}", id, option)));
 
-    let mut cursor = partition_result(id, device.call(control, Some(input), Some(output)))?;
+    let cursor = partition_result(id, result)?;
 
-    Ok(cursor.read_u64::<LittleEndian>()?)
+    Ok(wire::Id::decode(cursor.get_ref())?.id)
 }
 
-#[allow(dead_code)]
-pub fn set_partition_option(device: &Device, id: u64, option: u64, value: u64) -> Result<(), Error> {
+pub fn set_partition_option(device: &dyn Transport, id: u64, option: u64, value: u64) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A03, None, None);
 
 
-    let mut input = vec![];
+    let input = wire::SetPartitionOption { partition_id: id, option: option, value: value }.encode();
     let output: Vec<u8> = Vec::with_capacity(1000);
 
-    input.write_u64::<LittleEndian>(id)?;
-    input.write_u64::<LittleEndian>(option)?;
-    input.write_u64::<LittleEndian>(value)?;
-
-    let _ = device.call(control, Some(input), Some(output))?;
+    let _ = device.call(control, Some(input), Some(output)).map_err(about(format!("partition {}, option {}", id, option)))?;
 
     Ok(())
 }
 
-pub fn register_guard_extended(device: &Device, id: u64, process: Option<Process>, filter: Option<Filter>, flags: GuardFlags, priority: u64, _function: u64) -> Result<u64, Error> {
+pub fn register_guard_extended(device: &dyn Transport, id: u64, process: Option<Process>, filter: Option<Filter>, flags: GuardFlags, priority: u64, _function: u64) -> Result<u64, Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A10, None, None);
 
 
@@ -189,326 +200,302 @@ pub fn register_guard_extended(device: &Device, id: u64, process: Option<Process
     // the device call to avoid dropping the internal allocation
     let (ptr, _filter) = if let Some(filter) = filter { (filter.kernel_ptr(), Some(filter)) } else { (0, None) };
 
-    let mut input = vec![];
     let output: Vec<u8> = Vec::with_capacity(1000);
 
     let eprocess = if let Some(process) = process { process.object() } else { 0 };
 
-    input.write_u64::<LittleEndian>(id)?;
-    input.write_u64::<LittleEndian>(eprocess)?;
-    input.write_u64::<LittleEndian>(ptr)?;
-    input.write_u64::<LittleEndian>(u64::from(flags.bits()))?;
-    input.write_u64::<LittleEndian>(priority)?;
+    let input = wire::RegisterGuard {
+        partition_id: id,
+        process: eprocess,
+        filter: ptr,
+        flags: u64::from(flags.bits()),
+        priority: priority,
+    }.encode();
 
-    let mut cursor = device.call(control, Some(input), Some(output))?;
+    let cursor = device.call(control, Some(input), Some(output)).map_err(about(format!("partition {}", id)))?;
 
-    Ok(cursor.read_u64::<LittleEndian>()?)
+    self::id(cursor)
 }
 
-pub fn register_guard(device: &Device, id: u64, filter: Option<Filter>) -> Result<u64, Error> {
-    let current = misc::Process::current();
-    Ok(register_guard_extended(device, id, Some(current), filter, GuardFlags::STOPPED, 0, 0)?)
+// guards the calling process, found by walking the process list through `session`
+pub fn register_guard(session: &SentrySession, id: u64, filter: Option<Filter>) -> Result<u64, Error> {
+    let current = misc::Process::current(session)?;
+    Ok(register_guard_extended(session, id, Some(current), filter, GuardFlags::STOPPED, 0, 0)?)
 }
 
-pub fn unregister_guard(device: &Device, id: u64) -> Result<(), Error> {
+pub fn unregister_guard(device: &dyn Transport, id: u64) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A11, None, None);
 
 
-    let mut input = vec![];
-
-    input.write_u64::<LittleEndian>(id)?;
+    let input = wire::Id { id: id }.encode();
 
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("guard {}", id)))?;
     Ok(())
 }
 
-pub fn stop_guard(device: &Device, id: u64) -> Result<(), Error> {
+pub fn stop_guard(device: &dyn Transport, id: u64) -> Result<(), Error> {
     control_guard(device, id, ControlGuard::Stop)?;
     Ok(())
 }
 
-pub fn start_guard(device: &Device, id: u64) -> Result<(), Error> {
+pub fn start_guard(device: &dyn Transport, id: u64) -> Result<(), Error> {
     control_guard(device, id, ControlGuard::Start)?;
     Ok(())
 }
 
-fn control_guard(device: &Device, id: u64, action: ControlGuard) -> Result<(), Error> {
+fn control_guard(device: &dyn Transport, id: u64, action: ControlGuard) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A12, None, None);
-    let mut input = vec![];
+    let input = wire::ControlGuard { guard_id: id, action: action as u64 }.encode();
 
-    input.write_u64::<LittleEndian>(id)?;
-    input.write_u64::<LittleEndian>(action as u64)?;
-
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("guard {}", id)))?;
 
     Ok(())
 }
 
-pub fn create_region(device: &Device, partition_id: u64, range: &Range, action: Action, access: Access, weight: Option<usize>) -> Result<u64, Error> {
-    let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A20, None, None);
-    let mut input = vec![];
-
-    input.write_u64::<LittleEndian>(partition_id)?;
-    input.write_u64::<LittleEndian>(range.base)?;
-    input.write_u64::<LittleEndian>(range.limit)?;
-
-    // each regions starts disabled
-    input.write_u32::<LittleEndian>(RegionFlags::ENABLED.bits())?; // flags
-
-    // access
-    input.write_u32::<LittleEndian>(u32::from(access.bits()))?;
-
-    // action
-    input.write_u64::<LittleEndian>(u64::from(action.bits()))?;
+pub fn create_region(device: &dyn Transport, partition_id: u64, range: &Range, action: Action, access: Access, weight: Option<usize>) -> Result<u64, Error> {
+    create_region_extended(device, partition_id, range, action, access, weight, (0, 0))
+}
 
-    // readbuffer
-    input.write_u64::<LittleEndian>(0)?;
+// `buffers` are the kernel addresses sent as the ReadBuffer and WriteBuffer of the region
+pub fn create_region_extended(device: &dyn Transport, partition_id: u64, range: &Range, action: Action, access: Access, weight: Option<usize>, buffers: (u64, u64)) -> Result<u64, Error> {
+    let (read_buffer, write_buffer) = buffers;
 
-    // writebuffer
-    input.write_u64::<LittleEndian>(0)?;
+    let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A20, None, None);
 
-    input.write_u64::<LittleEndian>(weight.unwrap_or(0) as u64)?;
+    let input = wire::CreateRegion {
+        partition_id: partition_id,
+        base: range.base,
+        limit: range.limit,
+        // each regions starts disabled
+        flags: RegionFlags::ENABLED.bits(),
+        access: u32::from(access.bits()),
+        action: u64::from(action.bits()),
+        read_buffer: read_buffer,
+        write_buffer: write_buffer,
+        weight: weight.unwrap_or(0) as u64,
+    }.encode();
 
     let output: Vec<u8> = Vec::with_capacity(1000);
-    let mut cursor = device.call(control, Some(input), Some(output))?;
+    let cursor = device.call(control, Some(input), Some(output)).map_err(about(format!("partition {}", partition_id)))?;
 
-    Ok(cursor.read_u64::<LittleEndian>()?)
+    id(cursor)
 }
 
-pub fn delete_region(device: &Device, region_id: u64) -> Result<(), Error> {
+pub fn delete_region(device: &dyn Transport, region_id: u64) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A21, None, None);
 
 
-    let mut input = vec![];
+    let input = wire::Id { id: region_id }.encode();
 
-    input.write_u64::<LittleEndian>(region_id)?;
-
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("region {}", region_id)))?;
     Ok(())
 }
 
-pub fn add_region(device: &Device, guard_id: u64, region_id: u64) -> Result<(), Error> {
+pub fn add_region(device: &dyn Transport, guard_id: u64, region_id: u64) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A22, None, None);
 
 
-    let mut input = vec![];
-
-    input.write_u64::<LittleEndian>(guard_id)?;
-    input.write_u64::<LittleEndian>(region_id)?;
+    let input = wire::GuardItem { guard_id: guard_id, item_id: region_id }.encode();
 
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("guard {}, region {}", guard_id, region_id)))?;
     Ok(())
 }
 
 #[allow(dead_code)]
-pub fn remove_region(device: &Device, guard_id: u64, region_id: u64) -> Result<(), Error> {
+pub fn remove_region(device: &dyn Transport, guard_id: u64, region_id: u64) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A23, None, None);
 
 
-    let mut input = vec![];
-
-    input.write_u64::<LittleEndian>(guard_id)?;
-    input.write_u64::<LittleEndian>(region_id)?;
+    let input = wire::GuardItem { guard_id: guard_id, item_id: region_id }.encode();
 
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("guard {}, region {}", guard_id, region_id)))?;
     Ok(())
 }
 
 #[allow(dead_code)]
-pub fn set_state_region(device: &Device, region_id: u64, state: RegionStatus) -> Result<(), Error> {
+pub fn set_state_region(device: &dyn Transport, region_id: u64, state: RegionStatus) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A24, None, None);
 
 
-    let mut input = vec![];
+    let input = wire::SetStateRegion { region_id: region_id, state: state as u64 }.encode();
 
-    input.write_u64::<LittleEndian>(region_id)?;
-    input.write_u64::<LittleEndian>(state as u64)?;
-
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("region {}", region_id)))?;
     Ok(())
 }
 
 #[allow(dead_code)]
-pub fn get_info_region(device: &Device, region_id: u64) -> Result<(), Error> {
+pub fn get_info_region(device: &dyn Transport, region_id: u64) -> Result<wire::RegionInfo, Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A25, None, None);
 
 
-    let mut input = vec![];
-
-    input.write_u64::<LittleEndian>(region_id)?;
-
-    let mut cursor = device.call(control, Some(input), None)?;
-
-    let region_id = cursor.read_u64::<LittleEndian>()?;
-    let next_entry_offset = cursor.read_u64::<LittleEndian>()?;
-    let base_address = cursor.read_u64::<LittleEndian>()?;
-    let size = cursor.read_u64::<LittleEndian>()?;
-    let access_type = cursor.read_u32::<LittleEndian>()?;
-    let flags = cursor.read_u64::<LittleEndian>()?;
-    let _action = cursor.read_u64::<LittleEndian>()?;
-    let _action = cursor.read_u64::<LittleEndian>()?;
-    let action = cursor.read_u64::<LittleEndian>()?;
-    let weight = cursor.read_u64::<LittleEndian>()?;
-    let context = cursor.read_u64::<LittleEndian>()?;
-    let guard_count = cursor.read_u64::<LittleEndian>()?;
-
-    println!("region_id: 0x{:08X}", region_id);
-    println!("base_address: 0x{:08X}", base_address);
-    println!("next_entry_offset: 0x{:08X}", next_entry_offset);
-    println!("size: 0x{:08X}", size);
-    println!("access_type: 0x{:08X}", access_type);
-    println!("flags: 0x{:08X}", flags);
-    println!("action: 0x{:08X}", action);
-    println!("weight: 0x{:08X}", weight);
-    println!("context: 0x{:08X}", context);
-    println!("guard_count: 0x{:08X}", guard_count);
-    println!("region_id: 0x{:08X}", region_id);
+    let input = wire::Id { id: region_id }.encode();
+    let output: Vec<u8> = Vec::with_capacity(wire::RegionInfo::SIZE);
 
-    Ok(())
+    let cursor = device.call(control, Some(input), Some(output)).map_err(about(format!("region {}", region_id)))?;
+
+    Ok(wire::RegionInfo::decode(cursor.get_ref())?)
 }
 
-#[allow(dead_code)]
-pub fn enumerate_region(device: &Device, partition_id: u64, guard_id: u64) -> Result<(), Error> {
+// regions of a partition, or only those linked to `guard_id` when it isn't 0
+pub fn enumerate_region(device: &dyn Transport, partition_id: u64, guard_id: u64) -> Result<wire::Entries<wire::RegionInfo>, Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A26, None, None);
-    let mut input = vec![];
+    let input = wire::Enumerate { partition_id: partition_id, guard_id: guard_id }.encode();
 
-    input.write_u64::<LittleEndian>(partition_id)?;
-    input.write_u64::<LittleEndian>(guard_id)?;
+    let size = ENUMERATION_HINT * wire::RegionInfo::SIZE;
 
-    let output: Vec<u8> = Vec::with_capacity(8 * 1000); // by default it supports 1000 regions
+    let cursor = device.call_sized(control, Some(input), size, ENUMERATION_LIMIT).map_err(about(format!("partition {}, guard {}", partition_id, guard_id)))?;
 
-    let mut cursor = device.call(control, Some(input), Some(output))?;
-
-    let _region_id = cursor.read_u64::<LittleEndian>()?;
-
-    // TODO: result in an enumerate object (iterator over buffer?)
-    Ok(())
+    Ok(wire::Entries::new(cursor.into_inner()))
 }
 
 
-pub fn create_patch(device: &Device, partition_id: u64, base_address: u64, patch_range: &Range) -> Result<u64, Error> {
+pub fn create_patch(device: &dyn Transport, partition_id: u64, base_address: u64, patch_range: &Range) -> Result<u64, Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A40, None, None);
-    let mut input = vec![];
 
-    input.write_u64::<LittleEndian>(partition_id)?;
-    input.write_u64::<LittleEndian>(base_address)?;
-    input.write_u64::<LittleEndian>(patch_range.base)?;
-    input.write_u64::<LittleEndian>(patch_range.limit)?;
-    input.write_u64::<LittleEndian>(0)?;
+    let input = wire::CreatePatch {
+        partition_id: partition_id,
+        base_address: base_address,
+        patch_base: patch_range.base,
+        patch_limit: patch_range.limit,
+        flags: 0,
+    }.encode();
 
     let output: Vec<u8> = Vec::with_capacity(1000);
-    let mut cursor = device.call(control, Some(input), Some(output))?;
+    let cursor = device.call(control, Some(input), Some(output)).map_err(about(format!("partition {}", partition_id)))?;
 
-    Ok(cursor.read_u64::<LittleEndian>()?)
+    id(cursor)
 }
 
 #[allow(dead_code)]
-pub fn delete_patch(device: &Device, patch_id: u64) -> Result<(), Error> {
+pub fn delete_patch(device: &dyn Transport, patch_id: u64) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A41, None, None);
 
 
-    let mut input = vec![];
-
-    input.write_u64::<LittleEndian>(patch_id)?;
+    let input = wire::Id { id: patch_id }.encode();
 
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("patch {}", patch_id)))?;
     Ok(())
 }
 
-pub fn add_patch(device: &Device, guard_id: u64, patch_id: u64) -> Result<(), Error> {
+pub fn add_patch(device: &dyn Transport, guard_id: u64, patch_id: u64) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A42, None, None);
 
 
-    let mut input = vec![];
+    let input = wire::GuardItem { guard_id: guard_id, item_id: patch_id }.encode();
 
-    input.write_u64::<LittleEndian>(guard_id)?;
-    input.write_u64::<LittleEndian>(patch_id)?;
-
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("guard {}, patch {}", guard_id, patch_id)))?;
     Ok(())
 }
 
-pub fn remove_patch(device: &Device, guard_id: u64, patch_id: u64) -> Result<(), Error> {
+pub fn remove_patch(device: &dyn Transport, guard_id: u64, patch_id: u64) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A43, None, None);
 
 
-    let mut input = vec![];
-
-    input.write_u64::<LittleEndian>(guard_id)?;
-    input.write_u64::<LittleEndian>(patch_id)?;
+    let input = wire::GuardItem { guard_id: guard_id, item_id: patch_id }.encode();
 
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("guard {}, patch {}", guard_id, patch_id)))?;
     Ok(())
 }
 
 #[allow(dead_code)]
-pub fn enable_patch(device: &Device, patch_id: u64) -> Result<(), Error> {
+pub fn enable_patch(device: &dyn Transport, patch_id: u64) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A44, None, None);
 
 
-    let mut input = vec![];
+    let input = wire::Id { id: patch_id }.encode();
 
-    input.write_u64::<LittleEndian>(patch_id)?;
-
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("patch {}", patch_id)))?;
     Ok(())
 }
 
 #[allow(dead_code)]
-pub fn disable_patch(device: &Device, patch_id: u64) -> Result<(), Error> {
+pub fn disable_patch(device: &dyn Transport, patch_id: u64) -> Result<(), Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A45, None, None);
 
 
-    let mut input = vec![];
-
-    input.write_u64::<LittleEndian>(patch_id)?;
+    let input = wire::Id { id: patch_id }.encode();
 
-    let _ = device.call(control, Some(input), None)?;
+    let _ = device.call(control, Some(input), None).map_err(about(format!("patch {}", patch_id)))?;
     Ok(())
 }
 
 
 #[allow(dead_code)]
-pub fn get_info_patch(device: &Device, patch_id: u64) -> Result<(), Error> {
+pub fn get_info_patch(device: &dyn Transport, patch_id: u64) -> Result<wire::PatchInfo, Error> {
     let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A46, None, None);
 
 
-    let mut input = vec![];
+    let input = wire::Id { id: patch_id }.encode();
+    let output: Vec<u8> = Vec::with_capacity(wire::PatchInfo::SIZE);
 
-    input.write_u64::<LittleEndian>(patch_id)?;
+    let cursor = device.call(control, Some(input), Some(output)).map_err(about(format!("patch {}", patch_id)))?;
 
-    let mut cursor = device.call(control, Some(input), None)?;
+    Ok(wire::PatchInfo::decode(cursor.get_ref())?)
+}
+
+// patches of a partition, or only those linked to `guard_id` when it isn't 0
+pub fn enumerate_patch(device: &dyn Transport, partition_id: u64, guard_id: u64) -> Result<wire::Entries<wire::PatchInfo>, Error> {
+    let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A47, None, None);
+    let input = wire::Enumerate { partition_id: partition_id, guard_id: guard_id }.encode();
 
-    let patch_id          = cursor.read_u64::<LittleEndian>()?;
-    let next_entry_offset = cursor.read_u64::<LittleEndian>()?;
-    let base_address      = cursor.read_u64::<LittleEndian>()?;
-    let patch_address     = cursor.read_u64::<LittleEndian>()?;
-    let patch_size        = cursor.read_u64::<LittleEndian>()?;
-    let flags             = cursor.read_u64::<LittleEndian>()?;
-    let guard_count       = cursor.read_u64::<LittleEndian>()?;
+    let size = ENUMERATION_HINT * wire::PatchInfo::SIZE;
 
-    println!("patch_id: 0x{:08X}", patch_id);
-    println!("next_entry_offset: 0x{:08X}", next_entry_offset);
-    println!("base_address: 0x{:08X}", base_address);
-    println!("patch_address: 0x{:08X}", patch_address);
-    println!("patch_size: 0x{:08X}", patch_size);
-    println!("flags: 0x{:08X}", flags);
-    println!("guard_count: 0x{:08X}", guard_count);
-    Ok(())
+    let cursor = device.call_sized(control, Some(input), size, ENUMERATION_LIMIT).map_err(about(format!("partition {}, guard {}", partition_id, guard_id)))?;
+
+    Ok(wire::Entries::new(cursor.into_inner()))
 }
 
-#[allow(dead_code)]
-pub fn enumerate_patch(device: &Device, partition_id: u64, guard_id: u64) -> Result<(), Error> {
-    let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A47, None, None);
-    let mut input = vec![];
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentry::simulator::Simulator;
 
-    input.write_u64::<LittleEndian>(partition_id)?;
-    input.write_u64::<LittleEndian>(guard_id)?;
+    // the simulator builds its answers by hand, so it double checks the wire declarations
+    #[test]
+    fn test_info_matches_simulator() {
+        let simulator = Simulator::new();
+        let partition = create_partition(&simulator).unwrap();
 
-    let output: Vec<u8> = Vec::with_capacity(8 * 1000);
+        let range = Range::new(0x1000, 0x200);
+        let region = create_region(&simulator, partition.id, &range, Action::NOTIFY, Access::WRITE, Some(3)).unwrap();
+        let info = get_info_region(&simulator, region).unwrap();
 
-    let mut cursor = device.call(control, Some(input), Some(output))?;
+        assert_eq!(info.region_id, region);
+        assert_eq!(info.base_address, 0x1000);
+        assert_eq!(info.access_type, u32::from(Access::WRITE.bits()));
+        assert_eq!(info.weight, 3);
 
-    let _patch_id = cursor.read_u64::<LittleEndian>()?;
-    Ok(())
+        let patch = create_patch(&simulator, partition.id, 0x2000, &Range::new(0x3000, 0x10)).unwrap();
+        let info = get_info_patch(&simulator, patch).unwrap();
+
+        assert_eq!((info.patch_id, info.base_address, info.patch_address), (patch, 0x2000, 0x3000));
+    }
+
+    #[test]
+    fn test_enumerations() {
+        let simulator = Simulator::new();
+        let partition = create_partition(&simulator).unwrap();
+        let guard = register_guard_extended(&simulator, partition.id, None, None, GuardFlags::STOPPED, 0, 0).unwrap();
+
+        let regions = (0..3).map(|index| {
+            create_region(&simulator, partition.id, &Range::new(0x1000 * (index + 1), 0x10),
+                          Action::NOTIFY, Access::READ, None).unwrap()
+        }).collect::<Vec<u64>>();
+
+        add_region(&simulator, guard, regions[1]).unwrap();
+
+        let all = enumerate_region(&simulator, partition.id, 0).unwrap()
+                        .collect::<Result<Vec<wire::RegionInfo>, _>>().unwrap();
+
+        assert_eq!(all.iter().map(|info| info.region_id).collect::<Vec<u64>>(), regions);
+        assert_eq!(all[2].base_address, 0x3000);
+
+        let guarded = enumerate_region(&simulator, partition.id, guard).unwrap()
+                        .collect::<Result<Vec<wire::RegionInfo>, _>>().unwrap();
+
+        assert_eq!(guarded.len(), 1);
+        assert_eq!((guarded[0].region_id, guarded[0].guard_count), (regions[1], 1));
+
+        assert_eq!(enumerate_patch(&simulator, partition.id, 0).unwrap().count(), 0);
+    }
 }
@@ -9,10 +9,14 @@ use super::structs::{ ObjectType,
                       QUERYNAME_MESSAGE,
                       OKAYTOCLOSE_MESSAGE };
 
-use std::{mem, fmt, slice};
+use std::{mem, slice};
+use std::panic::{self, AssertUnwindSafe};
 
 use std::fmt::Debug;
-use super::{Action, Access, CallbackMap};
+use super::{Action, CallbackMap, InterceptionEvent};
+use super::watchdog::panic_message;
+use sentry::wire::{Handles, InterceptionMessage, Message};
+use sentry::error::WireError;
 
 const BUCKET_SIZE: usize = 240 + 16;
 
@@ -37,8 +41,13 @@ pub struct Syncronizers {
 }
 
 impl Syncronizers {
-    pub unsafe fn from_raw(ptr: *const u8) -> Syncronizers {
-        mem::transmute_copy(&*ptr)
+    pub fn decode(data: &[u8]) -> Result<Syncronizers, WireError> {
+        let handles = Handles::decode(data)?;
+
+        Ok(Syncronizers {
+            user: Event::from_u64(handles.user),
+            kernel: Event::from_u64(handles.kernel),
+        })
     }
 }
 
@@ -74,30 +83,6 @@ struct MessageHeader {
 //     WriteBuffer: u64
 // }
 
-#[repr(C)]
-pub struct FrameContext {
-    r15: u64,
-    r14: u64,
-    r13: u64,
-    r12: u64,
-    r11: u64,
-    r10: u64,
-    r9: u64,
-    r8: u64,
-    rdi: u64,
-    rsi: u64,
-    rbp: u64,
-    rsp: u64,
-    rbx: u64,
-    rdx: u64,
-    rcx: u64,
-    rax: u64,
-    rip: u64,
-    rflags: u64
-}
-
-const MAX_INST_LENGHT: usize = 16;
-
 #[repr(C)]
 pub struct Monitor {
     header: MessageHeader,
@@ -115,38 +100,6 @@ impl Monitor {
     }
 }
 
-#[repr(C)]
-pub struct Interception {
-    header: MessageHeader,
-    pub guard_id: u64,
-    pub region_id: u64,
-    pub cpu: FrameContext,
-    pub instruction: [u8; MAX_INST_LENGHT],
-    pub processor: u8,
-    pub process: u64,
-    pub address: u64,
-    pub access: Access,
-    pub flags: u32,
-    pub context: u64,
-    pub action: Action
-}
-
-impl Interception {
-    pub unsafe fn from_raw(ptr: *const u8) -> Interception {
-        mem::transmute_copy(&*ptr)
-    }
-}
-
-impl Debug for Interception {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Region(0x{:016X}) => 0x{:016x} - ({:?}) - ({:?})",
-                self.region_id,
-                self.address,
-                self.access,
-                self.action)
-    }
-}
-
 #[derive(Debug)]
 pub struct Response {
     message: Option<String>,
@@ -205,16 +158,16 @@ impl Bucket {
     }
 
     fn set_action(&self, ptr: *const u8, action: Action) {
+        let offset = InterceptionMessage::LAYOUT.offset("action").expect("interceptions carry an action");
+
         unsafe {
-            // let intercept: &mut Interception = &mut mapping.as_mut_ptr().offset(mem::size_of::<Syncronizers>() as isize) as *mut Interception;
-            let intercept: &mut Interception = mem::transmute(ptr
-                                                .offset(mem::size_of::<Syncronizers>() as isize));
-            intercept.action = action;
+            let answer = ptr.offset((Handles::SIZE + offset) as isize) as *mut u16;
+            answer.write_unaligned(action.bits());
         }
     }
 
-    pub fn handler(messenger: mpsc::Sender<String>, mapping: Vec<u8>, default: Box<dyn Fn(Interception) -> Response>, callbacks: CallbackMap) {
-        let sync = unsafe{ Syncronizers::from_raw(mapping.as_ptr()) } ;
+    pub fn handler(messenger: mpsc::Sender<String>, mapping: Vec<u8>, default: Box<dyn Fn(InterceptionEvent) -> Response>, callbacks: CallbackMap) {
+        let sync = Syncronizers::decode(&mapping).expect("Bucket too small to hold its events");
         // println!("#{:?} - {:?}", thread::current().id(), sync);
 
         // in order to prevent heapfree over false Vec reference
@@ -236,7 +189,7 @@ impl Bucket {
 
             let bucket = unsafe{ Bucket::from_raw(mapping.as_ptr()
                                             // skip events
-                                            .offset(mem::size_of::<Syncronizers>() as isize)) } ;
+                                            .offset(Handles::SIZE as isize)) } ;
 
             // println!("#{:?} - parsed bucket", thread::current().id());
 
@@ -248,14 +201,23 @@ impl Bucket {
                 },
                 MessageType::Intercept => {
                     // println!("#{:?} - redirecting interception", thread::current().id());
-                    let interception = unsafe { Interception::from_raw(mapping.as_ptr()
-                                    .offset(mem::size_of::<Syncronizers>() as isize)) };
-
-                    let map = callbacks.read().expect("Unable to unlock callbacks for reading");
-
-                    let response = match map.get(&interception.guard_id) {
-                        Some(callback) => callback(interception),
-                        None => default(interception)
+                    let response = match InterceptionEvent::decode(&mapping[Handles::SIZE..]) {
+                        Ok(event) => {
+                            // the kernel thread waits for an answer, even when the default
+                            // callback or an unwatched one panics, or a lock is poisoned
+                            panic::catch_unwind(AssertUnwindSafe(|| {
+                                let map = callbacks.read().expect("Unable to unlock callbacks for reading");
+
+                                match map.get(&event.guard_id) {
+                                    Some(callback) => callback(event.clone()),
+                                    None => default(event.clone())
+                                }
+                            })).unwrap_or_else(|payload| {
+                                Response::new(Some(format!("callback panicked: {}, applying continue", panic_message(&*payload))), Action::CONTINUE)
+                            })
+                        },
+                        // let the access through rather than stall the faulting thread
+                        Err(err) => Response::new(Some(format!("malformed interception: {}", err)), Action::CONTINUE),
                     };
 
                     bucket.set_action(mapping.as_ptr(), response.action());
@@ -264,9 +226,9 @@ impl Bucket {
                 },
                 MessageType::Monitor => {
                     let monitor = unsafe { Monitor::from_raw(mapping.as_ptr()
-                                    .offset(mem::size_of::<Syncronizers>() as isize)) };
+                                    .offset(Handles::SIZE as isize)) };
 
-                    let offset = mem::size_of::<Syncronizers>() as isize +
+                    let offset = Handles::SIZE as isize +
                                  mem::size_of::<Monitor>() as isize;
 
                     let message = match monitor.kind {
@@ -0,0 +1,139 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::super::clap::{App, Arg, ArgMatches, SubCommand};
+use super::super::failure::Error;
+use super::super::session::{SentrySession, SessionOptions};
+use super::super::io;
+
+use super::options::{self, PartitionOption};
+use super::error::MemguardError;
+use super::{Predicate, PARTITION_ROOT_ID};
+
+use std::sync::mpsc::Sender;
+use super::super::cli::output::{MessageType, ShellMessage};
+use super::console::style;
+
+pub fn bind() -> App<'static, 'static> {
+    SubCommand::with_name("memguard")
+        .about("inspects and configures memguard partitions")
+        .subcommand(
+            SubCommand::with_name("partition")
+                .about("partition management")
+                .subcommand(
+                    SubCommand::with_name("options")
+                        .about("shows the options of a partition, changing them first when asked to")
+                        .arg(
+                            Arg::with_name("partition")
+                                .short("p")
+                                .long("partition")
+                                .value_name("ID")
+                                .help("partition identifier, the root partition by default"),
+                        )
+                        .arg(
+                            Arg::with_name("set")
+                                .short("s")
+                                .long("set")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .value_name("OPTION=on|off")
+                                .help("changes an option, e.g. collect-stats=on"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("filter")
+                .about("checks a filter expression and shows the conditions the driver receives")
+                .arg(
+                    Arg::with_name("expression")
+                        .required(true)
+                        .value_name("EXPRESSION")
+                        .help("e.g. \"pid == 4 && sid != S-1-5-18\" or \"image == notepad.exe\""),
+                ),
+        )
+}
+
+fn partition_id(matches: &ArgMatches) -> Result<u64, Error> {
+    match matches.value_of("partition") {
+        Some(id) if id.starts_with("0x") => Ok(u64::from_str_radix(&id[2..], 16)?),
+        Some(id) => Ok(id.parse()?),
+        None => Ok(PARTITION_ROOT_ID),
+    }
+}
+
+fn assignment(value: &str) -> Result<(PartitionOption, bool), Error> {
+    let mut parts = value.splitn(2, '=');
+
+    let option = parts.next().unwrap_or_default().trim().parse::<PartitionOption>()?;
+    let enabled = parts.next()
+                       .ok_or_else(|| MemguardError::OptionValue(String::new()))
+                       .and_then(|value| options::parse_switch(value.trim()))?;
+
+    Ok((option, enabled))
+}
+
+fn partition_options(matches: &ArgMatches, options: &SessionOptions, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    let id = partition_id(matches)?;
+
+    // validate every assignment before changing anything
+    let changes = matches.values_of("set")
+                         .map(|values| values.map(assignment).collect::<Result<Vec<_>, _>>())
+                         .unwrap_or_else(|| Ok(vec![]))?;
+
+    let session = SentrySession::open_with(options)?;
+
+    for (option, enabled) in changes {
+        io::set_partition_option(&session, id, option.id(), u64::from(enabled))?;
+    }
+
+    let current = options::read(&session, id)?;
+
+    ShellMessage::send(messenger, format!("Partition {}", style(id).cyan()), MessageType::Close, 0);
+
+    for &option in PartitionOption::ALL {
+        let state = if current.get(option) { style("on").green() } else { style("off").dim() };
+
+        ShellMessage::send(messenger, format!("  {:<24} {}", option, state), MessageType::Close, 0);
+    }
+
+    Ok(())
+}
+
+fn filter(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    let expression = matches.value_of("expression").expect("argument `expression` is not present");
+
+    let predicate = expression.parse::<Predicate>()?;
+    let alternatives = predicate.alternatives()?;
+
+    ShellMessage::send(messenger, format!("{} needs {} guards", style(&predicate).cyan(), alternatives.len()), MessageType::Close, 0);
+
+    for (index, conditions) in alternatives.iter().enumerate() {
+        ShellMessage::send(messenger, format!("  Filter {}", style(index + 1).blue()), MessageType::Close, 0);
+
+        for comparison in conditions {
+            let line = format!("    {:<32} {:<12} {:<18} {}",
+                               comparison.to_string(),
+                               format!("{:?}", comparison.field),
+                               format!("{:?}", comparison.cmp),
+                               comparison.kind().map_or(String::from("resolved to pids"), |kind| format!("{:?}", kind)));
+
+            ShellMessage::send(messenger, line, MessageType::Close, 0);
+        }
+    }
+
+    Ok(())
+}
+
+fn partition(matches: &ArgMatches, options: &SessionOptions, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("options", Some(matches)) => partition_options(matches, options, messenger),
+        _ => Ok(println!("{}", matches.usage())),
+    }
+}
+
+pub fn parse(matches: &ArgMatches, session: &SessionOptions, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("partition", Some(matches)) => partition(matches, session, messenger),
+        ("filter", Some(matches)) => filter(matches, messenger),
+        _ => Ok(println!("{}", matches.usage())),
+    }
+}
@@ -0,0 +1,764 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+//
+// x86-64 decoding of the instructions that usually trip a region: moves, arithmetic
+// and logic, shifts, exchanges, bit tests, string operations, pushes, pops and indirect
+// calls or jumps. SSE, AVX, x87 and relative branches aren't decoded, events holding
+// them fall back to their raw bytes.
+//
+// Operands are printed in Intel syntax, e.g. `mov qword ptr [rcx+0x358], rax`.
+//
+
+use super::{Access, Registers};
+use super::error::MemguardError;
+
+use std::fmt;
+
+// the longest encoding the CPU accepts
+const MAX_LENGTH: usize = 15;
+
+const REG64: [&str; 16] = ["rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi",
+                           "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15"];
+const REG32: [&str; 16] = ["eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi",
+                           "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d"];
+const REG16: [&str; 16] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di",
+                           "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w"];
+const REG8: [&str; 16] = ["al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil",
+                          "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b", "r15b"];
+const REG8_HIGH: [&str; 4] = ["ah", "ch", "dh", "bh"];
+
+const ALU: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const SHIFTS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+const CMOV: [&str; 16] = ["cmovo", "cmovno", "cmovb", "cmovae", "cmove", "cmovne", "cmovbe", "cmova",
+                          "cmovs", "cmovns", "cmovp", "cmovnp", "cmovl", "cmovge", "cmovle", "cmovg"];
+const SET: [&str; 16] = ["seto", "setno", "setb", "setae", "sete", "setne", "setbe", "seta",
+                         "sets", "setns", "setp", "setnp", "setl", "setge", "setle", "setg"];
+
+const MOVS: [&str; 4] = ["movsb", "movsw", "movsd", "movsq"];
+const STOS: [&str; 4] = ["stosb", "stosw", "stosd", "stosq"];
+const LODS: [&str; 4] = ["lodsb", "lodsw", "lodsd", "lodsq"];
+const CMPS: [&str; 4] = ["cmpsb", "cmpsw", "cmpsd", "cmpsq"];
+const SCAS: [&str; 4] = ["scasb", "scasw", "scasd", "scasq"];
+
+// `value` truncated to `width` bytes
+fn truncate(value: u64, width: u8) -> u64 {
+    match width {
+        8 => value,
+        width => value & ((1 << (u32::from(width) * 8)) - 1),
+    }
+}
+
+// the name of a string operation of `width` bytes
+fn sized(names: &[&'static str; 4], width: u8) -> &'static str {
+    match width {
+        1 => names[0],
+        2 => names[1],
+        4 => names[2],
+        _ => names[3],
+    }
+}
+
+/// A general purpose register, or part of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Register {
+    // encoding, 0 (rax) to 15 (r15)
+    pub number: u8,
+    pub width: u8,
+    // ah, ch, dh and bh
+    pub high: bool,
+}
+
+impl Register {
+    fn new(number: u8, width: u8) -> Register {
+        Register {
+            number: number,
+            width: width,
+            high: false,
+        }
+    }
+
+    // without a REX prefix, byte registers 4 to 7 are the high halves of ax to bx
+    fn byte(number: u8, rex: bool) -> Register {
+        match (rex, number) {
+            (false, 4...7) => Register { number: number - 4, width: 1, high: true },
+            _ => Register::new(number, 1),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        let index = self.number as usize;
+
+        match (self.width, self.high) {
+            (1, true) => REG8_HIGH[index],
+            (1, false) => REG8[index],
+            (2, _) => REG16[index],
+            (4, _) => REG32[index],
+            _ => REG64[index],
+        }
+    }
+
+    pub fn value(&self, registers: &Registers) -> u64 {
+        let full = registers.get(REG64[self.number as usize]).expect("general purpose registers are saved");
+
+        match self.high {
+            true => (full >> 8) & 0xFF,
+            false => truncate(full, self.width),
+        }
+    }
+}
+
+/// A memory operand, `width` being 0 when only its address is used, i.e. `lea`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Memory {
+    pub segment: Option<&'static str>,
+    pub base: Option<Register>,
+    pub index: Option<Register>,
+    pub scale: u8,
+    pub displacement: i64,
+    pub rip_relative: bool,
+    pub width: u8,
+}
+
+impl Memory {
+    /// The address accessed, given the registers and the address of the next instruction.
+    ///
+    /// `None` for fs and gs relative operands, their base isn't part of the trap frame.
+    pub fn address(&self, registers: &Registers, next: u64) -> Option<u64> {
+        if self.segment.is_some() {
+            return None
+        }
+
+        let base = match (self.rip_relative, self.base) {
+            (true, _) => next,
+            (false, Some(base)) => base.value(registers),
+            (false, None) => 0,
+        };
+
+        let index = self.index.map(|index| index.value(registers).wrapping_mul(u64::from(self.scale))).unwrap_or(0);
+
+        Some(base.wrapping_add(index).wrapping_add(self.displacement as u64))
+    }
+}
+
+impl fmt::Display for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.width {
+            1 => write!(f, "byte ptr ")?,
+            2 => write!(f, "word ptr ")?,
+            4 => write!(f, "dword ptr ")?,
+            8 => write!(f, "qword ptr ")?,
+            _ => (),
+        }
+
+        if let Some(segment) = self.segment {
+            write!(f, "{}:", segment)?;
+        }
+
+        let mut parts = vec![];
+
+        if self.rip_relative {
+            parts.push(String::from("rip"));
+        } else if let Some(base) = self.base {
+            parts.push(base.name().to_string());
+        }
+
+        if let Some(index) = self.index {
+            parts.push(match self.scale {
+                1 => index.name().to_string(),
+                scale => format!("{}*{}", index.name(), scale),
+            });
+        }
+
+        let mut address = parts.join("+");
+
+        match self.displacement {
+            0 if !address.is_empty() => (),
+            displacement if address.is_empty() => address = format!("0x{:x}", displacement as u64),
+            displacement if displacement < 0 => address.push_str(&format!("-0x{:x}", displacement.wrapping_neg() as u64)),
+            displacement => address.push_str(&format!("+0x{:x}", displacement)),
+        }
+
+        write!(f, "[{}]", address)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Memory(Memory),
+    // truncated to the width of the operation
+    Immediate(u64),
+}
+
+impl Operand {
+    // the value a register or immediate holds, memory isn't read
+    pub fn value(&self, registers: &Registers) -> Option<u64> {
+        match *self {
+            Operand::Register(register) => Some(register.value(registers)),
+            Operand::Immediate(value) => Some(value),
+            Operand::Memory(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Register(register) => write!(f, "{}", register.name()),
+            Operand::Memory(memory) => write!(f, "{}", memory),
+            Operand::Immediate(value) if value < 10 => write!(f, "{}", value),
+            Operand::Immediate(value) => write!(f, "0x{:x}", value),
+        }
+    }
+}
+
+/// A decoded instruction, see `Instruction::decode`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    // lock, rep or repne
+    pub prefix: Option<&'static str>,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    pub length: usize,
+    // how the memory operand is accessed, empty without one
+    pub access: Access,
+}
+
+impl Instruction {
+    /// Decodes the instruction at the start of `bytes`.
+    pub fn decode(bytes: &[u8]) -> Result<Instruction, MemguardError> {
+        let mut decoder = Decoder {
+            bytes: bytes,
+            position: 0,
+            rex: 0,
+            operand16: false,
+            address32: false,
+            segment: None,
+            prefix: None,
+        };
+
+        let (mnemonic, operands) = decoder.instruction()?;
+
+        let memory = operands.iter().position(|operand| match *operand {
+            Operand::Memory(_) => true,
+            _ => false,
+        });
+
+        let access = match memory {
+            None => Access::empty(),
+            Some(_) if mnemonic == "lea" => Access::empty(),
+            Some(0) => match mnemonic {
+                "mov" | "pop" => Access::WRITE,
+                "cmp" | "test" | "bt" | "push" | "call" | "jmp" | "mul" | "imul" | "div" | "idiv" => Access::READ,
+                mnemonic if mnemonic.starts_with("set") || mnemonic.starts_with("stos") => Access::WRITE,
+                mnemonic if mnemonic.starts_with("lods") || mnemonic.starts_with("cmps") || mnemonic.starts_with("scas") => Access::READ,
+                _ => Access::READ | Access::WRITE,
+            },
+            Some(_) => Access::READ,
+        };
+
+        Ok(Instruction {
+            prefix: decoder.prefix,
+            mnemonic: mnemonic,
+            operands: operands,
+            length: decoder.position,
+            access: access,
+        })
+    }
+
+    pub fn memory(&self) -> Option<&Memory> {
+        self.operands.iter().filter_map(|operand| match *operand {
+            Operand::Memory(ref memory) => Some(memory),
+            _ => None,
+        }).next()
+    }
+
+    // bytes read or written through the memory operand
+    pub fn width(&self) -> Option<u8> {
+        self.memory().map(|memory| memory.width).filter(|&width| width != 0)
+    }
+
+    /// The address accessed through the memory operand, registers being those of the trap frame.
+    pub fn address(&self, registers: &Registers) -> Option<u64> {
+        self.memory().and_then(|memory| memory.address(registers, registers.rip.wrapping_add(self.length as u64)))
+    }
+
+    /// The value stored by plain writes such as `mov [rcx], rax` or `stosq`.
+    ///
+    /// `None` whenever the value depends on the memory being written, e.g. `add [rcx], rax`.
+    pub fn written_value(&self, registers: &Registers) -> Option<u64> {
+        if !self.access.contains(Access::WRITE) {
+            return None
+        }
+
+        match self.mnemonic {
+            "mov" | "xchg" => self.operands.get(1).and_then(|source| source.value(registers)),
+            mnemonic if mnemonic.starts_with("stos") => self.operands.get(1).and_then(|source| source.value(registers)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(prefix) = self.prefix {
+            write!(f, "{} ", prefix)?;
+        }
+
+        write!(f, "{}", self.mnemonic)?;
+
+        let operands = self.operands.iter().map(|operand| operand.to_string()).collect::<Vec<String>>();
+
+        if !operands.is_empty() {
+            write!(f, " {}", operands.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+type Decoded = (&'static str, Vec<Operand>);
+
+struct Decoder<'b> {
+    bytes: &'b [u8],
+    position: usize,
+    rex: u8,
+    operand16: bool,
+    address32: bool,
+    segment: Option<&'static str>,
+    prefix: Option<&'static str>,
+}
+
+impl<'b> Decoder<'b> {
+    fn error(&self, reason: String) -> MemguardError {
+        let bytes = self.bytes.iter()
+                              .take(MAX_LENGTH)
+                              .map(|byte| format!("{:02x}", byte))
+                              .collect::<Vec<String>>();
+
+        MemguardError::Instruction(bytes.join(" "), reason)
+    }
+
+    fn unsupported(&self, opcode: &str) -> MemguardError {
+        self.error(format!("unsupported opcode {}", opcode))
+    }
+
+    fn byte(&mut self) -> Result<u8, MemguardError> {
+        if self.position >= MAX_LENGTH || self.position >= self.bytes.len() {
+            return Err(self.error(String::from("the instruction is truncated")))
+        }
+
+        self.position += 1;
+
+        Ok(self.bytes[self.position - 1])
+    }
+
+    fn peek(&self) -> Result<u8, MemguardError> {
+        match self.bytes.get(self.position) {
+            Some(&byte) if self.position < MAX_LENGTH => Ok(byte),
+            _ => Err(self.error(String::from("the instruction is truncated"))),
+        }
+    }
+
+    // little-endian and sign extended
+    fn signed(&mut self, size: u8) -> Result<i64, MemguardError> {
+        if size == 0 {
+            return Ok(0)
+        }
+
+        let mut value = 0u64;
+
+        for index in 0..size {
+            value |= u64::from(self.byte()?) << (index * 8);
+        }
+
+        let unused = 64 - u32::from(size) * 8;
+
+        Ok(((value << unused) as i64) >> unused)
+    }
+
+    // an immediate of `size` bytes for an operation of `width` bytes
+    fn immediate(&mut self, size: u8, width: u8) -> Result<Operand, MemguardError> {
+        Ok(Operand::Immediate(truncate(self.signed(size)? as u64, width)))
+    }
+
+    fn rex(&self, bit: u8) -> u8 {
+        (self.rex >> bit) & 1
+    }
+
+    fn width(&self, byte: bool) -> u8 {
+        match (byte, self.rex(3), self.operand16) {
+            (true, _, _) => 1,
+            (false, 1, _) => 8,
+            (false, 0, true) => 2,
+            _ => 4,
+        }
+    }
+
+    // immediates are 32 bits at most, sign extended for 64-bit operations
+    fn immediate_size(width: u8) -> u8 {
+        match width {
+            8 => 4,
+            width => width,
+        }
+    }
+
+    fn register(&self, number: u8, width: u8) -> Register {
+        match width {
+            1 => Register::byte(number, self.rex != 0),
+            width => Register::new(number, width),
+        }
+    }
+
+    // the reg field and the r/m operand of a ModRM byte
+    fn modrm(&mut self, width: u8) -> Result<(u8, Operand), MemguardError> {
+        let modrm = self.byte()?;
+        let (mode, rm) = (modrm >> 6, modrm & 7);
+        let reg = ((modrm >> 3) & 7) | (self.rex(2) << 3);
+
+        if mode == 3 {
+            return Ok((reg, Operand::Register(self.register(rm | (self.rex(0) << 3), width))))
+        }
+
+        let address = if self.address32 { 4 } else { 8 };
+
+        let mut memory = Memory {
+            segment: self.segment,
+            base: None,
+            index: None,
+            scale: 1,
+            displacement: 0,
+            rip_relative: false,
+            width: width,
+        };
+
+        let displacement = match (mode, rm) {
+            (0, 4) | (1, 4) | (2, 4) => {
+                let sib = self.byte()?;
+                let (index, base) = (((sib >> 3) & 7) | (self.rex(1) << 3), sib & 7);
+
+                memory.scale = 1 << (sib >> 6);
+
+                // rsp can't be an index, r12 can
+                if index != 4 {
+                    memory.index = Some(Register::new(index, address));
+                }
+
+                match (mode, base) {
+                    (0, 5) => 4,
+                    _ => {
+                        memory.base = Some(Register::new(base | (self.rex(0) << 3), address));
+                        0
+                    },
+                }
+            },
+            (0, 5) => {
+                memory.rip_relative = true;
+                4
+            },
+            _ => {
+                memory.base = Some(Register::new(rm | (self.rex(0) << 3), address));
+                0
+            },
+        };
+
+        memory.displacement = match mode {
+            1 => self.signed(1)?,
+            2 => self.signed(4)?,
+            _ => self.signed(displacement)?,
+        };
+
+        Ok((reg, Operand::Memory(memory)))
+    }
+
+    // r/m first, e.g. `mov [rcx], rax`
+    fn rm_reg(&mut self, mnemonic: &'static str, byte: bool) -> Result<Decoded, MemguardError> {
+        let width = self.width(byte);
+        let (reg, rm) = self.modrm(width)?;
+
+        Ok((mnemonic, vec![rm, Operand::Register(self.register(reg, width))]))
+    }
+
+    // register first, e.g. `mov rax, [rcx]`
+    fn reg_rm(&mut self, mnemonic: &'static str, byte: bool) -> Result<Decoded, MemguardError> {
+        let width = self.width(byte);
+        let (reg, rm) = self.modrm(width)?;
+
+        Ok((mnemonic, vec![Operand::Register(self.register(reg, width)), rm]))
+    }
+
+    // movzx, movsx and movsxd, whose source is narrower
+    fn extend(&mut self, mnemonic: &'static str, source: u8) -> Result<Decoded, MemguardError> {
+        let width = self.width(false);
+        let (reg, rm) = self.modrm(source)?;
+
+        Ok((mnemonic, vec![Operand::Register(self.register(reg, width)), rm]))
+    }
+
+    // the string operations, through rsi and rdi
+    fn string(&mut self, opcode: u8) -> Result<Decoded, MemguardError> {
+        let width = self.width(opcode & 1 == 0);
+        let address = if self.address32 { 4 } else { 8 };
+
+        let operand = |number: u8| Operand::Memory(Memory {
+            segment: None,
+            base: Some(Register::new(number, address)),
+            index: None,
+            scale: 1,
+            displacement: 0,
+            rip_relative: false,
+            width: width,
+        });
+
+        let (source, destination, accumulator) = (operand(6), operand(7), Operand::Register(Register::new(0, width)));
+
+        let decoded = match opcode & !1 {
+            0xA4 => (sized(&MOVS, width), vec![destination, source]),
+            0xA6 => (sized(&CMPS, width), vec![source, destination]),
+            0xAA => (sized(&STOS, width), vec![destination, accumulator]),
+            0xAC => (sized(&LODS, width), vec![accumulator, source]),
+            _ => (sized(&SCAS, width), vec![accumulator, destination]),
+        };
+
+        Ok(decoded)
+    }
+
+    fn instruction(&mut self) -> Result<Decoded, MemguardError> {
+        let opcode = loop {
+            match self.byte()? {
+                // REX only counts right before the opcode
+                prefix @ 0x40...0x4F => {
+                    self.rex = prefix;
+                    continue
+                },
+                0xF0 => self.prefix = Some("lock"),
+                0xF2 => self.prefix = Some("repne"),
+                0xF3 => self.prefix = Some("rep"),
+                0x66 => self.operand16 = true,
+                0x67 => self.address32 = true,
+                0x64 => self.segment = Some("fs"),
+                0x65 => self.segment = Some("gs"),
+                // es, cs, ss and ds are ignored in 64-bit mode
+                0x26 | 0x2E | 0x36 | 0x3E => (),
+                opcode => break opcode,
+            }
+
+            self.rex = 0;
+        };
+
+        match opcode {
+            0x0F => self.escaped(),
+            opcode @ 0x00...0x3F if opcode & 7 < 6 => {
+                let mnemonic = ALU[(opcode >> 3) as usize];
+
+                match opcode & 7 {
+                    0 => self.rm_reg(mnemonic, true),
+                    1 => self.rm_reg(mnemonic, false),
+                    2 => self.reg_rm(mnemonic, true),
+                    3 => self.reg_rm(mnemonic, false),
+                    byte => {
+                        let width = self.width(byte == 4);
+                        let size = Decoder::immediate_size(width);
+
+                        Ok((mnemonic, vec![Operand::Register(Register::new(0, width)), self.immediate(size, width)?]))
+                    },
+                }
+            },
+            opcode @ 0x50...0x5F => {
+                let width = if self.operand16 { 2 } else { 8 };
+                let register = Register::new((opcode & 7) | (self.rex(0) << 3), width);
+
+                Ok((if opcode < 0x58 { "push" } else { "pop" }, vec![Operand::Register(register)]))
+            },
+            0x63 => self.extend("movsxd", 4),
+            0x69 | 0x6B => {
+                let (mnemonic, mut operands) = self.reg_rm("imul", false)?;
+                let width = self.width(false);
+                let size = if opcode == 0x6B { 1 } else { Decoder::immediate_size(width) };
+
+                operands.push(self.immediate(size, width)?);
+
+                Ok((mnemonic, operands))
+            },
+            0x80 | 0x81 | 0x83 => {
+                let width = self.width(opcode == 0x80);
+                let (reg, rm) = self.modrm(width)?;
+                let size = if opcode == 0x81 { Decoder::immediate_size(width) } else { 1 };
+
+                Ok((ALU[(reg & 7) as usize], vec![rm, self.immediate(size, width)?]))
+            },
+            0x84 | 0x85 => self.rm_reg("test", opcode == 0x84),
+            0x86 | 0x87 => self.rm_reg("xchg", opcode == 0x86),
+            0x88 | 0x89 => self.rm_reg("mov", opcode == 0x88),
+            0x8A | 0x8B => self.reg_rm("mov", opcode == 0x8A),
+            0x8D => {
+                let (mnemonic, mut operands) = self.reg_rm("lea", false)?;
+
+                match operands[1] {
+                    Operand::Memory(ref mut memory) => memory.width = 0,
+                    _ => return Err(self.error(String::from("lea takes a memory operand"))),
+                }
+
+                Ok((mnemonic, operands))
+            },
+            0x8F => match self.modrm(if self.operand16 { 2 } else { 8 })? {
+                (reg, rm) if reg & 7 == 0 => Ok(("pop", vec![rm])),
+                _ => Err(self.unsupported("8f")),
+            },
+            0x90 => Ok(("nop", vec![])),
+            0xA4...0xAF if opcode != 0xA8 && opcode != 0xA9 => self.string(opcode),
+            opcode @ 0xB0...0xBF => {
+                let width = self.width(opcode < 0xB8);
+                let register = self.register((opcode & 7) | (self.rex(0) << 3), width);
+
+                // the only 64-bit immediate
+                let immediate = match width {
+                    8 => Operand::Immediate(self.signed(8)? as u64),
+                    width => self.immediate(width, width)?,
+                };
+
+                Ok(("mov", vec![Operand::Register(register), immediate]))
+            },
+            0xC0 | 0xC1 | 0xD0 | 0xD1 | 0xD2 | 0xD3 => {
+                let width = self.width(opcode & 1 == 0);
+                let (reg, rm) = self.modrm(width)?;
+
+                let count = match opcode {
+                    0xC0 | 0xC1 => self.immediate(1, 1)?,
+                    0xD0 | 0xD1 => Operand::Immediate(1),
+                    _ => Operand::Register(Register::new(1, 1)),
+                };
+
+                Ok((SHIFTS[(reg & 7) as usize], vec![rm, count]))
+            },
+            0xC3 => Ok(("ret", vec![])),
+            0xC6 | 0xC7 => {
+                let width = self.width(opcode == 0xC6);
+
+                match self.modrm(width)? {
+                    (reg, rm) if reg & 7 == 0 => Ok(("mov", vec![rm, self.immediate(Decoder::immediate_size(width), width)?])),
+                    _ => Err(self.unsupported(&format!("{:02x}", opcode))),
+                }
+            },
+            0xCC => Ok(("int3", vec![])),
+            0xF6 | 0xF7 => {
+                let width = self.width(opcode == 0xF6);
+                let (reg, rm) = self.modrm(width)?;
+
+                match reg & 7 {
+                    0 | 1 => Ok(("test", vec![rm, self.immediate(Decoder::immediate_size(width), width)?])),
+                    operation => Ok((["", "", "not", "neg", "mul", "imul", "div", "idiv"][operation as usize], vec![rm])),
+                }
+            },
+            0xFE | 0xFF => {
+                let mnemonic = match (opcode, (self.peek()? >> 3) & 7) {
+                    (_, 0) => "inc",
+                    (_, 1) => "dec",
+                    (0xFF, 2) => "call",
+                    (0xFF, 4) => "jmp",
+                    (0xFF, 6) => "push",
+                    (opcode, operation) => return Err(self.unsupported(&format!("{:02x} /{}", opcode, operation))),
+                };
+
+                // calls, jumps and pushes are 64 bits wide whatever REX says
+                let width = match mnemonic {
+                    "inc" | "dec" => self.width(opcode == 0xFE),
+                    _ if self.operand16 => 2,
+                    _ => 8,
+                };
+
+                let (_, rm) = self.modrm(width)?;
+
+                Ok((mnemonic, vec![rm]))
+            },
+            opcode => Err(self.unsupported(&format!("{:02x}", opcode))),
+        }
+    }
+
+    // two-byte opcodes, after 0F
+    fn escaped(&mut self) -> Result<Decoded, MemguardError> {
+        let opcode = self.byte()?;
+
+        match opcode {
+            0x40...0x4F => self.reg_rm(CMOV[(opcode & 0xF) as usize], false),
+            0x90...0x9F => {
+                let (_, rm) = self.modrm(1)?;
+                Ok((SET[(opcode & 0xF) as usize], vec![rm]))
+            },
+            0xA3 => self.rm_reg("bt", false),
+            0xAB => self.rm_reg("bts", false),
+            0xAF => self.reg_rm("imul", false),
+            0xB0 | 0xB1 => self.rm_reg("cmpxchg", opcode == 0xB0),
+            0xB3 => self.rm_reg("btr", false),
+            0xB6 => self.extend("movzx", 1),
+            0xB7 => self.extend("movzx", 2),
+            0xBB => self.rm_reg("btc", false),
+            0xBE => self.extend("movsx", 1),
+            0xBF => self.extend("movsx", 2),
+            0xC0 | 0xC1 => self.rm_reg("xadd", opcode == 0xC0),
+            opcode => Err(self.unsupported(&format!("0f {:02x}", opcode))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8]) -> String {
+        Instruction::decode(bytes).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(text(&[0x48, 0x89, 0x81, 0x58, 0x03, 0x00, 0x00]), "mov qword ptr [rcx+0x358], rax");
+        assert_eq!(text(&[0x48, 0x8b, 0x05, 0xb8, 0x13, 0x00, 0x00]), "mov rax, qword ptr [rip+0x13b8]");
+        assert_eq!(text(&[0x65, 0x48, 0x8b, 0x04, 0x25, 0x88, 0x01, 0x00, 0x00]), "mov rax, qword ptr gs:[0x188]");
+        assert_eq!(text(&[0xf0, 0x48, 0x0f, 0xb1, 0x4c, 0xd3, 0xf8]), "lock cmpxchg qword ptr [rbx+rdx*8-0x8], rcx");
+        assert_eq!(text(&[0x88, 0x26]), "mov byte ptr [rsi], ah");
+        assert_eq!(text(&[0x40, 0x88, 0x26]), "mov byte ptr [rsi], spl");
+        assert_eq!(text(&[0x66, 0xc7, 0x40, 0x10, 0x34, 0x12]), "mov word ptr [rax+0x10], 0x1234");
+        assert_eq!(text(&[0x48, 0x83, 0x28, 0xff]), "sub qword ptr [rax], 0xffffffffffffffff");
+        assert_eq!(text(&[0x41, 0x0f, 0xb6, 0x44, 0x24, 0x01]), "movzx eax, byte ptr [r12+0x1]");
+        assert_eq!(text(&[0x48, 0x8d, 0x4c, 0x24, 0x20]), "lea rcx, [rsp+0x20]");
+        assert_eq!(text(&[0xff, 0x15, 0x10, 0x00, 0x00, 0x00]), "call qword ptr [rip+0x10]");
+        assert_eq!(text(&[0xf3, 0x48, 0xab]), "rep stosq qword ptr [rdi], rax");
+        assert_eq!(text(&[0x45, 0x31, 0xe4]), "xor r12d, r12d");
+        assert_eq!(text(&[0xd1, 0x20]), "shl dword ptr [rax], 1");
+        assert_eq!(text(&[0x0f, 0x94, 0x01]), "sete byte ptr [rcx]");
+
+        assert!(Instruction::decode(&[0x48, 0x89]).is_err());
+        assert!(Instruction::decode(&[0x0f, 0x10, 0x01]).is_err());
+        assert!(Instruction::decode(&[0x66; 16]).is_err());
+    }
+
+    #[test]
+    fn test_memory_access() {
+        let registers = Registers { rax: 0xffff_a70d_3e00_6a70, rcx: 0xffff_c40f_9e46_b040, rip: 0xffff_f801_5a70_1000, ..Default::default() };
+
+        let write = Instruction::decode(&[0x48, 0x89, 0x81, 0x58, 0x03, 0x00, 0x00, 0xc3]).unwrap();
+        assert_eq!(write.length, 7);
+        assert_eq!(write.access, Access::WRITE);
+        assert_eq!(write.width(), Some(8));
+        assert_eq!(write.address(&registers), Some(0xffff_c40f_9e46_b398));
+        assert_eq!(write.written_value(&registers), Some(0xffff_a70d_3e00_6a70));
+
+        let read = Instruction::decode(&[0x8b, 0x05, 0x10, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(read.access, Access::READ);
+        assert_eq!(read.width(), Some(4));
+        assert_eq!(read.address(&registers), Some(0xffff_f801_5a70_1016));
+        assert_eq!(read.written_value(&registers), None);
+
+        let update = Instruction::decode(&[0x48, 0x01, 0x01]).unwrap();
+        assert_eq!(update.access, Access::READ | Access::WRITE);
+        assert_eq!(update.written_value(&registers), None);
+
+        let store = Instruction::decode(&[0xc6, 0x41, 0x08, 0x01]).unwrap();
+        assert_eq!(store.written_value(&registers), Some(1));
+        assert_eq!(store.width(), Some(1));
+
+        let lea = Instruction::decode(&[0x48, 0x8d, 0x41, 0x08]).unwrap();
+        assert_eq!(lea.access, Access::empty());
+        assert_eq!(lea.width(), None);
+    }
+}
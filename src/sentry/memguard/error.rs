@@ -1,10 +1,54 @@
-use super::failure;
-use super::iochannel::IoCtl;
-
 use std::io::Error;
 
+use super::{Expired, GuardState};
+
 #[derive(Fail, Debug)]
 pub enum MemguardError {
     #[fail(display = "IOCTL ({}) error: ({})", _0, _1)]
     Io(String, #[cause] Error),
+
+    #[fail(display = "A filter holds at most {} conditions", _0)]
+    TooManyConditions(usize),
+
+    #[fail(display = "{} needs {} conditions, a filter holds at most {}; move some of them into an alternative", _0, _1, _2)]
+    FilterTooLarge(String, usize, usize),
+
+    #[fail(display = "The filter expands into {} alternatives, at most {} guards are built from one filter", _0, _1)]
+    TooManyAlternatives(usize, usize),
+
+    #[fail(display = "Invalid condition {}: {}", _0, _1)]
+    InvalidCondition(String, String),
+
+    #[fail(display = "Invalid filter {:?}: {} at column {}", _0, _2, _1)]
+    Expression(String, usize, String),
+
+    #[fail(display = "Unknown partition option {:?}", _0)]
+    UnknownOption(String),
+
+    #[fail(display = "Invalid option value {:?}, expected on or off", _0)]
+    OptionValue(String),
+
+    #[fail(display = "Invalid SID {:?}, expected e.g. S-1-5-18", _0)]
+    InvalidSid(String),
+
+    #[fail(display = "Invalid target {:?}: {}", _0, _1)]
+    Target(String, String),
+
+    #[fail(display = "Unable to resolve {}: {}", _0, _1)]
+    Unresolved(String, String),
+
+    #[fail(display = "Guard {} can't {} while {}", _0, _1, _2)]
+    InvalidTransition(u64, &'static str, GuardState),
+
+    #[fail(display = "Guard expired ({:?}) but couldn't be stopped: {}", _0, _1)]
+    Unstopped(Expired, String),
+
+    #[fail(display = "Guard {} doesn't hold a region or patch {}", _0, _1)]
+    UnknownSentinel(u64, u64),
+
+    #[fail(display = "Interception {} was caught outside of any process", _0)]
+    NoProcess(u64),
+
+    #[fail(display = "Unable to decode {}: {}", _0, _1)]
+    Instruction(String, String),
 }
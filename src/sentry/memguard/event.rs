@@ -0,0 +1,353 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::{Access, Action, Instruction};
+use super::error::MemguardError;
+use super::super::wire::{InterceptionMessage, Message};
+use super::super::error::WireError;
+use super::super::session::SentrySession;
+use super::super::failure::Error;
+use super::super::{memory, misc};
+
+use std::fmt;
+
+pub const MAX_INSTRUCTION_LENGTH: usize = 16;
+
+const ACCESS_NAMES: &[(u16, &str)] = &[
+    (Access::READ.bits, "READ"),
+    (Access::WRITE.bits, "WRITE"),
+    (Access::EXECUTE.bits, "EXECUTE"),
+];
+
+// composites first, so that INSPECT isn't reported as NOTIFY
+const ACTION_NAMES: &[(u16, &str)] = &[
+    (Action::INSPECT.bits, "INSPECT"),
+    (Action::NOTIFY.bits, "NOTIFY"),
+    (Action::CONTINUE.bits, "CONTINUE"),
+    (Action::BLOCK.bits, "BLOCK"),
+    (Action::STEALTH.bits, "STEALTH"),
+];
+
+/// General purpose registers of the thread that was intercepted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+}
+
+impl Registers {
+    pub const NAMES: [&'static str; 18] = ["rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp",
+                                           "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+                                           "rip", "rflags"];
+
+    // registers by name, in `NAMES` order
+    pub fn values(&self) -> [u64; 18] {
+        [self.rax, self.rbx, self.rcx, self.rdx, self.rsi, self.rdi, self.rbp, self.rsp,
+         self.r8, self.r9, self.r10, self.r11, self.r12, self.r13, self.r14, self.r15,
+         self.rip, self.rflags]
+    }
+
+    pub fn get(&self, name: &str) -> Option<u64> {
+        Registers::NAMES.iter()
+                        .position(|&known| known.eq_ignore_ascii_case(name))
+                        .map(|index| self.values()[index])
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let registers = Registers::NAMES.iter()
+                                        .zip(self.values().iter())
+                                        .map(|(name, value)| format!("{}=0x{:016x}", name, value))
+                                        .collect::<Vec<String>>();
+
+        // four per line, as debuggers print them
+        let lines = registers.chunks(4).map(|line| line.join(" ")).collect::<Vec<String>>();
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// An access caught by a guard, decoded from its interception bucket.
+///
+/// Unlike the bucket itself, events are owned and hold nothing that points back into
+/// the shared memory, so callbacks may keep them or send them to other threads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterceptionEvent {
+    pub id: u64,
+    pub guard_id: u64,
+    pub region_id: u64,
+    pub registers: Registers,
+    // the faulting instruction, followed by whatever comes next up to the maximum x64 length
+    pub instruction: [u8; MAX_INSTRUCTION_LENGTH],
+    pub processor: u8,
+    // _EPROCESS of the intercepted thread, see `pid`
+    pub process: u64,
+    pub address: u64,
+    pub access: Access,
+    pub flags: u32,
+    pub context: u64,
+    pub action: Action,
+}
+
+impl InterceptionEvent {
+    pub fn decode(data: &[u8]) -> Result<InterceptionEvent, WireError> {
+        InterceptionMessage::decode(data).map(|message| InterceptionEvent::from(&message))
+    }
+
+    /// Reads the id of the intercepted process, which may have exited since.
+    ///
+    /// Fails for accesses the driver caught outside of any process.
+    pub fn pid(&self, session: &SentrySession) -> Result<u64, Error> {
+        if self.process == 0 {
+            return Err(MemguardError::NoProcess(self.id).into())
+        }
+
+        let offset = misc::get_offset("_EPROCESS.UniqueProcessId")?;
+
+        memory::read_u64(session, self.process + u64::from(offset))
+    }
+
+    /// Decodes the faulting instruction, see `Instruction` for what is supported.
+    pub fn decoded(&self) -> Result<Instruction, MemguardError> {
+        Instruction::decode(&self.instruction)
+    }
+
+    // the faulting instruction in Intel syntax, or its bytes when it can't be decoded
+    pub fn disassembly(&self) -> String {
+        match self.decoded() {
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => self.instruction_hex(),
+        }
+    }
+
+    /// The value a plain write such as `mov [rcx], rax` was about to store.
+    pub fn written_value(&self) -> Option<u64> {
+        self.decoded().ok().and_then(|instruction| instruction.written_value(&self.registers))
+    }
+
+    pub fn instruction_hex(&self) -> String {
+        self.instruction.iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+    }
+
+    /// The event as a JSON object.
+    ///
+    /// Addresses and registers are "0x..." strings, as 64-bit values don't survive
+    /// JSON parsers that read numbers as doubles.
+    pub fn to_json(&self) -> String {
+        let registers = Registers::NAMES.iter()
+                                        .zip(self.registers.values().iter())
+                                        .map(|(name, value)| format!("\"{}\": {}", name, hex(*value)))
+                                        .collect::<Vec<String>>();
+
+        let width = match self.decoded().ok().and_then(|instruction| instruction.width()) {
+            Some(width) => width.to_string(),
+            None => String::from("null"),
+        };
+
+        format!("{{\"id\": {}, \"guard\": {}, \"region\": {}, \"address\": {}, \"access\": {}, \
+                 \"action\": {}, \"processor\": {}, \"process\": {}, \"flags\": {}, \"context\": {}, \
+                 \"instruction\": {}, \"disassembly\": {}, \"width\": {}, \"registers\": {{{}}}}}",
+                self.id,
+                hex(self.guard_id),
+                hex(self.region_id),
+                hex(self.address),
+                names(self.access.bits(), ACCESS_NAMES),
+                names(self.action.bits(), ACTION_NAMES),
+                self.processor,
+                hex(self.process),
+                self.flags,
+                hex(self.context),
+                quote(&self.instruction_hex()),
+                quote(&self.disassembly()),
+                width,
+                registers.join(", "))
+    }
+}
+
+// a JSON string, escaping quotes, backslashes and control characters
+fn quote(value: &str) -> String {
+    let escaped = value.chars().map(|c| match c {
+        '"' | '\\' => format!("\\{}", c),
+        '\n' => String::from("\\n"),
+        c if c.is_control() => format!("\\u{:04x}", c as u32),
+        c => c.to_string(),
+    }).collect::<String>();
+
+    format!("\"{}\"", escaped)
+}
+
+fn hex(value: u64) -> String {
+    format!("\"0x{:x}\"", value)
+}
+
+// flags as a JSON array, e.g. ["READ", "WRITE"], bits without a name are written in hex
+fn names(bits: u16, known: &[(u16, &str)]) -> String {
+    let mut left = bits;
+    let mut names = vec![];
+
+    for &(flag, name) in known {
+        if left & flag == flag {
+            names.push(format!("\"{}\"", name));
+            left &= !flag;
+        }
+    }
+
+    if left != 0 {
+        names.push(format!("\"0x{:x}\"", left));
+    }
+
+    format!("[{}]", names.join(", "))
+}
+
+impl<'a> From<&'a InterceptionMessage> for InterceptionEvent {
+    fn from(message: &'a InterceptionMessage) -> InterceptionEvent {
+        InterceptionEvent {
+            id: message.id,
+            guard_id: message.guard_id,
+            region_id: message.region_id,
+            registers: Registers {
+                rax: message.rax,
+                rbx: message.rbx,
+                rcx: message.rcx,
+                rdx: message.rdx,
+                rsi: message.rsi,
+                rdi: message.rdi,
+                rbp: message.rbp,
+                rsp: message.rsp,
+                r8: message.r8,
+                r9: message.r9,
+                r10: message.r10,
+                r11: message.r11,
+                r12: message.r12,
+                r13: message.r13,
+                r14: message.r14,
+                r15: message.r15,
+                rip: message.rip,
+                rflags: message.rflags,
+            },
+            instruction: message.instruction,
+            processor: message.processor,
+            process: message.process,
+            address: message.address,
+            access: Access::from_bits_truncate(message.access),
+            flags: message.flags,
+            context: message.context,
+            action: Action::from_bits_truncate(message.action),
+        }
+    }
+}
+
+impl fmt::Display for InterceptionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Region(0x{:016X}) => 0x{:016x} - ({:?}) by 0x{:016x} on cpu {} at rip 0x{:016x}: {}",
+                self.region_id,
+                self.address,
+                self.access,
+                self.process,
+                self.processor,
+                self.registers.rip,
+                self.disassembly())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> InterceptionMessage {
+        InterceptionMessage {
+            id: 7,
+            guard_id: 0x10,
+            region_id: 0x20,
+            rax: 0x4141,
+            rcx: 0xffff_f801_5a8b_0e10,
+            rip: 0xffff_f801_5a70_1234,
+            rflags: 0x246,
+            // mov qword ptr [rcx], rax
+            instruction: [0x48, 0x89, 0x01, 0xc3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            processor: 2,
+            process: 0xffff_c40f_9e46_b040,
+            address: 0xffff_f801_5a8b_0e10,
+            access: Access::WRITE.bits(),
+            action: Action::CONTINUE.bits(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_decode_event() {
+        let data = message().encode();
+
+        let event = InterceptionEvent::decode(&data).unwrap();
+
+        assert_eq!(event.registers.rcx, event.address);
+        assert_eq!(event.registers.get("RAX"), Some(0x4141));
+        assert_eq!(event.registers.get("cr3"), None);
+        assert_eq!(event.access, Access::WRITE);
+        assert_eq!(event.processor, 2);
+        assert_eq!(&event.instruction[..3], &[0x48, 0x89, 0x01]);
+
+        match InterceptionEvent::decode(&data[..data.len() - 1]) {
+            Err(WireError::Truncated("InterceptionMessage", 240, 239)) => (),
+            other => panic!("expected a truncated bucket, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_event_output() {
+        let event = InterceptionEvent::from(&message());
+
+        assert_eq!(event.to_string(), "Region(0x0000000000000020) => 0xfffff8015a8b0e10 - (WRITE) by 0xffffc40f9e46b040 \
+                                       on cpu 2 at rip 0xfffff8015a701234: mov qword ptr [rcx], rax");
+
+        let json = event.to_json();
+        assert!(json.starts_with("{\"id\": 7, \"guard\": \"0x10\", \"region\": \"0x20\", \"address\": \"0xfffff8015a8b0e10\", \"access\": [\"WRITE\"], \"action\": [\"CONTINUE\"]"));
+        assert!(json.contains("\"disassembly\": \"mov qword ptr [rcx], rax\", \"width\": 8"));
+        assert!(json.ends_with("\"rip\": \"0xfffff8015a701234\", \"rflags\": \"0x246\"}}"));
+
+        assert_eq!(names(Action::INSPECT.bits() | Action::BLOCK.bits(), ACTION_NAMES), "[\"INSPECT\", \"BLOCK\"]");
+        assert_eq!(names(Access::READ.bits() | 0x80, ACCESS_NAMES), "[\"READ\", \"0x80\"]");
+        assert_eq!(names(0, ACCESS_NAMES), "[]");
+        assert_eq!(quote("a \"b\" \\ c\n\t"), "\"a \\\"b\\\" \\\\ c\\n\\u0009\"");
+
+        assert_eq!(event.written_value(), Some(0x4141));
+
+        let unknown = InterceptionEvent { instruction: [0x0f, 0x0b, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], ..event.clone() };
+        assert_eq!(unknown.disassembly(), "0f 0b 00 00 00 00 00 00 00 00 00 00 00 00 00 00");
+        assert!(unknown.to_json().contains("\"width\": null"));
+
+        assert!(event.registers.to_string().starts_with("rax=0x0000000000004141 rbx=0x0000000000000000"));
+        assert_eq!(event.registers.to_string().lines().count(), 5);
+    }
+
+    #[test]
+    fn test_event_without_process() {
+        use super::super::super::simulator::Simulator;
+
+        let event = InterceptionEvent { process: 0, ..InterceptionEvent::from(&message()) };
+
+        match event.pid(&SentrySession::new(Simulator::new())) {
+            Err(err) => assert_eq!(err.to_string(), "Interception 7 was caught outside of any process"),
+            Ok(pid) => panic!("read pid {} through a null process", pid),
+        }
+    }
+}
@@ -0,0 +1,295 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+//
+// Filter expressions, e.g. `pid == 4 && sid != S-1-5-18` or `image == "notepad.exe"`.
+//
+//     expression := all ('||' all)*
+//     all        := unary ('&&' unary)*
+//     unary      := '!' unary | '(' expression ')' | FIELD OPERATOR VALUE
+//
+// Fields are pid, session, sid and image. Numbers are decimal or hexadecimal, SIDs are
+// written as is and image names either bare or quoted. There's no substring matching,
+// `~` and `!~` are recognized only to be refused.
+//
+
+use super::filter::{self, Comparison, Operand, Predicate};
+use super::error::MemguardError;
+use super::filter::Field;
+use super::Sid;
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Operator(&'static str),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    End,
+}
+
+// longest first, so that `>=` isn't read as `>`
+const SYMBOLS: &[(&str, Option<&str>)] = &[
+    ("&&", None),
+    ("||", None),
+    ("==", Some("==")),
+    ("!=", Some("!=")),
+    (">=", Some(">=")),
+    ("<=", Some("<=")),
+    ("!~", Some("!~")),
+    (">", Some(">")),
+    ("<", Some("<")),
+    ("~", Some("~")),
+];
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || "-._:\\$".contains(c)
+}
+
+struct Parser<'e> {
+    expression: &'e str,
+    // tokens and the column they start at
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl<'e> Parser<'e> {
+    fn error(&self, column: usize, message: String) -> MemguardError {
+        MemguardError::Expression(self.expression.to_string(), column + 1, message)
+    }
+
+    fn tokenize(expression: &'e str) -> Result<Parser<'e>, MemguardError> {
+        let mut parser = Parser {
+            expression: expression,
+            tokens: vec![],
+            position: 0,
+        };
+
+        let mut chars = expression.char_indices().peekable();
+
+        while let Some(&(column, c)) = chars.peek() {
+            let rest = &expression[column..];
+
+            let token = if c.is_whitespace() {
+                chars.next();
+                continue
+            } else if c == '"' {
+                chars.next();
+
+                let mut text = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => text.push(escaped),
+                            None => return Err(parser.error(column, String::from("unterminated string"))),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err(parser.error(column, String::from("unterminated string"))),
+                    }
+                }
+
+                Token::Quoted(text)
+            } else if is_word(c) {
+                let mut word = String::new();
+
+                while let Some(&(_, c)) = chars.peek() {
+                    if !is_word(c) {
+                        break
+                    }
+
+                    word.push(c);
+                    chars.next();
+                }
+
+                Token::Word(word)
+            } else {
+                let token = match SYMBOLS.iter().find(|&&(symbol, _)| rest.starts_with(symbol)) {
+                    Some(&("&&", _)) => Token::And,
+                    Some(&("||", _)) => Token::Or,
+                    Some(&(_, Some(operator))) => Token::Operator(operator),
+                    _ => match c {
+                        '!' => Token::Not,
+                        '(' => Token::Open,
+                        ')' => Token::Close,
+                        _ => return Err(parser.error(column, format!("unexpected {:?}", c))),
+                    },
+                };
+
+                let length = match token {
+                    Token::And | Token::Or => 2,
+                    Token::Operator(operator) => operator.len(),
+                    _ => 1,
+                };
+
+                for _ in 0..length {
+                    chars.next();
+                }
+
+                token
+            };
+
+            parser.tokens.push((token, column));
+        }
+
+        parser.tokens.push((Token::End, expression.len()));
+
+        Ok(parser)
+    }
+
+    fn peek(&self) -> &(Token, usize) {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> (Token, usize) {
+        let token = self.tokens[self.position].clone();
+
+        if token.0 != Token::End {
+            self.position += 1;
+        }
+
+        token
+    }
+
+    fn describe(token: &Token) -> String {
+        match *token {
+            Token::Word(ref word) => format!("{:?}", word),
+            Token::Quoted(ref text) => format!("\"{}\"", text),
+            Token::Operator(operator) => format!("{:?}", operator),
+            Token::And => String::from("\"&&\""),
+            Token::Or => String::from("\"||\""),
+            Token::Not => String::from("\"!\""),
+            Token::Open => String::from("\"(\""),
+            Token::Close => String::from("\")\""),
+            Token::End => String::from("the end of the filter"),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Predicate, MemguardError> {
+        let mut predicate = self.all()?;
+
+        while self.peek().0 == Token::Or {
+            self.next();
+            predicate = predicate.or(self.all()?);
+        }
+
+        Ok(predicate)
+    }
+
+    fn all(&mut self) -> Result<Predicate, MemguardError> {
+        let mut predicate = self.unary()?;
+
+        while self.peek().0 == Token::And {
+            self.next();
+            predicate = predicate.and(self.unary()?);
+        }
+
+        Ok(predicate)
+    }
+
+    fn unary(&mut self) -> Result<Predicate, MemguardError> {
+        match self.next() {
+            (Token::Not, _) => Ok(self.unary()?.negate()),
+            (Token::Open, column) => {
+                let predicate = self.expression()?;
+
+                match self.next() {
+                    (Token::Close, _) => Ok(predicate),
+                    (token, at) => Err(self.error(at, format!("expected \")\" closing the one at column {}, found {}", column + 1, Parser::describe(&token)))),
+                }
+            },
+            (Token::Word(field), column) => self.comparison(&field, column),
+            (token, column) => Err(self.error(column, format!("expected a condition, found {}", Parser::describe(&token)))),
+        }
+    }
+
+    fn comparison(&mut self, name: &str, column: usize) -> Result<Predicate, MemguardError> {
+        let field = filter::FIELDS.iter()
+                                  .find(|&&(known, _)| known.eq_ignore_ascii_case(name))
+                                  .map(|&(_, field)| field)
+                                  .ok_or_else(|| self.error(column, format!("unknown field {:?}, expected pid, session, sid or image", name)))?;
+
+        let cmp = match self.next() {
+            (Token::Operator(operator), at) => filter::OPERATORS.iter()
+                                                                .find(|&&(known, _)| known == operator)
+                                                                .map(|&(_, cmp)| cmp)
+                                                                .ok_or_else(|| self.error(at, format!("{} isn't supported, names are only compared with == and !=", operator)))?,
+            (token, at) => return Err(self.error(at, format!("expected an operator after {}, found {}", name, Parser::describe(&token)))),
+        };
+
+        let (value, at) = match self.next() {
+            (Token::Word(value), at) | (Token::Quoted(value), at) => (value, at),
+            (token, at) => return Err(self.error(at, format!("expected a value, found {}", Parser::describe(&token)))),
+        };
+
+        let operand = match field {
+            Field::Pid | Field::Session => {
+                let number = match value.starts_with("0x") {
+                    true => u64::from_str_radix(&value[2..], 16).ok(),
+                    false => value.parse::<u64>().ok(),
+                };
+
+                Operand::Integer(number.ok_or_else(|| self.error(at, format!("{} expects a number, found {:?}", name, value)))?)
+            },
+            Field::Sid => Operand::Sid(value.parse::<Sid>().map_err(|err| self.error(at, err.to_string()))?),
+            Field::Image => Operand::Text(value),
+        };
+
+        Comparison::new(field, cmp, operand)
+            .map(Predicate::Compare)
+            .map_err(|err| self.error(column, err.to_string()))
+    }
+}
+
+impl FromStr for Predicate {
+    type Err = MemguardError;
+
+    fn from_str(expression: &str) -> Result<Predicate, MemguardError> {
+        let mut parser = Parser::tokenize(expression)?;
+
+        let predicate = parser.expression()?;
+
+        match parser.next() {
+            (Token::End, _) => Ok(predicate),
+            (token, column) => Err(parser.error(column, format!("unexpected {}", Parser::describe(&token)))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::MatchType;
+
+    #[test]
+    fn test_expressions() {
+        let predicate = "pid == 4 && sid != S-1-5-18".parse::<Predicate>().unwrap();
+
+        assert_eq!(predicate, Predicate::pid(MatchType::EQUAL, 4).and(Predicate::sid(MatchType::NOT_EQUAL, "S-1-5-18".parse().unwrap()).unwrap()));
+
+        let predicate = "!(image == \"note pad.exe\" || session>=0x1) && image != lsass.exe".parse::<Predicate>().unwrap();
+
+        assert_eq!(predicate.to_string(), "!(image == \"note pad.exe\" || session >= 1) && image != \"lsass.exe\"");
+        assert_eq!(predicate.to_string().parse::<Predicate>().unwrap(), predicate);
+        assert_eq!(predicate.alternatives().unwrap()[0].len(), 3);
+    }
+
+    #[test]
+    fn test_errors_point_at_the_token() {
+        let error = |expression: &str| expression.parse::<Predicate>().unwrap_err().to_string();
+
+        assert_eq!(error("pid == 4 && uid == 3"), "Invalid filter \"pid == 4 && uid == 3\": unknown field \"uid\", expected pid, session, sid or image at column 13");
+        assert!(error("pid == ").ends_with("expected a value, found the end of the filter at column 8"));
+        assert!(error("(pid == 4").contains("expected \")\" closing the one at column 1"));
+        assert!(error("pid = 4").ends_with("unexpected '=' at column 5"));
+        assert!(error("sid > S-1-5-18").ends_with("at column 1"));
+        assert!(error("session == -1").contains("session expects a number"));
+        assert!(error("image ~ lsass").ends_with("~ isn't supported, names are only compared with == and != at column 7"));
+        assert!(error("pid == 4 pid == 5").ends_with("unexpected \"pid\" at column 10"));
+    }
+}
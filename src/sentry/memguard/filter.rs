@@ -0,0 +1,465 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::{Condition, Filter, FieldKey, MatchType, Sid, ValueType};
+use super::error::MemguardError;
+use super::super::session::SentrySession;
+use super::super::failure::Error;
+use super::super::{memory, misc};
+
+use std::fmt;
+
+// MG_GUARD_FILTER capacity
+pub const MAX_CONDITIONS: usize = 16;
+// one filter, hence one guard, per alternative
+pub const MAX_ALTERNATIVES: usize = 16;
+
+// _EPROCESS.ImageFileName holds 15 characters at most
+const IMAGE_NAME_LENGTH: usize = 15;
+
+/// What a comparison tests.
+///
+/// The driver only compares process ids and SIDs. Image names and sessions are matched
+/// in user mode against the processes running when the filter is built, which are then
+/// filtered by id, as `Filter::process` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Pid,
+    Sid,
+    Image,
+    Session,
+}
+
+impl Field {
+    // the key the driver compares, `None` for fields resolved to process ids
+    pub fn key(&self) -> Option<FieldKey> {
+        match *self {
+            Field::Pid => Some(FieldKey::PROCESS_ID),
+            Field::Sid => Some(FieldKey::SID),
+            Field::Image | Field::Session => None,
+        }
+    }
+}
+
+/// Fields a condition can test and the names they're written with.
+pub const FIELDS: &[(&str, Field)] = &[
+    ("pid", Field::Pid),
+    ("sid", Field::Sid),
+    ("image", Field::Image),
+    ("session", Field::Session),
+];
+
+pub const OPERATORS: &[(&str, MatchType)] = &[
+    ("==", MatchType::EQUAL),
+    ("!=", MatchType::NOT_EQUAL),
+    (">", MatchType::GREATER),
+    ("<", MatchType::LESS),
+    (">=", MatchType::GREATER_OR_EQUAL),
+    ("<=", MatchType::LESS_OR_EQUAL),
+];
+
+pub fn field_name(field: Field) -> &'static str {
+    FIELDS.iter()
+          .find(|&&(_, known)| known == field)
+          .map_or("?", |&(name, _)| name)
+}
+
+pub fn operator(cmp: MatchType) -> &'static str {
+    OPERATORS.iter()
+             .find(|&&(_, known)| known == cmp)
+             .map_or("?", |&(operator, _)| operator)
+}
+
+// the comparison holding whenever `cmp` doesn't
+fn opposite(cmp: MatchType) -> MatchType {
+    match cmp {
+        MatchType::EQUAL => MatchType::NOT_EQUAL,
+        MatchType::NOT_EQUAL => MatchType::EQUAL,
+        MatchType::GREATER => MatchType::LESS_OR_EQUAL,
+        MatchType::LESS_OR_EQUAL => MatchType::GREATER,
+        MatchType::LESS => MatchType::GREATER_OR_EQUAL,
+        MatchType::GREATER_OR_EQUAL => MatchType::LESS,
+    }
+}
+
+fn holds<T: PartialOrd>(value: T, cmp: MatchType, operand: T) -> bool {
+    match cmp {
+        MatchType::EQUAL => value == operand,
+        MatchType::NOT_EQUAL => value != operand,
+        MatchType::GREATER => value > operand,
+        MatchType::LESS => value < operand,
+        MatchType::GREATER_OR_EQUAL => value >= operand,
+        MatchType::LESS_OR_EQUAL => value <= operand,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Integer(u64),
+    Sid(Sid),
+    Text(String),
+}
+
+/// A single `field cmp value` test, the unit the driver evaluates once resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub field: Field,
+    pub cmp: MatchType,
+    pub operand: Operand,
+}
+
+impl Comparison {
+    /// Checks that `operand` fits `field` and that it can be ordered when `cmp` asks for it.
+    pub fn new(field: Field, cmp: MatchType, operand: Operand) -> Result<Comparison, MemguardError> {
+        let comparison = Comparison {
+            field: field,
+            cmp: cmp,
+            operand: operand,
+        };
+
+        let fits = match (field, &comparison.operand) {
+            (Field::Pid, &Operand::Integer(_)) => true,
+            (Field::Session, &Operand::Integer(value)) => value <= u64::from(u32::max_value()),
+            (Field::Sid, &Operand::Sid(_)) => true,
+            (Field::Image, &Operand::Text(ref text)) => !text.is_empty(),
+            _ => false,
+        };
+
+        if !fits {
+            return Err(MemguardError::InvalidCondition(comparison.to_string(), format!("not a valid {} value", field_name(field))))
+        }
+
+        let equality = cmp == MatchType::EQUAL || cmp == MatchType::NOT_EQUAL;
+
+        let (comparable, expected) = match comparison.operand {
+            Operand::Integer(_) => (true, "==, !=, >, <, >= and <="),
+            Operand::Sid(_) | Operand::Text(_) => (equality, "== and !="),
+        };
+
+        if !comparable {
+            return Err(MemguardError::InvalidCondition(comparison.to_string(), format!("{} values are only compared with {}", field_name(field), expected)))
+        }
+
+        Ok(comparison)
+    }
+
+    fn negated(&self) -> Comparison {
+        Comparison {
+            cmp: opposite(self.cmp),
+            ..self.clone()
+        }
+    }
+
+    // the value the driver compares, `None` for fields resolved to process ids
+    pub fn kind(&self) -> Option<ValueType> {
+        match self.field {
+            Field::Pid => Some(ValueType::UINT64),
+            Field::Sid => Some(ValueType::SID_TYPE),
+            Field::Image | Field::Session => None,
+        }
+    }
+
+    // whether `process` passes an image or session comparison
+    fn matches(&self, process: &misc::Process) -> Result<bool, Error> {
+        match (self.field, &self.operand) {
+            (Field::Image, &Operand::Text(ref text)) => {
+                // names are truncated by the kernel, and compared without case by Windows
+                let expected = text.chars().take(IMAGE_NAME_LENGTH).collect::<String>().to_lowercase();
+
+                Ok(holds(process.name()?.to_lowercase(), self.cmp, expected))
+            },
+            (Field::Session, &Operand::Integer(session)) => Ok(holds(u64::from(process.session_id()?), self.cmp, session)),
+            _ => Err(MemguardError::InvalidCondition(self.to_string(), String::from("is evaluated by the driver")).into()),
+        }
+    }
+
+    //
+    // The ids of the processes the comparison holds for. A negative comparison becomes the
+    // negation of the positive one, so that processes started afterwards still pass it.
+    //
+    fn resolve(&self, session: &SentrySession) -> Result<Predicate, Error> {
+        if self.field.key().is_some() {
+            return Ok(Predicate::Compare(self.clone()))
+        }
+
+        if self.cmp == MatchType::NOT_EQUAL {
+            return Ok(self.negated().resolve(session)?.negate())
+        }
+
+        let mut pids = vec![];
+
+        for process in misc::WalkProcess::new(session)? {
+            let process = process?;
+
+            if self.matches(&process)? {
+                pids.push(Predicate::pid(MatchType::EQUAL, process.id()?));
+            }
+        }
+
+        Ok(Predicate::Any(pids))
+    }
+}
+
+/// Conditions combined with AND, OR and NOT.
+///
+/// The driver only evaluates ANDed conditions, so a predicate is flattened into
+/// alternatives: every OR becomes a filter of its own, hence a guard of its own,
+/// and NOT is pushed down to the comparisons.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Compare(Comparison),
+    All(Vec<Predicate>),
+    Any(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    pub fn pid(cmp: MatchType, pid: u64) -> Predicate {
+        Predicate::Compare(Comparison { field: Field::Pid, cmp: cmp, operand: Operand::Integer(pid) })
+    }
+
+    pub fn session(cmp: MatchType, session: u32) -> Predicate {
+        Predicate::Compare(Comparison { field: Field::Session, cmp: cmp, operand: Operand::Integer(u64::from(session)) })
+    }
+
+    pub fn sid(cmp: MatchType, sid: Sid) -> Result<Predicate, MemguardError> {
+        Ok(Predicate::Compare(Comparison::new(Field::Sid, cmp, Operand::Sid(sid))?))
+    }
+
+    pub fn image(cmp: MatchType, name: &str) -> Result<Predicate, MemguardError> {
+        Ok(Predicate::Compare(Comparison::new(Field::Image, cmp, Operand::Text(name.to_string()))?))
+    }
+
+    pub fn and(self, other: Predicate) -> Predicate {
+        match self {
+            Predicate::All(mut predicates) => {
+                predicates.push(other);
+                Predicate::All(predicates)
+            },
+            predicate => Predicate::All(vec![predicate, other]),
+        }
+    }
+
+    pub fn or(self, other: Predicate) -> Predicate {
+        match self {
+            Predicate::Any(mut predicates) => {
+                predicates.push(other);
+                Predicate::Any(predicates)
+            },
+            predicate => Predicate::Any(vec![predicate, other]),
+        }
+    }
+
+    pub fn negate(self) -> Predicate {
+        match self {
+            Predicate::Not(predicate) => *predicate,
+            predicate => Predicate::Not(Box::new(predicate)),
+        }
+    }
+
+    fn expand(&self, negated: bool) -> Vec<Vec<Comparison>> {
+        // AND of every combination of the alternatives of `predicates`
+        let product = |predicates: &[Predicate]| -> Vec<Vec<Comparison>> {
+            predicates.iter().fold(vec![vec![]], |alternatives, predicate| {
+                let expanded = predicate.expand(negated);
+
+                alternatives.iter()
+                            .flat_map(|left| expanded.iter().map(move |right| left.iter().chain(right).cloned().collect()))
+                            .collect()
+            })
+        };
+
+        let union = |predicates: &[Predicate]| -> Vec<Vec<Comparison>> {
+            predicates.iter().flat_map(|predicate| predicate.expand(negated)).collect()
+        };
+
+        match (self, negated) {
+            (&Predicate::Compare(ref comparison), false) => vec![vec![comparison.clone()]],
+            (&Predicate::Compare(ref comparison), true) => vec![vec![comparison.negated()]],
+            (&Predicate::Not(ref predicate), _) => predicate.expand(!negated),
+            (&Predicate::All(ref predicates), false) | (&Predicate::Any(ref predicates), true) => product(predicates),
+            (&Predicate::Any(ref predicates), false) | (&Predicate::All(ref predicates), true) => union(predicates),
+        }
+    }
+
+    /// The predicate with image and session comparisons replaced by the ids of the
+    /// processes they hold for right now, see `Field`.
+    pub fn resolve(&self, session: &SentrySession) -> Result<Predicate, Error> {
+        let all = |predicates: &[Predicate]| predicates.iter()
+                                                      .map(|predicate| predicate.resolve(session))
+                                                      .collect::<Result<Vec<Predicate>, Error>>();
+
+        Ok(match *self {
+            Predicate::Compare(ref comparison) => comparison.resolve(session)?,
+            Predicate::All(ref predicates) => Predicate::All(all(predicates)?),
+            Predicate::Any(ref predicates) => Predicate::Any(all(predicates)?),
+            Predicate::Not(ref predicate) => Predicate::Not(Box::new(predicate.resolve(session)?)),
+        })
+    }
+
+    /// The ANDed conditions of every filter needed, checked against the driver limits.
+    ///
+    /// Image and session comparisons count as one condition here, resolving them may
+    /// take more.
+    pub fn alternatives(&self) -> Result<Vec<Vec<Comparison>>, MemguardError> {
+        let alternatives = self.expand(false);
+
+        // comparisons built by `Predicate::pid` and `Predicate::session` aren't checked yet
+        for comparison in alternatives.iter().flat_map(|conditions| conditions.iter()) {
+            Comparison::new(comparison.field, comparison.cmp, comparison.operand.clone())?;
+        }
+
+        if alternatives.is_empty() {
+            return Err(MemguardError::InvalidCondition(self.to_string(), String::from("never matches")))
+        }
+
+        if alternatives.len() > MAX_ALTERNATIVES {
+            return Err(MemguardError::TooManyAlternatives(alternatives.len(), MAX_ALTERNATIVES))
+        }
+
+        if let Some(conditions) = alternatives.iter().find(|conditions| conditions.len() > MAX_CONDITIONS) {
+            let text = conditions.iter().map(|comparison| comparison.to_string()).collect::<Vec<_>>().join(" && ");
+
+            return Err(MemguardError::FilterTooLarge(text, conditions.len(), MAX_CONDITIONS))
+        }
+
+        Ok(alternatives)
+    }
+
+    /// One kernel filter per alternative, register a guard with each of them.
+    pub fn filters<'a>(&self, session: &'a SentrySession) -> Result<Vec<Filter<'a>>, Error> {
+        let resolved = self.resolve(session)?;
+
+        if resolved.expand(false).is_empty() {
+            return Err(MemguardError::InvalidCondition(self.to_string(), String::from("no running process matches")).into())
+        }
+
+        resolved.alternatives()?
+            .iter()
+            .map(|conditions| {
+                let mut filter = Filter::new(session)?;
+
+                for comparison in conditions {
+                    filter.compare(comparison)?;
+                }
+
+                Ok(filter)
+            })
+            .collect()
+    }
+
+    /// The kernel filter of a predicate without alternatives, i.e. fitting a single guard.
+    pub fn filter<'a>(&self, session: &'a SentrySession) -> Result<Filter<'a>, Error> {
+        let mut filters = self.filters(session)?;
+
+        match filters.len() {
+            1 => Ok(filters.remove(0)),
+            count => Err(MemguardError::InvalidCondition(self.to_string(), format!("needs {} guards, one per alternative", count)).into()),
+        }
+    }
+}
+
+impl<'a> Filter<'a> {
+    // copies `data` into a kernel buffer living as long as the filter
+    fn store(&mut self, data: Vec<u8>) -> Result<u64, Error> {
+        let address = memory::alloc_virtual_memory(self.session, data.len())?;
+
+        self.values.push(address);
+
+        memory::write_virtual_memory(self.session, address, data)?;
+
+        Ok(address)
+    }
+
+    /// Adds a resolved comparison, SID values are referenced by kernel address.
+    pub fn compare(&mut self, comparison: &Comparison) -> Result<(), Error> {
+        let (key, kind) = match (comparison.field.key(), comparison.kind()) {
+            (Some(key), Some(kind)) => (key, kind),
+            _ => return Err(MemguardError::InvalidCondition(comparison.to_string(), String::from("must be resolved to process ids first")).into()),
+        };
+
+        let value = match comparison.operand {
+            Operand::Integer(value) => value,
+            Operand::Sid(ref sid) => self.store(sid.to_bytes())?,
+            Operand::Text(_) => unreachable!("text is only compared in user mode"),
+        };
+
+        self.add(&Condition::new(key, comparison.cmp, kind, value))
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} ", field_name(self.field), operator(self.cmp))?;
+
+        match self.operand {
+            Operand::Integer(value) => write!(f, "{}", value),
+            Operand::Sid(ref sid) => write!(f, "{}", sid),
+            Operand::Text(ref text) => write!(f, "{:?}", text),
+        }
+    }
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (predicates, separator) = match *self {
+            Predicate::Compare(ref comparison) => return write!(f, "{}", comparison),
+            Predicate::Not(ref predicate) => return write!(f, "!({})", predicate),
+            Predicate::All(ref predicates) => (predicates, " && "),
+            Predicate::Any(ref predicates) => (predicates, " || "),
+        };
+
+        let text = predicates.iter().map(|predicate| match *predicate {
+            Predicate::All(_) | Predicate::Any(_) => format!("({})", predicate),
+            _ => predicate.to_string(),
+        }).collect::<Vec<String>>();
+
+        write!(f, "{}", text.join(separator))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predicates_flatten_into_filters() {
+        let system = "S-1-5-18".parse::<Sid>().unwrap();
+
+        let predicate = Predicate::pid(MatchType::EQUAL, 4)
+                            .or(Predicate::image(MatchType::EQUAL, "lsass.exe").unwrap())
+                            .and(Predicate::sid(MatchType::EQUAL, system.clone()).unwrap().negate());
+
+        assert_eq!(predicate.to_string(), "(pid == 4 || image == \"lsass.exe\") && !(sid == S-1-5-18)");
+
+        let alternatives = predicate.alternatives().unwrap();
+        assert_eq!(alternatives.len(), 2);
+        assert_eq!(alternatives[1][0].field, Field::Image);
+        assert_eq!(alternatives[1][1], Comparison::new(Field::Sid, MatchType::NOT_EQUAL, Operand::Sid(system)).unwrap());
+
+        // !(a && b) == !a || !b
+        let alternatives = Predicate::pid(MatchType::LESS, 10).and(Predicate::session(MatchType::GREATER, 1)).negate().alternatives().unwrap();
+        assert_eq!(alternatives.len(), 2);
+        assert_eq!(alternatives[0][0].to_string(), "pid >= 10");
+        assert_eq!(alternatives[1][0].to_string(), "session <= 1");
+    }
+
+    #[test]
+    fn test_predicates_respect_driver_limits() {
+        assert!(Predicate::image(MatchType::GREATER, "notepad.exe").is_err());
+        assert!(Predicate::image(MatchType::NOT_EQUAL, "notepad.exe").is_ok());
+        assert!(Comparison::new(Field::Pid, MatchType::EQUAL, Operand::Text(String::from("4"))).is_err());
+        assert!(Comparison::new(Field::Sid, MatchType::EQUAL, Operand::Integer(4)).is_err());
+        assert!(Predicate::Any(vec![]).alternatives().is_err());
+
+        let wide = Predicate::All((0..17).map(|pid| Predicate::pid(MatchType::NOT_EQUAL, pid)).collect());
+        let err = wide.alternatives().unwrap_err().to_string();
+        assert!(err.contains("needs 17 conditions"), err);
+
+        let many = Predicate::Any((0..17).map(|pid| Predicate::pid(MatchType::EQUAL, pid)).collect());
+        assert!(many.alternatives().is_err());
+        assert!(many.negate().alternatives().is_err());
+
+        let many = Predicate::Any((0..16).map(|pid| Predicate::pid(MatchType::EQUAL, pid)).collect());
+        assert_eq!(many.negate().alternatives().unwrap().len(), 1);
+    }
+}
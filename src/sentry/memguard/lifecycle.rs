@@ -0,0 +1,225 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::{CallbackPolicy, Filter, Guard, GuardFlags, Partition, Sentinel, SyncCallback};
+use super::super::failure::Error;
+use super::super::misc::Process;
+use super::super::io;
+
+use std::fmt;
+
+/// Where a guard stands in the driver.
+///
+///   Created --start--> Armed --stop--> Stopped --start--> Armed
+///
+/// A guard whose start or stop failed is `Faulted`: the driver may or may not have
+/// applied the change, stopping it again is the only way out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuardState {
+    Created,
+    Armed,
+    Stopped,
+    Faulted,
+}
+
+impl fmt::Display for GuardState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            GuardState::Created => "created",
+            GuardState::Armed => "armed",
+            GuardState::Stopped => "stopped",
+            GuardState::Faulted => "faulted",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Registers a guard with everything it protects in one step.
+///
+/// When any step fails, what was already applied is rolled back: sentinels are
+/// removed from the guard and deleted, then the guard is unregistered.
+pub struct GuardBuilder<'p> {
+    partition: &'p Partition,
+    filter: Option<Filter<'p>>,
+    // the calling process when not set
+    process: Option<Option<Process>>,
+    sentinels: Vec<Box<dyn Sentinel + 'p>>,
+    callback: Option<SyncCallback>,
+    policy: Option<CallbackPolicy>,
+    start: bool,
+}
+
+impl<'p> GuardBuilder<'p> {
+    pub fn new(partition: &'p Partition) -> GuardBuilder<'p> {
+        GuardBuilder {
+            partition: partition,
+            filter: None,
+            process: None,
+            sentinels: vec![],
+            callback: None,
+            policy: None,
+            start: false,
+        }
+    }
+
+    pub fn filter(mut self, filter: Filter<'p>) -> GuardBuilder<'p> {
+        self.filter = Some(filter);
+        self
+    }
+
+    // the process registering the guard, `None` registers it on behalf of no process
+    pub fn process(mut self, process: Option<Process>) -> GuardBuilder<'p> {
+        self.process = Some(process);
+        self
+    }
+
+    pub fn add<T>(mut self, sentinel: T) -> GuardBuilder<'p> where T: Sentinel + 'p {
+        self.sentinels.push(Box::new(sentinel));
+        self
+    }
+
+    pub fn callback(mut self, callback: SyncCallback) -> GuardBuilder<'p> {
+        self.callback = Some(callback);
+        self
+    }
+
+    pub fn policy(mut self, policy: CallbackPolicy) -> GuardBuilder<'p> {
+        self.policy = Some(policy);
+        self
+    }
+
+    // arms the guard once everything is registered
+    pub fn start(mut self) -> GuardBuilder<'p> {
+        self.start = true;
+        self
+    }
+
+    pub fn build(self) -> Result<Guard<'p>, Error> {
+        let session = &self.partition.session;
+
+        let process = match self.process {
+            Some(process) => process,
+            None => Some(Process::current(session)?),
+        };
+
+        let id = io::register_guard_extended(session, self.partition.id, process, self.filter, GuardFlags::STOPPED, 0, 0)?;
+
+        // dropping `guard` on any error below is the rollback
+        let mut guard = Guard::registered(self.partition, id);
+
+        for sentinel in self.sentinels {
+            guard.attach(sentinel)?;
+        }
+
+        if let Some(policy) = self.policy {
+            guard.set_callback_policy(policy);
+        }
+
+        if let Some(callback) = self.callback {
+            guard.set_callback(callback);
+        }
+
+        if self.start {
+            guard.start()?;
+        }
+
+        Ok(guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Access, Action, CallbackPolicy, CallbackStats, FailPolicy, InterceptionEvent, Patch, Region, Response};
+    use super::super::super::wire::InterceptionMessage;
+    use super::super::error::MemguardError;
+    use super::super::super::simulator::Simulator;
+    use super::super::super::session::SentrySession;
+    use super::super::super::iochannel::Transport;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn test_guard_lifecycle() {
+        let simulator = Arc::new(Simulator::new());
+        let session = SentrySession::from(simulator.clone() as Arc<dyn Transport>);
+
+        let partition = Partition::with_session(&session).unwrap();
+
+        {
+            let guard = Guard::builder(&partition)
+                             .process(None)
+                             .add(Region::new(&partition, 0x1000, 0x100, None, Access::WRITE).unwrap())
+                             .add(Region::new(&partition, 0x2000, 0x100, None, Access::READ).unwrap())
+                             .start()
+                             .build()
+                             .unwrap();
+
+            assert_eq!(guard.state(), GuardState::Armed);
+            assert_eq!(simulator.is_started(guard.id()), Some(true));
+            assert_eq!(guard.regions().unwrap().count(), 2);
+
+            match guard.start().map(|_| ()).unwrap_err().downcast::<MemguardError>() {
+                Ok(MemguardError::InvalidTransition(_, "start", GuardState::Armed)) => (),
+                other => panic!("expected an invalid transition, got {:?}", other),
+            }
+
+            guard.stop().unwrap();
+            assert_eq!(guard.state(), GuardState::Stopped);
+        }
+
+        // dropping the guard unregisters it and deletes its regions
+        assert!(simulator.guards(partition.id).is_empty());
+        assert_eq!(partition.regions().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_failed_build_rolls_back() {
+        let simulator = Arc::new(Simulator::new());
+        let session = SentrySession::from(simulator.clone() as Arc<dyn Transport>);
+
+        let partition = Partition::with_session(&session).unwrap();
+        let other = Partition::with_session(&session).unwrap();
+
+        // a patch of another partition can't be linked to the guard
+        let result = Guard::builder(&partition)
+                          .process(None)
+                          .add(Region::new(&partition, 0x1000, 0x100, None, Access::WRITE).unwrap())
+                          .add(Patch::new(&other, 0x3000, 0x4000, 0x100).unwrap())
+                          .add(Region::new(&partition, 0x5000, 0x100, None, Access::WRITE).unwrap())
+                          .start()
+                          .build();
+
+        assert!(result.is_err());
+        assert!(simulator.guards(partition.id).is_empty());
+        assert_eq!(partition.regions().unwrap().count(), 0);
+        assert_eq!(other.patches().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_guard_callback_policy() {
+        let simulator = Arc::new(Simulator::new());
+        let session = SentrySession::from(simulator.clone() as Arc<dyn Transport>);
+
+        let partition = Partition::with_session(&session).unwrap();
+
+        let guard = Guard::builder(&partition)
+                         .process(None)
+                         .add(Region::new(&partition, 0x1000, 0x100, None, Access::WRITE).unwrap())
+                         .callback(Box::new(|_: InterceptionEvent| -> Response { panic!("callback failed") }))
+                         .policy(CallbackPolicy::new(None, FailPolicy::Block))
+                         .build()
+                         .unwrap();
+
+        assert_eq!(guard.callback_policy().on_failure, FailPolicy::Block);
+
+        let event = InterceptionEvent::from(&InterceptionMessage { guard_id: guard.id(), ..Default::default() });
+
+        // as a bucket thread would answer it
+        let response = partition.tunnel.callbacks.read().unwrap()[&guard.id()](event);
+
+        assert_eq!(response.action(), Action::BLOCK);
+        assert_eq!(guard.callback_stats(), CallbackStats { calls: 1, timeouts: 0, busy: 0, panics: 1 });
+        assert_eq!(partition.callback_stats(), CallbackStats::default());
+    }
+}
@@ -2,7 +2,7 @@ extern crate byteorder;
 extern crate winapi;
 extern crate console;
 
-use super::iochannel::{Device};
+use super::session::SentrySession;
 use super::cli::output::{create_messenger, ShellMessage, MessageType};
 
 use std::sync::mpsc;
@@ -10,30 +10,55 @@ use std::sync::mpsc;
 mod bucket;
 mod sync;
 mod structs;
+mod options;
+mod sid;
+mod target;
+mod templates;
+mod filter;
+mod expression;
+mod lifecycle;
+mod supervisor;
+mod event;
+mod disasm;
+mod stream;
+mod watchdog;
+pub mod error;
+pub mod command;
 
 use self::console::style;
 use super::{io, memory, misc};
-use std::rc::{Rc, Weak};
 
 use std::{fmt, thread, time};
 use std::thread::{JoinHandle};
 
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::collections::HashMap;
 
-pub use self::bucket::{Interception, Response};
-
-pub use self::structs::MatchType;
+pub use self::bucket::Response;
+pub use self::event::{InterceptionEvent, Registers, MAX_INSTRUCTION_LENGTH};
+pub use self::disasm::{Instruction, Memory, Operand as InstructionOperand, Register};
+pub use self::stream::{Decision, Subscription};
+pub use self::watchdog::{CallbackPolicy, CallbackStats, FailPolicy, Watchdog};
+
+pub use self::structs::{FieldKey, MatchType, ValueType};
+pub use self::sid::Sid;
+pub use self::target::{Instance, Target};
+pub use self::templates::{CallbackKind, KernelLayout, LiveKernel, Template};
+pub use self::filter::{Comparison, Field, Operand, Predicate, MAX_CONDITIONS};
+pub use self::options::{PartitionBuilder, PartitionOption, PartitionOptions};
+pub use self::lifecycle::{GuardBuilder, GuardState};
+pub use self::supervisor::{interrupted_since, interrupts, Expired, Expiry, Supervision, Supervisor};
+pub use super::wire::{Entries, PatchInfo, RegionInfo};
 
 use super::failure::Error;
+use self::error::MemguardError;
+use self::supervisor::Hold;
 
-use self::structs::{FieldKey,
-                    ValueType,
-                    MG_GUARD_CONDITION,
+use self::structs::{MG_GUARD_CONDITION,
                     MG_GUARD_FILTER,
                     MG_FIELD_VALUE};
 
-const _PARTITION_ROOT_ID: u64 = 4;
+pub const PARTITION_ROOT_ID: u64 = 4;
 pub const MESSENGER_FINISH_MSG: &str = "END-LOOP-MSG";
 
 
@@ -85,8 +110,10 @@ impl Access {
     }
 }
 
-pub type SyncCallback = Box<dyn Fn(bucket::Interception) -> Response + Send + Sync>;
+pub type SyncCallback = Box<dyn Fn(InterceptionEvent) -> Response + Send + Sync>;
 pub type CallbackMap = Arc<RwLock<HashMap<u64, SyncCallback>>>;
+// answers guards without a callback of their own, see `Partition::subscribe`
+pub type FallbackCallback = Arc<RwLock<Option<SyncCallback>>>;
 
 #[derive(Debug)]
 pub enum Handler {
@@ -96,34 +123,67 @@ pub enum Handler {
 
 struct Tunnel {
     workers: Vec<Handler>,
-    messenger: mpsc::Sender<String>,
-    callbacks: CallbackMap
+    // senders aren't Sync, the lock lets partitions be shared between threads
+    messenger: Mutex<mpsc::Sender<String>>,
+    callbacks: CallbackMap,
+    fallback: FallbackCallback,
+    // policies and counters of the callbacks, by guard, and of the fallback
+    watchdogs: Mutex<HashMap<u64, Arc<Watchdog>>>,
+    watchdog: Arc<Watchdog>,
 }
 
 impl Tunnel {
-    fn default_callback(_interception: bucket::Interception) -> Response {
+    fn default_callback(_interception: InterceptionEvent) -> Response {
         Response::new(Some(String::from("default-callback()")), Action::CONTINUE)
     }
 
     pub fn register_callback(&self, guard: &Guard, callback: SyncCallback) {
+        let callback = Watchdog::watch(&self.watchdog_of(guard), callback);
+
         let mut map = self.callbacks.write().expect("Failed to unlock as a writer");
         map.insert(guard.id, callback);
     }
 
+    pub fn unregister_callback(&self, guard: &Guard) {
+        let mut map = self.callbacks.write().expect("Failed to unlock as a writer");
+        map.remove(&guard.id);
+
+        self.watchdogs.lock().expect("watchdogs lock poisoned").remove(&guard.id);
+    }
+
+    pub fn set_fallback(&self, callback: Option<SyncCallback>) {
+        let callback = callback.map(|callback| Watchdog::watch(&self.watchdog, callback));
+
+        *self.fallback.write().expect("Failed to unlock as a writer") = callback;
+    }
+
+    pub fn watchdog_of(&self, guard: &Guard) -> Arc<Watchdog> {
+        let mut watchdogs = self.watchdogs.lock().expect("watchdogs lock poisoned");
+
+        Arc::clone(watchdogs.entry(guard.id).or_insert_with(|| Arc::new(Watchdog::default())))
+    }
+
     fn create_workers(&self,
                       tx: mpsc::Sender<String>,
                       rx: mpsc::Receiver<String>,
                       buckets: Vec<Vec<u8>>,
-                      callbacks: &CallbackMap) -> Vec<Handler> {
+                      callbacks: &CallbackMap,
+                      fallback: &FallbackCallback) -> Vec<Handler> {
 
         let mut handlers = buckets.into_iter().map(|bucket|
         {
             let callbacks = Arc::clone(callbacks);
+            let fallback = Arc::clone(fallback);
             let sender = tx.clone();
             Handler::Interceptor(
                 thread::spawn(move|| bucket::Bucket::handler(sender,
                                         bucket,
-                                        Box::new(Tunnel::default_callback),
+                                        Box::new(move |event| {
+                                            match *fallback.read().expect("Unable to unlock fallback for reading") {
+                                                Some(ref callback) => callback(event),
+                                                None => Tunnel::default_callback(event),
+                                            }
+                                        }),
                                         callbacks)
                  )
             )
@@ -161,12 +221,16 @@ impl Tunnel {
 
     pub fn new(channel: &io::Channel) -> Result<Tunnel, Error> {
         let callbacks = Arc::new(RwLock::new(HashMap::new()));
+        let fallback = Arc::new(RwLock::new(None));
 
         let (tx, rx) = mpsc::channel();
 
         let mut tunnel = Tunnel {
             callbacks: Arc::clone(&callbacks),
-            messenger: tx.clone(),
+            fallback: Arc::clone(&fallback),
+            watchdogs: Mutex::new(HashMap::new()),
+            watchdog: Arc::new(Watchdog::default()),
+            messenger: Mutex::new(tx.clone()),
             workers: Vec::new()
         };
 
@@ -174,7 +238,8 @@ impl Tunnel {
             tx,
             rx,
             bucket::Bucket::slice_buckets(channel.address, channel.size as usize),
-            &callbacks
+            &callbacks,
+            &fallback
         );
 
         tunnel.workers.extend(workers.into_iter());
@@ -198,7 +263,9 @@ impl Tunnel {
              }
         }
 
-        self.messenger.send(MESSENGER_FINISH_MSG.to_string())
+        self.messenger.lock()
+                    .expect("messenger lock poisoned")
+                    .send(MESSENGER_FINISH_MSG.to_string())
                     .expect("error finishing displayer thread");
 
         if let Some(handle) = handler {
@@ -217,37 +284,36 @@ impl Drop for Tunnel {
 pub struct ObjectFilter {
     pub id: u64,
     _tunnel: Tunnel,
-    pub device: Rc<Device>,
+    pub session: SentrySession,
 }
 
 impl ObjectFilter {
-    pub fn new() -> Result<ObjectFilter, Error> {
-        let device = Rc::new(Device::new(io::SE_NT_DEVICE_NAME).expect("sentry device"));
-        let channel = io::create_monitor(&device)?;
+    pub fn new(session: &SentrySession) -> Result<ObjectFilter, Error> {
+        let channel = io::create_monitor(session)?;
         let tunnel = Tunnel::new(&channel)?;
 
         Ok(
             ObjectFilter {
                 id: channel.id,
-                device: Rc::clone(&device),
+                session: session.clone(),
                 _tunnel: tunnel
             }
         )
     }
 
     pub fn start(&self) -> Result<(), Error> {
-        Ok(io::start_monitor(&self.device, self.id)?)
+        Ok(io::start_monitor(&self.session, self.id)?)
     }
 
     pub fn stop(&self) -> Result<(), Error> {
-        Ok(io::stop_monitor(&self.device, self.id)?)
+        Ok(io::stop_monitor(&self.session, self.id)?)
     }
 }
 
 
 impl Drop for ObjectFilter {
     fn drop(&mut self) {
-        if let Err(err) = io::destroy_monitor(&self.device, self.id) {
+        if let Err(err) = io::destroy_monitor(&self.session, self.id) {
             println!("io::destroy_monitor() {}", err);
         }
     }
@@ -261,42 +327,99 @@ impl fmt::Debug for ObjectFilter {
 
 pub struct Partition {
     pub id: u64,
-    pub device: Rc<Device>,
+    pub session: SentrySession,
     tunnel: Tunnel,
+    supervisor: Supervisor,
 }
 
 impl Partition
 {
+    // opens a session of its own, prefer `with_session` when creating several partitions
     pub fn new() -> Result<Partition, Error> {
-        let device = Rc::new(Device::new(io::SE_NT_DEVICE_NAME).expect("sentry device"));
-        let channel = io::create_partition(&device)?;
+        Partition::with_session(&SentrySession::open()?)
+    }
+
+    // creates a partition over an existing session, e.g. one backed by the in-process simulator
+    pub fn with_session(session: &SentrySession) -> Result<Partition, Error> {
+        let channel = io::create_partition(session)?;
         let tunnel = Tunnel::new(&channel)?;
 
         Ok(
             Partition {
                 id: channel.id,
-                device: Rc::clone(&device),
-                tunnel: tunnel
+                session: session.clone(),
+                tunnel: tunnel,
+                supervisor: Supervisor::new(session),
             }
         )
     }
 
+    pub fn builder() -> PartitionBuilder {
+        PartitionBuilder::new()
+    }
+
     pub fn register_callback(&self, guard: &Guard, callback: SyncCallback) {
         self.tunnel.register_callback(guard, callback)
     }
 
-    pub fn device(&self) -> Weak<Device> {
-        Rc::downgrade(&self.device)
+    /// Streams the interceptions of every guard in the partition that has no callback
+    /// or subscription of its own, replacing any earlier partition subscription.
+    pub fn subscribe<D>(&self, capacity: usize, decision: D) -> Subscription
+        where D: Fn(&InterceptionEvent) -> Response + Send + Sync + 'static
+    {
+        let (callback, subscription) = Subscription::channel(capacity, Box::new(decision));
+        self.tunnel.set_fallback(Some(callback));
+
+        subscription
+    }
+
+    // back to the default callback, ending the partition subscription
+    pub fn unsubscribe(&self) {
+        self.tunnel.set_fallback(None)
     }
 
-    pub fn root() -> Partition {
-        Partition::new().unwrap()
+    /// Deadline and fail policy of the partition subscription, see `Guard::set_callback_policy`.
+    pub fn set_callback_policy(&self, policy: CallbackPolicy) {
+        self.tunnel.watchdog.set_policy(policy)
+    }
+
+    pub fn callback_stats(&self) -> CallbackStats {
+        self.tunnel.watchdog.stats()
+    }
+
+    pub fn set_option(&self, option: PartitionOption, enabled: bool) -> Result<(), Error> {
+        io::set_partition_option(&self.session, self.id, option.id(), u64::from(enabled))
+    }
+
+    pub fn get_option(&self, option: PartitionOption) -> Result<bool, Error> {
+        Ok(io::get_partition_option(&self.session, self.id, option.id())? != 0)
+    }
+
+    pub fn options(&self) -> Result<PartitionOptions, Error> {
+        options::read(&self.session, self.id)
+    }
+
+    pub fn session(&self) -> &SentrySession {
+        &self.session
+    }
+
+    // every region created in this partition, as the driver sees them
+    pub fn regions(&self) -> Result<Entries<RegionInfo>, Error> {
+        io::enumerate_region(&self.session, self.id, 0)
+    }
+
+    pub fn patches(&self) -> Result<Entries<PatchInfo>, Error> {
+        io::enumerate_patch(&self.session, self.id, 0)
+    }
+
+    pub fn root() -> Result<Partition, Error> {
+        Partition::new()
     }
 }
 
 impl Drop for Partition {
     fn drop(&mut self) {
-        if let Err(err) = io::delete_partition(&self.device, self.id) {
+        if let Err(err) = io::delete_partition(&self.session, self.id) {
             println!("io::delete_partition() {}", err);
         }
     }
@@ -323,7 +446,9 @@ impl Range {
     }
 }
 
-pub trait Sentinel {
+/// Something a guard protects, deleted from its partition once dropped.
+pub trait Sentinel: fmt::Display {
+    fn id(&self) -> u64;
     fn remove(&self, guard: &Guard) -> Result<(), Error>;
     fn register(&self, guard: &Guard) -> Result<(), Error>;
 }
@@ -343,7 +468,7 @@ impl<'p> Region<'p> {
 
         let action = action.unwrap_or(Action::INSPECT | Action::NOTIFY);
 
-        let id = io::create_region(&partition.device, partition.id, &range, action, access, Some(0x100))?;
+        let id = io::create_region(&partition.session, partition.id, &range, action, access, Some(0x100))?;
 
         Ok(
             Region {
@@ -354,24 +479,110 @@ impl<'p> Region<'p> {
                 action: action
         })
     }
+
+    /// Creates a region at a symbolic address such as `nt!KiServiceTable` or
+    /// `_EPROCESS(pid=1234).Token`, see `Target`.
+    pub fn at(partition: &'p Partition, expression: &str, limit: u64, action: Option<Action>, access: Access) -> Result<Region<'p>, Error> {
+        let target = expression.parse::<Target>()?;
+
+        Region::target(partition, &target, limit, action, access)
+    }
+
+    pub fn target(partition: &'p Partition, target: &Target, limit: u64, action: Option<Action>, access: Access) -> Result<Region<'p>, Error> {
+        let base = target.resolve(&partition.session)?;
+
+        Region::new(partition, base, limit, action, access)
+    }
+
+    /// Creates a region over a known kernel structure such as `ssdt` or `token:1234`,
+    /// sized by the structure itself, see `Template`.
+    pub fn template(partition: &'p Partition, template: &Template, action: Option<Action>, access: Access) -> Result<Region<'p>, Error> {
+        let range = template.resolve(&LiveKernel::new(&partition.session))?;
+
+        Region::new(partition, range.base, range.limit, action, access)
+    }
+}
+
+impl<'p> Region<'p> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn info(&self) -> Result<RegionInfo, Error> {
+        io::get_info_region(&self.partition.session, self.id)
+    }
+
+    // the accesses and the action the region was created with, see `info` for the driver's view
+    pub fn access(&self) -> Access {
+        self.access
+    }
+
+    pub fn action(&self) -> Action {
+        self.action
+    }
+}
+
+impl RegionInfo {
+    pub fn range(&self) -> Range {
+        Range::new(self.base_address, self.size)
+    }
+
+    pub fn access(&self) -> Access {
+        Access::from_bits_truncate(self.access_type as u16)
+    }
+
+    pub fn action(&self) -> Action {
+        Action::from_bits_truncate(self.action as u16)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !RegionFlags::from_bits_truncate(self.flags as u32).contains(RegionFlags::DISABLED)
+    }
+}
+
+impl fmt::Display for RegionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Region(id: 0x{:08X}, base: 0x{:08X} limit: 0x{:X}, access: {:?}, action: {:?}, enabled: {}, guards: {})",
+                        self.region_id,
+                        self.base_address,
+                        self.size,
+                        self.access(),
+                        self.action(),
+                        self.is_enabled(),
+                        self.guard_count)
+    }
 }
 
 impl<'p> fmt::Display for Region<'p> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Region(id: 0x{:08X}, base: 0x{:08X} limit: 0x{:X})",
+        write!(f, "Region(id: 0x{:08X}, base: 0x{:08X} limit: 0x{:X}, access: {:?}, action: {:?})",
                         self.id,
                         self.range.base,
-                        self.range.limit)
+                        self.range.limit,
+                        self.access(),
+                        self.action())
+    }
+}
+
+impl<'p> Drop for Region<'p> {
+    fn drop(&mut self) {
+        if let Err(err) = io::delete_region(&self.partition.session, self.id) {
+            println!("io::delete_region() {}", err);
+        }
     }
 }
 
 impl<'p> Sentinel for Region<'p> {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
     fn remove(&self, guard: &Guard) -> Result<(), Error> {
-        io::remove_region(&self.partition.device, guard.id, self.id)
+        io::remove_region(&self.partition.session, guard.id, self.id)
     }
 
     fn register(&self, guard: &Guard) -> Result<(), Error> {
-        io::add_region(&self.partition.device, guard.id, self.id)
+        io::add_region(&self.partition.session, guard.id, self.id)
     }
 }
 
@@ -388,7 +599,7 @@ impl<'p> Patch<'p> {
     pub fn new(partition: &'p Partition, base: u64, patch: u64, limit: u64) -> Result<Patch<'p>, Error> {
         let patch_range = Range::new(patch, limit);
 
-        let id = io::create_patch(&partition.device, partition.id, base, &patch_range)?;
+        let id = io::create_patch(&partition.session, partition.id, base, &patch_range)?;
 
         Ok(
             Patch {
@@ -402,6 +613,27 @@ impl<'p> Patch<'p> {
     }
 }
 
+impl<'p> Patch<'p> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn info(&self) -> Result<PatchInfo, Error> {
+        io::get_info_patch(&self.partition.session, self.id)
+    }
+}
+
+impl fmt::Display for PatchInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Patch(id: 0x{:08X}, base: 0x{:08X} patch: 0x{:08X} limit: 0x{:X}, guards: {})",
+                        self.patch_id,
+                        self.base_address,
+                        self.patch_address,
+                        self.patch_size,
+                        self.guard_count)
+    }
+}
+
 impl<'p> fmt::Display for Patch<'p> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Patch(id: 0x{:08X}, base: 0x{:08X} patch: 0x{:08X} limit: 0x{:X} )",
@@ -412,42 +644,63 @@ impl<'p> fmt::Display for Patch<'p> {
     }
 }
 
+impl<'p> Drop for Patch<'p> {
+    fn drop(&mut self) {
+        if let Err(err) = io::delete_patch(&self.partition.session, self.id) {
+            println!("io::delete_patch() {}", err);
+        }
+    }
+}
+
 impl<'p> Sentinel for Patch<'p> {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
     fn remove(&self, guard: &Guard) -> Result<(), Error> {
-        io::remove_patch(&self.partition.device, guard.id, self.id)
+        io::remove_patch(&self.partition.session, guard.id, self.id)
     }
 
     fn register(&self, guard: &Guard) -> Result<(), Error> {
-        io::add_patch(&self.partition.device, guard.id, self.id)
+        io::add_patch(&self.partition.session, guard.id, self.id)
     }
 }
 
 #[derive(Debug)]
 pub struct Filter<'a> {
     pub alloc: memory::KernelAlloc<'a, MG_GUARD_FILTER>,
-    pub filter: &'a mut MG_GUARD_FILTER
+    pub filter: &'a mut MG_GUARD_FILTER,
+    session: &'a SentrySession,
+    // buffers of SID and string values, see `Filter::compare`
+    values: Vec<u64>,
 }
 
 impl<'a> Filter<'a> {
-    pub fn new(device: &'a Device) -> Filter {
-        let alloc = memory::KernelAlloc::new(device);
+    pub fn new(session: &'a SentrySession) -> Result<Filter, Error> {
+        let alloc = memory::KernelAlloc::new(session)?;
 
         let filter = unsafe { &mut *alloc.as_mut_ptr() };
 
-        Filter {
+        Ok(Filter {
             alloc: alloc,
             filter: filter,
-        }
+            session: session,
+            values: vec![],
+        })
     }
 
     pub fn kernel_ptr(&self) -> u64 {
         self.alloc.kernel_ptr()
     }
 
-    pub fn add(&mut self, condition: &Condition) {
-        let current = &mut self.filter.Conditions[self.filter.NumberOfConditions as usize];
+    pub fn add(&mut self, condition: &Condition) -> Result<(), Error> {
+        let capacity = self.filter.Conditions.len();
+
+        if self.filter.NumberOfConditions as usize >= capacity {
+            return Err(MemguardError::TooManyConditions(capacity).into())
+        }
 
-        assert!(self.filter.NumberOfConditions < 16);
+        let current = &mut self.filter.Conditions[self.filter.NumberOfConditions as usize];
 
         current.Field = condition.condition.Field;
         current.Match = condition.condition.Match;
@@ -456,24 +709,35 @@ impl<'a> Filter<'a> {
 
         self.filter.NumberOfConditions += 1;
 
+        Ok(())
     }
 
-    pub fn process(device: &'a Device, name: &str, cmp: MatchType) -> Option<Filter<'a>> {
-        if let Some(current) = misc::WalkProcess::iter().find(|p| p.name().contains(name)) {
-            let mut filter = Filter::new(device);
+    pub fn process(session: &'a SentrySession, name: &str, cmp: MatchType) -> Result<Option<Filter<'a>>, Error> {
+        if let Some(current) = misc::WalkProcess::by_name(session, name)? {
+            let mut filter = Filter::new(session)?;
             filter.add(&Condition::new(FieldKey::PROCESS_ID,
                                     cmp,
                                     ValueType::UINT64,
-                                    current.id()));
+                                    current.id()?))?;
 
-            return Some(filter)
+            return Ok(Some(filter))
         }
 
-        None
+        Ok(None)
     }
 
-    pub fn current_process(device: &'a Device, cmp: MatchType) -> Option<Filter<'a>> {
-        Filter::process(device, "conveyor.exe", cmp)
+    pub fn current_process(session: &'a SentrySession, cmp: MatchType) -> Result<Option<Filter<'a>>, Error> {
+        Filter::process(session, "conveyor.exe", cmp)
+    }
+}
+
+impl<'a> Drop for Filter<'a> {
+    fn drop(&mut self) {
+        for &address in &self.values {
+            if let Err(err) = memory::free_virtual_memory(self.session, address) {
+                println!("memory::free_virtual_memory() {}", err);
+            }
+        }
     }
 }
 
@@ -497,49 +761,169 @@ impl Condition {
     }
 }
 
+/// A registered guard owning the regions and patches it protects.
+///
+/// Dropping it stops it when armed, unlinks and deletes its sentinels, then
+/// unregisters it, so that nothing is left behind in the kernel.
 pub struct Guard<'p> {
     id: u64,
     partition: &'p Partition,
-    // sentinels: Vec<Sentinel>,
+    // shared with the supervisor once armed through `arm_for` or `arm_until`
+    state: Arc<Mutex<GuardState>>,
+    sentinels: Vec<Box<dyn Sentinel + 'p>>,
+    // released last, once the sentinels are deleted too
+    _hold: Hold,
 }
 
 impl<'p> Guard<'p> {
-    pub fn new(partition: &'p Partition, filter: Option<Filter<'p>>) -> Guard<'p> {
-        let id = io::register_guard(&partition.device, partition.id, filter)
-            .expect("Unable to connect guard with root partition");
+    pub fn new(partition: &'p Partition, filter: Option<Filter<'p>>) -> Result<Guard<'p>, Error> {
+        let id = io::register_guard(&partition.session, partition.id, filter)?;
+
+        Ok(Guard::registered(partition, id))
+    }
 
+    pub fn builder(partition: &'p Partition) -> GuardBuilder<'p> {
+        GuardBuilder::new(partition)
+    }
+
+    // takes ownership of a guard the driver just registered
+    fn registered(partition: &'p Partition, id: u64) -> Guard<'p> {
         Guard {
             id: id,
             partition: partition,
-            // sentinels: Vec::new()
+            state: Arc::new(Mutex::new(GuardState::Created)),
+            sentinels: vec![],
+            _hold: Hold::new(),
         }
     }
 
-    pub fn start(&self) -> &Self {
-        io::start_guard(&self.partition.device, self.id)
-                            .expect("start error");
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn state(&self) -> GuardState {
+        *self.state.lock().expect("guard state lock poisoned")
+    }
 
-        self
+    // regions currently linked to this guard
+    pub fn regions(&self) -> Result<Entries<RegionInfo>, Error> {
+        io::enumerate_region(&self.partition.session, self.partition.id, self.id)
     }
 
-    pub fn stop(&self) -> &Self {
-        io::stop_guard(&self.partition.device, self.id)
-                            .expect("stop error");
-        self
+    pub fn patches(&self) -> Result<Entries<PatchInfo>, Error> {
+        io::enumerate_patch(&self.partition.session, self.partition.id, self.id)
     }
 
-    pub fn remove<T>(&mut self, sentinel: T) where T:
-        Sentinel + fmt::Display {
-        sentinel.remove(self).expect(format!("Unable to register {}", sentinel).as_ref());
+    // moves to `to` when `control` succeeds, a failure leaves the driver in an unknown state
+    fn transition<F>(&self, action: &'static str, from: &[GuardState], to: GuardState, control: F) -> Result<(), Error>
+        where F: FnOnce() -> Result<(), Error> {
+        let mut state = self.state.lock().expect("guard state lock poisoned");
+
+        if !from.contains(&*state) {
+            return Err(MemguardError::InvalidTransition(self.id, action, *state).into())
+        }
+
+        match control() {
+            Ok(()) => {
+                *state = to;
+                Ok(())
+            },
+            Err(err) => {
+                *state = GuardState::Faulted;
+                Err(err)
+            },
+        }
+    }
+
+    pub fn start(&self) -> Result<&Self, Error> {
+        self.transition("start", &[GuardState::Created, GuardState::Stopped], GuardState::Armed,
+                        || io::start_guard(&self.partition.session, self.id))?;
+
+        Ok(self)
+    }
+
+    pub fn stop(&self) -> Result<&Self, Error> {
+        self.transition("stop", &[GuardState::Armed, GuardState::Faulted], GuardState::Stopped,
+                        || io::stop_guard(&self.partition.session, self.id))?;
+
+        Ok(self)
+    }
+
+    /// Arms the guard and has its partition supervisor stop it once `expiry` is
+    /// reached or the console is interrupted, whichever comes first.
+    ///
+    /// The stopped guard keeps its sentinels until it's dropped.
+    pub fn arm_until(&self, expiry: Expiry) -> Result<Supervision, Error> {
+        self.start()?;
+
+        Ok(self.partition.supervisor.watch(self.id, Arc::clone(&self.state), expiry))
+    }
+
+    pub fn arm_for(&self, duration: time::Duration) -> Result<Supervision, Error> {
+        self.arm_until(Expiry::After(duration))
+    }
+
+    fn attach(&mut self, sentinel: Box<dyn Sentinel + 'p>) -> Result<(), Error> {
+        if self.state() == GuardState::Faulted {
+            return Err(MemguardError::InvalidTransition(self.id, "add", GuardState::Faulted).into())
+        }
+
+        // a sentinel the driver refused is deleted right away
+        sentinel.register(self)?;
+        self.sentinels.push(sentinel);
+
+        Ok(())
+    }
+
+    /// Links `sentinel` to the guard, which owns it from now on.
+    pub fn add<T>(&mut self, sentinel: T) -> Result<(), Error> where T:
+        Sentinel + 'p {
+        self.attach(Box::new(sentinel))
+    }
+
+    /// Unlinks and deletes the sentinel `id`, i.e. the region or patch `id` added before.
+    pub fn remove(&mut self, id: u64) -> Result<(), Error> {
+        let index = self.sentinels.iter()
+                                  .position(|sentinel| sentinel.id() == id)
+                                  .ok_or_else(|| MemguardError::UnknownSentinel(self.id, id))?;
+
+        self.sentinels[index].remove(self)?;
+        self.sentinels.remove(index);
+
+        Ok(())
     }
 
     pub fn set_callback(&self, callback: SyncCallback) {
         self.partition.register_callback(self, callback)
     }
 
-    pub fn add<T>(&mut self, sentinel: T) where T:
-        Sentinel + fmt::Display {
-        sentinel.register(self).expect(format!("Unable to register {}", sentinel).as_ref());
+    /// Bounds how long the kernel waits for the callback of this guard.
+    ///
+    /// Once the deadline passes, or when the callback panics, the access is answered
+    /// with `policy.on_failure` and counted in `callback_stats`.
+    pub fn set_callback_policy(&self, policy: CallbackPolicy) {
+        self.partition.tunnel.watchdog_of(self).set_policy(policy)
+    }
+
+    pub fn callback_policy(&self) -> CallbackPolicy {
+        self.partition.tunnel.watchdog_of(self).policy()
+    }
+
+    pub fn callback_stats(&self) -> CallbackStats {
+        self.partition.tunnel.watchdog_of(self).stats()
+    }
+
+    /// Streams the interceptions of this guard, replacing its callback.
+    ///
+    /// `decision` answers the driver right away, events are queued afterwards and
+    /// dropped once `capacity` of them are waiting, at least one always fits.
+    pub fn subscribe<D>(&self, capacity: usize, decision: D) -> Subscription
+        where D: Fn(&InterceptionEvent) -> Response + Send + Sync + 'static
+    {
+        let (callback, subscription) = Subscription::channel(capacity, Box::new(decision));
+        self.set_callback(callback);
+
+        subscription
     }
 }
 
@@ -552,8 +936,26 @@ impl<'p> fmt::Display for Guard<'p> {
 
 impl<'p> Drop for Guard<'p> {
     fn drop(&mut self) {
-        if let Err(err) = io::unregister_guard(&self.partition.device, self.id) {
+        if self.state() == GuardState::Armed || self.state() == GuardState::Faulted {
+            if let Err(err) = self.stop() {
+                println!("error stopping guard: {}", err);
+            }
+        }
+
+        // newest first, as a rollback would
+        for sentinel in self.sentinels.iter().rev() {
+            if let Err(err) = sentinel.remove(self) {
+                println!("error removing {} from guard: {}", sentinel, err);
+            }
+        }
+
+        if let Err(err) = io::unregister_guard(&self.partition.session, self.id) {
             println!("error unregistering guard: {}", err);
         }
+
+        // ends its subscription, if any
+        self.partition.tunnel.unregister_callback(self);
+
+        // sentinels delete themselves once dropped, right after this
     }
 }
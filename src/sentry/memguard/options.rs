@@ -0,0 +1,209 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::error::MemguardError;
+use super::super::session::SentrySession;
+use super::super::failure::Error;
+use super::super::io;
+use super::Partition;
+
+use std::str::FromStr;
+use std::fmt;
+
+enum_from_primitive! {
+    // partition switches, numbered as the driver expects them
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PartitionOption {
+        TraceDebugEvents = 1,
+        TraceToDisk,
+        CoalesceNotifications,
+        CollectStats,
+        SecureMode,
+    }
+}
+
+impl PartitionOption {
+    pub const ALL: &'static [PartitionOption] = &[
+        PartitionOption::TraceDebugEvents,
+        PartitionOption::TraceToDisk,
+        PartitionOption::CoalesceNotifications,
+        PartitionOption::CollectStats,
+        PartitionOption::SecureMode,
+    ];
+
+    pub fn id(&self) -> u64 {
+        *self as u64
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PartitionOption::TraceDebugEvents => "trace-debug-events",
+            PartitionOption::TraceToDisk => "trace-to-disk",
+            PartitionOption::CoalesceNotifications => "coalesce-notifications",
+            PartitionOption::CollectStats => "collect-stats",
+            PartitionOption::SecureMode => "secure-mode",
+        }
+    }
+}
+
+impl fmt::Display for PartitionOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// accepts "collect-stats", "collect_stats" or "CollectStats"
+impl FromStr for PartitionOption {
+    type Err = MemguardError;
+
+    fn from_str(value: &str) -> Result<PartitionOption, MemguardError> {
+        let wanted = value.replace(|c: char| c == '-' || c == '_', "");
+
+        PartitionOption::ALL.iter()
+                            .find(|option| option.name().replace('-', "").eq_ignore_ascii_case(&wanted))
+                            .cloned()
+                            .ok_or_else(|| MemguardError::UnknownOption(value.to_string()))
+    }
+}
+
+pub fn parse_switch(value: &str) -> Result<bool, MemguardError> {
+    match value.to_lowercase().as_str() {
+        "1" | "on" | "true" | "yes" | "enable" => Ok(true),
+        "0" | "off" | "false" | "no" | "disable" => Ok(false),
+        _ => Err(MemguardError::OptionValue(value.to_string())),
+    }
+}
+
+// a snapshot of every switch of a partition
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PartitionOptions {
+    pub trace_debug_events: bool,
+    pub trace_to_disk: bool,
+    pub coalesce_notifications: bool,
+    pub collect_stats: bool,
+    pub secure_mode: bool,
+}
+
+impl PartitionOptions {
+    pub fn get(&self, option: PartitionOption) -> bool {
+        match option {
+            PartitionOption::TraceDebugEvents => self.trace_debug_events,
+            PartitionOption::TraceToDisk => self.trace_to_disk,
+            PartitionOption::CoalesceNotifications => self.coalesce_notifications,
+            PartitionOption::CollectStats => self.collect_stats,
+            PartitionOption::SecureMode => self.secure_mode,
+        }
+    }
+
+    pub fn set(&mut self, option: PartitionOption, enabled: bool) {
+        let field = match option {
+            PartitionOption::TraceDebugEvents => &mut self.trace_debug_events,
+            PartitionOption::TraceToDisk => &mut self.trace_to_disk,
+            PartitionOption::CoalesceNotifications => &mut self.coalesce_notifications,
+            PartitionOption::CollectStats => &mut self.collect_stats,
+            PartitionOption::SecureMode => &mut self.secure_mode,
+        };
+
+        *field = enabled;
+    }
+}
+
+impl fmt::Display for PartitionOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let options = PartitionOption::ALL.iter()
+                                          .map(|&option| format!("{}: {}", option, if self.get(option) { "on" } else { "off" }))
+                                          .collect::<Vec<String>>();
+
+        write!(f, "{}", options.join(", "))
+    }
+}
+
+// reads every option of any partition, including those owned by other processes
+pub fn read(session: &SentrySession, partition: u64) -> Result<PartitionOptions, Error> {
+    let mut options = PartitionOptions::default();
+
+    for &option in PartitionOption::ALL {
+        options.set(option, io::get_partition_option(session, partition, option.id())? != 0);
+    }
+
+    Ok(options)
+}
+
+/// Creates partitions with their options already applied.
+///
+/// ```ignore
+/// let partition = Partition::builder()
+///                     .enable(PartitionOption::CollectStats)
+///                     .create(&session)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PartitionBuilder {
+    options: Vec<(PartitionOption, bool)>,
+}
+
+impl PartitionBuilder {
+    pub fn new() -> PartitionBuilder {
+        PartitionBuilder::default()
+    }
+
+    pub fn option(mut self, option: PartitionOption, enabled: bool) -> PartitionBuilder {
+        self.options.push((option, enabled));
+        self
+    }
+
+    pub fn enable(self, option: PartitionOption) -> PartitionBuilder {
+        self.option(option, true)
+    }
+
+    pub fn options(self, options: &PartitionOptions) -> PartitionBuilder {
+        PartitionOption::ALL.iter().fold(self, |builder, &option| builder.option(option, options.get(option)))
+    }
+
+    // the partition is deleted again if any option is rejected
+    pub fn create(&self, session: &SentrySession) -> Result<Partition, Error> {
+        let partition = Partition::with_session(session)?;
+
+        for &(option, enabled) in &self.options {
+            partition.set_option(option, enabled)?;
+        }
+
+        Ok(partition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::simulator::Simulator;
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!("collect-stats".parse::<PartitionOption>().unwrap(), PartitionOption::CollectStats);
+        assert_eq!("SecureMode".parse::<PartitionOption>().unwrap(), PartitionOption::SecureMode);
+        assert_eq!("trace_to_disk".parse::<PartitionOption>().unwrap(), PartitionOption::TraceToDisk);
+        assert!("stats".parse::<PartitionOption>().is_err());
+
+        assert_eq!(parse_switch("ON").unwrap(), true);
+        assert_eq!(parse_switch("0").unwrap(), false);
+        assert!(parse_switch("maybe").is_err());
+    }
+
+    #[test]
+    fn test_builder_presets_options() {
+        let session = SentrySession::new(Simulator::new());
+
+        let partition = Partition::builder()
+                            .enable(PartitionOption::CollectStats)
+                            .option(PartitionOption::SecureMode, true)
+                            .create(&session)
+                            .unwrap();
+
+        assert!(partition.get_option(PartitionOption::CollectStats).unwrap());
+        assert!(!partition.get_option(PartitionOption::TraceToDisk).unwrap());
+
+        partition.set_option(PartitionOption::SecureMode, false).unwrap();
+
+        let options = partition.options().unwrap();
+        assert_eq!(options, PartitionOptions { collect_stats: true, ..Default::default() });
+        assert_eq!(read(&session, partition.id).unwrap(), options);
+    }
+}
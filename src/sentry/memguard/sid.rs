@@ -0,0 +1,102 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::error::MemguardError;
+use super::byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+
+use std::str::FromStr;
+use std::fmt;
+
+const MAX_SUB_AUTHORITIES: usize = 15;
+const MAX_AUTHORITY: u64 = (1 << 48) - 1;
+
+/// A security identifier such as `S-1-5-18`, laid out as the kernel expects it
+/// once converted with `to_bytes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sid {
+    pub revision: u8,
+    pub authority: u64,
+    pub sub_authorities: Vec<u32>,
+}
+
+impl Sid {
+    // SID structure: revision, sub authority count, big endian 48 bits authority, little endian sub authorities
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.revision, self.sub_authorities.len() as u8];
+
+        bytes.write_uint::<BigEndian>(self.authority, 6).expect("write to vector");
+
+        for &sub_authority in &self.sub_authorities {
+            bytes.write_u32::<LittleEndian>(sub_authority).expect("write to vector");
+        }
+
+        bytes
+    }
+}
+
+impl FromStr for Sid {
+    type Err = MemguardError;
+
+    fn from_str(value: &str) -> Result<Sid, MemguardError> {
+        let invalid = || MemguardError::InvalidSid(value.to_string());
+
+        let mut parts = value.trim().split('-');
+
+        if !parts.next().map_or(false, |prefix| prefix.eq_ignore_ascii_case("S")) {
+            return Err(invalid())
+        }
+
+        let revision = parts.next().and_then(|part| part.parse::<u8>().ok()).ok_or_else(invalid)?;
+
+        // large authorities may be written in hexadecimal
+        let authority = parts.next().and_then(|part| match part.starts_with("0x") {
+            true => u64::from_str_radix(&part[2..], 16).ok(),
+            false => part.parse::<u64>().ok(),
+        }).filter(|&authority| authority <= MAX_AUTHORITY).ok_or_else(invalid)?;
+
+        let sub_authorities = parts.map(|part| part.parse::<u32>().map_err(|_| invalid()))
+                                   .collect::<Result<Vec<u32>, _>>()?;
+
+        if revision != 1 || sub_authorities.len() > MAX_SUB_AUTHORITIES {
+            return Err(invalid())
+        }
+
+        Ok(Sid {
+            revision: revision,
+            authority: authority,
+            sub_authorities: sub_authorities,
+        })
+    }
+}
+
+impl fmt::Display for Sid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S-{}-{}", self.revision, self.authority)?;
+
+        for sub_authority in &self.sub_authorities {
+            write!(f, "-{}", sub_authority)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sid_layout() {
+        let sid = "S-1-5-21-1004-500".parse::<Sid>().unwrap();
+
+        assert_eq!(sid.to_string(), "S-1-5-21-1004-500");
+        assert_eq!(sid.to_bytes(), vec![1, 3, 0, 0, 0, 0, 0, 5,
+                                        21, 0, 0, 0,
+                                        0xEC, 0x03, 0, 0,
+                                        0xF4, 0x01, 0, 0]);
+
+        assert!("S-1".parse::<Sid>().is_err());
+        assert!("S-2-5-18".parse::<Sid>().is_err());
+        assert!("X-1-5-18".parse::<Sid>().is_err());
+        assert!("S-1-5-18-nope".parse::<Sid>().is_err());
+    }
+}
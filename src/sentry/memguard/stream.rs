@@ -0,0 +1,206 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+//
+// Subscriptions split an interception in two halves. The decision, which the kernel
+// waits for, runs on the bucket thread and should be as quick as a lookup. The event
+// itself is queued afterwards, so that logging, aggregation or forwarding happen on the
+// consumer's own thread once the faulting CPU has already been released.
+//
+// Queues are bounded: when the consumer falls behind, new events are dropped and
+// counted rather than holding the bucket until there's room for them.
+//
+
+use super::{InterceptionEvent, Response, SyncCallback};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub type Decision = Box<dyn Fn(&InterceptionEvent) -> Response + Send + Sync>;
+
+#[derive(Debug, Default)]
+struct Counters {
+    delivered: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+/// The receiving end of `Guard::subscribe` and `Partition::subscribe`.
+///
+/// Events stop once the guard or the partition is gone, then `recv` returns `None`
+/// and iterating ends.
+#[derive(Debug)]
+pub struct Subscription {
+    events: mpsc::Receiver<InterceptionEvent>,
+    counters: Arc<Counters>,
+    capacity: usize,
+}
+
+impl Subscription {
+    /// Creates a subscription and the callback that feeds it, `decision` answers the driver.
+    ///
+    /// A `capacity` of 0 is taken as 1: without room to queue in, an event is only
+    /// delivered when the consumer happens to be blocked in `recv` at that moment.
+    pub fn channel(capacity: usize, decision: Decision) -> (SyncCallback, Subscription) {
+        let capacity = capacity.max(1);
+        let (sender, events) = mpsc::sync_channel(capacity);
+        // as with the messenger, the lock lets the callback be shared between bucket threads
+        let sender = Mutex::new(sender);
+        let counters = Arc::new(Counters::default());
+
+        let subscription = Subscription {
+            events: events,
+            counters: Arc::clone(&counters),
+            capacity: capacity,
+        };
+
+        let callback = move |event: InterceptionEvent| {
+            let response = decision(&event);
+
+            let sent = sender.lock().expect("subscription lock poisoned").try_send(event);
+
+            match sent {
+                Ok(_) => counters.delivered.fetch_add(1, Ordering::SeqCst),
+                Err(TrySendError::Full(_)) => counters.dropped.fetch_add(1, Ordering::SeqCst),
+                // nobody listens anymore, the decision still holds
+                Err(TrySendError::Disconnected(_)) => 0,
+            };
+
+            response
+        };
+
+        (Box::new(callback), subscription)
+    }
+
+    pub fn recv(&self) -> Option<InterceptionEvent> {
+        self.events.recv().ok()
+    }
+
+    pub fn try_recv(&self) -> Option<InterceptionEvent> {
+        self.events.try_recv().ok()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<InterceptionEvent, RecvTimeoutError> {
+        self.events.recv_timeout(timeout)
+    }
+
+    pub fn iter(&self) -> mpsc::Iter<InterceptionEvent> {
+        self.events.iter()
+    }
+
+    // events queued but not taken yet
+    pub fn drain(&self) -> Vec<InterceptionEvent> {
+        self.events.try_iter().collect()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn delivered(&self) -> usize {
+        self.counters.delivered.load(Ordering::SeqCst)
+    }
+
+    /// Events lost because the queue was full when they were caught.
+    pub fn dropped(&self) -> usize {
+        self.counters.dropped.load(Ordering::SeqCst)
+    }
+}
+
+impl<'a> IntoIterator for &'a Subscription {
+    type Item = InterceptionEvent;
+    type IntoIter = mpsc::Iter<'a, InterceptionEvent>;
+
+    fn into_iter(self) -> mpsc::Iter<'a, InterceptionEvent> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Access, Action, Guard, Partition, Region};
+    use super::super::super::simulator::Simulator;
+    use super::super::super::session::SentrySession;
+    use super::super::super::iochannel::Transport;
+    use super::super::super::wire::InterceptionMessage;
+
+    fn event(id: u64) -> InterceptionEvent {
+        InterceptionEvent::from(&InterceptionMessage {
+            id: id,
+            guard_id: 0x10,
+            address: 0x1000 + id,
+            access: Access::WRITE.bits(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_subscription_back_pressure() {
+        let decision = |event: &InterceptionEvent| match event.id % 2 {
+            0 => Response::new(None, Action::CONTINUE),
+            _ => Response::new(None, Action::BLOCK),
+        };
+
+        let (callback, subscription) = Subscription::channel(2, Box::new(decision));
+
+        let actions = (0..4).map(|id| callback(event(id)).action()).collect::<Vec<Action>>();
+
+        // decisions don't depend on the queue
+        assert_eq!(actions, vec![Action::CONTINUE, Action::BLOCK, Action::CONTINUE, Action::BLOCK]);
+        assert_eq!((subscription.delivered(), subscription.dropped()), (2, 2));
+
+        let events = subscription.drain();
+        assert_eq!(events.iter().map(|event| event.id).collect::<Vec<u64>>(), vec![0, 1]);
+        assert!(subscription.try_recv().is_none());
+
+        callback(event(4));
+        drop(callback);
+
+        // the queued event outlives the callback, then the stream ends
+        assert_eq!(subscription.iter().map(|event| event.address).collect::<Vec<u64>>(), vec![0x1004]);
+        assert!(subscription.recv().is_none());
+    }
+
+    #[test]
+    fn test_empty_subscriptions_still_queue() {
+        let (callback, subscription) = Subscription::channel(0, Box::new(|_: &InterceptionEvent| Response::empty()));
+
+        callback(event(0));
+        callback(event(1));
+
+        assert_eq!(subscription.capacity(), 1);
+        assert_eq!((subscription.delivered(), subscription.dropped()), (1, 1));
+        assert_eq!(subscription.try_recv().map(|event| event.id), Some(0));
+    }
+
+    #[test]
+    fn test_subscriptions_end_with_guards() {
+        let simulator = Arc::new(Simulator::new());
+        let session = SentrySession::from(simulator.clone() as Arc<dyn Transport>);
+
+        let partition = Partition::with_session(&session).unwrap();
+
+        let subscription = {
+            let guard = Guard::builder(&partition)
+                             .process(None)
+                             .add(Region::new(&partition, 0x1000, 0x100, None, Access::WRITE).unwrap())
+                             .build()
+                             .unwrap();
+
+            let subscription = guard.subscribe(8, |_| Response::empty());
+            assert!(partition.tunnel.callbacks.read().unwrap().contains_key(&guard.id()));
+
+            subscription
+        };
+
+        assert!(partition.tunnel.callbacks.read().unwrap().is_empty());
+        assert!(subscription.recv().is_none());
+
+        let subscription = partition.subscribe(8, |_| Response::empty());
+        assert!(partition.tunnel.fallback.read().unwrap().is_some());
+
+        partition.unsubscribe();
+        assert_eq!(subscription.recv_timeout(Duration::from_millis(10)), Err(RecvTimeoutError::Disconnected));
+    }
+}
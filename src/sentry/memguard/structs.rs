@@ -1,13 +1,14 @@
 // Copyright © ByteHeed.  All rights reserved.
 #![allow(non_camel_case_types, non_snake_case, dead_code)]
 
-use super::winapi::shared::minwindef::{LPVOID, ULONG, USHORT, PULONG};
-use super::winapi::shared::ntdef::{BOOLEAN, HANDLE};
+use super::super::structs::{LPVOID, ULONG, USHORT, HANDLE};
 
 use std::mem;
 
 type ULONG64 = u64;
 type SIZE_T = usize;
+type PULONG = *mut ULONG;
+type BOOLEAN = u8;
 
 pub trait RawStruct<T> {
     fn init() -> T {
@@ -28,14 +29,14 @@ pub trait RawStruct<T> {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C)]
 pub enum FieldKey {
     PROCESS_ID,
     SID
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C)]
 pub enum MatchType {
     EQUAL,
@@ -46,7 +47,7 @@ pub enum MatchType {
     NOT_EQUAL
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C)]
 pub enum ValueType {
     EMPTY,
@@ -0,0 +1,416 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::GuardState;
+use super::error::MemguardError;
+use super::super::failure::Error;
+use super::super::session::SentrySession;
+use super::super::{io, misc};
+#[cfg(windows)]
+use super::winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+#[cfg(windows)]
+use super::winapi::um::consoleapi::SetConsoleCtrlHandler;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+#[cfg(windows)]
+use std::sync::Once;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::fmt;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// how long the console handler keeps the process alive for owners to drop their guards,
+// closing the console leaves about 5 seconds before Windows ends it anyway
+#[cfg(windows)]
+const TEARDOWN_LIMIT: Duration = Duration::from_secs(4);
+
+#[cfg(windows)]
+static HANDLER: Once = Once::new();
+// console interrupts so far, supervisions only react to those after they started
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+// live guards, see `Hold`
+static HOLDERS: AtomicUsize = AtomicUsize::new(0);
+
+// Ctrl+C, Ctrl+Break or the console closing: supervisors only stop the guards they watch,
+// the handler then waits for owners to drop their guards, which deletes their sentinels
+// and unregisters them, before letting the default handler end the process
+#[cfg(windows)]
+unsafe extern "system" fn console_handler(_event: DWORD) -> BOOL {
+    INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+
+    let interrupted = Instant::now();
+
+    while HOLDERS.load(Ordering::SeqCst) > 0 && interrupted.elapsed() < TEARDOWN_LIMIT {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    FALSE
+}
+
+/// Console interrupts received so far, to be handed to `interrupted_since`.
+pub fn interrupts() -> usize {
+    INTERRUPTS.load(Ordering::SeqCst)
+}
+
+/// Whether the console was interrupted after `interrupts` were counted.
+///
+/// Owners of guards that don't wait on a `Supervision` poll it to tear them down in
+/// time, the console handler keeps the process alive until then.
+pub fn interrupted_since(interrupts: usize) -> bool {
+    INTERRUPTS.load(Ordering::SeqCst) != interrupts
+}
+
+/// Counts a live guard, the console handler waits for every one to be released.
+pub struct Hold(());
+
+impl Hold {
+    pub fn new() -> Hold {
+        #[cfg(windows)]
+        HANDLER.call_once(|| unsafe {
+            SetConsoleCtrlHandler(Some(console_handler), TRUE);
+        });
+
+        HOLDERS.fetch_add(1, Ordering::SeqCst);
+        Hold(())
+    }
+}
+
+impl Drop for Hold {
+    fn drop(&mut self) {
+        HOLDERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// When a supervised guard is stopped.
+pub enum Expiry {
+    After(Duration),
+    // the process, usually the one the guard filters on, exits
+    ProcessExit(u64),
+    When(Box<dyn Fn() -> bool + Send>),
+}
+
+impl Expiry {
+    pub fn when<F>(condition: F) -> Expiry where F: Fn() -> bool + Send + 'static {
+        Expiry::When(Box::new(condition))
+    }
+}
+
+impl fmt::Debug for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expiry::After(duration) => write!(f, "After({:?})", duration),
+            Expiry::ProcessExit(pid) => write!(f, "ProcessExit({})", pid),
+            Expiry::When(_) => write!(f, "When(..)"),
+        }
+    }
+}
+
+/// Why a supervision ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expired {
+    Elapsed,
+    ProcessExited,
+    Condition,
+    Interrupted,
+    // stopped or dropped by its owner first
+    Released,
+}
+
+// why the supervision ended and, when the guard couldn't be stopped, the driver error
+type Outcome = Arc<(Mutex<Option<(Expired, Option<String>)>>, Condvar)>;
+
+// console interrupts received so far, or any other count supervisions end on
+type Interrupts = Arc<dyn Fn() -> usize + Send + Sync>;
+
+struct Watch {
+    guard: u64,
+    state: Arc<Mutex<GuardState>>,
+    expiry: Expiry,
+    deadline: Instant,
+    // interrupts counted when the watch started
+    interrupts: usize,
+    outcome: Outcome,
+}
+
+impl Watch {
+    fn expired(&self, session: &SentrySession, interrupts: &Interrupts) -> Option<Expired> {
+        if interrupts() != self.interrupts {
+            return Some(Expired::Interrupted)
+        }
+
+        match self.expiry {
+            Expiry::After(_) if Instant::now() >= self.deadline => Some(Expired::Elapsed),
+            Expiry::ProcessExit(pid) => {
+                // a failed walk isn't taken as the process exiting
+                let running = misc::WalkProcess::new(session).and_then(|processes| processes.find_by(|process| Ok(process.id()? == pid)));
+
+                match running {
+                    Ok(None) => Some(Expired::ProcessExited),
+                    _ => None,
+                }
+            },
+            Expiry::When(ref condition) if condition() => Some(Expired::Condition),
+            _ => None,
+        }
+    }
+
+    fn finish(&self, expired: Expired, fault: Option<String>) {
+        let &(ref outcome, ref changed) = &*self.outcome;
+
+        *outcome.lock().expect("supervision lock poisoned") = Some((expired, fault));
+        changed.notify_all();
+    }
+
+    // true once the watch is over, the guard is only stopped: its owner tears it down
+    fn poll(&self, session: &SentrySession, interrupts: &Interrupts) -> bool {
+        let mut state = self.state.lock().expect("guard state lock poisoned");
+
+        if *state != GuardState::Armed {
+            self.finish(Expired::Released, None);
+            return true
+        }
+
+        match self.expired(session, interrupts) {
+            Some(expired) => {
+                let fault = match io::stop_guard(session, self.guard) {
+                    Ok(()) => {
+                        *state = GuardState::Stopped;
+                        None
+                    },
+                    Err(err) => {
+                        *state = GuardState::Faulted;
+                        Some(err.to_string())
+                    },
+                };
+
+                self.finish(expired, fault);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/// Stops the guards of a partition once their expiry is reached, see `Guard::arm_for`.
+///
+/// The worker thread is started with the first supervised guard. The supervisor only
+/// stops guards: they stay registered with their regions and patches until their owner
+/// drops them, e.g. once `Supervision::wait` returns.
+pub struct Supervisor {
+    session: SentrySession,
+    interrupts: Interrupts,
+    watches: Arc<Mutex<Vec<Watch>>>,
+    running: Arc<AtomicBool>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Supervisor {
+    // supervisions also end on console interrupts
+    pub fn new(session: &SentrySession) -> Supervisor {
+        Supervisor::with_interrupts(session, interrupts)
+    }
+
+    /// Ends supervisions whenever the count `interrupts` returns changes instead of
+    /// on console interrupts.
+    pub fn with_interrupts<F>(session: &SentrySession, interrupts: F) -> Supervisor
+        where F: Fn() -> usize + Send + Sync + 'static {
+        Supervisor {
+            session: session.clone(),
+            interrupts: Arc::new(interrupts),
+            watches: Arc::new(Mutex::new(vec![])),
+            running: Arc::new(AtomicBool::new(true)),
+            worker: Mutex::new(None),
+        }
+    }
+
+    fn spawn(&self) -> JoinHandle<()> {
+        let session = self.session.clone();
+        let interrupts = Arc::clone(&self.interrupts);
+        let watches = Arc::clone(&self.watches);
+        let running = Arc::clone(&self.running);
+
+        thread::spawn(move || {
+            while running.load(Ordering::SeqCst) {
+                watches.lock()
+                       .expect("supervisor lock poisoned")
+                       .retain(|watch| !watch.poll(&session, &interrupts));
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        })
+    }
+
+    // `guard` must be armed already, `state` being the one it shares with its `Guard`
+    pub fn watch(&self, guard: u64, state: Arc<Mutex<GuardState>>, expiry: Expiry) -> Supervision {
+        let outcome: Outcome = Arc::new((Mutex::new(None), Condvar::new()));
+
+        let deadline = match expiry {
+            Expiry::After(duration) => Instant::now() + duration,
+            _ => Instant::now(),
+        };
+
+        self.watches.lock().expect("supervisor lock poisoned").push(Watch {
+            guard: guard,
+            state: state,
+            expiry: expiry,
+            deadline: deadline,
+            interrupts: (self.interrupts)(),
+            outcome: Arc::clone(&outcome),
+        });
+
+        let mut worker = self.worker.lock().expect("supervisor lock poisoned");
+
+        if worker.is_none() {
+            *worker = Some(self.spawn());
+        }
+
+        Supervision {
+            outcome: outcome,
+        }
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        if let Some(worker) = self.worker.lock().expect("supervisor lock poisoned").take() {
+            worker.join().expect("unable to join the supervisor");
+        }
+
+        // nothing stops these guards anymore, their waiters shouldn't wait for it
+        for watch in self.watches.lock().expect("supervisor lock poisoned").drain(..) {
+            watch.finish(Expired::Released, None);
+        }
+    }
+}
+
+/// Follows a supervised guard until it expires.
+pub struct Supervision {
+    outcome: Outcome,
+}
+
+impl Supervision {
+    // how the supervision ended, `None` while the guard is still armed
+    pub fn outcome(&self) -> Option<Expired> {
+        self.outcome.0.lock().expect("supervision lock poisoned").as_ref().map(|&(expired, _)| expired)
+    }
+
+    /// Blocks until the guard expires, it has been stopped by then, or until its
+    /// partition is dropped.
+    ///
+    /// Fails when the driver refused to stop the expired guard, which is left faulted.
+    pub fn wait(&self) -> Result<Expired, Error> {
+        let &(ref outcome, ref changed) = &*self.outcome;
+
+        let mut current = outcome.lock().expect("supervision lock poisoned");
+
+        while current.is_none() {
+            current = changed.wait(current).expect("supervision lock poisoned");
+        }
+
+        match *current {
+            Some((expired, None)) => Ok(expired),
+            Some((expired, Some(ref fault))) => Err(MemguardError::Unstopped(expired, fault.clone()).into()),
+            None => unreachable!("supervision outcome is set"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Access, Guard, Partition, Region};
+    use super::super::super::simulator::Simulator;
+    use super::super::super::iochannel::Transport;
+
+    #[test]
+    fn test_supervised_guards_expire() {
+        let simulator = Arc::new(Simulator::new());
+        let session = SentrySession::from(simulator.clone() as Arc<dyn Transport>);
+
+        // interrupts of this partition only, the console ones are left alone
+        let counter = Arc::new(AtomicUsize::new(0));
+        let count = Arc::clone(&counter);
+
+        let mut partition = Partition::with_session(&session).unwrap();
+        partition.supervisor = Supervisor::with_interrupts(&session, move || count.load(Ordering::SeqCst));
+
+        let guard = Guard::builder(&partition)
+                         .process(None)
+                         .add(Region::new(&partition, 0x1000, 0x100, None, Access::WRITE).unwrap())
+                         .build()
+                         .unwrap();
+
+        let supervision = guard.arm_for(Duration::from_millis(200)).unwrap();
+
+        assert_eq!(simulator.is_started(guard.id()), Some(true));
+        assert_eq!(supervision.outcome(), None);
+
+        assert_eq!(supervision.wait().unwrap(), Expired::Elapsed);
+        assert_eq!(guard.state(), GuardState::Stopped);
+        assert_eq!(simulator.is_started(guard.id()), Some(false));
+
+        // rearmed until a condition holds
+        let done = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&done);
+
+        let supervision = guard.arm_until(Expiry::when(move || flag.load(Ordering::SeqCst))).unwrap();
+        done.store(true, Ordering::SeqCst);
+
+        assert_eq!(supervision.wait().unwrap(), Expired::Condition);
+        assert_eq!(simulator.is_started(guard.id()), Some(false));
+
+        // owners stopping a guard end its supervision
+        let supervision = guard.arm_for(Duration::from_secs(60)).unwrap();
+        guard.stop().unwrap();
+
+        assert_eq!(supervision.wait().unwrap(), Expired::Released);
+
+        // only interrupts after the supervision started end it
+        let supervision = guard.arm_for(Duration::from_secs(60)).unwrap();
+        counter.fetch_add(1, Ordering::SeqCst);
+
+        assert_eq!(supervision.wait().unwrap(), Expired::Interrupted);
+        assert_eq!(simulator.is_started(guard.id()), Some(false));
+
+        let supervision = guard.arm_for(Duration::from_millis(200)).unwrap();
+        assert_eq!(supervision.wait().unwrap(), Expired::Elapsed);
+
+        // nor by dropping the guard, which tears it down
+        let supervision = guard.arm_for(Duration::from_secs(60)).unwrap();
+        let id = guard.id();
+        drop(guard);
+
+        assert_eq!(supervision.wait().unwrap(), Expired::Released);
+        assert_eq!(simulator.is_started(id), None);
+    }
+
+    #[test]
+    fn test_supervisions_end_with_their_supervisor() {
+        let session = SentrySession::new(Simulator::new());
+        let supervisor = Supervisor::with_interrupts(&session, || 0);
+
+        let state = Arc::new(Mutex::new(GuardState::Armed));
+        let supervision = supervisor.watch(1, Arc::clone(&state), Expiry::After(Duration::from_secs(60)));
+
+        drop(supervisor);
+
+        assert_eq!(supervision.wait().unwrap(), Expired::Released);
+        assert_eq!(*state.lock().unwrap(), GuardState::Armed);
+    }
+
+    #[test]
+    fn test_unstoppable_guards_fault() {
+        let session = SentrySession::new(Simulator::new());
+        let supervisor = Supervisor::with_interrupts(&session, || 0);
+
+        // the simulator knows no guard 1
+        let state = Arc::new(Mutex::new(GuardState::Armed));
+        let supervision = supervisor.watch(1, Arc::clone(&state), Expiry::After(Duration::from_millis(0)));
+
+        assert!(supervision.wait().is_err());
+        assert_eq!(supervision.outcome(), Some(Expired::Elapsed));
+        assert_eq!(*state.lock().unwrap(), GuardState::Faulted);
+    }
+}
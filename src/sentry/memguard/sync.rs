@@ -1,115 +1,170 @@
-extern crate winapi;
+#[cfg(windows)]
+pub use self::windows::Event;
+#[cfg(not(windows))]
+pub use self::portable::Event;
 
-use std::io::Error;
-use std::ptr::{null_mut, null};
-use std::ops::Deref;
+#[cfg(windows)]
+mod windows {
+    extern crate winapi;
 
-use self::winapi::um::synchapi;
+    use std::io::Error;
+    use std::ptr::{null_mut, null};
+    use std::ops::Deref;
 
-use self::winapi::um::winbase;
-use self::winapi::um::winnt;
-use self::winapi::shared::minwindef;
+    use self::winapi::um::synchapi;
 
+    use self::winapi::um::winbase;
+    use self::winapi::um::winnt;
+    use self::winapi::shared::minwindef;
 
-#[derive(Debug)]
-pub struct Event(winnt::HANDLE);
 
-impl Event {
+    #[derive(Debug)]
+    pub struct Event(winnt::HANDLE);
 
-    #[allow(dead_code)]
-    pub fn create() -> winnt::HANDLE {
-        let (manual, init) = (false, false);
+    impl Event {
 
-        unsafe {
-            synchapi::CreateEventW(null_mut(),
-                        manual as minwindef::BOOL,
-                        init as minwindef::BOOL,
-                        null())
+        #[allow(dead_code)]
+        pub fn create() -> winnt::HANDLE {
+            let (manual, init) = (false, false);
+
+            unsafe {
+                synchapi::CreateEventW(null_mut(),
+                            manual as minwindef::BOOL,
+                            init as minwindef::BOOL,
+                            null())
+            }
         }
-    }
 
-    #[allow(dead_code)]
-    pub fn new() -> Event {
-        Event(Event::create())
-    }
+        #[allow(dead_code)]
+        pub fn new() -> Event {
+            Event(Event::create())
+        }
 
-    #[allow(dead_code)]
-    pub fn as_u64(&self) -> u64 {
-        self.0 as u64
-    }
+        // wraps a handle shared by the driver
+        pub fn from_u64(handle: u64) -> Event {
+            Event(handle as winnt::HANDLE)
+        }
 
-    #[allow(dead_code)]
-    pub fn reset(&self) -> &Self {
-        if unsafe { synchapi::ResetEvent(self.0) } == 0 {
-            panic!("Failed to wait for the event: {}", 
-                    Error::last_os_error());
+        #[allow(dead_code)]
+        pub fn as_u64(&self) -> u64 {
+            self.0 as u64
         }
 
-        self
-    }
+        #[allow(dead_code)]
+        pub fn reset(&self) -> &Self {
+            if unsafe { synchapi::ResetEvent(self.0) } == 0 {
+                panic!("Failed to wait for the event: {}", 
+                        Error::last_os_error());
+            }
+
+            self
+        }
 
-    pub fn signal(&self) -> &Self {
+        pub fn signal(&self) -> &Self {
 
-        if unsafe { synchapi::SetEvent(self.0) } == 0 {
-            panic!("Failed to signal event: {}", 
-                    Error::last_os_error());
+            if unsafe { synchapi::SetEvent(self.0) } == 0 {
+                panic!("Failed to signal event: {}", 
+                        Error::last_os_error());
+            }
+
+            self
+        }
+
+        pub fn wait(&self) {
+            let rc = unsafe { synchapi::WaitForSingleObject(self.0, winbase::INFINITE) };
+            if rc == winbase::WAIT_FAILED {
+                panic!("Failed to wait for the event: {}", 
+                        Error::last_os_error());
+            }
         }
 
-        self
     }
 
-    pub fn wait(&self) {
-        let rc = unsafe { synchapi::WaitForSingleObject(self.0, winbase::INFINITE) };
-        if rc == winbase::WAIT_FAILED {
-            panic!("Failed to wait for the event: {}", 
-                    Error::last_os_error());
+    impl Into<u64> for Event {
+        fn into(self) -> u64 {
+            self.0 as u64
         }
     }
 
-}
-
-impl Into<u64> for Event {
-    fn into(self) -> u64 {
-        self.0 as u64
+    impl From<u64> for Event {
+        fn from(handle: u64) -> Self {
+            Event(handle as winnt::HANDLE)
+        }
     }
-}
 
-impl From<u64> for Event {
-    fn from(handle: u64) -> Self {
-        Event(handle as winnt::HANDLE)
+    impl From<winnt::HANDLE> for Event {
+        fn from(handle: winnt::HANDLE) -> Self {
+            Event(handle)
+        }
     }
-}
 
-impl From<winnt::HANDLE> for Event {
-    fn from(handle: winnt::HANDLE) -> Self {
-        Event(handle)
+    //
+    // dropping is disabled due to ownership of event
+    //
+    // impl Drop for Event {
+    //     fn drop(&mut self) {
+    //         println!("droping event {:?}", self.0);
+
+    //         unsafe {
+    //             CloseHandle(self.0);
+    //         }
+    //     }
+    // }
+
+    impl Deref for Event {
+        type Target = winnt::HANDLE;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
     }
+
+    // impl Deref for Event {
+
+    //     fn deref(&self) -> HANDLE {
+    //         self.0
+    //     }
+    // }
 }
 
 //
-// dropping is disabled due to ownership of event
+// Buckets are only shared by the driver, off Windows there's no handle to wait on: the
+// event keeps the value it was given, and using it is a bug.
 //
-// impl Drop for Event {
-//     fn drop(&mut self) {
-//         println!("droping event {:?}", self.0);
+#[cfg(not(windows))]
+mod portable {
+    #[derive(Debug)]
+    pub struct Event(u64);
+
+    impl Event {
+        // wraps a handle shared by the driver
+        pub fn from_u64(handle: u64) -> Event {
+            Event(handle)
+        }
 
-//         unsafe {
-//             CloseHandle(self.0);
-//         }
-//     }
-// }
+        #[allow(dead_code)]
+        pub fn as_u64(&self) -> u64 {
+            self.0
+        }
 
-impl Deref for Event {
-    type Target = winnt::HANDLE;
+        pub fn signal(&self) -> &Self {
+            panic!("Failed to signal event {}: kernel events are only available on Windows", self.0);
+        }
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+        pub fn wait(&self) {
+            panic!("Failed to wait for the event {}: kernel events are only available on Windows", self.0);
+        }
     }
-}
 
-// impl Deref for Event {
+    impl Into<u64> for Event {
+        fn into(self) -> u64 {
+            self.0
+        }
+    }
 
-//     fn deref(&self) -> HANDLE {
-//         self.0
-//     }
-// }
+    impl From<u64> for Event {
+        fn from(handle: u64) -> Self {
+            Event(handle)
+        }
+    }
+}
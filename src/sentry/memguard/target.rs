@@ -0,0 +1,243 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::error::MemguardError;
+use super::super::session::SentrySession;
+use super::super::failure::Error;
+use super::super::misc;
+
+use std::str::FromStr;
+use std::fmt;
+
+const KERNEL_NAMES: &[&str] = &["nt", "ntoskrnl", "ntoskrnl.exe"];
+
+/// Selects the instance of a kernel structure a `Target::Field` reads from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instance {
+    // the _EPROCESS of a running process
+    Pid(u64),
+    Address(u64),
+}
+
+/// A kernel address written symbolically, resolved against the running kernel.
+///
+///   * `0xfffff80012345678`
+///   * `nt!KiServiceTable`, `ntoskrnl!PsInitialSystemProcess+0x10`: exports of a loaded
+///     module, kernel symbols that aren't exported are looked up in its PDB
+///   * `driver:HEVD.sys+0x5BEC`: an offset from the base of a loaded driver
+///   * `_EPROCESS(pid=1234).Token`, `_KTHREAD(0xffff...).Teb`: a field of a kernel structure
+///
+/// Every form but plain addresses accepts a `+offset` suffix.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Address(u64),
+    Symbol { module: String, name: String, offset: u64 },
+    Driver { name: String, offset: u64 },
+    Field { structure: String, instance: Instance, field: String, offset: u64 },
+}
+
+fn parse_number(value: &str) -> Option<u64> {
+    let value = value.trim();
+
+    match value.starts_with("0x") || value.starts_with("0X") {
+        true => u64::from_str_radix(&value[2..], 16).ok(),
+        false => value.parse::<u64>().ok(),
+    }
+}
+
+fn is_kernel(module: &str) -> bool {
+    KERNEL_NAMES.iter().any(|kernel| kernel.eq_ignore_ascii_case(module))
+}
+
+// base of the kernel or of the first loaded driver whose name contains `name`
+pub fn module_base(name: &str) -> Result<Option<u64>, Error> {
+    if is_kernel(name) {
+        return misc::get_kernel_base().map(Some)
+    }
+
+    let wanted = name.to_lowercase();
+
+    Ok(misc::Drivers::iter()?
+        .find(|driver| driver.name.to_lowercase().contains(&wanted))
+        .map(|driver| driver.base))
+}
+
+impl Target {
+    fn unresolved(&self, reason: String) -> Error {
+        MemguardError::Unresolved(self.to_string(), reason).into()
+    }
+
+    fn base(&self, module: &str) -> Result<u64, Error> {
+        module_base(module)?.ok_or_else(|| self.unresolved(format!("no loaded driver matches {:?}", module)))
+    }
+
+    pub fn resolve(&self, session: &SentrySession) -> Result<u64, Error> {
+        match *self {
+            Target::Address(address) => Ok(address),
+            Target::Symbol { ref module, ref name, offset } => {
+                let base = self.base(module)?;
+
+                match misc::kernel_export_address(session, base, name)? {
+                    0 if is_kernel(module) => {
+                        let rva = misc::get_symbol_rva(name)
+                                      .map_err(|err| self.unresolved(format!("not exported, {}", err)))?;
+
+                        Ok(base + rva + offset)
+                    },
+                    0 => Err(self.unresolved(format!("{} doesn't export it", module))),
+                    address => Ok(address + offset),
+                }
+            },
+            Target::Driver { ref name, offset } => Ok(self.base(name)? + offset),
+            Target::Field { ref structure, ref instance, ref field, offset } => {
+                let object = match *instance {
+                    Instance::Address(address) => address,
+                    Instance::Pid(pid) => misc::WalkProcess::new(session)?
+                                              .find_by(|process| Ok(process.id()? == pid))?
+                                              .map(|process| process.object())
+                                              .ok_or_else(|| self.unresolved(format!("process {} isn't running", pid)))?,
+                };
+
+                let field_offset = misc::get_offset(&format!("{}.{}", structure, field))
+                                       .map_err(|err| self.unresolved(err.to_string()))?;
+
+                Ok(object + u64::from(field_offset) + offset)
+            },
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = MemguardError;
+
+    fn from_str(value: &str) -> Result<Target, MemguardError> {
+        let expression = value.trim();
+        let invalid = |reason: &str| MemguardError::Target(expression.to_string(), reason.to_string());
+
+        if let Some(address) = parse_number(expression) {
+            return Ok(Target::Address(address))
+        }
+
+        let (body, offset) = match expression.rfind('+') {
+            Some(plus) => (&expression[..plus], parse_number(&expression[plus + 1..]).ok_or_else(|| invalid("the offset isn't a number"))?),
+            None => (expression, 0),
+        };
+
+        if body.starts_with("driver:") {
+            return match &body["driver:".len()..] {
+                "" => Err(invalid("the driver name is missing")),
+                name => Ok(Target::Driver { name: name.to_string(), offset: offset }),
+            }
+        }
+
+        if let Some(bang) = body.find('!') {
+            let (module, name) = (&body[..bang], &body[bang + 1..]);
+
+            if module.is_empty() || name.is_empty() {
+                return Err(invalid("symbols are written as module!name"))
+            }
+
+            return Ok(Target::Symbol { module: module.to_string(), name: name.to_string(), offset: offset })
+        }
+
+        if let (Some(open), Some(close)) = (body.find('('), body.rfind(").")) {
+            if close < open {
+                return Err(invalid("fields are written as _STRUCTURE(instance).Field"))
+            }
+
+            let (structure, selector, field) = (&body[..open], &body[open + 1..close], &body[close + 2..]);
+
+            if structure.is_empty() || field.is_empty() {
+                return Err(invalid("fields are written as _STRUCTURE(instance).Field"))
+            }
+
+            let instance = match selector.trim().starts_with("pid=") {
+                true if structure != "_EPROCESS" => return Err(invalid("pid= only selects an _EPROCESS")),
+                true => Instance::Pid(parse_number(&selector.trim()["pid=".len()..]).ok_or_else(|| invalid("the pid isn't a number"))?),
+                false => Instance::Address(parse_number(selector).ok_or_else(|| invalid("the instance is either pid=N or an address"))?),
+            };
+
+            return Ok(Target::Field {
+                structure: structure.to_string(),
+                instance: instance,
+                field: field.to_string(),
+                offset: offset,
+            })
+        }
+
+        Err(invalid("expected an address, module!symbol, driver:NAME or _STRUCTURE(instance).Field"))
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instance::Pid(pid) => write!(f, "pid={}", pid),
+            Instance::Address(address) => write!(f, "0x{:016x}", address),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let offset = match *self {
+            Target::Address(address) => return write!(f, "0x{:016x}", address),
+            Target::Symbol { ref module, ref name, offset } => {
+                write!(f, "{}!{}", module, name)?;
+                offset
+            },
+            Target::Driver { ref name, offset } => {
+                write!(f, "driver:{}", name)?;
+                offset
+            },
+            Target::Field { ref structure, ref instance, ref field, offset } => {
+                write!(f, "{}({}).{}", structure, instance, field)?;
+                offset
+            },
+        };
+
+        match offset {
+            0 => Ok(()),
+            offset => write!(f, "+0x{:x}", offset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_expressions() {
+        let expressions = [
+            ("0xfffff80012345678", Target::Address(0xfffff80012345678)),
+            ("ntoskrnl!KiServiceTable", Target::Symbol { module: "ntoskrnl".to_string(), name: "KiServiceTable".to_string(), offset: 0 }),
+            ("nt!PsInitialSystemProcess+0x10", Target::Symbol { module: "nt".to_string(), name: "PsInitialSystemProcess".to_string(), offset: 0x10 }),
+            ("driver:HEVD.sys+0x5bec", Target::Driver { name: "HEVD.sys".to_string(), offset: 0x5BEC }),
+            ("_EPROCESS(pid=1234).Token", Target::Field {
+                structure: "_EPROCESS".to_string(),
+                instance: Instance::Pid(1234),
+                field: "Token".to_string(),
+                offset: 0,
+            }),
+            ("_KTHREAD(0xffff800000001000).Teb+0x8", Target::Field {
+                structure: "_KTHREAD".to_string(),
+                instance: Instance::Address(0xffff800000001000),
+                field: "Teb".to_string(),
+                offset: 8,
+            }),
+        ];
+
+        for &(expression, ref target) in expressions.iter() {
+            assert_eq!(expression.parse::<Target>().unwrap(), *target);
+            assert_eq!(target.to_string().parse::<Target>().unwrap(), *target);
+        }
+
+        assert_eq!(expressions[3].1.to_string(), "driver:HEVD.sys+0x5bec");
+
+        assert!("nt!".parse::<Target>().is_err());
+        assert!("driver:".parse::<Target>().is_err());
+        assert!("nt!Foo+bar".parse::<Target>().is_err());
+        assert!("_KTHREAD(pid=4).Teb".parse::<Target>().is_err());
+        assert!("KiServiceTable".parse::<Target>().is_err());
+    }
+}
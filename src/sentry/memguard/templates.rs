@@ -0,0 +1,394 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::{Range, Target};
+use super::error::MemguardError;
+use super::byteorder::{ByteOrder, LittleEndian};
+use super::super::session::SentrySession;
+use super::super::failure::Error;
+use super::super::error::MiscError;
+use super::super::{memory, misc};
+
+use std::str::FromStr;
+use std::fmt;
+
+// the low bits of an EX_FAST_REF hold a reference count
+const FAST_REF_MASK: u64 = !0xF;
+// _SEP_TOKEN_PRIVILEGES: Present, Enabled and EnabledByDefault
+const TOKEN_PRIVILEGES_SIZE: u64 = 0x18;
+const SSDT_ENTRY_SIZE: u64 = 4;
+// 256 vectors of 16 bytes
+const IDT_SIZE: u64 = 0x1000;
+// up to KGDT64_LAST
+const GDT_SIZE: u64 = 0x70;
+// PSP_MAX_CREATE_PROCESS_NOTIFY and its thread and image load counterparts
+const CALLBACK_SLOTS: u64 = 64;
+const PE_HEADERS_SIZE: usize = 0x1000;
+const PE_SECTION_SIZE: usize = 40;
+
+/// Where templates find kernel offsets, symbols and memory, see `LiveKernel`.
+pub trait KernelLayout {
+    // offset of a structure field, e.g. `_EPROCESS.Token`
+    fn offset(&self, field: &str) -> Result<u64, Error>;
+    // address of a kernel symbol, exported or not
+    fn symbol(&self, name: &str) -> Result<u64, Error>;
+    // _EPROCESS of a running process
+    fn process(&self, pid: u64) -> Result<u64, Error>;
+    fn driver_base(&self, name: &str) -> Result<u64, Error>;
+    fn read(&self, address: u64, size: usize) -> Result<Vec<u8>, Error>;
+
+    fn read_u64(&self, address: u64) -> Result<u64, Error> {
+        Ok(LittleEndian::read_u64(&self.read(address, 8)?))
+    }
+
+    fn read_u32(&self, address: u64) -> Result<u32, Error> {
+        Ok(LittleEndian::read_u32(&self.read(address, 4)?))
+    }
+}
+
+/// The running kernel, through the PDB of ntoskrnl and the driver.
+pub struct LiveKernel<'s> {
+    session: &'s SentrySession,
+}
+
+impl<'s> LiveKernel<'s> {
+    pub fn new(session: &'s SentrySession) -> LiveKernel<'s> {
+        LiveKernel {
+            session: session,
+        }
+    }
+}
+
+impl<'s> KernelLayout for LiveKernel<'s> {
+    fn offset(&self, field: &str) -> Result<u64, Error> {
+        Ok(u64::from(misc::get_offset(field)?))
+    }
+
+    fn symbol(&self, name: &str) -> Result<u64, Error> {
+        let target = Target::Symbol { module: String::from("nt"), name: name.to_string(), offset: 0 };
+
+        target.resolve(self.session)
+    }
+
+    fn process(&self, pid: u64) -> Result<u64, Error> {
+        misc::WalkProcess::new(self.session)?
+            .find_by(|process| Ok(process.id()? == pid))?
+            .map(|process| process.object())
+            .ok_or_else(|| process_not_found(pid))
+    }
+
+    fn driver_base(&self, name: &str) -> Result<u64, Error> {
+        super::target::module_base(name)?.ok_or_else(|| MemguardError::Unresolved(name.to_string(), String::from("no loaded driver matches")).into())
+    }
+
+    fn read(&self, address: u64, size: usize) -> Result<Vec<u8>, Error> {
+        memory::read_virtual_memory(self.session, address, size)
+    }
+}
+
+fn process_not_found(pid: u64) -> Error {
+    MiscError::ProcessNotFound(pid).into()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallbackKind {
+    Process,
+    Thread,
+    Image,
+}
+
+impl CallbackKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CallbackKind::Process => "process",
+            CallbackKind::Thread => "thread",
+            CallbackKind::Image => "image",
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match *self {
+            CallbackKind::Process => "PspCreateProcessNotifyRoutine",
+            CallbackKind::Thread => "PspCreateThreadNotifyRoutine",
+            CallbackKind::Image => "PspLoadImageNotifyRoutine",
+        }
+    }
+}
+
+/// Named kernel areas worth protecting, resolved on the running kernel.
+///
+/// Written as `token:PID`, `privileges:PID`, `protection:PID`, `ssdt`, `ssdt:INDEX`,
+/// `idt:CPU`, `gdt:CPU`, `text:DRIVER` or `callbacks:process|thread|image`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Template {
+    // the _EPROCESS.Token pointer of a process
+    ProcessToken(u64),
+    // the privileges of the token a process points to
+    TokenPrivileges(u64),
+    // the _EPROCESS.Protection byte, i.e. PPL level and signer
+    ProcessProtection(u64),
+    // every entry of KiServiceTable
+    Ssdt,
+    SsdtEntry(u32),
+    Idt(u32),
+    Gdt(u32),
+    // the .text section of a loaded driver
+    DriverText(String),
+    Callbacks(CallbackKind),
+}
+
+impl Template {
+    fn unresolved(&self, reason: String) -> Error {
+        MemguardError::Unresolved(self.to_string(), reason).into()
+    }
+
+    // _KPCR of a processor, found through the _KPRCB that KiProcessorBlock lists
+    fn pcr(&self, kernel: &dyn KernelLayout, cpu: u32) -> Result<u64, Error> {
+        let prcb = kernel.read_u64(kernel.symbol("KiProcessorBlock")? + u64::from(cpu) * 8)?;
+
+        if prcb == 0 {
+            return Err(self.unresolved(format!("processor {} isn't present", cpu)))
+        }
+
+        Ok(prcb - kernel.offset("_KPCR.Prcb")?)
+    }
+
+    fn ssdt_limit(&self, kernel: &dyn KernelLayout) -> Result<u64, Error> {
+        Ok(u64::from(kernel.read_u32(kernel.symbol("KiServiceLimit")?)?))
+    }
+
+    // .text from the section table of the image headers
+    fn text_section(&self, kernel: &dyn KernelLayout, base: u64) -> Result<Range, Error> {
+        let headers = kernel.read(base, PE_HEADERS_SIZE)?;
+        let invalid = || self.unresolved(String::from("the image headers aren't valid"));
+
+        if headers.len() < 0x40 || &headers[..2] != b"MZ" {
+            return Err(invalid())
+        }
+
+        let nt = LittleEndian::read_u32(&headers[0x3C..]) as usize;
+
+        if nt + 24 > headers.len() || &headers[nt..nt + 4] != b"PE\0\0" {
+            return Err(invalid())
+        }
+
+        let sections = LittleEndian::read_u16(&headers[nt + 6..]) as usize;
+        let optional = LittleEndian::read_u16(&headers[nt + 20..]) as usize;
+        let table = nt + 24 + optional;
+
+        (0..sections).map(|index| table + index * PE_SECTION_SIZE)
+                      .take_while(|&section| section + PE_SECTION_SIZE <= headers.len())
+                      .map(|section| &headers[section..section + PE_SECTION_SIZE])
+                      .find(|section| &section[..8] == b".text\0\0\0")
+                      .map(|section| Range::new(base + u64::from(LittleEndian::read_u32(&section[12..])),
+                                                u64::from(LittleEndian::read_u32(&section[8..]))))
+                      .ok_or_else(|| self.unresolved(String::from("the image has no .text section")))
+    }
+
+    /// Base and size of the area on the kernel described by `kernel`.
+    pub fn resolve(&self, kernel: &dyn KernelLayout) -> Result<Range, Error> {
+        let range = match *self {
+            Template::ProcessToken(pid) => Range::new(kernel.process(pid)? + kernel.offset("_EPROCESS.Token")?, 8),
+            Template::TokenPrivileges(pid) => {
+                let token = kernel.read_u64(kernel.process(pid)? + kernel.offset("_EPROCESS.Token")?)? & FAST_REF_MASK;
+
+                Range::new(token + kernel.offset("_TOKEN.Privileges")?, TOKEN_PRIVILEGES_SIZE)
+            },
+            Template::ProcessProtection(pid) => Range::new(kernel.process(pid)? + kernel.offset("_EPROCESS.Protection")?, 1),
+            Template::Ssdt => Range::new(kernel.symbol("KiServiceTable")?, self.ssdt_limit(kernel)? * SSDT_ENTRY_SIZE),
+            Template::SsdtEntry(index) => {
+                let limit = self.ssdt_limit(kernel)?;
+
+                if u64::from(index) >= limit {
+                    return Err(self.unresolved(format!("the service table holds {} entries", limit)))
+                }
+
+                Range::new(kernel.symbol("KiServiceTable")? + u64::from(index) * SSDT_ENTRY_SIZE, SSDT_ENTRY_SIZE)
+            },
+            Template::Idt(cpu) => Range::new(kernel.read_u64(self.pcr(kernel, cpu)? + kernel.offset("_KPCR.IdtBase")?)?, IDT_SIZE),
+            Template::Gdt(cpu) => Range::new(kernel.read_u64(self.pcr(kernel, cpu)? + kernel.offset("_KPCR.GdtBase")?)?, GDT_SIZE),
+            Template::DriverText(ref name) => self.text_section(kernel, kernel.driver_base(name)?)?,
+            Template::Callbacks(kind) => Range::new(kernel.symbol(kind.symbol())?, CALLBACK_SLOTS * 8),
+        };
+
+        Ok(range)
+    }
+}
+
+impl FromStr for Template {
+    type Err = MemguardError;
+
+    fn from_str(value: &str) -> Result<Template, MemguardError> {
+        let invalid = |reason: &str| MemguardError::Target(value.to_string(), reason.to_string());
+
+        let mut parts = value.trim().splitn(2, ':');
+        let (name, argument) = (parts.next().unwrap_or_default(), parts.next());
+
+        let number = |argument: Option<&str>| -> Result<u64, MemguardError> {
+            let argument = argument.ok_or_else(|| invalid("the template expects a number, e.g. token:4"))?;
+
+            match argument.starts_with("0x") {
+                true => u64::from_str_radix(&argument[2..], 16).ok(),
+                false => argument.parse::<u64>().ok(),
+            }.ok_or_else(|| invalid("the template expects a number, e.g. token:4"))
+        };
+
+        let template = match (name, argument) {
+            ("token", _) => Template::ProcessToken(number(argument)?),
+            ("privileges", _) => Template::TokenPrivileges(number(argument)?),
+            ("protection", _) => Template::ProcessProtection(number(argument)?),
+            ("ssdt", None) => Template::Ssdt,
+            ("ssdt", _) => Template::SsdtEntry(number(argument)? as u32),
+            ("idt", _) => Template::Idt(number(argument)? as u32),
+            ("gdt", _) => Template::Gdt(number(argument)? as u32),
+            ("text", Some(driver)) if !driver.is_empty() => Template::DriverText(driver.to_string()),
+            ("callbacks", Some("process")) => Template::Callbacks(CallbackKind::Process),
+            ("callbacks", Some("thread")) => Template::Callbacks(CallbackKind::Thread),
+            ("callbacks", Some("image")) => Template::Callbacks(CallbackKind::Image),
+            _ => return Err(invalid("expected token, privileges, protection, ssdt, idt, gdt, text or callbacks")),
+        };
+
+        Ok(template)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Template::ProcessToken(pid) => write!(f, "token:{}", pid),
+            Template::TokenPrivileges(pid) => write!(f, "privileges:{}", pid),
+            Template::ProcessProtection(pid) => write!(f, "protection:{}", pid),
+            Template::Ssdt => write!(f, "ssdt"),
+            Template::SsdtEntry(index) => write!(f, "ssdt:{}", index),
+            Template::Idt(cpu) => write!(f, "idt:{}", cpu),
+            Template::Gdt(cpu) => write!(f, "gdt:{}", cpu),
+            Template::DriverText(ref name) => write!(f, "text:{}", name),
+            Template::Callbacks(kind) => write!(f, "callbacks:{}", kind.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::failure::err_msg;
+    use super::super::byteorder::WriteBytesExt;
+
+    use std::collections::HashMap;
+
+    const SYSTEM: u64 = 0xffff_c40f_9e46_b040;
+    const HEVD: u64 = 0xffff_f806_2c1a_0000;
+
+    // offsets and symbols recorded from the ntoskrnl.pdb of Windows 10 1809 x64
+    struct Recorded {
+        offsets: HashMap<&'static str, u64>,
+        symbols: HashMap<&'static str, u64>,
+        memory: HashMap<u64, Vec<u8>>,
+    }
+
+    impl Recorded {
+        fn new() -> Recorded {
+            let offsets = [("_EPROCESS.Token", 0x358), ("_EPROCESS.Protection", 0x6ca), ("_TOKEN.Privileges", 0x40),
+                           ("_KPCR.Prcb", 0x180), ("_KPCR.IdtBase", 0x38), ("_KPCR.GdtBase", 0x0)];
+
+            let symbols = [("KiServiceTable", 0xffff_f801_5a8b_0e10), ("KiServiceLimit", 0xffff_f801_5a8b_1b1c),
+                           ("KiProcessorBlock", 0xffff_f801_5a9a_b300), ("PspCreateProcessNotifyRoutine", 0xffff_f801_5ac0_d4c0)];
+
+            let mut recorded = Recorded {
+                offsets: offsets.iter().cloned().collect(),
+                symbols: symbols.iter().cloned().collect(),
+                memory: HashMap::new(),
+            };
+
+            recorded.write_u64(SYSTEM + 0x358, 0xffff_a70d_3e00_6a7b);
+            recorded.write_u64(0xffff_f801_5a9a_b300, 0xffff_f801_57b6_f180);
+            recorded.write_u64(0xffff_f801_57b6_f000 + 0x38, 0xffff_f801_5b35_e000);
+            recorded.write_u64(0xffff_f801_57b6_f000, 0xffff_f801_5b35_cfb0);
+
+            let mut limit = vec![];
+            limit.write_u32::<LittleEndian>(0x1cf).unwrap();
+            recorded.memory.insert(0xffff_f801_5a8b_1b1c, limit);
+
+            // MZ, e_lfanew 0x80, PE header with two sections, .text second
+            let mut headers = vec![0; PE_HEADERS_SIZE];
+            headers[..2].copy_from_slice(b"MZ");
+            LittleEndian::write_u32(&mut headers[0x3C..], 0x80);
+            headers[0x80..0x84].copy_from_slice(b"PE\0\0");
+            LittleEndian::write_u16(&mut headers[0x86..], 2);
+            LittleEndian::write_u16(&mut headers[0x94..], 0xF0);
+
+            let table = 0x80 + 24 + 0xF0;
+            headers[table..table + 5].copy_from_slice(b"INIT\0");
+            headers[table + 40..table + 45].copy_from_slice(b".text");
+            LittleEndian::write_u32(&mut headers[table + 48..], 0x4D1A);
+            LittleEndian::write_u32(&mut headers[table + 52..], 0x1000);
+            recorded.memory.insert(HEVD, headers);
+
+            recorded
+        }
+
+        fn write_u64(&mut self, address: u64, value: u64) {
+            let mut bytes = vec![];
+            bytes.write_u64::<LittleEndian>(value).unwrap();
+            self.memory.insert(address, bytes);
+        }
+    }
+
+    impl KernelLayout for Recorded {
+        fn offset(&self, field: &str) -> Result<u64, Error> {
+            self.offsets.get(field).cloned().ok_or_else(|| err_msg(format!("no offset recorded for {}", field)))
+        }
+
+        fn symbol(&self, name: &str) -> Result<u64, Error> {
+            self.symbols.get(name).cloned().ok_or_else(|| err_msg(format!("no symbol recorded for {}", name)))
+        }
+
+        fn process(&self, pid: u64) -> Result<u64, Error> {
+            match pid {
+                4 => Ok(SYSTEM),
+                _ => Err(process_not_found(pid)),
+            }
+        }
+
+        fn driver_base(&self, name: &str) -> Result<u64, Error> {
+            match name {
+                "HEVD.sys" => Ok(HEVD),
+                _ => Err(err_msg("driver not loaded")),
+            }
+        }
+
+        fn read(&self, address: u64, size: usize) -> Result<Vec<u8>, Error> {
+            match self.memory.get(&address) {
+                Some(bytes) if bytes.len() >= size => Ok(bytes[..size].to_vec()),
+                _ => Err(err_msg(format!("no memory recorded at 0x{:x}", address))),
+            }
+        }
+    }
+
+    fn resolve(template: &str) -> (u64, u64) {
+        let range = template.parse::<Template>().unwrap().resolve(&Recorded::new()).unwrap();
+
+        (range.base, range.limit)
+    }
+
+    #[test]
+    fn test_templates_against_recorded_symbols() {
+        assert_eq!(resolve("token:4"), (SYSTEM + 0x358, 8));
+        assert_eq!(resolve("privileges:4"), (0xffff_a70d_3e00_6a70 + 0x40, 0x18));
+        assert_eq!(resolve("protection:4"), (SYSTEM + 0x6ca, 1));
+        assert_eq!(resolve("ssdt"), (0xffff_f801_5a8b_0e10, 0x1cf * 4));
+        assert_eq!(resolve("ssdt:0x10"), (0xffff_f801_5a8b_0e50, 4));
+        assert_eq!(resolve("idt:0"), (0xffff_f801_5b35_e000, 0x1000));
+        assert_eq!(resolve("gdt:0"), (0xffff_f801_5b35_cfb0, 0x70));
+        assert_eq!(resolve("text:HEVD.sys"), (HEVD + 0x1000, 0x4D1A));
+        assert_eq!(resolve("callbacks:process"), (0xffff_f801_5ac0_d4c0, 0x200));
+
+        let recorded = Recorded::new();
+        assert!(Template::SsdtEntry(0x1cf).resolve(&recorded).is_err());
+        assert!(Template::Idt(1).resolve(&recorded).is_err());
+        assert!(Template::ProcessToken(1234).resolve(&recorded).is_err());
+
+        assert_eq!("callbacks:image".parse::<Template>().unwrap().to_string(), "callbacks:image");
+        assert!("token".parse::<Template>().is_err());
+        assert!("callbacks:registry".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+//
+// The kernel thread that caught an access waits for the answer of its callback. A
+// watchdog bounds that wait: panics are caught, and callbacks with a deadline run on a
+// worker thread of their own so that the bucket answers without them once it passes.
+// Either way the fail policy decides what happens to the access, and the watchdog
+// counts it.
+//
+// Each watched callback has one worker, started with its first deadline and fed one
+// interception at a time. While it's still busy with a late answer, further
+// interceptions fail right away, counted as busy, instead of piling up threads; a
+// callback that never returns only ever holds that one.
+//
+
+use super::{Action, InterceptionEvent, Response, SyncCallback};
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::panic::{self, AssertUnwindSafe};
+use std::any::Any;
+use std::time::Duration;
+use std::{fmt, thread};
+
+/// What to do with an access whose callback failed to answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailPolicy {
+    // fail-open
+    Continue,
+    // fail-closed
+    Block,
+    Stealth,
+}
+
+impl FailPolicy {
+    pub fn action(&self) -> Action {
+        match *self {
+            FailPolicy::Continue => Action::CONTINUE,
+            FailPolicy::Block => Action::BLOCK,
+            FailPolicy::Stealth => Action::STEALTH,
+        }
+    }
+}
+
+impl Default for FailPolicy {
+    fn default() -> FailPolicy {
+        FailPolicy::Continue
+    }
+}
+
+impl fmt::Display for FailPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FailPolicy::Continue => write!(f, "continue"),
+            FailPolicy::Block => write!(f, "block"),
+            FailPolicy::Stealth => write!(f, "stealth"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallbackPolicy {
+    // no deadline runs callbacks on the bucket thread, as long as they take
+    pub deadline: Option<Duration>,
+    pub on_failure: FailPolicy,
+}
+
+impl CallbackPolicy {
+    pub fn new(deadline: Option<Duration>, on_failure: FailPolicy) -> CallbackPolicy {
+        CallbackPolicy {
+            deadline: deadline,
+            on_failure: on_failure,
+        }
+    }
+}
+
+/// Counters of a watched callback, see `Guard::callback_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallbackStats {
+    pub calls: usize,
+    pub timeouts: usize,
+    // interceptions answered without the callback, still busy with an earlier one
+    pub busy: usize,
+    pub panics: usize,
+}
+
+impl CallbackStats {
+    pub fn failures(&self) -> usize {
+        self.timeouts + self.busy + self.panics
+    }
+
+    pub fn to_json(&self) -> String {
+        format!("{{\"calls\": {}, \"timeouts\": {}, \"busy\": {}, \"panics\": {}}}",
+                self.calls,
+                self.timeouts,
+                self.busy,
+                self.panics)
+    }
+}
+
+impl fmt::Display for CallbackStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "calls: {}, timeouts: {}, busy: {}, panics: {}", self.calls, self.timeouts, self.busy, self.panics)
+    }
+}
+
+#[derive(Debug)]
+enum Failure {
+    Timeout(Duration),
+    // the worker hasn't answered an earlier interception yet
+    Busy,
+    Panic(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Failure::Timeout(deadline) => write!(f, "missed its {}ms deadline", deadline.as_secs() * 1_000 +
+                                                                               u64::from(deadline.subsec_millis())),
+            Failure::Busy => write!(f, "is still answering an earlier interception"),
+            Failure::Panic(ref message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+// what `panic!` was given, when it's printable
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string()
+    }
+
+    match payload.downcast_ref::<String>() {
+        Some(message) => message.clone(),
+        None => String::from("unknown payload"),
+    }
+}
+
+fn call(callback: &SyncCallback, event: InterceptionEvent) -> Result<Response, Failure> {
+    panic::catch_unwind(AssertUnwindSafe(|| callback(event)))
+        .map_err(|payload| Failure::Panic(panic_message(&*payload)))
+}
+
+type Job = (InterceptionEvent, mpsc::Sender<Result<Response, Failure>>);
+
+// the thread a watched callback runs on when it has a deadline
+struct Worker {
+    callback: Arc<SyncCallback>,
+    // senders aren't Sync, and the worker only starts with the first deadline
+    jobs: Mutex<Option<mpsc::Sender<Job>>>,
+    busy: Arc<AtomicBool>,
+}
+
+impl Worker {
+    fn new(callback: SyncCallback) -> Worker {
+        Worker {
+            callback: Arc::new(callback),
+            jobs: Mutex::new(None),
+            busy: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // runs until the watched callback, and with it the sender, is dropped
+    fn spawn(&self) -> mpsc::Sender<Job> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let callback = Arc::clone(&self.callback);
+        let busy = Arc::clone(&self.busy);
+
+        thread::spawn(move || {
+            for (event, answer) in queue {
+                // a late answer finds the receiver gone, and is dropped
+                let _ = answer.send(call(&callback, event));
+                busy.store(false, Ordering::SeqCst);
+            }
+        });
+
+        jobs
+    }
+
+    fn call(&self, event: InterceptionEvent, deadline: Duration) -> Result<Response, Failure> {
+        if self.busy.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            return Err(Failure::Busy)
+        }
+
+        let (sender, receiver) = mpsc::channel();
+
+        let sent = {
+            let mut jobs = self.jobs.lock().expect("worker lock poisoned");
+
+            if jobs.is_none() {
+                *jobs = Some(self.spawn());
+            }
+
+            let sent = jobs.as_ref().map_or(false, |jobs| jobs.send((event, sender)).is_ok());
+
+            // started again with the next interception
+            if !sent {
+                *jobs = None;
+            }
+
+            sent
+        };
+
+        if !sent {
+            self.busy.store(false, Ordering::SeqCst);
+            return Err(Failure::Panic(String::from("callback worker exited")))
+        }
+
+        match receiver.recv_timeout(deadline) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(Failure::Timeout(deadline)),
+            Err(RecvTimeoutError::Disconnected) => Err(Failure::Panic(String::from("callback worker exited"))),
+        }
+    }
+}
+
+/// The policy and counters of the callbacks of a guard, or of a partition.
+#[derive(Debug, Default)]
+pub struct Watchdog {
+    policy: RwLock<CallbackPolicy>,
+    calls: AtomicUsize,
+    timeouts: AtomicUsize,
+    busy: AtomicUsize,
+    panics: AtomicUsize,
+}
+
+impl Watchdog {
+    pub fn new(policy: CallbackPolicy) -> Watchdog {
+        Watchdog {
+            policy: RwLock::new(policy),
+            ..Default::default()
+        }
+    }
+
+    // applies to the next interceptions, including those of callbacks already watched
+    pub fn set_policy(&self, policy: CallbackPolicy) {
+        *self.policy.write().expect("Failed to unlock as a writer") = policy;
+    }
+
+    pub fn policy(&self) -> CallbackPolicy {
+        *self.policy.read().expect("Failed to unlock for reading")
+    }
+
+    pub fn stats(&self) -> CallbackStats {
+        CallbackStats {
+            calls: self.calls.load(Ordering::SeqCst),
+            timeouts: self.timeouts.load(Ordering::SeqCst),
+            busy: self.busy.load(Ordering::SeqCst),
+            panics: self.panics.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Wraps `callback` so that its answers go through the watchdog.
+    pub fn watch(watchdog: &Arc<Watchdog>, callback: SyncCallback) -> SyncCallback {
+        let watchdog = Arc::clone(watchdog);
+        let worker = Worker::new(callback);
+
+        Box::new(move |event| watchdog.answer(&worker, event))
+    }
+
+    fn answer(&self, worker: &Worker, event: InterceptionEvent) -> Response {
+        let policy = self.policy();
+
+        self.calls.fetch_add(1, Ordering::SeqCst);
+
+        let result = match policy.deadline {
+            None => call(&worker.callback, event),
+            Some(deadline) => worker.call(event, deadline),
+        };
+
+        match result {
+            Ok(response) => response,
+            Err(failure) => {
+                match failure {
+                    Failure::Timeout(_) => self.timeouts.fetch_add(1, Ordering::SeqCst),
+                    Failure::Busy => self.busy.fetch_add(1, Ordering::SeqCst),
+                    Failure::Panic(_) => self.panics.fetch_add(1, Ordering::SeqCst),
+                };
+
+                Response::new(Some(format!("callback {}, applying {}", failure, policy.on_failure)),
+                              policy.on_failure.action())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::wire::InterceptionMessage;
+
+    fn event() -> InterceptionEvent {
+        InterceptionEvent::from(&InterceptionMessage::default())
+    }
+
+    #[test]
+    fn test_watchdog_catches_panics() {
+        let watchdog = Arc::new(Watchdog::new(CallbackPolicy::new(None, FailPolicy::Block)));
+
+        let callback = Watchdog::watch(&watchdog, Box::new(|event: InterceptionEvent| {
+            if event.id == 0 {
+                panic!("no answer for {}", event.id);
+            }
+
+            Response::empty()
+        }));
+
+        let response = callback(event());
+        assert_eq!(response.action(), Action::BLOCK);
+        assert_eq!(response.message(), "callback panicked: no answer for 0, applying block");
+
+        assert_eq!(callback(InterceptionEvent { id: 1, ..event() }).action(), Action::CONTINUE);
+        assert_eq!(watchdog.stats(), CallbackStats { calls: 2, timeouts: 0, busy: 0, panics: 1 });
+    }
+
+    // a callback answering right away for event 0 and once released for the others
+    fn gated(action: Action) -> (mpsc::Sender<()>, SyncCallback) {
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+
+        (release, Box::new(move |event: InterceptionEvent| {
+            if event.id != 0 {
+                gate.lock().unwrap().recv().unwrap();
+            }
+
+            Response::new(None, action)
+        }))
+    }
+
+    #[test]
+    fn test_watchdog_deadlines() {
+        let watchdog = Arc::new(Watchdog::default());
+
+        let (release, callback) = gated(Action::BLOCK);
+        let callback = Watchdog::watch(&watchdog, callback);
+
+        watchdog.set_policy(CallbackPolicy::new(Some(Duration::from_secs(60)), FailPolicy::Stealth));
+        assert_eq!(callback(event()).action(), Action::BLOCK);
+
+        // held until the deadline passed
+        watchdog.set_policy(CallbackPolicy::new(Some(Duration::from_millis(10)), FailPolicy::Stealth));
+
+        let response = callback(InterceptionEvent { id: 1, ..event() });
+        assert_eq!(response.action(), Action::STEALTH);
+        assert_eq!(response.message(), "callback missed its 10ms deadline, applying stealth");
+
+        release.send(()).unwrap();
+
+        let stats = watchdog.stats();
+        assert_eq!((stats.calls, stats.failures()), (2, 1));
+        assert_eq!(stats.to_json(), "{\"calls\": 2, \"timeouts\": 1, \"busy\": 0, \"panics\": 0}");
+    }
+
+    #[test]
+    fn test_watchdog_busy_workers() {
+        let watchdog = Watchdog::new(CallbackPolicy::new(Some(Duration::from_millis(10)), FailPolicy::Block));
+
+        let (release, callback) = gated(Action::CONTINUE);
+        let worker = Worker::new(callback);
+
+        assert_eq!(watchdog.answer(&worker, InterceptionEvent { id: 1, ..event() }).action(), Action::BLOCK);
+
+        // the worker still waits for its release, no second thread is started
+        let response = watchdog.answer(&worker, event());
+        assert_eq!(response.message(), "callback is still answering an earlier interception, applying block");
+
+        release.send(()).unwrap();
+
+        while worker.busy.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+
+        watchdog.set_policy(CallbackPolicy::new(Some(Duration::from_secs(60)), FailPolicy::Block));
+        assert_eq!(watchdog.answer(&worker, event()).action(), Action::CONTINUE);
+
+        assert_eq!(watchdog.stats(), CallbackStats { calls: 3, timeouts: 1, busy: 1, panics: 0 });
+        assert_eq!(watchdog.stats().to_string(), "calls: 3, timeouts: 1, busy: 1, panics: 0");
+    }
+}
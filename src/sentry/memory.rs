@@ -2,20 +2,19 @@
 
 // use ffi::traits::EncodeUtf16;
 
-use super::winapi::shared::minwindef::{LPVOID, LPHANDLE};
-
-use super::winapi::um::{ processthreadsapi};
+use super::structs::{LPVOID, LPHANDLE};
 
 use super::byteorder::{LittleEndian, ReadBytesExt};
 
 use std::marker::PhantomData;
 use std::io::Cursor;
 
-use std::{slice, mem};
+use std::{mem, process, slice};
 
 use super::failure::Error;
 use super::io::IOCTL_SENTRY_TYPE;
-use super::iochannel::{Device, IoCtl};
+use super::iochannel::{Transport, IoCtl};
+use super::session::SentrySession;
 use super::structs;
 
 pub use super::structs::MapMode;
@@ -37,29 +36,34 @@ use super::structs::{RawStruct,
 
 #[derive(Debug)]
 pub struct KernelAlloc<'a, T> {
-    device: &'a Device,
+    session: &'a SentrySession,
     map: mem::ManuallyDrop<Map<'a>>,
     phantom: PhantomData<T>
 }
 
 impl<'a, T> KernelAlloc<'a, T> {
-    pub fn new(device: &'a Device) -> KernelAlloc<'a, T> {
+    pub fn new(session: &'a SentrySession) -> Result<KernelAlloc<'a, T>, Error> {
         let size = mem::size_of::<T>();
-        let ptr = alloc_virtual_memory(device, size)
-                        .expect("failed to allocate memory");
+        let ptr = alloc_virtual_memory(session, size)?;
 
-        // memset
+        // memset, then map it, releasing the allocation if either fails
         let v: Vec<u8> = vec![0; size];
-        write_virtual_memory(device, ptr, v).expect("write memory");
-
-
-        KernelAlloc {
-            device: device,
-            map: mem::ManuallyDrop::new(
-                            Map::new(device, ptr, size, Some(MapMode::UserMode))
-                      ),
+        let map = write_virtual_memory(session, ptr, v)
+                        .and_then(|_| Map::new(session, ptr, size, Some(MapMode::UserMode)));
+
+        let map = match map {
+            Ok(map) => map,
+            Err(err) => {
+                let _ = free_virtual_memory(session, ptr);
+                return Err(err)
+            }
+        };
+
+        Ok(KernelAlloc {
+            session: session,
+            map: mem::ManuallyDrop::new(map),
             phantom: PhantomData
-        }
+        })
     }
 
     pub fn size(&self) -> usize {
@@ -87,29 +91,29 @@ impl<'a, T> KernelAlloc<'a, T> {
 impl<'a, T> Drop for KernelAlloc<'a, T> {
     fn drop(&mut self) {
         unsafe { mem::ManuallyDrop::drop(&mut self.map) }
-        free_virtual_memory(self.device, self.map.kernel_ptr()).expect("free error");
+
+        if let Err(err) = free_virtual_memory(self.session, self.map.kernel_ptr()) {
+            println!("memory::free_virtual_memory() {}", err);
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct Map<'a> {
-    device: &'a Device,
-    address: u64,
+    session: &'a SentrySession,
     size: usize,
     raw: structs::SE_MAP_VIRTUAL_MEMORY
 }
 
 impl<'a> Map<'a> {
-    pub fn new(device: &'a Device, address: u64, size: usize, mode: Option<MapMode>) -> Map<'a> {
-        let raw = map_memory(device, address, size, mode)
-                            .expect("failed to map memory");
+    pub fn new(session: &'a SentrySession, address: u64, size: usize, mode: Option<MapMode>) -> Result<Map<'a>, Error> {
+        let raw = map_memory(session, address, size, mode)?;
 
-        Map {
-            device: device,
-            address: address,
+        Ok(Map {
+            session: session,
             size: size,
             raw: raw,
-        }
+        })
     }
 
     pub fn kernel_ptr(&self) -> u64 {
@@ -133,12 +137,13 @@ impl<'a> Map<'a> {
 
 impl<'a> Drop for Map<'a> {
     fn drop(&mut self) {
-        unmap_memory(self.device, self.raw)
-                .expect("unmap error");
+        if let Err(err) = unmap_memory(self.session, self.raw) {
+            println!("memory::unmap_memory() {}", err);
+        }
     }
 }
 
-pub fn alloc_virtual_memory(device: &Device, size: usize) -> Result<u64, Error> {
+pub fn alloc_virtual_memory(device: &dyn Transport, size: usize) -> Result<u64, Error> {
     let control = IoCtl::new(Some("SE_ALLOC_VIRTUAL_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A50, None, None);
 
     let mut alloc = SE_ALLOC_VIRTUAL_MEMORY::init();
@@ -153,7 +158,7 @@ pub fn alloc_virtual_memory(device: &Device, size: usize) -> Result<u64, Error>
     Ok(alloc.BaseAddress as u64)
 }
 
-pub fn free_virtual_memory(device: &Device, address: u64) -> Result<(), Error> {
+pub fn free_virtual_memory(device: &dyn Transport, address: u64) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_FREE_VIRTUAL_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A51, None, None);
 
     let mut alloc = SE_FREE_VIRTUAL_MEMORY::init();
@@ -169,7 +174,7 @@ pub fn free_virtual_memory(device: &Device, address: u64) -> Result<(), Error> {
 }
 
 #[allow(dead_code)]
-pub fn copy_virtual_memory(device: &Device, from: u64, to: u64, size: usize) -> Result<(), Error> {
+pub fn copy_virtual_memory(device: &dyn Transport, from: u64, to: u64, size: usize) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_COPY_VIRTUAL_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A52, None, None);
 
     let mut copy = SE_COPY_VIRTUAL_MEMORY::init();
@@ -187,7 +192,7 @@ pub fn copy_virtual_memory(device: &Device, from: u64, to: u64, size: usize) ->
 }
 
 #[allow(dead_code)]
-pub fn secure_virtual_memory(device: &Device, address: u64, size: usize) -> Result<u64, Error> {
+pub fn secure_virtual_memory(device: &dyn Transport, address: u64, size: usize) -> Result<u64, Error> {
     let control = IoCtl::new(Some("SE_SECURE_VIRTUAL_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A53, None, None);
 
     let mut secure = SE_SECURE_VIRTUAL_MEMORY::init();
@@ -205,7 +210,7 @@ pub fn secure_virtual_memory(device: &Device, address: u64, size: usize) -> Resu
 }
 
 #[allow(dead_code)]
-pub fn unsecure_virtual_memory(device: &Device, handle: u64) -> Result<(), Error> {
+pub fn unsecure_virtual_memory(device: &dyn Transport, handle: u64) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_UNSECURE_VIRTUAL_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A54, None, None);
 
     let mut secure = SE_UNSECURE_VIRTUAL_MEMORY::init();
@@ -220,12 +225,12 @@ pub fn unsecure_virtual_memory(device: &Device, handle: u64) -> Result<(), Error
 
 }
 
-pub fn map_memory(device: &Device, address: u64, size: usize, mode: Option<MapMode>) -> Result<SE_MAP_VIRTUAL_MEMORY, Error> {
+pub fn map_memory(device: &dyn Transport, address: u64, size: usize, mode: Option<MapMode>) -> Result<SE_MAP_VIRTUAL_MEMORY, Error> {
     let control = IoCtl::new(Some("SE_MAP_VIRTUAL_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A55, None, None);
 
     let mut map = SE_MAP_VIRTUAL_MEMORY::init();
 
-    map.ToProcessId = u64::from(unsafe { processthreadsapi::GetCurrentProcessId()});
+    map.ToProcessId = u64::from(process::id());
     map.BaseAddress = address as LPVOID;
     map.MapMode = mode.unwrap_or(MapMode::UserMode);
     map.Size = size as u32;
@@ -239,7 +244,7 @@ pub fn map_memory(device: &Device, address: u64, size: usize, mode: Option<MapMo
     Ok(map)
 }
 
-pub fn unmap_memory(device: &Device, map: SE_MAP_VIRTUAL_MEMORY) -> Result<(), Error> {
+pub fn unmap_memory(device: &dyn Transport, map: SE_MAP_VIRTUAL_MEMORY) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_UNMAP_VIRTUAL_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A56, None, None);
 
     let mut unmap = SE_UNMAP_VIRTUAL_MEMORY::init();
@@ -255,7 +260,7 @@ pub fn unmap_memory(device: &Device, map: SE_MAP_VIRTUAL_MEMORY) -> Result<(), E
 }
 
 // TODO: Evaluate if we should shrink_to_if output vector to BytesCopied
-pub fn read_virtual_memory(device: &Device, address: u64, size: usize) -> Result<Vec<u8>, Error> {
+pub fn read_virtual_memory(device: &dyn Transport, address: u64, size: usize) -> Result<Vec<u8>, Error> {
     let control = IoCtl::new(Some("SE_READ_VIRTUAL_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A57, None, None);
 
     let mut read = SE_READ_VIRTUAL_MEMORY::init();
@@ -275,7 +280,7 @@ pub fn read_virtual_memory(device: &Device, address: u64, size: usize) -> Result
     Ok(v)
 }
 
-pub fn write_virtual_memory(device: &Device, address: u64, mut data: Vec<u8>) -> Result<usize, Error> {
+pub fn write_virtual_memory(device: &dyn Transport, address: u64, mut data: Vec<u8>) -> Result<usize, Error> {
     let control = IoCtl::new(Some("SE_WRITE_VIRTUAL_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A58, None, None);
 
     let mut write = SE_WRITE_VIRTUAL_MEMORY::init();
@@ -292,7 +297,7 @@ pub fn write_virtual_memory(device: &Device, address: u64, mut data: Vec<u8>) ->
 }
 
 #[allow(dead_code)]
-pub fn alloc_process_memory(device: &Device, pid: u64, size: usize) -> Result<u64, Error> {
+pub fn alloc_process_memory(device: &dyn Transport, pid: u64, size: usize) -> Result<u64, Error> {
     let control = IoCtl::new(Some("SE_ALLOC_PROCESS_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A59, None, None);
 
     let mut alloc = SE_ALLOC_PROCESS_MEMORY::init();
@@ -308,7 +313,7 @@ pub fn alloc_process_memory(device: &Device, pid: u64, size: usize) -> Result<u6
 }
 
 #[allow(dead_code)]
-pub fn free_process_memory(device: &Device, pid: u64, address: u64) -> Result<(), Error> {
+pub fn free_process_memory(device: &dyn Transport, pid: u64, address: u64) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_FREE_PROCESS_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A5A, None, None);
 
     let mut alloc = SE_FREE_PROCESS_MEMORY::init();
@@ -325,7 +330,7 @@ pub fn free_process_memory(device: &Device, pid: u64, address: u64) -> Result<()
 }
 
 #[allow(dead_code)]
-pub fn read_process_memory(device: &Device, pid: u64, address: u64, size: usize) -> Result<Vec<u8>, Error> {
+pub fn read_process_memory(device: &dyn Transport, pid: u64, address: u64, size: usize) -> Result<Vec<u8>, Error> {
     let control = IoCtl::new(Some("SE_READ_PROCESS_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A5B, None, None);
 
     let mut read = SE_READ_PROCESS_MEMORY::init();
@@ -347,7 +352,7 @@ pub fn read_process_memory(device: &Device, pid: u64, address: u64, size: usize)
 }
 
 #[allow(dead_code)]
-pub fn write_process_memory(device: &Device, pid: u64, address: u64, mut data: Vec<u8>) -> Result<(), Error> {
+pub fn write_process_memory(device: &dyn Transport, pid: u64, address: u64, mut data: Vec<u8>) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_WRITE_PROCESS_MEMORY"), IOCTL_SENTRY_TYPE, 0x0A5C, None, None);
 
     let mut write = SE_WRITE_PROCESS_MEMORY::init();
@@ -365,11 +370,11 @@ pub fn write_process_memory(device: &Device, pid: u64, address: u64, mut data: V
 }
 
 #[allow(dead_code)]
-pub fn read_pointer(device: &Device, address: u64) -> Result<u64, Error> {
+pub fn read_pointer(device: &dyn Transport, address: u64) -> Result<u64, Error> {
     read_u64(device, read_u64(device, address)?)
 }
 
-pub fn read_u64(device: &Device, address: u64) -> Result<u64, Error> {
+pub fn read_u64(device: &dyn Transport, address: u64) -> Result<u64, Error> {
     let v = read_virtual_memory(device, address, 8)?;
 
     let mut cursor = Cursor::new(v);
@@ -378,7 +383,7 @@ pub fn read_u64(device: &Device, address: u64) -> Result<u64, Error> {
 }
 
 #[allow(dead_code)]
-pub fn read_u32(device: &Device, address: u64) -> Result<u32, Error> {
+pub fn read_u32(device: &dyn Transport, address: u64) -> Result<u32, Error> {
     let v = read_virtual_memory(device, address, 8)?;
 
     let mut cursor = Cursor::new(v);
@@ -387,7 +392,7 @@ pub fn read_u32(device: &Device, address: u64) -> Result<u32, Error> {
 }
 
 #[allow(dead_code)]
-pub fn read_u16(device: &Device, address: u64) -> Result<u16, Error> {
+pub fn read_u16(device: &dyn Transport, address: u64) -> Result<u16, Error> {
     let v = read_virtual_memory(device, address, 8)?;
 
     let mut cursor = Cursor::new(v);
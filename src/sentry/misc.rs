@@ -1,21 +1,27 @@
 use std::fmt;
-use std::sync::Arc;
 
+#[cfg(windows)]
 use super::ffi::traits::EncodeUtf16;
-use super::ffi::{NtQuerySystemInformation, SystemInformationClass};
+#[cfg(windows)]
+use super::ffi::NtQuerySystemInformation;
+use super::ffi::SystemInformationClass;
 
-use super::winapi::um::{libloaderapi, processthreadsapi};
-use super::{io, memory, misc, symbols};
+#[cfg(windows)]
+use super::winapi::um::libloaderapi;
+use super::{memory, misc, symbols};
 
-use std::{ptr, slice};
+use std::{mem, process, ptr, slice};
 
 use super::error::MiscError;
 use super::failure::Error;
 
+#[cfg(windows)]
 use std::io::Error as BaseError;
 
 use super::io::IOCTL_SENTRY_TYPE;
-use super::iochannel::{Device, IoCtl};
+use super::iochannel::{IoCtl, Transport};
+use super::session::SentrySession;
+#[cfg(windows)]
 use super::winapi::shared::minwindef::{HMODULE, LPVOID};
 
 use super::structs::{RawStruct, RTL_PROCESS_MODULE_INFORMATION, SE_GET_EXPORT_ADDRESS};
@@ -24,41 +30,59 @@ use std::sync::mpsc::channel;
 
 use super::cli::output::create_messenger;
 
-pub fn get_offset(target: &str) -> Result<u16, Error> {
-    match symbols::parser::find_offset("ntoskrnl.pdb", target) {
+pub const KERNEL_IMAGE: &str = "c:\\windows\\system32\\ntoskrnl.exe";
+const KERNEL_PDB: &str = "ntoskrnl.pdb";
+
+// looks something up in the kernel PDB, downloading it first when missing
+fn with_kernel_pdb<T, F>(lookup: F) -> Result<T, Error>
+    where F: Fn() -> Result<T, PdbError> {
+    match lookup() {
         Err(PdbError::IoError(_)) => {
             // TODO:REVIEW: Temporlal addition of channel to support printed
             let (tx, rx) = channel();
             let tt = create_messenger(rx, None, 0);
 
             symbols::downloader::PdbDownloader::new(
-                "c:\\windows\\system32\\ntoskrnl.exe".to_string(),
+                KERNEL_IMAGE.to_string(),
             )
             .download(&tx)?;
             tt.join().expect("unable to wait for channel");
 
-            Ok(symbols::parser::find_offset("ntoskrnl.pdb", target)?)
+            Ok(lookup()?)
         }
-        Err(err) => {
-            panic!("error parsing PDB {}", err);
-        }
-        Ok(offset) => Ok(offset),
+        Err(err) => Err(err.into()),
+        Ok(value) => Ok(value),
     }
 }
 
+pub fn get_offset(target: &str) -> Result<u16, Error> {
+    with_kernel_pdb(|| symbols::parser::find_offset(KERNEL_PDB, target))
+}
+
+// RVA of any public kernel symbol, exported or not
+pub fn get_symbol_rva(name: &str) -> Result<u64, Error> {
+    let (segment, offset) = with_kernel_pdb(|| symbols::parser::find_public_symbol(KERNEL_PDB, name))?
+                                .ok_or_else(|| MiscError::SymbolNotFound(name.to_string()))?;
+
+    Ok(u64::from(symbols::parser::section_rva(KERNEL_IMAGE, segment, offset)?))
+}
+
 #[derive(Clone)]
 pub struct LinkedList {
-    device: Arc<Device>,
+    session: SentrySession,
     offset: u16,
     pointer: u64,
+    // set once a link couldn't be read, iterating stops there
+    broken: bool,
 }
 
 impl LinkedList {
-    pub fn new(device: Arc<Device>, pointer: u64, offset: u16) -> LinkedList {
+    pub fn new(session: SentrySession, pointer: u64, offset: u16) -> LinkedList {
         LinkedList {
-            device: device,
+            session: session,
             offset: offset,
             pointer: pointer + u64::from(offset),
+            broken: false,
         }
     }
 
@@ -67,35 +91,47 @@ impl LinkedList {
     }
 
     #[allow(dead_code)]
-    pub fn backward(&self) -> LinkedList {
-        let blink = memory::read_u64(&self.device, self.pointer + 8).unwrap();
+    pub fn backward(&self) -> Result<LinkedList, Error> {
+        let blink = memory::read_u64(&self.session, self.pointer + 8)?;
 
-        LinkedList {
-            device: Arc::clone(&self.device),
+        Ok(LinkedList {
+            session: self.session.clone(),
             offset: self.offset,
             pointer: blink,
-        }
+            broken: false,
+        })
     }
 
-    pub fn forward(&self) -> LinkedList {
-        let flink = memory::read_u64(&self.device, self.pointer).unwrap();
+    pub fn forward(&self) -> Result<LinkedList, Error> {
+        let flink = memory::read_u64(&self.session, self.pointer)?;
 
-        LinkedList {
-            device: Arc::clone(&self.device),
+        Ok(LinkedList {
+            session: self.session.clone(),
             offset: self.offset,
             pointer: flink,
-        }
+            broken: false,
+        })
     }
 }
 
 impl Iterator for LinkedList {
-    type Item = LinkedList;
+    type Item = Result<LinkedList, Error>;
+
+    fn next(&mut self) -> Option<Result<LinkedList, Error>> {
+        if self.broken {
+            return None
+        }
 
-    fn next(&mut self) -> Option<LinkedList> {
-        let next = self.forward();
-        self.offset = next.offset;
-        self.pointer = next.pointer;
-        Some(next)
+        match self.forward() {
+            Ok(next) => {
+                self.pointer = next.pointer;
+                Some(Ok(next))
+            },
+            Err(err) => {
+                self.broken = true;
+                Some(Err(err))
+            },
+        }
     }
 }
 
@@ -117,84 +153,93 @@ impl PartialEq for LinkedList {
 
 #[derive(Clone)]
 pub struct Process {
-    device: Arc<Device>,
+    session: SentrySession,
     object: u64,
     list: LinkedList,
 }
 
 impl Process {
-    pub fn current() -> Process {
-        let pid = unsafe { processthreadsapi::GetCurrentProcessId() };
-        misc::WalkProcess::iter()
-            .find(|p| p.id() == u64::from(pid))
-            .expect("can't find own EPROCESS")
+    pub fn current(session: &SentrySession) -> Result<Process, Error> {
+        let pid = u64::from(process::id());
+        misc::WalkProcess::by_pid(session, pid)
     }
-    pub fn system() -> Result<Process, Error> {
-        let device = Device::new(io::SE_NT_DEVICE_NAME)?;
-        let system_pointer = system_process_pointer(&device)?;
-        let addr = memory::read_u64(&device, system_pointer)?;
-        Ok(Process::new(Arc::new(device), addr)?)
+    pub fn system(session: &SentrySession) -> Result<Process, Error> {
+        let system_pointer = system_process_pointer(session)?;
+        let addr = memory::read_u64(session, system_pointer)?;
+        Ok(Process::new(session, addr)?)
     }
 
-    pub fn new(device: Arc<Device>, object: u64) -> Result<Process, Error> {
+    pub fn new(session: &SentrySession, object: u64) -> Result<Process, Error> {
         let target = "_EPROCESS.ActiveProcessLinks";
         let offset = get_offset(target)?;
 
         Ok(Process {
-            device: Arc::clone(&device),
+            session: session.clone(),
             object: object,
-            list: LinkedList::new(Arc::clone(&device), object, offset),
+            list: LinkedList::new(session.clone(), object, offset),
         })
     }
 
+    pub fn session(&self) -> &SentrySession {
+        &self.session
+    }
+
     #[allow(dead_code)]
-    pub fn backward(&self) -> Process {
-        let next = self.list.backward();
+    pub fn backward(&self) -> Result<Process, Error> {
+        let next = self.list.backward()?;
 
-        Process {
-            device: Arc::clone(&self.device),
+        Ok(Process {
+            session: self.session.clone(),
             object: next.ptr(),
             list: next,
-        }
+        })
     }
 
-    pub fn forward(&self) -> Process {
-        let next = self.list.forward();
+    pub fn forward(&self) -> Result<Process, Error> {
+        let next = self.list.forward()?;
 
-        Process {
-            device: Arc::clone(&self.device),
+        Ok(Process {
+            session: self.session.clone(),
             object: next.ptr(),
             list: next,
-        }
+        })
     }
 
     pub fn object(&self) -> u64 {
         self.object
     }
 
-    pub fn token(&self) -> u64 {
-        let target = "_EPROCESS.Token";
-        let offset = get_offset(target).expect(target);
-        memory::read_u64(&self.device, self.object + u64::from(offset)).unwrap()
+    pub fn token(&self) -> Result<u64, Error> {
+        let offset = get_offset("_EPROCESS.Token")?;
+        memory::read_u64(&self.session, self.object + u64::from(offset))
     }
 
-    pub fn id(&self) -> u64 {
-        let target = "_EPROCESS.UniqueProcessId";
-        let offset = get_offset(target).expect(target);
-        memory::read_u64(&self.device, self.object + u64::from(offset)).unwrap()
+    pub fn id(&self) -> Result<u64, Error> {
+        let offset = get_offset("_EPROCESS.UniqueProcessId")?;
+        memory::read_u64(&self.session, self.object + u64::from(offset))
     }
 
-    pub fn name(&self) -> String {
-        let target = "_EPROCESS.ImageFileName";
-        let offset = get_offset(target).expect(target);
-        let name =
-            memory::read_virtual_memory(&self.device, self.object + u64::from(offset), 15).unwrap();
-        String::from_utf8(name)
-            .expect("can't build process name")
-            .split(|c| c as u8 == 0x00)
-            .nth(0)
-            .unwrap()
-            .to_string()
+    pub fn name(&self) -> Result<String, Error> {
+        let offset = get_offset("_EPROCESS.ImageFileName")?;
+        let name = memory::read_virtual_memory(&self.session, self.object + u64::from(offset), 15)?;
+
+        // ImageFileName is a NUL padded array of ANSI characters
+        let length = name.iter().position(|&c| c == 0x00).unwrap_or(name.len());
+
+        Ok(String::from_utf8_lossy(&name[..length]).into_owned())
+    }
+
+    // Terminal Services session, System and the processes started before smss have none
+    pub fn session_id(&self) -> Result<u32, Error> {
+        let offset = get_offset("_EPROCESS.Session")?;
+        let space = memory::read_pointer(&self.session, self.object + u64::from(offset))?;
+
+        if space == 0 {
+            return Ok(0)
+        }
+
+        let offset = get_offset("_MM_SESSION_SPACE.SessionId")?;
+        memory::read_u32(&self.session, space + u64::from(offset))
     }
 }
 
@@ -204,40 +249,75 @@ impl PartialEq for Process {
     }
 }
 
+/// Walks ActiveProcessLinks from the System process.
+///
+/// A link that can't be read is yielded as an error and ends the walk.
 pub struct WalkProcess {
     head: Process,
-    curr: Process,
+    curr: Option<Process>,
 }
 
 impl WalkProcess {
-    pub fn iter() -> WalkProcess {
-        let head = Process::system().expect("can't get system process");
-        WalkProcess {
-            head: head.clone(),
-            curr: head.forward(),
-        }
+    pub fn new(session: &SentrySession) -> Result<WalkProcess, Error> {
+        let head = Process::system(session)?;
+        let curr = head.forward()?;
+
+        Ok(WalkProcess {
+            head: head,
+            curr: Some(curr),
+        })
     }
-}
 
-impl Iterator for WalkProcess {
-    type Item = Process;
+    // first process `predicate` holds for, any failed read along the way is returned instead
+    pub fn find_by<F>(self, mut predicate: F) -> Result<Option<Process>, Error>
+        where F: FnMut(&Process) -> Result<bool, Error> {
+        for process in self {
+            let process = process?;
+
+            if predicate(&process)? {
+                return Ok(Some(process))
+            }
+        }
 
-    fn next(&mut self) -> Option<Process> {
-        let process = self.curr.clone();
-        let next = self.curr.forward();
+        Ok(None)
+    }
 
-        self.curr = next.clone();
+    pub fn by_pid(session: &SentrySession, pid: u64) -> Result<Process, Error> {
+        WalkProcess::new(session)?
+            .find_by(|process| Ok(process.id()? == pid))?
+            .ok_or_else(|| MiscError::ProcessNotFound(pid).into())
+    }
 
-        if next == self.head {
-            return None;
-        };
-        Some(process)
+    // first process whose image name contains `name`
+    pub fn by_name(session: &SentrySession, name: &str) -> Result<Option<Process>, Error> {
+        WalkProcess::new(session)?.find_by(|process| Ok(process.name()?.contains(name)))
+    }
+}
+
+impl Iterator for WalkProcess {
+    type Item = Result<Process, Error>;
+
+    fn next(&mut self) -> Option<Result<Process, Error>> {
+        let process = self.curr.take()?;
+
+        match process.forward() {
+            // links back to the head belong to PsActiveProcessHead, not to a process
+            Ok(ref next) if *next == self.head => None,
+            Ok(next) => {
+                self.curr = Some(next);
+                Some(Ok(process))
+            },
+            Err(err) => Some(Err(err)),
+        }
     }
 }
 
 impl fmt::Display for Process {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Process(name: {:?}, list: {})", self.name(), self.list)
+        match self.name() {
+            Ok(name) => write!(f, "Process(name: {:?}, list: {})", name, self.list),
+            Err(_) => write!(f, "Process(name: ?, list: {})", self.list),
+        }
     }
 }
 
@@ -274,38 +354,45 @@ pub struct Drivers {
 }
 
 impl Drivers {
-    pub fn contains(name: &str) -> Option<Driver> {
-        Drivers::iter().find(|driver| driver.name.contains(name))
+    pub fn contains(name: &str) -> Result<Option<Driver>, Error> {
+        Ok(Drivers::iter()?.find(|driver| driver.name.contains(name)))
     }
 
-    pub fn iter() -> Drivers {
+    pub fn iter() -> Result<Drivers, Error> {
         // get total size of allocation
         let size = query_system_information_size(SystemInformationClass::SystemModuleInformationEx);
 
         let mut buffer: Vec<u8> = vec![0; size];
 
         // fill module information
-        let _ = query_system_information(
+        let filled = query_system_information(
             SystemInformationClass::SystemModuleInformationEx,
             buffer.as_mut_ptr(),
             buffer.len(),
         );
 
-        let (count, modules) = unsafe {
-            let count = *{ buffer.as_ptr() as *const u32 } as usize;
-            let modules = slice::from_raw_parts(
-                buffer.as_ptr().offset(8) as *const RTL_PROCESS_MODULE_INFORMATION,
-                count,
-            );
+        // a count followed by the modules, 8 bytes in
+        let header = 8;
+
+        if size < header || filled < header {
+            return Err(MiscError::ModuleInformation(size).into())
+        }
+
+        let count = unsafe { *{ buffer.as_ptr() as *const u32 } } as usize;
+        let count = count.min((filled.min(size) - header) / mem::size_of::<RTL_PROCESS_MODULE_INFORMATION>());
 
-            (count, modules)
+        let modules = unsafe {
+            slice::from_raw_parts(
+                buffer.as_ptr().offset(header as isize) as *const RTL_PROCESS_MODULE_INFORMATION,
+                count,
+            )
         };
 
-        Drivers {
+        Ok(Drivers {
             drivers: modules.to_vec(),
             curr: 0,
             limit: count,
-        }
+        })
     }
 }
 
@@ -338,6 +425,7 @@ pub fn query_system_information_size(class: SystemInformationClass) -> usize {
     query_system_information::<u8>(class, ptr::null_mut(), 0)
 }
 
+#[cfg(windows)]
 pub fn query_system_information<T>(
     class: SystemInformationClass,
     buffer: *mut T,
@@ -351,14 +439,29 @@ pub fn query_system_information<T>(
     bytes as usize
 }
 
-pub fn list_kernel_drivers() {
-    Drivers::iter().for_each(|driver| println!("{}", driver));
+// no kernel modules to report off Windows
+#[cfg(not(windows))]
+pub fn query_system_information<T>(
+    _class: SystemInformationClass,
+    _buffer: *mut T,
+    _size: usize,
+) -> usize {
+    0
 }
 
-pub fn get_kernel_base() -> u64 {
-    Drivers::iter().take(1).nth(0).unwrap().base
+pub fn list_kernel_drivers() -> Result<(), Error> {
+    Drivers::iter()?.for_each(|driver| println!("{}", driver));
+    Ok(())
 }
 
+// ntoskrnl is always the first module loaded
+pub fn get_kernel_base() -> Result<u64, Error> {
+    Drivers::iter()?.next()
+                    .map(|driver| driver.base)
+                    .ok_or_else(|| MiscError::ModuleInformation(0).into())
+}
+
+#[cfg(windows)]
 pub fn load_library(name: &str) -> Result<u64, MiscError> {
     unsafe {
         let value = libloaderapi::LoadLibraryW(name.encode_utf16_null().as_ptr()) as u64;
@@ -372,7 +475,7 @@ pub fn load_library(name: &str) -> Result<u64, MiscError> {
     }
 }
 
-pub fn kernel_export_address(device: &Device, base: u64, name: &str) -> Result<u64, Error> {
+pub fn kernel_export_address(device: &dyn Transport, base: u64, name: &str) -> Result<u64, Error> {
     let control = IoCtl::new(
         Some("SE_GET_EXPORT_ADDRESS"),
         IOCTL_SENTRY_TYPE,
@@ -396,6 +499,7 @@ pub fn kernel_export_address(device: &Device, base: u64, name: &str) -> Result<u
     Ok(info.Address)
 }
 
+#[cfg(windows)]
 pub fn user_proc_addr(base: u64, name: &str) -> Result<u64, MiscError> {
     // for some reason its necessary to do this in order to correctly pass the string
     // at some point the reference to native string breaks the result
@@ -414,19 +518,16 @@ pub fn user_proc_addr(base: u64, name: &str) -> Result<u64, MiscError> {
     }
 }
 
-pub fn fixed_procedure_address(base: u64, name: &str, procedure: &str) -> u64 {
-    let dynamic_base = load_library(name).expect(name);
-
-    let address = match user_proc_addr(dynamic_base, procedure) {
-        Err(err) => panic!("{}", err.to_string()),
-        Ok(address) => address,
-    };
+#[cfg(windows)]
+pub fn fixed_procedure_address(base: u64, name: &str, procedure: &str) -> Result<u64, MiscError> {
+    let dynamic_base = load_library(name)?;
+    let address = user_proc_addr(dynamic_base, procedure)?;
 
-    (address - dynamic_base) + base
+    Ok((address - dynamic_base) + base)
 }
 
-pub fn system_process_pointer(device: &Device) -> Result<u64, Error> {
-    kernel_export_address(device, get_kernel_base(), "PsInitialSystemProcess")
+pub fn system_process_pointer(device: &dyn Transport) -> Result<u64, Error> {
+    kernel_export_address(device, get_kernel_base()?, "PsInitialSystemProcess")
 }
 
 #[allow(dead_code)]
@@ -12,11 +12,16 @@ use super::{symbols, ffi, iochannel, cli};
 pub mod error;
 pub mod structs;
 pub mod io;
+pub mod session;
+pub mod wire;
 pub mod token;
 pub mod memory;
 pub mod misc;
 pub mod search;
 pub mod memguard;
+pub mod policy;
 pub mod command;
+pub mod simulator;
 
 pub use self::io::SE_NT_DEVICE_NAME as DeviceName;
+pub use self::session::SentrySession;
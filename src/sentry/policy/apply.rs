@@ -0,0 +1,177 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::super::memguard::{Filter, Guard, GuardState, Partition, Patch, Predicate, Region, Response};
+use super::super::session::SentrySession;
+use super::super::{memory, misc};
+use super::super::failure::Error;
+use super::error::PolicyError;
+use super::{ConditionPolicy, GuardPolicy, Policy, Subject};
+
+use std::sync::Mutex;
+
+/// Kernel buffers backing a deployment, i.e. patched copies of code.
+///
+/// Declare it before the partitions so that it is dropped after them: patches keep
+/// pointing at their copy until their partition is deleted.
+pub struct Allocations<'s> {
+    session: &'s SentrySession,
+    addresses: Mutex<Vec<u64>>,
+}
+
+impl<'s> Allocations<'s> {
+    pub fn new(session: &'s SentrySession) -> Allocations<'s> {
+        Allocations {
+            session: session,
+            addresses: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn alloc(&self, size: usize) -> Result<u64, Error> {
+        let address = memory::alloc_virtual_memory(self.session, size)?;
+
+        self.addresses.lock().expect("allocations lock poisoned").push(address);
+
+        Ok(address)
+    }
+
+    // copies `data` into a buffer of its own
+    pub fn copy(&self, data: Vec<u8>) -> Result<u64, Error> {
+        let address = self.alloc(data.len())?;
+
+        memory::write_virtual_memory(self.session, address, data)?;
+
+        Ok(address)
+    }
+}
+
+impl<'s> Drop for Allocations<'s> {
+    fn drop(&mut self) {
+        for &address in self.addresses.lock().expect("allocations lock poisoned").iter() {
+            if let Err(err) = memory::free_virtual_memory(self.session, address) {
+                println!("memory::free_virtual_memory() {}", err);
+            }
+        }
+    }
+}
+
+/// Guards built from a policy, named `partition/guard`.
+///
+/// Dropping it unregisters every guard, dropping the partitions afterwards
+/// deletes their regions and patches.
+pub struct Deployment<'p> {
+    pub guards: Vec<(String, Guard<'p>)>,
+}
+
+impl<'p> Deployment<'p> {
+    pub fn guard(&self, name: &str) -> Option<&Guard<'p>> {
+        self.guards.iter()
+                   .find(|&&(ref guard, _)| guard == name)
+                   .map(|&(_, ref guard)| guard)
+    }
+
+    pub fn stop(&self) -> Result<(), Error> {
+        for &(_, ref guard) in self.guards.iter().filter(|&&(_, ref guard)| guard.state() == GuardState::Armed) {
+            guard.stop()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn filter<'p>(session: &'p SentrySession, conditions: &[ConditionPolicy], expression: &Option<Predicate>) -> Result<Option<Filter<'p>>, Error> {
+    if conditions.is_empty() && expression.is_none() {
+        return Ok(None)
+    }
+
+    let mut predicates = expression.iter().cloned().collect::<Vec<Predicate>>();
+
+    for condition in conditions {
+        let predicate = match condition.subject {
+            Subject::Pid(pid) => Predicate::pid(condition.cmp, pid),
+            Subject::Process(ref name) => {
+                let process = misc::WalkProcess::by_name(session, name)?
+                                  .ok_or_else(|| PolicyError::Unresolved(condition.to_string(), String::from("no running process matches")))?;
+
+                Predicate::pid(condition.cmp, process.id()?)
+            },
+            Subject::Sid(ref sid) => Predicate::sid(condition.cmp, sid.clone())?,
+        };
+
+        predicates.push(predicate);
+    }
+
+    Ok(Some(Predicate::All(predicates).filter(session)?))
+}
+
+fn deploy_guard<'p>(partition: &'p Partition, policy: &GuardPolicy, allocations: &Allocations) -> Result<Guard<'p>, Error> {
+    let session = &partition.session;
+
+    let mut guard = Guard::new(partition, filter(session, &policy.filter, &policy.expression)?)?;
+
+    for region in &policy.regions {
+        guard.add(Region::target(partition, &region.target, region.size, region.action, region.access)?)?;
+    }
+
+    for patch in &policy.patches {
+        let base = patch.target.resolve(session)?;
+        let copy = allocations.alloc(patch.size as usize)?;
+
+        memory::copy_virtual_memory(session, base, copy, patch.size as usize)?;
+        memory::write_virtual_memory(session, copy + patch.at, patch.bytes.clone())?;
+
+        guard.add(Patch::new(partition, base, copy, patch.size)?)?;
+    }
+
+    if let Some(action) = policy.response {
+        guard.set_callback(Box::new(move |_| Response::new(None, action)));
+    }
+
+    if policy.start {
+        guard.start()?;
+    }
+
+    Ok(guard)
+}
+
+impl Policy {
+    // one partition per policy partition, in order, with its options applied
+    pub fn create_partitions(&self, session: &SentrySession) -> Result<Vec<Partition>, Error> {
+        self.partitions.iter()
+                       .map(|partition| Partition::builder().options(&partition.options).create(session))
+                       .collect()
+    }
+
+    /// Builds and starts the guards of every partition.
+    ///
+    /// Guards registered before a failing step are unregistered again on return.
+    pub fn deploy<'p>(&self, partitions: &'p [Partition], allocations: &Allocations) -> Result<Deployment<'p>, Error> {
+        let mut deployment = Deployment { guards: vec![] };
+
+        for (policy, partition) in self.partitions.iter().zip(partitions) {
+            for guard in &policy.guards {
+                let name = format!("{}/{}", policy.name, guard.name);
+                let built = deploy_guard(partition, guard, allocations)?;
+
+                deployment.guards.push((name, built));
+            }
+        }
+
+        Ok(deployment)
+    }
+
+    // resolves every target without changing anything, e.g. to validate a policy on a given machine
+    pub fn resolve(&self, session: &SentrySession) -> Vec<(String, Result<u64, Error>)> {
+        self.partitions.iter()
+                       .flat_map(|partition| partition.guards.iter().map(move |guard| (partition, guard)))
+                       .flat_map(|(partition, guard)| {
+                           let name = format!("{}/{}", partition.name, guard.name);
+
+                           guard.regions.iter().map(|region| &region.target)
+                                .chain(guard.patches.iter().map(|patch| &patch.target))
+                                .map(move |target| (format!("{} {}", name, target), target))
+                                .collect::<Vec<_>>()
+                       })
+                       .map(|(name, target)| (name, target.resolve(session)))
+                       .collect()
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+use super::super::clap::{App, Arg, ArgMatches, SubCommand};
+use super::super::failure::Error;
+use super::super::session::{SentrySession, SessionOptions};
+use super::super::memguard;
+use super::{Allocations, Change, Policy};
+
+use std::sync::mpsc::Sender;
+use std::{thread, time};
+use super::super::cli::output::{MessageType, ShellMessage};
+use super::console::style;
+
+pub fn bind() -> App<'static, 'static> {
+    let file = Arg::with_name("file")
+                   .required(true)
+                   .value_name("FILE")
+                   .help("policy file, .toml, .yaml or .yml");
+
+    SubCommand::with_name("policy")
+        .about("applies protections described in policy files")
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("checks a policy without applying it")
+                .arg(file.clone())
+                .arg(
+                    Arg::with_name("resolve")
+                        .short("r")
+                        .long("resolve")
+                        .help("also resolves every target against the running kernel"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .about("builds the partitions and guards of a policy and keeps them running")
+                .arg(file.clone())
+                .arg(
+                    Arg::with_name("duration")
+                        .short("d")
+                        .long("duration")
+                        .value_name("SECONDS")
+                        .help("removes the protections after SECONDS, they run until interrupted otherwise"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("shows what changes between two policies")
+                .arg(file.clone().value_name("OLD").help("policy currently applied"))
+                .arg(
+                    Arg::with_name("new")
+                        .required(true)
+                        .value_name("NEW")
+                        .help("policy replacing it"),
+                ),
+        )
+}
+
+fn summary(policy: &Policy) -> String {
+    let guards = policy.partitions.iter().flat_map(|partition| partition.guards.iter());
+    let (guards, regions, patches) = guards.fold((0, 0, 0), |(guards, regions, patches), guard| {
+        (guards + 1, regions + guard.regions.len(), patches + guard.patches.len())
+    });
+
+    format!("{} partitions, {} guards, {} regions, {} patches",
+            style(policy.partitions.len()).cyan(),
+            style(guards).cyan(),
+            style(regions).cyan(),
+            style(patches).cyan())
+}
+
+fn validate(matches: &ArgMatches, options: &SessionOptions, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    let file = matches.value_of("file").expect("argument `file` is not present");
+    let policy = Policy::load(file)?;
+
+    ShellMessage::send(messenger, format!("{} is valid: {}", style(file).underlined(), summary(&policy)), MessageType::Close, 0);
+
+    if !matches.is_present("resolve") {
+        return Ok(())
+    }
+
+    let session = SentrySession::open_with(options)?;
+    let mut failures = 0;
+
+    for (target, address) in policy.resolve(&session) {
+        let line = match address {
+            Ok(address) => format!("  {} => {}", target, style(format!("0x{:016x}", address)).cyan()),
+            Err(err) => {
+                failures += 1;
+                format!("  {} => {}", target, style(err).red())
+            },
+        };
+
+        ShellMessage::send(messenger, line, MessageType::Close, 0);
+    }
+
+    match failures {
+        0 => Ok(()),
+        failures => Err(format_err!("{} targets can't be resolved", failures)),
+    }
+}
+
+fn apply(matches: &ArgMatches, options: &SessionOptions, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    let file = matches.value_of("file").expect("argument `file` is not present");
+
+    let duration = match matches.value_of("duration") {
+        Some(seconds) => Some(seconds.parse::<usize>()?),
+        None => None,
+    };
+
+    let policy = Policy::load(file)?;
+    let session = SentrySession::open_with(options)?;
+
+    // declared first to be freed last, see `Allocations`
+    let allocations = Allocations::new(&session);
+    let partitions = policy.create_partitions(&session)?;
+    let deployment = policy.deploy(&partitions, &allocations)?;
+
+    ShellMessage::send(messenger, format!("Applied {}: {}", style(file).underlined(), summary(&policy)), MessageType::Close, 0);
+
+    for &(ref name, ref guard) in &deployment.guards {
+        ShellMessage::send(messenger, format!("  {} {}", style(name).blue(), guard), MessageType::Close, 0);
+    }
+
+    // an interrupt ends the wait early, the console handler gives us time to clean up
+    let interrupts = memguard::interrupts();
+
+    match duration {
+        Some(seconds) => {
+            let bar = ShellMessage::new(messenger, "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}".to_string(), 0, seconds);
+
+            for elapsed in 1..seconds {
+                if memguard::interrupted_since(interrupts) {
+                    break
+                }
+
+                bar.set_progress(messenger, elapsed);
+                thread::sleep(time::Duration::from_secs(1));
+            }
+
+            bar.complete(messenger);
+        },
+        None => while !memguard::interrupted_since(interrupts) {
+            thread::sleep(time::Duration::from_millis(100))
+        },
+    }
+
+    deployment.stop()?;
+    ShellMessage::send(messenger, format!("{}", style("Protections removed").yellow()), MessageType::Close, 0);
+
+    // only guards with a response of their own have a callback to account for
+    for &(ref name, ref guard) in &deployment.guards {
+        let stats = guard.callback_stats();
+
+        if stats.calls > 0 {
+            let stats = match stats.failures() {
+                0 => style(stats.to_string()).green(),
+                _ => style(stats.to_string()).red(),
+            };
+
+            ShellMessage::send(messenger, format!("  {} {}", style(name).blue(), stats), MessageType::Close, 0);
+        }
+    }
+
+    Ok(())
+}
+
+fn diff(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    let old = Policy::load(matches.value_of("file").expect("argument `file` is not present"))?;
+    let new = Policy::load(matches.value_of("new").expect("argument `new` is not present"))?;
+
+    let changes = old.diff(&new);
+
+    if changes.is_empty() {
+        ShellMessage::send(messenger, String::from("No changes"), MessageType::Close, 0);
+    }
+
+    for change in changes {
+        let line = match change {
+            Change::Added(..) => style(change.to_string()).green(),
+            Change::Removed(..) => style(change.to_string()).red(),
+            Change::Changed(..) => style(change.to_string()).yellow(),
+        };
+
+        ShellMessage::send(messenger, line.to_string(), MessageType::Close, 0);
+    }
+
+    Ok(())
+}
+
+pub fn parse(matches: &ArgMatches, session: &SessionOptions, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("validate", Some(matches)) => validate(matches, session, messenger),
+        ("apply", Some(matches)) => apply(matches, session, messenger),
+        ("diff", Some(matches)) => diff(matches, messenger),
+        _ => Ok(println!("{}", matches.usage())),
+    }
+}
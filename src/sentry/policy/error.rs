@@ -0,0 +1,25 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+#[derive(Fail, Debug)]
+pub enum PolicyError {
+    #[fail(display = "Unsupported policy format {:?}, expected .toml, .yaml or .yml", _0)]
+    Format(String),
+
+    #[fail(display = "Policy syntax error: {}", _0)]
+    Syntax(String),
+
+    #[fail(display = "{}: missing {:?}", _0, _1)]
+    Missing(String, &'static str),
+
+    #[fail(display = "{}: unknown key {:?}", _0, _1)]
+    UnknownKey(String, String),
+
+    #[fail(display = "{}: {}", _0, _1)]
+    Invalid(String, String),
+
+    #[fail(display = "{}: {} is declared twice", _0, _1)]
+    Duplicate(String, String),
+
+    #[fail(display = "Unable to resolve {}: {}", _0, _1)]
+    Unresolved(String, String),
+}
@@ -0,0 +1,793 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+//
+// Declarative protections.
+//
+// A policy file lists partitions, their guards and what each guard protects, e.g.
+//
+//     [[partition]]
+//     name = "tokens"
+//     options = { collect-stats = true }
+//
+//     [[partition.guard]]
+//     name = "lsass"
+//     filter = [{ process = "lsass.exe" }, { sid = "S-1-5-18", match = "!=" }]
+//     response = "stealth"
+//
+// The filter may also be written as an expression, e.g. `filter = "image == lsass.exe && sid != S-1-5-18"`.
+//
+//     [[partition.guard.region]]
+//     symbol = "nt!PsInitialSystemProcess"
+//     offset = "0x10"
+//     size = 8
+//     access = ["write"]
+//
+//     [[partition.guard.patch]]
+//     driver = "HEVD.sys"
+//     offset = "0x5000"
+//     size = "0x1000"
+//     at = "0xBEC"
+//     bytes = "90 90 90 90 90 90"
+//
+// Regions and patches may also name their target with a single expression, e.g.
+// `target = "_EPROCESS(pid=1234).Token"`, see `memguard::Target`.
+//
+// YAML documents use the same keys. Numbers may be written as strings, in decimal or
+// hexadecimal, since kernel addresses don't fit TOML integers. Everything but target
+// resolution is checked while loading, `apply` resolves targets against the running kernel.
+//
+
+extern crate console;
+extern crate toml;
+extern crate yaml_rust;
+
+pub mod error;
+pub mod apply;
+pub mod command;
+
+use self::toml::Value;
+use self::toml::value::Table;
+use self::yaml_rust::{Yaml, YamlLoader};
+
+use super::memguard::{Access, Action, MatchType, PartitionOption, PartitionOptions, Predicate, Sid, MAX_CONDITIONS};
+
+pub use super::memguard::Target;
+use super::failure::Error;
+use self::error::PolicyError;
+
+use std::collections::{BTreeMap, HashSet};
+use std::ops::BitOr;
+use std::path::Path;
+use std::{fmt, fs};
+
+pub use self::apply::{Allocations, Deployment};
+
+const ACCESS: &[(&str, Access)] = &[
+    ("read", Access::READ),
+    ("write", Access::WRITE),
+    ("execute", Access::EXECUTE),
+];
+
+const ACTIONS: &[(&str, Action)] = &[
+    ("notify", Action::NOTIFY),
+    ("continue", Action::CONTINUE),
+    ("block", Action::BLOCK),
+    ("stealth", Action::STEALTH),
+    ("inspect", Action::INSPECT),
+];
+
+const MATCHES: &[(&str, &str, MatchType)] = &[
+    ("equal", "==", MatchType::EQUAL),
+    ("not-equal", "!=", MatchType::NOT_EQUAL),
+    ("greater", ">", MatchType::GREATER),
+    ("less", "<", MatchType::LESS),
+    ("greater-or-equal", ">=", MatchType::GREATER_OR_EQUAL),
+    ("less-or-equal", "<=", MatchType::LESS_OR_EQUAL),
+];
+
+fn operator(cmp: MatchType) -> &'static str {
+    MATCHES.iter()
+           .find(|&&(_, _, known)| known == cmp)
+           .map_or("?", |&(_, operator, _)| operator)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subject {
+    Pid(u64),
+    // resolved to the pid of the first running process whose name contains it
+    Process(String),
+    Sid(Sid),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionPolicy {
+    pub subject: Subject,
+    pub cmp: MatchType,
+}
+
+impl fmt::Display for ConditionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cmp = operator(self.cmp);
+
+        match self.subject {
+            Subject::Pid(pid) => write!(f, "pid {} {}", cmp, pid),
+            Subject::Process(ref name) => write!(f, "process {} {:?}", cmp, name),
+            Subject::Sid(ref sid) => write!(f, "sid {} {}", cmp, sid),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionPolicy {
+    pub target: Target,
+    pub size: u64,
+    pub access: Access,
+    // `Region::new` picks its default action when missing
+    pub action: Option<Action>,
+}
+
+impl fmt::Display for RegionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "size: 0x{:x}, access: {:?}", self.size, self.access)?;
+
+        match self.action {
+            Some(action) => write!(f, ", action: {:?}", action),
+            None => write!(f, ", action: default"),
+        }
+    }
+}
+
+// `size` bytes starting at the target are copied, then overwritten with `bytes` at offset `at`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchPolicy {
+    pub target: Target,
+    pub size: u64,
+    pub at: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for PatchPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>();
+
+        write!(f, "size: 0x{:x}, at: 0x{:x}, bytes: {}", self.size, self.at, bytes.join(" "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuardPolicy {
+    pub name: String,
+    // conditions are ANDed, no filter guards every process
+    pub filter: Vec<ConditionPolicy>,
+    // written as a filter expression instead, see `memguard::Predicate`
+    pub expression: Option<Predicate>,
+    // answered to every interception forwarded to user mode
+    pub response: Option<Action>,
+    pub start: bool,
+    pub regions: Vec<RegionPolicy>,
+    pub patches: Vec<PatchPolicy>,
+}
+
+impl fmt::Display for GuardPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let filter = self.filter.iter()
+                                .map(|condition| condition.to_string())
+                                .chain(self.expression.iter().map(|predicate| predicate.to_string()))
+                                .collect::<Vec<String>>();
+
+        write!(f, "filter: [{}], response: ", filter.join(" && "))?;
+
+        match self.response {
+            Some(action) => write!(f, "{:?}", action)?,
+            None => write!(f, "default")?,
+        }
+
+        write!(f, ", start: {}", self.start)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionPolicy {
+    pub name: String,
+    pub options: PartitionOptions,
+    pub guards: Vec<GuardPolicy>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Policy {
+    pub partitions: Vec<PartitionPolicy>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(String, String),
+    Removed(String, String),
+    Changed(String, String, String),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::Added(ref item, ref description) => write!(f, "+ {}: {}", item, description),
+            Change::Removed(ref item, ref description) => write!(f, "- {}: {}", item, description),
+            Change::Changed(ref item, ref old, ref new) => write!(f, "~ {}: {} => {}", item, old, new),
+        }
+    }
+}
+
+impl Policy {
+    // picks the format from the file extension
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Policy, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+
+        match path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_lowercase()) {
+            Some(ref extension) if extension == "toml" => Ok(Policy::from_toml(&text)?),
+            Some(ref extension) if extension == "yaml" || extension == "yml" => Ok(Policy::from_yaml(&text)?),
+            _ => Err(PolicyError::Format(path.display().to_string()).into()),
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Policy, PolicyError> {
+        let document = text.parse::<Value>().map_err(|err| PolicyError::Syntax(err.to_string()))?;
+
+        Policy::decode(&document)
+    }
+
+    pub fn from_yaml(text: &str) -> Result<Policy, PolicyError> {
+        let documents = YamlLoader::load_from_str(text).map_err(|err| PolicyError::Syntax(err.to_string()))?;
+
+        match documents.first() {
+            Some(document) => Policy::decode(&from_yaml(document, "policy")?),
+            None => Ok(Policy::default()),
+        }
+    }
+
+    fn decode(document: &Value) -> Result<Policy, PolicyError> {
+        let root = Node::new(String::from("policy"), document)?;
+        root.keys(&["partition"])?;
+
+        let partitions = root.children("partition", "partition")?
+                             .iter()
+                             .map(decode_partition)
+                             .collect::<Result<Vec<PartitionPolicy>, _>>()?;
+
+        unique(&root.path, partitions.iter().map(|partition| format!("partition {:?}", partition.name)))?;
+
+        Ok(Policy { partitions: partitions })
+    }
+
+    // one line per object, keyed by its position in the policy
+    pub fn describe(&self) -> BTreeMap<String, String> {
+        let mut items = BTreeMap::new();
+
+        for partition in &self.partitions {
+            let key = format!("partition {:?}", partition.name);
+            items.insert(key.clone(), partition.options.to_string());
+
+            for guard in &partition.guards {
+                let key = format!("{} > guard {:?}", key, guard.name);
+                items.insert(key.clone(), guard.to_string());
+
+                for region in &guard.regions {
+                    items.insert(format!("{} > region {}", key, region.target), region.to_string());
+                }
+
+                for patch in &guard.patches {
+                    items.insert(format!("{} > patch {}", key, patch.target), patch.to_string());
+                }
+            }
+        }
+
+        items
+    }
+
+    // what applying `other` instead of this policy would change
+    pub fn diff(&self, other: &Policy) -> Vec<Change> {
+        let (old, new) = (self.describe(), other.describe());
+
+        let mut changes = old.iter().filter_map(|(item, description)| match new.get(item) {
+            None => Some(Change::Removed(item.clone(), description.clone())),
+            Some(updated) if updated != description => Some(Change::Changed(item.clone(), description.clone(), updated.clone())),
+            Some(_) => None,
+        }).collect::<Vec<Change>>();
+
+        changes.extend(new.iter()
+                          .filter(|&(item, _)| !old.contains_key(item))
+                          .map(|(item, description)| Change::Added(item.clone(), description.clone())));
+
+        changes
+    }
+}
+
+fn decode_partition(node: &Node) -> Result<PartitionPolicy, PolicyError> {
+    node.keys(&["name", "options", "guard"])?;
+
+    let name = node.required(node.string("name"), "name")?.to_string();
+
+    let mut options = PartitionOptions::default();
+
+    if let Some(switches) = node.child("options")? {
+        for (key, value) in switches.table {
+            let option = key.parse::<PartitionOption>().map_err(|err| switches.invalid(err.to_string()))?;
+            let enabled = value.as_bool().ok_or_else(|| switches.invalid(format!("{} expects true or false", key)))?;
+
+            options.set(option, enabled);
+        }
+    }
+
+    let guards = node.children("guard", "guard")?
+                     .iter()
+                     .map(decode_guard)
+                     .collect::<Result<Vec<GuardPolicy>, _>>()?;
+
+    unique(&node.path, guards.iter().map(|guard| format!("guard {:?}", guard.name)))?;
+
+    Ok(PartitionPolicy {
+        name: name,
+        options: options,
+        guards: guards,
+    })
+}
+
+fn decode_guard(node: &Node) -> Result<GuardPolicy, PolicyError> {
+    node.keys(&["name", "filter", "response", "start", "region", "patch"])?;
+
+    // either a list of conditions or a filter expression
+    let (filter, expression) = match node.table.get("filter").and_then(Value::as_str) {
+        Some(expression) => {
+            let predicate = expression.parse::<Predicate>().map_err(|err| node.invalid(err.to_string()))?;
+
+            match predicate.alternatives().map_err(|err| node.invalid(err.to_string()))?.len() {
+                1 => (vec![], Some(predicate)),
+                _ => return Err(node.invalid(String::from("a guard takes a single filter, split alternatives (||) into guards of their own"))),
+            }
+        },
+        None => {
+            let filter = node.children("filter", "condition")?
+                             .iter()
+                             .map(decode_condition)
+                             .collect::<Result<Vec<ConditionPolicy>, _>>()?;
+
+            (filter, None)
+        },
+    };
+
+    if filter.len() > MAX_CONDITIONS {
+        return Err(node.invalid(format!("a filter holds at most {} conditions", MAX_CONDITIONS)))
+    }
+
+    let regions = node.children("region", "region")?
+                      .iter()
+                      .map(decode_region)
+                      .collect::<Result<Vec<RegionPolicy>, _>>()?;
+
+    let patches = node.children("patch", "patch")?
+                      .iter()
+                      .map(decode_patch)
+                      .collect::<Result<Vec<PatchPolicy>, _>>()?;
+
+    unique(&node.path, regions.iter().map(|region| format!("region {}", region.target)))?;
+    unique(&node.path, patches.iter().map(|patch| format!("patch {}", patch.target)))?;
+
+    Ok(GuardPolicy {
+        name: node.required(node.string("name"), "name")?.to_string(),
+        filter: filter,
+        expression: expression,
+        response: node.flags("response", ACTIONS)?,
+        start: node.boolean("start")?.unwrap_or(true),
+        regions: regions,
+        patches: patches,
+    })
+}
+
+fn decode_condition(node: &Node) -> Result<ConditionPolicy, PolicyError> {
+    node.keys(&["pid", "process", "sid", "match"])?;
+
+    let subjects = [node.number("pid")?.map(Subject::Pid),
+                    node.string("process")?.map(|name| Subject::Process(name.to_string())),
+                    node.string("sid")?.map(|sid| sid.parse::<Sid>().map(Subject::Sid))
+                                       .map_or(Ok(None), |sid| sid.map(Some))
+                                       .map_err(|err| node.invalid(err.to_string()))?];
+
+    let mut subjects = subjects.iter().filter_map(|subject| subject.clone());
+
+    let subject = match (subjects.next(), subjects.next()) {
+        (Some(subject), None) => subject,
+        (None, _) => return Err(PolicyError::Missing(node.path.clone(), "pid, process or sid")),
+        (Some(_), Some(_)) => return Err(node.invalid(String::from("a condition checks one of pid, process or sid"))),
+    };
+
+    let cmp = match node.string("match")? {
+        None => MatchType::EQUAL,
+        Some(wanted) => MATCHES.iter()
+                               .find(|&&(name, operator, _)| name == wanted || operator == wanted)
+                               .map(|&(_, _, cmp)| cmp)
+                               .ok_or_else(|| node.invalid(format!("unknown match {:?}", wanted)))?,
+    };
+
+    Ok(ConditionPolicy {
+        subject: subject,
+        cmp: cmp,
+    })
+}
+
+fn decode_target(node: &Node) -> Result<Target, PolicyError> {
+    let offset = node.number("offset")?.unwrap_or(0);
+
+    let target = match (node.number("address")?, node.string("symbol")?, node.string("driver")?, node.string("target")?) {
+        (Some(address), None, None, None) => Target::Address(address.checked_add(offset)
+                                                                     .ok_or_else(|| node.invalid(format!("offset 0x{:x} overflows address 0x{:x}", offset, address)))?),
+        (None, Some(symbol), None, None) => {
+            let mut parts = symbol.splitn(2, '!');
+
+            match (parts.next(), parts.next()) {
+                (Some(module), Some(name)) if !module.is_empty() && !name.is_empty() => Target::Symbol {
+                    module: module.to_string(),
+                    name: name.to_string(),
+                    offset: offset,
+                },
+                _ => return Err(node.invalid(format!("symbol {:?} isn't written as module!name", symbol))),
+            }
+        },
+        (None, None, Some(driver), None) => Target::Driver {
+            name: driver.to_string(),
+            offset: offset,
+        },
+        // any expression `Target` parses, e.g. _EPROCESS(pid=4).Token
+        (None, None, None, Some(expression)) if offset == 0 => expression.parse::<Target>().map_err(|err| node.invalid(err.to_string()))?,
+        (None, None, None, Some(_)) => return Err(node.invalid(String::from("target expressions carry their own +offset"))),
+        (None, None, None, None) => return Err(PolicyError::Missing(node.path.clone(), "address, symbol, driver or target")),
+        _ => return Err(node.invalid(String::from("a target is one of address, symbol, driver or target"))),
+    };
+
+    Ok(target)
+}
+
+fn decode_region(node: &Node) -> Result<RegionPolicy, PolicyError> {
+    node.keys(&["address", "symbol", "driver", "target", "offset", "size", "access", "action"])?;
+
+    let size = node.required(node.number("size"), "size")?;
+    let access = node.required(node.flags("access", ACCESS), "access")?;
+
+    if size == 0 || access.is_empty() {
+        return Err(node.invalid(String::from("regions need a size and at least one access")))
+    }
+
+    Ok(RegionPolicy {
+        target: decode_target(node)?,
+        size: size,
+        access: access,
+        action: node.flags("action", ACTIONS)?,
+    })
+}
+
+fn decode_patch(node: &Node) -> Result<PatchPolicy, PolicyError> {
+    node.keys(&["address", "symbol", "driver", "target", "offset", "size", "at", "bytes"])?;
+
+    let size = node.required(node.number("size"), "size")?;
+    let at = node.number("at")?.unwrap_or(0);
+
+    let bytes = node.required(node.string("bytes"), "bytes")?;
+    let bytes = bytes.split_whitespace()
+                     .flat_map(|chunk| chunk.as_bytes().chunks(2).map(|pair| String::from_utf8_lossy(pair).into_owned()).collect::<Vec<String>>())
+                     .map(|pair| if pair.len() == 2 { u8::from_str_radix(&pair, 16).ok() } else { None })
+                     .collect::<Option<Vec<u8>>>()
+                     .ok_or_else(|| node.invalid(format!("bytes {:?} aren't hexadecimal pairs", bytes)))?;
+
+    let fits = (bytes.len() as u64).checked_add(at).map_or(false, |end| end <= size);
+
+    if bytes.is_empty() || !fits {
+        return Err(node.invalid(format!("{} bytes at 0x{:x} don't fit in a 0x{:x} bytes patch", bytes.len(), at, size)))
+    }
+
+    Ok(PatchPolicy {
+        target: decode_target(node)?,
+        size: size,
+        at: at,
+        bytes: bytes,
+    })
+}
+
+fn unique<I: Iterator<Item = String>>(path: &str, items: I) -> Result<(), PolicyError> {
+    let mut seen = HashSet::new();
+
+    for item in items {
+        if !seen.insert(item.clone()) {
+            return Err(PolicyError::Duplicate(path.to_string(), item))
+        }
+    }
+
+    Ok(())
+}
+
+// accepts 4096, "4096", "0x1000" and WinDbg style "fffff800`00001000" with an 0x prefix
+fn parse_number(value: &str) -> Option<u64> {
+    let value = value.trim().replace('`', "").replace('_', "");
+
+    match value.starts_with("0x") || value.starts_with("0X") {
+        true => u64::from_str_radix(&value[2..], 16).ok(),
+        false => value.parse().ok(),
+    }
+}
+
+// YAML documents are turned into TOML values so that a single decoder handles both
+fn from_yaml(yaml: &Yaml, path: &str) -> Result<Value, PolicyError> {
+    let value = match *yaml {
+        Yaml::String(ref value) => Value::String(value.clone()),
+        Yaml::Integer(value) => Value::Integer(value),
+        Yaml::Real(ref value) => Value::Float(value.parse().map_err(|_| PolicyError::Invalid(path.to_string(), format!("invalid number {}", value)))?),
+        Yaml::Boolean(value) => Value::Boolean(value),
+        Yaml::Array(ref values) => Value::Array(values.iter().map(|value| from_yaml(value, path)).collect::<Result<Vec<Value>, _>>()?),
+        Yaml::Hash(ref entries) => {
+            let mut table = Table::new();
+
+            for (key, value) in entries {
+                let key = key.as_str().ok_or_else(|| PolicyError::Invalid(path.to_string(), format!("key {:?} isn't a string", key)))?;
+                table.insert(key.to_string(), from_yaml(value, &format!("{} > {}", path, key))?);
+            }
+
+            Value::Table(table)
+        },
+        _ => return Err(PolicyError::Invalid(path.to_string(), String::from("empty or unsupported value"))),
+    };
+
+    Ok(value)
+}
+
+// a table of the document along with a readable path to it, for error messages
+struct Node<'a> {
+    path: String,
+    table: &'a Table,
+}
+
+impl<'a> Node<'a> {
+    fn new(path: String, value: &'a Value) -> Result<Node<'a>, PolicyError> {
+        match value.as_table() {
+            Some(table) => Ok(Node { path: path, table: table }),
+            None => Err(PolicyError::Invalid(path, String::from("expected a table"))),
+        }
+    }
+
+    fn invalid(&self, message: String) -> PolicyError {
+        PolicyError::Invalid(self.path.clone(), message)
+    }
+
+    fn required<T>(&self, value: Result<Option<T>, PolicyError>, key: &'static str) -> Result<T, PolicyError> {
+        value?.ok_or_else(|| PolicyError::Missing(self.path.clone(), key))
+    }
+
+    // catches typos, which would otherwise silently weaken a protection
+    fn keys(&self, known: &[&str]) -> Result<(), PolicyError> {
+        match self.table.keys().find(|key| !known.contains(&key.as_str())) {
+            Some(key) => Err(PolicyError::UnknownKey(self.path.clone(), key.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<Option<&'a str>, PolicyError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(value) => value.as_str().map(Some).ok_or_else(|| self.invalid(format!("{} expects a string", key))),
+        }
+    }
+
+    fn boolean(&self, key: &str) -> Result<Option<bool>, PolicyError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(value) => value.as_bool().map(Some).ok_or_else(|| self.invalid(format!("{} expects true or false", key))),
+        }
+    }
+
+    fn number(&self, key: &str) -> Result<Option<u64>, PolicyError> {
+        let number = match self.table.get(key) {
+            None => return Ok(None),
+            Some(&Value::Integer(value)) if value >= 0 => Some(value as u64),
+            Some(&Value::String(ref value)) => parse_number(value),
+            Some(_) => None,
+        };
+
+        number.map(Some).ok_or_else(|| self.invalid(format!("{} expects a positive number", key)))
+    }
+
+    // either "read|write" or ["read", "write"]
+    fn flags<T: Copy + BitOr<Output = T>>(&self, key: &str, known: &[(&str, T)]) -> Result<Option<T>, PolicyError> {
+        let names = match self.table.get(key) {
+            None => return Ok(None),
+            Some(&Value::String(ref names)) => names.split('|').map(str::trim).collect::<Vec<&str>>(),
+            Some(&Value::Array(ref names)) => names.iter()
+                                                   .map(|name| name.as_str().ok_or_else(|| self.invalid(format!("{} expects names", key))))
+                                                   .collect::<Result<Vec<&str>, _>>()?,
+            Some(_) => return Err(self.invalid(format!("{} expects names", key))),
+        };
+
+        let flags = names.iter().map(|name| {
+            known.iter()
+                 .find(|&&(known, _)| known.eq_ignore_ascii_case(name))
+                 .map(|&(_, flag)| flag)
+                 .ok_or_else(|| self.invalid(format!("unknown {} {:?}", key, name)))
+        }).collect::<Result<Vec<T>, _>>()?;
+
+        let mut flags = flags.into_iter();
+
+        Ok(flags.next().map(|first| flags.fold(first, |all, flag| all | flag)))
+    }
+
+    fn child(&self, key: &str) -> Result<Option<Node<'a>>, PolicyError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(value) => Node::new(format!("{} > {}", self.path, key), value).map(Some),
+        }
+    }
+
+    // a single table or an array of tables, `label` names each of them in error messages
+    fn children(&self, key: &str, label: &str) -> Result<Vec<Node<'a>>, PolicyError> {
+        let values = match self.table.get(key) {
+            None => return Ok(vec![]),
+            Some(&Value::Array(ref values)) => values.iter().collect::<Vec<&Value>>(),
+            Some(value) => vec![value],
+        };
+
+        values.into_iter().enumerate().map(|(index, value)| {
+            let name = value.get("name").and_then(Value::as_str).map_or_else(|| index.to_string(), |name| format!("{:?}", name));
+
+            Node::new(format!("{} > {} {}", self.path, label, name), value)
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKENS: &str = r#"
+        [[partition]]
+        name = "tokens"
+        options = { collect-stats = true }
+
+        [[partition.guard]]
+        name = "lsass"
+        filter = [{ process = "lsass.exe" }, { sid = "S-1-5-18", match = "!=" }]
+        response = "stealth"
+
+        [[partition.guard.region]]
+        symbol = "nt!PsInitialSystemProcess"
+        offset = "0x10"
+        size = 8
+        access = ["write"]
+
+        [[partition.guard.patch]]
+        driver = "HEVD.sys"
+        offset = "0x5000"
+        size = "0x1000"
+        at = "0xBEC"
+        bytes = "90 90 9090"
+    "#;
+
+    #[test]
+    fn test_toml_and_yaml_agree() {
+        let policy = Policy::from_toml(TOKENS).unwrap();
+
+        let guard = &policy.partitions[0].guards[0];
+        assert!(policy.partitions[0].options.collect_stats);
+        assert_eq!(guard.filter[1], ConditionPolicy { subject: Subject::Sid("S-1-5-18".parse().unwrap()), cmp: MatchType::NOT_EQUAL });
+        assert_eq!(guard.response, Some(Action::STEALTH));
+        assert!(guard.start);
+        assert_eq!(guard.regions[0].target.to_string(), "nt!PsInitialSystemProcess+0x10");
+        assert_eq!(guard.regions[0].action, None);
+        assert_eq!(guard.patches[0].bytes, vec![0x90; 4]);
+
+        let yaml = Policy::from_yaml(r#"
+partition:
+  - name: tokens
+    options: { collect-stats: true }
+    guard:
+      - name: lsass
+        filter: [{ process: lsass.exe }, { sid: S-1-5-18, match: "!=" }]
+        response: stealth
+        region:
+          - { symbol: "nt!PsInitialSystemProcess", offset: "0x10", size: 8, access: write }
+        patch:
+          - { driver: HEVD.sys, offset: 0x5000, size: 4096, at: 3052, bytes: "90909090" }
+"#).unwrap();
+
+        assert_eq!(yaml, policy);
+    }
+
+    #[test]
+    fn test_errors_point_at_the_object() {
+        let err = Policy::from_toml(r#"
+            [[partition]]
+            name = "p"
+            [[partition.guard]]
+            name = "g"
+            [[partition.guard.region]]
+            address = "0x1000"
+            size = 8
+            acess = "write"
+        "#).unwrap_err();
+
+        assert_eq!(err.to_string(), "policy > partition \"p\" > guard \"g\" > region 0: unknown key \"acess\"");
+
+        let err = Policy::from_toml(r#"
+            [[partition]]
+            name = "p"
+            [[partition.guard]]
+            name = "g"
+            [[partition.guard.patch]]
+            address = 4096
+            size = 2
+            bytes = "90 90 90"
+        "#).unwrap_err();
+
+        assert!(err.to_string().ends_with("3 bytes at 0x0 don't fit in a 0x2 bytes patch"));
+
+        let err = Policy::from_toml(r#"
+            [[partition]]
+            name = "p"
+            [[partition.guard]]
+            name = "g"
+            [[partition.guard.patch]]
+            address = 4096
+            size = 2
+            at = "0xffffffffffffffff"
+            bytes = "90"
+        "#).unwrap_err();
+
+        assert!(err.to_string().ends_with("1 bytes at 0xffffffffffffffff don't fit in a 0x2 bytes patch"));
+
+        let err = Policy::from_toml(r#"
+            [[partition]]
+            name = "p"
+            [[partition.guard]]
+            name = "g"
+            [[partition.guard.region]]
+            address = "0xfffffffffffff000"
+            offset = "0x1000"
+            size = 8
+            access = "write"
+        "#).unwrap_err();
+
+        assert!(err.to_string().ends_with("offset 0x1000 overflows address 0xfffffffffffff000"));
+
+        let err = Policy::from_toml("[[partition]]\nname = \"p\"\n[[partition]]\nname = \"p\"").unwrap_err();
+        assert_eq!(err.to_string(), "policy: partition \"p\" is declared twice");
+    }
+
+    #[test]
+    fn test_filter_expressions() {
+        let guard = |filter: &str| Policy::from_toml(&format!("[[partition]]\nname = \"p\"\n[[partition.guard]]\nname = \"g\"\nfilter = {:?}", filter));
+
+        let policy = guard("image == lsass.exe && sid != S-1-5-18").unwrap();
+        let expression = policy.partitions[0].guards[0].expression.clone().unwrap();
+        assert_eq!(expression.to_string(), "image == \"lsass.exe\" && sid != S-1-5-18");
+
+        let err = guard("pid == 4 || pid == 8").unwrap_err();
+        assert!(err.to_string().contains("split alternatives"));
+
+        let err = guard("pid == 4 &&").unwrap_err();
+        assert!(err.to_string().starts_with("policy > partition \"p\" > guard \"g\": Invalid filter"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Policy::from_toml(TOKENS).unwrap();
+
+        let mut new = old.clone();
+        new.partitions[0].guards[0].regions[0].size = 0x10;
+        new.partitions[0].guards[0].patches.clear();
+        new.partitions[0].options.secure_mode = true;
+
+        let changes = old.diff(&new);
+
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].to_string().chars().next(), Some('~'));
+        assert!(changes.iter().any(|change| match *change {
+            Change::Removed(ref item, _) => item.ends_with("> patch driver:HEVD.sys+0x5000"),
+            _ => false,
+        }));
+        assert!(changes.iter().any(|change| change.to_string().contains("size: 0x8, access: WRITE, action: default => size: 0x10")));
+
+        assert!(old.diff(&old).is_empty());
+    }
+}
@@ -3,25 +3,26 @@
 
 use super::{memory, misc};
 
-use super::iochannel::{Device};
+use super::session::SentrySession;
+use super::failure::Error;
 use std::str;
 
 // use super::symbols::parser::Error;
 const MAX_SEARCH_SIZE: usize = 0x1_0000;
 
-pub fn pattern(device: &Device, name: &str, pattern: &[u8], neighbour: Option<&str>) -> Option<u64> {
-    if let Some(driver) = misc::Drivers::contains(name) {
+// address of `pattern` within a driver, searched from one of its exports when given a `neighbour`
+pub fn pattern(session: &SentrySession, name: &str, pattern: &[u8], neighbour: Option<&str>) -> Result<Option<u64>, Error> {
+    if let Some(driver) = misc::Drivers::contains(name)? {
 
         let mut address = driver.base();
         let mut limit = MAX_SEARCH_SIZE;
 
         match neighbour {
-            Some(name) => address = misc::kernel_export_address(device, driver.base(), name)
-                                .expect("unable to find neighbour"),
+            Some(name) => address = misc::kernel_export_address(session, driver.base(), name)?,
             None => limit = driver.size()
         }
 
-        let map = memory::Map::new(device, address, limit, None);
+        let map = memory::Map::new(session, address, limit, None)?;
 
         //
         // this code looks with side-effects but its verified, there is an algorithm from str
@@ -34,10 +35,10 @@ pub fn pattern(device: &Device, name: &str, pattern: &[u8], neighbour: Option<&s
 
         if code.contains(pattern) {
             if let Some(offset) = code.find(pattern) {
-                return Some(address + offset as u64)
+                return Ok(Some(address + offset as u64))
             }
         }
     } 
 
-    None
+    Ok(None)
 }
\ No newline at end of file
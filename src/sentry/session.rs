@@ -0,0 +1,245 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+#[cfg(windows)]
+use super::iochannel::Device;
+use super::iochannel::{IoCtl, Transport};
+use super::iochannel::error::DeviceError;
+use super::failure::Error;
+use super::iochannel::record::Recorder;
+use super::iochannel::metrics::Metrics;
+#[cfg(windows)]
+use super::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use std::ops::Deref;
+use std::io::Cursor;
+use std::os::raw::c_void;
+use std::sync::Arc;
+use std::fmt;
+
+/// How `SentrySession::open_with` sets up the device, e.g. from `conveyor --record`
+/// and `conveyor --metrics`.
+#[derive(Debug, Clone, Default)]
+pub struct SessionOptions {
+    record: Option<PathBuf>,
+    // IOCTLs the recorders of these sessions failed to store
+    lost: Arc<AtomicUsize>,
+    // file the devices of these sessions add their metrics to
+    file: Option<PathBuf>,
+    metrics: Arc<Metrics>,
+}
+
+impl SessionOptions {
+    pub fn new() -> SessionOptions {
+        SessionOptions::default()
+    }
+
+    // appends every IOCTL of the sessions to `path`, see `Recorder`
+    pub fn record<P: Into<PathBuf>>(mut self, path: P) -> SessionOptions {
+        self.record = Some(path.into());
+        self
+    }
+
+    pub fn recording(&self) -> Option<&Path> {
+        self.record.as_ref().map(|path| path.as_path())
+    }
+
+    // accounts every IOCTL of the devices, saved to `path` by `finish`, see `device stats`
+    pub fn metrics<P: Into<PathBuf>>(mut self, path: P) -> SessionOptions {
+        self.file = Some(path.into());
+        self
+    }
+
+    // counters shared by every device these options account
+    pub fn accounted(&self) -> Option<&Arc<Metrics>> {
+        self.file.as_ref().map(|_| &self.metrics)
+    }
+
+    /// Lets `device` account its requests when these options collect metrics.
+    #[cfg(windows)]
+    pub fn account(&self, device: Device) -> Device {
+        match self.accounted() {
+            Some(metrics) => device.with_metrics(Arc::clone(metrics)),
+            None => device,
+        }
+    }
+
+    /// Wraps `transport` the way these options ask for.
+    pub fn session<T: Transport + 'static>(&self, transport: T) -> Result<SentrySession, Error> {
+        match self.record {
+            Some(ref path) => Ok(SentrySession::new(Recorder::append(transport, path)?.counting_losses(Arc::clone(&self.lost)))),
+            None => Ok(SentrySession::new(transport)),
+        }
+    }
+
+    /// Saves the metrics of the sessions and reports what they failed to record, once
+    /// they're done.
+    pub fn finish(&self) -> Result<(), Error> {
+        if let Some(ref file) = self.file {
+            self.metrics.save(file)
+                        .map_err(|err| format_err!("error saving metrics to {}: {}", file.display(), err))?;
+        }
+
+        match (self.lost.load(Ordering::SeqCst), &self.record) {
+            (0, _) | (_, &None) => Ok(()),
+            (lost, &Some(ref path)) => Err(format_err!("{} IOCTLs are missing from the recording {}", lost, path.display())),
+        }
+    }
+}
+
+/// A single handle to the Sentry driver, shared by everything built on top of it.
+///
+/// Cloning a session only bumps a reference count, so partitions, guards, filters,
+/// processes and memory maps created from the same session all issue their requests
+/// through one device handle, from any thread. The handle is closed once the last
+/// clone is dropped.
+#[derive(Clone)]
+pub struct SentrySession {
+    transport: Arc<dyn Transport>,
+}
+
+impl SentrySession {
+    // opens the Sentry device
+    pub fn open() -> Result<SentrySession, Error> {
+        SentrySession::open_with(&SessionOptions::default())
+    }
+
+    /// Opens the Sentry device as `options` ask for.
+    #[cfg(windows)]
+    pub fn open_with(options: &SessionOptions) -> Result<SentrySession, Error> {
+        options.session(options.account(Device::new(io::SE_NT_DEVICE_NAME)?))
+    }
+
+    // there's no driver to open off Windows, only other transports
+    #[cfg(not(windows))]
+    pub fn open_with(_options: &SessionOptions) -> Result<SentrySession, Error> {
+        Err(format_err!("The Sentry device is only available on Windows"))
+    }
+
+    // wraps any transport, e.g. a configured `Device`, a `Recorder` or the simulator
+    pub fn new<T: Transport + 'static>(transport: T) -> SentrySession {
+        SentrySession {
+            transport: Arc::new(transport),
+        }
+    }
+
+    pub fn transport(&self) -> &dyn Transport {
+        &*self.transport
+    }
+
+    // number of live clones, including this one
+    pub fn handles(&self) -> usize {
+        Arc::strong_count(&self.transport)
+    }
+}
+
+impl From<Arc<dyn Transport>> for SentrySession {
+    fn from(transport: Arc<dyn Transport>) -> SentrySession {
+        SentrySession { transport: transport }
+    }
+}
+
+impl Deref for SentrySession {
+    type Target = dyn Transport;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.transport
+    }
+}
+
+// lets `&session` be handed wherever a `&dyn Transport` is expected
+impl Transport for SentrySession {
+    fn call(&self, control: IoCtl, input: Option<Vec<u8>>, output: Option<Vec<u8>>) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        self.transport.call(control, input, output)
+    }
+
+    fn raw_call(&self, control: IoCtl, ptr: *mut c_void, len: usize) -> Result<(), DeviceError> {
+        self.transport.raw_call(control, ptr, len)
+    }
+
+    fn call_sized(&self, control: IoCtl, input: Option<Vec<u8>>, size: usize, limit: usize) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        self.transport.call_sized(control, input, size, limit)
+    }
+}
+
+impl fmt::Debug for SentrySession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SentrySession({:?})", self.transport)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::simulator::Simulator;
+    use super::super::memguard::Partition;
+    use super::super::io;
+    use super::super::iochannel::record;
+
+    use std::{env, fs, process, thread};
+    use std::time::Duration;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_shared_across_threads() {
+        assert_send_sync::<SentrySession>();
+        assert_send_sync::<Partition>();
+
+        let session = SentrySession::new(Simulator::new());
+
+        let workers = (0..4).map(|_| {
+            let session = session.clone();
+            thread::spawn(move || io::create_partition(&session).map(|channel| channel.id))
+        }).collect::<Vec<_>>();
+
+        let mut ids = workers.into_iter()
+                             .map(|worker| worker.join().expect("worker panicked").unwrap())
+                             .collect::<Vec<u64>>();
+        ids.sort();
+        ids.dedup();
+
+        assert_eq!(ids.len(), 4);
+        assert_eq!(session.handles(), 1);
+    }
+
+    #[test]
+    fn test_options_record_sessions() {
+        let path = env::temp_dir().join(format!("conveyor-session-{}.rec", process::id()));
+        let options = SessionOptions::new().record(&path);
+
+        // every session adds to the same recording
+        for _ in 0..2 {
+            let session = options.session(Simulator::new()).unwrap();
+            io::create_partition(&session).unwrap();
+        }
+
+        let entries = record::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert!(options.finish().is_ok());
+        assert!(SessionOptions::new().recording().is_none());
+    }
+
+    #[test]
+    fn test_options_save_metrics() {
+        let path = env::temp_dir().join(format!("conveyor-session-{}.metrics", process::id()));
+        let options = SessionOptions::new().metrics(&path);
+        let control = IoCtl::new(Some("SE_IOCTL_CREATE_PARTITION"), io::IOCTL_SENTRY_TYPE, 0x0A00, None, None);
+
+        // saving adds to the counters of earlier runs
+        for _ in 0..2 {
+            options.accounted().unwrap().reset();
+            options.accounted().unwrap().record(&control, 0, Ok(24), Duration::from_millis(1));
+            options.finish().unwrap();
+        }
+
+        let saved = Metrics::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved.get(&control).unwrap().calls, 2);
+        assert!(SessionOptions::new().accounted().is_none());
+    }
+}
@@ -0,0 +1,838 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+//
+// In-process emulation of the Sentry driver.
+//
+// Partitions, guards, regions and patches are kept in memory and every I/O control is
+// answered with the same layouts (and Win32 error codes) the driver uses, which allows
+// exercising the sentry and memguard layers without a loaded driver.
+//
+// "Kernel" memory is plain heap memory owned by the simulator, so mapping an address
+// returns the very same pointer.
+//
+
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use super::iochannel::{IoCtl, Transport};
+use super::iochannel::error::DeviceError;
+use super::io::IOCTL_SENTRY_TYPE;
+
+use super::structs::{LPVOID,
+                     SE_MAP_VIRTUAL_MEMORY,
+                     SE_UNMAP_VIRTUAL_MEMORY,
+                     SE_READ_PROCESS_MEMORY,
+                     SE_WRITE_PROCESS_MEMORY,
+                     SE_ALLOC_VIRTUAL_MEMORY,
+                     SE_FREE_VIRTUAL_MEMORY,
+                     SE_SECURE_VIRTUAL_MEMORY,
+                     SE_UNSECURE_VIRTUAL_MEMORY,
+                     SE_COPY_VIRTUAL_MEMORY,
+                     SE_READ_VIRTUAL_MEMORY,
+                     SE_WRITE_VIRTUAL_MEMORY,
+                     SE_ALLOC_PROCESS_MEMORY,
+                     SE_FREE_PROCESS_MEMORY,
+                     SE_GET_EXPORT_ADDRESS};
+
+use std::sync::{Mutex, MutexGuard};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::{fmt, mem, ptr, slice};
+
+// Win32 error codes returned by the driver
+pub const ERROR_INVALID_FUNCTION: i32 = 1;
+pub const ERROR_INVALID_PARAMETER: i32 = 87;
+pub const ERROR_INSUFFICIENT_BUFFER: i32 = 122;
+pub const ERROR_PROC_NOT_FOUND: i32 = 127;
+pub const ERROR_MORE_DATA: i32 = 234;
+pub const ERROR_ALREADY_EXISTS: i32 = 183;
+pub const ERROR_INVALID_ADDRESS: i32 = 487;
+pub const ERROR_NOACCESS: i32 = 998;
+pub const ERROR_DEVICE_NOT_CONNECTED: i32 = 1167;
+pub const ERROR_NOT_FOUND: i32 = 1168;
+
+const PARTITION_OPTIONS: u64 = 6;
+const FIRST_OBJECT_ID: u64 = 0x1000;
+
+type SimResult<T> = Result<T, i32>;
+
+struct SimPartition {
+    options: HashMap<u64, u64>,
+}
+
+#[allow(dead_code)]
+struct SimGuard {
+    partition: u64,
+    process: u64,
+    filter: u64,
+    priority: u64,
+    started: bool,
+    regions: Vec<u64>,
+    patches: Vec<u64>,
+}
+
+struct SimRegion {
+    partition: u64,
+    base: u64,
+    size: u64,
+    flags: u64,
+    access: u32,
+    action: u64,
+    read_buffer: u64,
+    write_buffer: u64,
+    weight: u64,
+}
+
+struct SimPatch {
+    partition: u64,
+    base: u64,
+    patch: u64,
+    size: u64,
+    flags: u64,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    partitions: BTreeMap<u64, SimPartition>,
+    guards: BTreeMap<u64, SimGuard>,
+    regions: BTreeMap<u64, SimRegion>,
+    patches: BTreeMap<u64, SimPatch>,
+    monitors: BTreeMap<u64, bool>,
+    memory: BTreeMap<u64, Box<[u8]>>,
+    mappings: BTreeMap<u64, u64>,
+    secured: BTreeMap<u64, (u64, usize)>,
+    exports: HashMap<String, u64>,
+}
+
+pub struct Simulator {
+    state: Mutex<State>,
+}
+
+impl Simulator {
+    pub fn new() -> Simulator {
+        Simulator {
+            state: Mutex::new(State {
+                next_id: FIRST_OBJECT_ID,
+                .. State::default()
+            })
+        }
+    }
+
+    // registers the address answered for `name` by SE_GET_EXPORT_ADDRESS
+    pub fn export(self, name: &str, address: u64) -> Simulator {
+        self.state().exports.insert(name.to_string(), address);
+        self
+    }
+
+    pub fn partitions(&self) -> Vec<u64> {
+        self.state().partitions.keys().cloned().collect()
+    }
+
+    pub fn guards(&self, partition: u64) -> Vec<u64> {
+        self.state().guards.iter()
+                  .filter(|&(_, guard)| guard.partition == partition)
+                  .map(|(&id, _)| id)
+                  .collect()
+    }
+
+    pub fn is_started(&self, guard: u64) -> Option<bool> {
+        self.state().guards.get(&guard).map(|guard| guard.started)
+    }
+
+    pub fn allocations(&self) -> usize {
+        self.state().memory.len()
+    }
+
+    fn state(&self) -> MutexGuard<State> {
+        self.state.lock().expect("simulator state poisoned")
+    }
+
+    fn error(control: IoCtl, code: i32) -> DeviceError {
+        DeviceError::io(control, code as u32, None)
+    }
+
+    fn dispatch(&self, function: u32, input: &[u8]) -> SimResult<Vec<u8>> {
+        let mut state = self.state();
+        let mut input = Cursor::new(input);
+
+        match function {
+            0x0A00 => state.create_partition(),
+            0x0A01 => state.delete_partition(read(&mut input)?),
+            0x0A02 => state.get_partition_option(read(&mut input)?, read(&mut input)?),
+            0x0A03 => state.set_partition_option(read(&mut input)?, read(&mut input)?, read(&mut input)?),
+            0x0A10 => state.register_guard(&mut input),
+            0x0A11 => state.unregister_guard(read(&mut input)?),
+            0x0A12 => state.control_guard(read(&mut input)?, read(&mut input)?),
+            0x0A20 => state.create_region(&mut input),
+            0x0A21 => state.delete_region(read(&mut input)?),
+            0x0A22 => state.add_region(read(&mut input)?, read(&mut input)?),
+            0x0A23 => state.remove_region(read(&mut input)?, read(&mut input)?),
+            0x0A24 => state.set_state_region(read(&mut input)?, read(&mut input)?),
+            0x0A25 => state.region_info(read(&mut input)?, 0),
+            0x0A26 => state.enumerate_regions(read(&mut input)?, read(&mut input)?),
+            0x0A40 => state.create_patch(&mut input),
+            0x0A41 => state.delete_patch(read(&mut input)?),
+            0x0A42 => state.add_patch(read(&mut input)?, read(&mut input)?),
+            0x0A43 => state.remove_patch(read(&mut input)?, read(&mut input)?),
+            0x0A44 => state.set_state_patch(read(&mut input)?, true),
+            0x0A45 => state.set_state_patch(read(&mut input)?, false),
+            0x0A46 => state.patch_info(read(&mut input)?, 0),
+            0x0A47 => state.enumerate_patches(read(&mut input)?, read(&mut input)?),
+            0x0A70 => state.create_monitor(),
+            0x0A71 => state.destroy_monitor(read(&mut input)?),
+            0x0A72 => state.control_monitor(read(&mut input)?, true),
+            0x0A73 => state.control_monitor(read(&mut input)?, false),
+            _ => Err(ERROR_INVALID_FUNCTION),
+        }
+    }
+
+    fn dispatch_raw(&self, function: u32, buffer: &mut [u8]) -> SimResult<()> {
+        let mut state = self.state();
+
+        match function {
+            0x0A50 => with_struct(buffer, |alloc: &mut SE_ALLOC_VIRTUAL_MEMORY| {
+                alloc.BaseAddress = state.alloc(alloc.Size)? as LPVOID;
+                Ok(())
+            }),
+            0x0A51 => with_struct(buffer, |free: &mut SE_FREE_VIRTUAL_MEMORY| {
+                state.free(free.BaseAddress as u64)
+            }),
+            0x0A52 => with_struct(buffer, |copy: &mut SE_COPY_VIRTUAL_MEMORY| {
+                let from = state.resolve(copy.FromAddress as u64, copy.Size)?;
+                let to = state.resolve(copy.ToAddress as u64, copy.Size)?;
+                unsafe { ptr::copy(from, to, copy.Size) };
+                Ok(())
+            }),
+            0x0A53 => with_struct(buffer, |secure: &mut SE_SECURE_VIRTUAL_MEMORY| {
+                state.resolve(secure.BaseAddress as u64, secure.Size)?;
+                let handle = state.id();
+                state.secured.insert(handle, (secure.BaseAddress as u64, secure.Size));
+                secure.SecureHandle = handle as _;
+                Ok(())
+            }),
+            0x0A54 => with_struct(buffer, |secure: &mut SE_UNSECURE_VIRTUAL_MEMORY| {
+                state.secured.remove(&(secure.SecureHandle as u64))
+                     .map(|_| ())
+                     .ok_or(ERROR_INVALID_PARAMETER)
+            }),
+            0x0A55 => with_struct(buffer, |map: &mut SE_MAP_VIRTUAL_MEMORY| {
+                let address = state.resolve(map.BaseAddress as u64, map.Size as usize)?;
+                let mdl = state.id();
+                state.mappings.insert(mdl, address as u64);
+                map.Mdl = mdl as LPVOID;
+                map.MappedMemory = address as LPVOID;
+                Ok(())
+            }),
+            0x0A56 => with_struct(buffer, |unmap: &mut SE_UNMAP_VIRTUAL_MEMORY| {
+                match state.mappings.remove(&(unmap.Mdl as u64)) {
+                    Some(address) if address == unmap.MappedMemory as u64 => Ok(()),
+                    _ => Err(ERROR_INVALID_PARAMETER),
+                }
+            }),
+            0x0A57 => with_struct(buffer, |read: &mut SE_READ_VIRTUAL_MEMORY| {
+                let from = state.resolve(read.BaseAddress as u64, read.BytesToRead as usize)?;
+                unsafe { ptr::copy(from, read.Buffer as *mut u8, read.BytesToRead as usize) };
+                read.BytesCopied = read.BytesToRead;
+                Ok(())
+            }),
+            0x0A58 => with_struct(buffer, |write: &mut SE_WRITE_VIRTUAL_MEMORY| {
+                let to = state.resolve(write.BaseAddress as u64, write.BytesToWrite as usize)?;
+                unsafe { ptr::copy(write.Buffer as *const u8, to, write.BytesToWrite as usize) };
+                write.BytesCopied = write.BytesToWrite;
+                Ok(())
+            }),
+            // there is a single address space, so process memory is regular memory
+            0x0A59 => with_struct(buffer, |alloc: &mut SE_ALLOC_PROCESS_MEMORY| {
+                alloc.BaseAddress = state.alloc(alloc.BytesToAlloc)? as LPVOID;
+                Ok(())
+            }),
+            0x0A5A => with_struct(buffer, |free: &mut SE_FREE_PROCESS_MEMORY| {
+                state.free(free.BaseAddress as u64)
+            }),
+            0x0A5B => with_struct(buffer, |read: &mut SE_READ_PROCESS_MEMORY| {
+                let from = state.resolve(read.BaseAddress as u64, read.BytesToRead)?;
+                unsafe { ptr::copy(from, read.Buffer as *mut u8, read.BytesToRead) };
+                read.BytesCopied = read.BytesToRead;
+                Ok(())
+            }),
+            0x0A5C => with_struct(buffer, |write: &mut SE_WRITE_PROCESS_MEMORY| {
+                let to = state.resolve(write.BaseAddress as u64, write.BytesToWrite)?;
+                unsafe { ptr::copy(write.Buffer as *const u8, to, write.BytesToWrite) };
+                write.BytesCopied = write.BytesToWrite;
+                Ok(())
+            }),
+            // token stealing and kernel tests have no observable state here
+            0x0A60 | 0x0A63 => Ok(()),
+            0x0A62 => with_struct(buffer, |info: &mut SE_GET_EXPORT_ADDRESS| {
+                let name = info.Name.iter()
+                               .map(|&c| char::from(c))
+                               .take_while(|&c| c != char::from(0))
+                               .collect::<String>();
+
+                info.Address = *state.exports.get(&name).ok_or(ERROR_PROC_NOT_FOUND)?;
+                Ok(())
+            }),
+            _ => Err(ERROR_INVALID_FUNCTION),
+        }
+    }
+
+    fn is_raw(function: u32) -> bool {
+        match function {
+            0x0A50...0x0A63 => true,
+            _ => false,
+        }
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Simulator {
+        Simulator::new()
+    }
+}
+
+impl fmt::Debug for Simulator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state();
+        write!(f, "Simulator(partitions: {}, guards: {}, regions: {}, patches: {})",
+                    state.partitions.len(),
+                    state.guards.len(),
+                    state.regions.len(),
+                    state.patches.len())
+    }
+}
+
+impl Transport for Simulator {
+    fn call(&self, control: IoCtl, input: Option<Vec<u8>>, output: Option<Vec<u8>>) -> Result<Cursor<Vec<u8>>, DeviceError> {
+        if control.device_type != IOCTL_SENTRY_TYPE {
+            return Err(Simulator::error(control, ERROR_INVALID_FUNCTION))
+        }
+
+        let input = input.unwrap_or_default();
+        let capacity = output.map(|buffer| buffer.capacity()).unwrap_or(0);
+
+        let result = if Simulator::is_raw(control.function) {
+            // METHOD_BUFFERED semantics: the same system buffer is used in both directions
+            let mut buffer = input;
+            self.dispatch_raw(control.function, &mut buffer).map(|_| buffer)
+        } else {
+            self.dispatch(control.function, &input)
+        };
+
+        match result {
+            Err(code) => Err(Simulator::error(control, code)),
+            // like the driver, report the required size whenever it fits in the buffer
+            Ok(ref data) if data.len() > capacity && capacity >= 8 =>
+                Err(Simulator::error(control, ERROR_MORE_DATA).requiring(data.len())),
+            Ok(ref data) if data.len() > capacity => Err(Simulator::error(control, ERROR_INSUFFICIENT_BUFFER)),
+            Ok(data) => Ok(Cursor::new(data)),
+        }
+    }
+
+    fn raw_call(&self, control: IoCtl, ptr: LPVOID, len: usize) -> Result<(), DeviceError> {
+        if control.device_type != IOCTL_SENTRY_TYPE {
+            return Err(Simulator::error(control, ERROR_INVALID_FUNCTION))
+        }
+
+        let mut empty = [0u8; 0];
+        let buffer: &mut [u8] = if ptr.is_null() { &mut empty } else {
+            unsafe { slice::from_raw_parts_mut(ptr as *mut u8, len) }
+        };
+
+        let result = if Simulator::is_raw(control.function) {
+            self.dispatch_raw(control.function, buffer)
+        } else {
+            self.dispatch(control.function, buffer).and_then(|output| {
+                if output.len() > buffer.len() {
+                    return Err(ERROR_INSUFFICIENT_BUFFER)
+                }
+                buffer[..output.len()].copy_from_slice(&output);
+                Ok(())
+            })
+        };
+
+        result.map_err(|code| Simulator::error(control, code))
+    }
+}
+
+fn read(input: &mut Cursor<&[u8]>) -> SimResult<u64> {
+    input.read_u64::<LittleEndian>().map_err(|_| ERROR_INVALID_PARAMETER)
+}
+
+fn with_struct<T: Copy, F>(buffer: &mut [u8], f: F) -> SimResult<()>
+    where F: FnOnce(&mut T) -> SimResult<()> {
+    if buffer.len() < mem::size_of::<T>() {
+        return Err(ERROR_INVALID_PARAMETER)
+    }
+
+    let mut raw: T = unsafe { ptr::read_unaligned(buffer.as_ptr() as *const T) };
+    f(&mut raw)?;
+    unsafe { ptr::write_unaligned(buffer.as_mut_ptr() as *mut T, raw) };
+
+    Ok(())
+}
+
+fn id_output(id: u64) -> SimResult<Vec<u8>> {
+    let mut output = vec![];
+    output.write_u64::<LittleEndian>(id).expect("write to vector");
+    Ok(output)
+}
+
+impl State {
+    fn id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn alloc(&mut self, size: usize) -> SimResult<u64> {
+        if size == 0 {
+            return Err(ERROR_INVALID_PARAMETER)
+        }
+
+        let buffer = vec![0u8; size].into_boxed_slice();
+        let address = buffer.as_ptr() as u64;
+        self.memory.insert(address, buffer);
+
+        Ok(address)
+    }
+
+    fn free(&mut self, address: u64) -> SimResult<()> {
+        self.memory.remove(&address)
+                   .map(|_| ())
+                   .ok_or(ERROR_INVALID_ADDRESS)
+    }
+
+    // translates a simulated kernel range into a pointer, checking it lies in one allocation
+    fn resolve(&mut self, address: u64, size: usize) -> SimResult<*mut u8> {
+        let (&base, buffer) = self.memory.range_mut(..=address)
+                                         .next_back()
+                                         .ok_or(ERROR_NOACCESS)?;
+
+        let offset = (address - base) as usize;
+
+        // sizes come from the caller's struct, they may wrap around
+        match offset.checked_add(size) {
+            Some(end) if end <= buffer.len() => {},
+            _ => return Err(ERROR_NOACCESS),
+        }
+
+        Ok(unsafe { buffer.as_mut_ptr().offset(offset as isize) })
+    }
+
+    fn partition(&self, id: u64) -> SimResult<&SimPartition> {
+        self.partitions.get(&id).ok_or(ERROR_DEVICE_NOT_CONNECTED)
+    }
+
+    fn channel(id: u64) -> SimResult<Vec<u8>> {
+        // mirrors io::Channel, an empty channel means no interception buckets
+        let mut output = vec![];
+        output.write_u64::<LittleEndian>(id).expect("write to vector");
+        output.write_u64::<LittleEndian>(0).expect("write to vector");
+        output.write_u32::<LittleEndian>(0).expect("write to vector");
+        output.write_u32::<LittleEndian>(0).expect("write to vector");
+        Ok(output)
+    }
+
+    fn create_partition(&mut self) -> SimResult<Vec<u8>> {
+        let id = self.id();
+        self.partitions.insert(id, SimPartition { options: HashMap::new() });
+        State::channel(id)
+    }
+
+    fn delete_partition(&mut self, id: u64) -> SimResult<Vec<u8>> {
+        self.partitions.remove(&id).ok_or(ERROR_DEVICE_NOT_CONNECTED)?;
+
+        // the driver tears down every object owned by the partition
+        self.guards.retain(|_, guard| guard.partition != id);
+        self.regions.retain(|_, region| region.partition != id);
+        self.patches.retain(|_, patch| patch.partition != id);
+
+        Ok(vec![])
+    }
+
+    fn get_partition_option(&mut self, id: u64, option: u64) -> SimResult<Vec<u8>> {
+        if option >= PARTITION_OPTIONS {
+            return Err(ERROR_INVALID_PARAMETER)
+        }
+
+        let value = *self.partition(id)?.options.get(&option).unwrap_or(&0);
+        id_output(value)
+    }
+
+    fn set_partition_option(&mut self, id: u64, option: u64, value: u64) -> SimResult<Vec<u8>> {
+        if option >= PARTITION_OPTIONS {
+            return Err(ERROR_INVALID_PARAMETER)
+        }
+
+        self.partitions.get_mut(&id)
+                       .ok_or(ERROR_DEVICE_NOT_CONNECTED)?
+                       .options.insert(option, value);
+        Ok(vec![])
+    }
+
+    fn register_guard(&mut self, input: &mut Cursor<&[u8]>) -> SimResult<Vec<u8>> {
+        let partition = read(input)?;
+        let process = read(input)?;
+        let filter = read(input)?;
+        let flags = read(input)?;
+        let priority = read(input)?;
+
+        self.partition(partition)?;
+
+        if filter != 0 {
+            self.resolve(filter, 1)?;
+        }
+
+        let id = self.id();
+        self.guards.insert(id, SimGuard {
+            partition: partition,
+            process: process,
+            filter: filter,
+            priority: priority,
+            // GuardFlags::STOPPED
+            started: flags & 1 == 0,
+            regions: Vec::new(),
+            patches: Vec::new(),
+        });
+
+        id_output(id)
+    }
+
+    fn unregister_guard(&mut self, id: u64) -> SimResult<Vec<u8>> {
+        self.guards.remove(&id).ok_or(ERROR_NOT_FOUND)?;
+        Ok(vec![])
+    }
+
+    fn control_guard(&mut self, id: u64, action: u64) -> SimResult<Vec<u8>> {
+        let guard = self.guards.get_mut(&id).ok_or(ERROR_NOT_FOUND)?;
+
+        // ControlGuard::Start and ControlGuard::Stop
+        guard.started = match action {
+            1 => true,
+            2 => false,
+            _ => return Err(ERROR_INVALID_PARAMETER),
+        };
+
+        Ok(vec![])
+    }
+
+    fn create_region(&mut self, input: &mut Cursor<&[u8]>) -> SimResult<Vec<u8>> {
+        let partition = read(input)?;
+        let base = read(input)?;
+        let size = read(input)?;
+        let flags = input.read_u32::<LittleEndian>().map_err(|_| ERROR_INVALID_PARAMETER)?;
+        let access = input.read_u32::<LittleEndian>().map_err(|_| ERROR_INVALID_PARAMETER)?;
+        let action = read(input)?;
+        let read_buffer = read(input)?;
+        let write_buffer = read(input)?;
+        let weight = read(input)?;
+
+        self.partition(partition)?;
+
+        if size == 0 || access == 0 {
+            return Err(ERROR_INVALID_PARAMETER)
+        }
+
+        let id = self.id();
+        self.regions.insert(id, SimRegion {
+            partition: partition,
+            base: base,
+            size: size,
+            flags: u64::from(flags),
+            access: access,
+            action: action,
+            read_buffer: read_buffer,
+            write_buffer: write_buffer,
+            weight: weight,
+        });
+
+        id_output(id)
+    }
+
+    fn delete_region(&mut self, id: u64) -> SimResult<Vec<u8>> {
+        self.regions.remove(&id).ok_or(ERROR_NOT_FOUND)?;
+        self.guards.values_mut().for_each(|guard| guard.regions.retain(|&region| region != id));
+        Ok(vec![])
+    }
+
+    fn add_region(&mut self, guard_id: u64, region_id: u64) -> SimResult<Vec<u8>> {
+        let partition = self.regions.get(&region_id).ok_or(ERROR_NOT_FOUND)?.partition;
+        let guard = self.guards.get_mut(&guard_id).ok_or(ERROR_NOT_FOUND)?;
+
+        if guard.partition != partition {
+            return Err(ERROR_INVALID_PARAMETER)
+        }
+
+        if guard.regions.contains(&region_id) {
+            return Err(ERROR_ALREADY_EXISTS)
+        }
+
+        guard.regions.push(region_id);
+        Ok(vec![])
+    }
+
+    fn remove_region(&mut self, guard_id: u64, region_id: u64) -> SimResult<Vec<u8>> {
+        let guard = self.guards.get_mut(&guard_id).ok_or(ERROR_NOT_FOUND)?;
+        let index = guard.regions.iter().position(|&id| id == region_id).ok_or(ERROR_NOT_FOUND)?;
+        guard.regions.remove(index);
+        Ok(vec![])
+    }
+
+    fn set_state_region(&mut self, id: u64, state: u64) -> SimResult<Vec<u8>> {
+        let region = self.regions.get_mut(&id).ok_or(ERROR_NOT_FOUND)?;
+
+        // RegionStatus::Enable and RegionStatus::Disable
+        region.flags = match state {
+            1 => 0,
+            2 => 1,
+            _ => return Err(ERROR_INVALID_PARAMETER),
+        };
+
+        Ok(vec![])
+    }
+
+    fn region_info(&self, id: u64, next_entry_offset: u64) -> SimResult<Vec<u8>> {
+        let region = self.regions.get(&id).ok_or(ERROR_NOT_FOUND)?;
+        let guard_count = self.guards.values().filter(|guard| guard.regions.contains(&id)).count();
+
+        let mut output = vec![];
+        for &value in &[id, next_entry_offset, region.base, region.size] {
+            output.write_u64::<LittleEndian>(value).expect("write to vector");
+        }
+        output.write_u32::<LittleEndian>(region.access).expect("write to vector");
+        output.extend_from_slice(&[0; 4]);
+        for &value in &[region.flags, region.read_buffer, region.write_buffer, region.action,
+                        region.weight, 0, guard_count as u64] {
+            output.write_u64::<LittleEndian>(value).expect("write to vector");
+        }
+
+        Ok(output)
+    }
+
+    fn enumerate_regions(&self, partition: u64, guard_id: u64) -> SimResult<Vec<u8>> {
+        self.partition(partition)?;
+
+        let ids: Vec<u64> = match guard_id {
+            0 => self.regions.iter()
+                             .filter(|&(_, region)| region.partition == partition)
+                             .map(|(&id, _)| id)
+                             .collect(),
+            _ => self.guards.get(&guard_id).ok_or(ERROR_NOT_FOUND)?.regions.clone(),
+        };
+
+        self.chain(&ids, |id, next| self.region_info(id, next))
+    }
+
+    fn create_patch(&mut self, input: &mut Cursor<&[u8]>) -> SimResult<Vec<u8>> {
+        let partition = read(input)?;
+        let base = read(input)?;
+        let patch = read(input)?;
+        let size = read(input)?;
+        let flags = read(input)?;
+
+        self.partition(partition)?;
+
+        if size == 0 {
+            return Err(ERROR_INVALID_PARAMETER)
+        }
+
+        let id = self.id();
+        self.patches.insert(id, SimPatch {
+            partition: partition,
+            base: base,
+            patch: patch,
+            size: size,
+            flags: flags,
+        });
+
+        id_output(id)
+    }
+
+    fn delete_patch(&mut self, id: u64) -> SimResult<Vec<u8>> {
+        self.patches.remove(&id).ok_or(ERROR_NOT_FOUND)?;
+        self.guards.values_mut().for_each(|guard| guard.patches.retain(|&patch| patch != id));
+        Ok(vec![])
+    }
+
+    fn add_patch(&mut self, guard_id: u64, patch_id: u64) -> SimResult<Vec<u8>> {
+        let partition = self.patches.get(&patch_id).ok_or(ERROR_NOT_FOUND)?.partition;
+        let guard = self.guards.get_mut(&guard_id).ok_or(ERROR_NOT_FOUND)?;
+
+        if guard.partition != partition {
+            return Err(ERROR_INVALID_PARAMETER)
+        }
+
+        if guard.patches.contains(&patch_id) {
+            return Err(ERROR_ALREADY_EXISTS)
+        }
+
+        guard.patches.push(patch_id);
+        Ok(vec![])
+    }
+
+    fn remove_patch(&mut self, guard_id: u64, patch_id: u64) -> SimResult<Vec<u8>> {
+        let guard = self.guards.get_mut(&guard_id).ok_or(ERROR_NOT_FOUND)?;
+        let index = guard.patches.iter().position(|&id| id == patch_id).ok_or(ERROR_NOT_FOUND)?;
+        guard.patches.remove(index);
+        Ok(vec![])
+    }
+
+    fn set_state_patch(&mut self, id: u64, enabled: bool) -> SimResult<Vec<u8>> {
+        let patch = self.patches.get_mut(&id).ok_or(ERROR_NOT_FOUND)?;
+        patch.flags = if enabled { 0 } else { 1 };
+        Ok(vec![])
+    }
+
+    fn patch_info(&self, id: u64, next_entry_offset: u64) -> SimResult<Vec<u8>> {
+        let patch = self.patches.get(&id).ok_or(ERROR_NOT_FOUND)?;
+        let guard_count = self.guards.values().filter(|guard| guard.patches.contains(&id)).count();
+
+        let mut output = vec![];
+        for &value in &[id, next_entry_offset, patch.base, patch.patch, patch.size,
+                        patch.flags, guard_count as u64] {
+            output.write_u64::<LittleEndian>(value).expect("write to vector");
+        }
+
+        Ok(output)
+    }
+
+    fn enumerate_patches(&self, partition: u64, guard_id: u64) -> SimResult<Vec<u8>> {
+        self.partition(partition)?;
+
+        let ids: Vec<u64> = match guard_id {
+            0 => self.patches.iter()
+                             .filter(|&(_, patch)| patch.partition == partition)
+                             .map(|(&id, _)| id)
+                             .collect(),
+            _ => self.guards.get(&guard_id).ok_or(ERROR_NOT_FOUND)?.patches.clone(),
+        };
+
+        self.chain(&ids, |id, next| self.patch_info(id, next))
+    }
+
+    // builds a list of info entries linked through their NextEntryOffset field
+    fn chain<F>(&self, ids: &[u64], info: F) -> SimResult<Vec<u8>>
+        where F: Fn(u64, u64) -> SimResult<Vec<u8>> {
+        let mut output = vec![];
+
+        for (index, &id) in ids.iter().enumerate() {
+            let size = info(id, 0)?.len() as u64;
+            let next = if index + 1 < ids.len() { size } else { 0 };
+            output.extend(info(id, next)?);
+        }
+
+        Ok(output)
+    }
+
+    fn create_monitor(&mut self) -> SimResult<Vec<u8>> {
+        let id = self.id();
+        self.monitors.insert(id, false);
+        State::channel(id)
+    }
+
+    fn destroy_monitor(&mut self, id: u64) -> SimResult<Vec<u8>> {
+        self.monitors.remove(&id).ok_or(ERROR_NOT_FOUND)?;
+        Ok(vec![])
+    }
+
+    fn control_monitor(&mut self, id: u64, started: bool) -> SimResult<Vec<u8>> {
+        *self.monitors.get_mut(&id).ok_or(ERROR_NOT_FOUND)? = started;
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::io;
+    use super::super::memory;
+    use super::super::memguard::{Access, Action, GuardFlags, Range};
+    use super::super::iochannel::error::ErrorKind;
+    use super::super::wire::{self, Message};
+
+    #[test]
+    fn test_missing_partition_is_not_connected() {
+        let simulator = Simulator::new();
+        let err = io::get_partition_option(&simulator, 0xDEAD, 0).unwrap_err();
+        assert_eq!(err.to_string(), "Partition 57005 doesn't exist");
+    }
+
+    #[test]
+    fn test_partition_workflow() {
+        let simulator = Simulator::new();
+        let channel = io::create_partition(&simulator).unwrap();
+
+        io::set_partition_option(&simulator, channel.id, 4, 1).unwrap();
+        assert_eq!(io::get_partition_option(&simulator, channel.id, 4).unwrap(), 1);
+
+        let guard = io::register_guard_extended(&simulator, channel.id, None, None,
+                                                GuardFlags::STOPPED, 0, 0).unwrap();
+        assert_eq!(simulator.is_started(guard), Some(false));
+
+        let region = io::create_region(&simulator, channel.id, &Range::new(0x1000, 0x100),
+                                       Action::NOTIFY, Access::READ, None).unwrap();
+        io::add_region(&simulator, guard, region).unwrap();
+        assert!(io::add_region(&simulator, guard, region).is_err());
+
+        io::start_guard(&simulator, guard).unwrap();
+        assert_eq!(simulator.is_started(guard), Some(true));
+
+        io::delete_partition(&simulator, channel.id).unwrap();
+        assert!(simulator.partitions().is_empty());
+        assert!(io::unregister_guard(&simulator, guard).is_err());
+    }
+
+    #[test]
+    fn test_output_grows_until_it_fits() {
+        let simulator = Simulator::new();
+        let channel = io::create_partition(&simulator).unwrap();
+
+        for base in 0..4 {
+            io::create_region(&simulator, channel.id, &Range::new(base * 0x1000, 0x100),
+                              Action::NOTIFY, Access::READ, None).unwrap();
+        }
+
+        let control = IoCtl::new(None, IOCTL_SENTRY_TYPE, 0x0A26, None, None);
+        let input = || Some(wire::Enumerate { partition_id: channel.id, guard_id: 0 }.encode());
+
+        // too small for the required size to be reported, so it doubles first
+        let cursor = simulator.call_sized(control.clone(), input(), 4, 0x1000).unwrap();
+        assert_eq!(cursor.get_ref().len(), 4 * wire::RegionInfo::SIZE);
+
+        match simulator.call_sized(control, input(), 8, 0x100) {
+            Err(DeviceError::BufferLimit(_, 0x100)) => (),
+            other => panic!("expected the buffer limit, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_errors_are_classified() {
+        let simulator = Simulator::new();
+        let channel = io::create_partition(&simulator).unwrap();
+
+        let err = io::add_region(&simulator, 0xBAD, 0xF00).unwrap_err();
+        let err = err.downcast::<DeviceError>().unwrap();
+
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(err.code(), Some(1168));
+        assert!(err.to_string().contains("(guard 2989, region 3840)"));
+
+        let err = io::create_region(&simulator, channel.id, &Range::new(0x1000, 0),
+                                    Action::NOTIFY, Access::READ, None).unwrap_err();
+
+        assert_eq!(err.downcast::<DeviceError>().unwrap().kind(), ErrorKind::InvalidParameter);
+    }
+
+    #[test]
+    fn test_memory_roundtrip() {
+        let simulator = Simulator::new();
+        let address = memory::alloc_virtual_memory(&simulator, 0x10).unwrap();
+
+        memory::write_virtual_memory(&simulator, address + 8, vec![0xCA, 0xFE]).unwrap();
+        assert_eq!(memory::read_virtual_memory(&simulator, address + 8, 2).unwrap(), vec![0xCA, 0xFE]);
+        assert!(memory::read_virtual_memory(&simulator, address + 8, 0x10).is_err());
+        assert!(memory::read_virtual_memory(&simulator, u64::max_value(), 1).is_err());
+
+        memory::free_virtual_memory(&simulator, address).unwrap();
+        assert_eq!(simulator.allocations(), 0);
+    }
+}
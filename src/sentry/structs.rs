@@ -1,10 +1,16 @@
 // Copyright © ByteHeed.  All rights reserved.
 #![allow(non_camel_case_types, non_snake_case, dead_code)]
 
-use super::winapi::shared::minwindef::{LPVOID, ULONG, LPHANDLE, USHORT};
-use super::winapi::shared::ntdef::{HANDLE};
+use std::os::raw::c_void;
 use std::mem;
 
+// the winapi aliases, spelled out so that layouts build where winapi is empty
+pub type LPVOID = *mut c_void;
+pub type HANDLE = *mut c_void;
+pub type LPHANDLE = *mut HANDLE;
+pub type ULONG = u32;
+pub type USHORT = u16;
+
 type ULONG64 = u64;
 type SIZE_T = usize;
 
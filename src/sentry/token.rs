@@ -1,12 +1,13 @@
 // Copyright © ByteHeed.  All rights reserved.
 
 use super::io::IOCTL_SENTRY_TYPE;
-use super::iochannel::{Device, IoCtl};
+use super::iochannel::{Transport, IoCtl};
 use super::structs::{RawStruct, SE_STEAL_TOKEN};
+use super::failure::Error;
 
 pub use super::structs::TokenType;                
 
-pub fn steal_token(device: &Device, source: u64, target: u64, kind: TokenType) {
+pub fn steal_token(device: &dyn Transport, source: u64, target: u64, kind: TokenType) -> Result<(), Error> {
     let control = IoCtl::new(Some("SE_STEAL_TOKEN"), IOCTL_SENTRY_TYPE, 0x0A60, None, None);
 
     let mut token = SE_STEAL_TOKEN::init();
@@ -17,7 +18,7 @@ pub fn steal_token(device: &Device, source: u64, target: u64, kind: TokenType) {
 
     let (ptr, len) = (token.as_ptr(), token.size());
 
-    device.raw_call(control, ptr, len)
-          .expect("Error calling IOCTL_SENTRY_STEAL_TOKEN");
+    device.raw_call(control, ptr, len)?;
 
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,511 @@
+// Copyright © ByteHeed.  All rights reserved.
+
+//
+// Every buffer exchanged with the Sentry driver is declared once below, field by
+// field and in driver order. The `wire!` macro derives the little-endian encoder,
+// the size-checked decoder and the `dump::Layout` used by `device call` from
+// that single declaration, so the request builders in `io` never hand-write offsets.
+//
+
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use super::iochannel::dump::{Layout, Width};
+use super::error::WireError;
+
+use std::io::Cursor;
+use std::marker::PhantomData;
+use std::fmt;
+
+pub trait Field: Sized {
+    const SIZE: usize;
+    const WIDTH: Width;
+
+    fn write(&self, buffer: &mut Vec<u8>);
+
+    // callers validate the buffer size before reading
+    fn read(cursor: &mut Cursor<&[u8]>) -> Self;
+}
+
+macro_rules! field {
+    ($ty:ty, $size:expr, $width:expr, $write:ident, $read:ident) => {
+        impl Field for $ty {
+            const SIZE: usize = $size;
+            const WIDTH: Width = $width;
+
+            fn write(&self, buffer: &mut Vec<u8>) {
+                buffer.$write::<LittleEndian>(*self).expect("write to vector");
+            }
+
+            fn read(cursor: &mut Cursor<&[u8]>) -> Self {
+                cursor.$read::<LittleEndian>().expect("size validated")
+            }
+        }
+    }
+}
+
+field!(u16, 2, Width::U16, write_u16, read_u16);
+field!(u32, 4, Width::U32, write_u32, read_u32);
+field!(u64, 8, Width::U64, write_u64, read_u64);
+
+impl Field for u8 {
+    const SIZE: usize = 1;
+    const WIDTH: Width = Width::U8;
+
+    fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.push(*self);
+    }
+
+    fn read(cursor: &mut Cursor<&[u8]>) -> Self {
+        cursor.read_u8().expect("size validated")
+    }
+}
+
+// fixed byte arrays: reserved bytes, kept as-is so that padding survives a round trip,
+// or raw bytes such as an instruction encoding
+macro_rules! bytes {
+    ($size:expr, $width:expr) => {
+        impl Field for [u8; $size] {
+            const SIZE: usize = $size;
+            const WIDTH: Width = $width;
+
+            fn write(&self, buffer: &mut Vec<u8>) {
+                buffer.extend_from_slice(self);
+            }
+
+            fn read(cursor: &mut Cursor<&[u8]>) -> Self {
+                let mut value = [0u8; $size];
+                value.copy_from_slice(&cursor.get_ref()[cursor.position() as usize..][..$size]);
+                cursor.set_position(cursor.position() + $size);
+                value
+            }
+        }
+    }
+}
+
+bytes!(2, Width::Pad(2));
+bytes!(4, Width::Pad(4));
+bytes!(6, Width::Pad(6));
+bytes!(7, Width::Pad(7));
+bytes!(16, Width::Bytes(16));
+
+pub trait Message: Sized {
+    const SIZE: usize;
+    const LAYOUT: Layout;
+
+    fn encode(&self) -> Vec<u8>;
+    fn decode(data: &[u8]) -> Result<Self, WireError>;
+}
+
+macro_rules! wire {
+    ($($(#[$meta:meta])* pub struct $name:ident { $(pub $field:ident: $ty:ty),* $(,)* })*) => {
+        $(
+            $(#[$meta])*
+            pub struct $name {
+                $(pub $field: $ty),*
+            }
+
+            impl Message for $name {
+                const SIZE: usize = 0 $(+ <$ty as Field>::SIZE)*;
+
+                const LAYOUT: Layout = Layout {
+                    name: stringify!($name),
+                    fields: &[$((stringify!($field), <$ty as Field>::WIDTH)),*],
+                };
+
+                fn encode(&self) -> Vec<u8> {
+                    let mut buffer = Vec::with_capacity(Self::SIZE);
+                    $(Field::write(&self.$field, &mut buffer);)*
+                    buffer
+                }
+
+                fn decode(data: &[u8]) -> Result<$name, WireError> {
+                    if data.len() < Self::SIZE {
+                        return Err(WireError::Truncated(stringify!($name), Self::SIZE, data.len()))
+                    }
+
+                    let mut cursor = Cursor::new(data);
+
+                    Ok($name {
+                        $($field: Field::read(&mut cursor)),*
+                    })
+                }
+            }
+        )*
+    }
+}
+
+wire! {
+    // any request or response made of a single identifier
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct Id {
+        pub id: u64,
+    }
+
+    // partitions and monitors answer with the shared interception buffers
+    #[derive(Clone, Default, PartialEq)]
+    pub struct Channel {
+        pub id: u64,
+        pub address: u64,
+        pub size: u32,
+        pub reserved: [u8; 4],
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct Handles {
+        pub user: u64,
+        pub kernel: u64,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct GetPartitionOption {
+        pub partition_id: u64,
+        pub option: u64,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct SetPartitionOption {
+        pub partition_id: u64,
+        pub option: u64,
+        pub value: u64,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct RegisterGuard {
+        pub partition_id: u64,
+        pub process: u64,
+        pub filter: u64,
+        pub flags: u64,
+        pub priority: u64,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct ControlGuard {
+        pub guard_id: u64,
+        pub action: u64,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct CreateRegion {
+        pub partition_id: u64,
+        pub base: u64,
+        pub limit: u64,
+        pub flags: u32,
+        pub access: u32,
+        pub action: u64,
+        pub read_buffer: u64,
+        pub write_buffer: u64,
+        pub weight: u64,
+    }
+
+    // links a region or a patch to a guard
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct GuardItem {
+        pub guard_id: u64,
+        pub item_id: u64,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct SetStateRegion {
+        pub region_id: u64,
+        pub state: u64,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct RegionInfo {
+        pub region_id: u64,
+        pub next_entry_offset: u64,
+        pub base_address: u64,
+        pub size: u64,
+        pub access_type: u32,
+        pub reserved_access: [u8; 4],
+        pub flags: u64,
+        pub read_buffer: u64,
+        pub write_buffer: u64,
+        pub action: u64,
+        pub weight: u64,
+        pub context: u64,
+        pub guard_count: u64,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct CreatePatch {
+        pub partition_id: u64,
+        pub base_address: u64,
+        pub patch_base: u64,
+        pub patch_limit: u64,
+        pub flags: u64,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct PatchInfo {
+        pub patch_id: u64,
+        pub next_entry_offset: u64,
+        pub base_address: u64,
+        pub patch_address: u64,
+        pub patch_size: u64,
+        pub flags: u64,
+        pub guard_count: u64,
+    }
+
+    // `guard_id` 0 enumerates the whole partition
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct Enumerate {
+        pub partition_id: u64,
+        pub guard_id: u64,
+    }
+
+    // an access caught by a region, as the driver lays it out in the interception buckets:
+    // the trap frame of the faulting thread, the instruction it was executing and the
+    // action user mode answers with
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct InterceptionMessage {
+        pub id: u64,
+        pub control: u32,
+        pub kind: u32,
+        pub guard_id: u64,
+        pub region_id: u64,
+        pub r15: u64,
+        pub r14: u64,
+        pub r13: u64,
+        pub r12: u64,
+        pub r11: u64,
+        pub r10: u64,
+        pub r9: u64,
+        pub r8: u64,
+        pub rdi: u64,
+        pub rsi: u64,
+        pub rbp: u64,
+        pub rsp: u64,
+        pub rbx: u64,
+        pub rdx: u64,
+        pub rcx: u64,
+        pub rax: u64,
+        pub rip: u64,
+        pub rflags: u64,
+        pub instruction: [u8; 16],
+        pub processor: u8,
+        pub reserved: [u8; 7],
+        pub process: u64,
+        pub address: u64,
+        pub access: u16,
+        pub reserved_access: [u8; 2],
+        pub flags: u32,
+        pub context: u64,
+        pub action: u16,
+        pub reserved_action: [u8; 6],
+    }
+}
+
+// enumerations answer with records linked by the byte offset of the next one, 0 ending the chain
+pub trait Chained: Message {
+    fn next_entry_offset(&self) -> u64;
+}
+
+impl Chained for RegionInfo {
+    fn next_entry_offset(&self) -> u64 {
+        self.next_entry_offset
+    }
+}
+
+impl Chained for PatchInfo {
+    fn next_entry_offset(&self) -> u64 {
+        self.next_entry_offset
+    }
+}
+
+/// Walks an enumeration buffer, decoding one record per step.
+///
+/// Iteration ends after the record whose `next_entry_offset` is 0, or right after
+/// the first malformed record.
+#[derive(Debug, Clone)]
+pub struct Entries<T> {
+    data: Vec<u8>,
+    offset: Option<usize>,
+    entry: PhantomData<T>,
+}
+
+impl<T: Chained> Entries<T> {
+    pub fn new(data: Vec<u8>) -> Entries<T> {
+        Entries {
+            offset: if data.is_empty() { None } else { Some(0) },
+            data: data,
+            entry: PhantomData,
+        }
+    }
+}
+
+impl<T: Chained> Iterator for Entries<T> {
+    type Item = Result<T, WireError>;
+
+    fn next(&mut self) -> Option<Result<T, WireError>> {
+        let offset = self.offset.take()?;
+
+        let entry = match T::decode(&self.data[offset.min(self.data.len())..]) {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(err)),
+        };
+
+        match entry.next_entry_offset() {
+            0 => (),
+            next if next < T::SIZE as u64 => return Some(Err(WireError::Chain(T::LAYOUT.name, offset, next))),
+            next => match offset.checked_add(next as usize) {
+                Some(following) => self.offset = Some(following),
+                None => return Some(Err(WireError::Chain(T::LAYOUT.name, offset, next))),
+            },
+        }
+
+        Some(Ok(entry))
+    }
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Channel(id: 0x{:016X}, address: 0x{:016x}, size: 0x{:016x})",
+                        self.id,
+                        self.address,
+                        self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Debug;
+
+    // decodes a patterned buffer, re-encodes it and expects the very same bytes
+    fn round_trip<T: Message + PartialEq + Debug>() {
+        let data: Vec<u8> = (1..=T::SIZE).map(|byte| byte as u8).collect();
+
+        let message = T::decode(&data).unwrap();
+        assert_eq!(message.encode(), data, "{} does not round trip", T::LAYOUT.name);
+        assert_eq!(T::decode(&message.encode()).unwrap(), message);
+        assert_eq!(T::LAYOUT.size(), T::SIZE);
+
+        match T::decode(&data[..T::SIZE - 1]) {
+            Err(WireError::Truncated(name, expected, found)) => {
+                assert_eq!((name, expected, found), (T::LAYOUT.name, T::SIZE, T::SIZE - 1))
+            },
+            other => panic!("{} decoded a truncated buffer: {:?}", T::LAYOUT.name, other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_round_trips() {
+        round_trip::<Id>();
+        round_trip::<Channel>();
+        round_trip::<Handles>();
+        round_trip::<GetPartitionOption>();
+        round_trip::<SetPartitionOption>();
+        round_trip::<RegisterGuard>();
+        round_trip::<ControlGuard>();
+        round_trip::<CreateRegion>();
+        round_trip::<GuardItem>();
+        round_trip::<SetStateRegion>();
+        round_trip::<RegionInfo>();
+        round_trip::<CreatePatch>();
+        round_trip::<PatchInfo>();
+        round_trip::<Enumerate>();
+        round_trip::<InterceptionMessage>();
+    }
+
+    // the driver is built with the default x64 packing: every field sits on a multiple of
+    // its own size and records end on 8 bytes, any gap is declared as a reserved field
+    fn aligned<T: Message>() {
+        let mut offset = 0;
+
+        for &(name, width) in T::LAYOUT.fields {
+            match width {
+                Width::Pad(_) | Width::Bytes(_) => (),
+                width => assert_eq!(offset % width.size(), 0, "{}.{} is misaligned", T::LAYOUT.name, name),
+            }
+            offset += width.size();
+        }
+
+        assert_eq!(T::SIZE % 8, 0, "{} isn't padded to 8 bytes", T::LAYOUT.name);
+    }
+
+    #[test]
+    fn test_driver_alignment() {
+        aligned::<Id>();
+        aligned::<Channel>();
+        aligned::<Handles>();
+        aligned::<GetPartitionOption>();
+        aligned::<SetPartitionOption>();
+        aligned::<RegisterGuard>();
+        aligned::<ControlGuard>();
+        aligned::<CreateRegion>();
+        aligned::<GuardItem>();
+        aligned::<SetStateRegion>();
+        aligned::<RegionInfo>();
+        aligned::<CreatePatch>();
+        aligned::<PatchInfo>();
+        aligned::<Enumerate>();
+        aligned::<InterceptionMessage>();
+    }
+
+    #[test]
+    fn test_driver_sizes() {
+        // sizes the naturally aligned layouts above add up to
+        assert_eq!(Channel::SIZE, 24);
+        assert_eq!(Handles::SIZE, 16);
+        assert_eq!(RegisterGuard::SIZE, 40);
+        assert_eq!(CreateRegion::SIZE, 64);
+        assert_eq!(RegionInfo::SIZE, 96);
+        assert_eq!(CreatePatch::SIZE, 40);
+        assert_eq!(PatchInfo::SIZE, 56);
+        // interceptions fill a bucket but its two event handles
+        assert_eq!(InterceptionMessage::SIZE, 240);
+        assert_eq!(InterceptionMessage::LAYOUT.offset("action"), Some(232));
+    }
+
+    #[test]
+    fn test_chained_entries() {
+        let chain = |ids: &[u64], next: u64| ids.iter().enumerate().flat_map(|(index, &id)| {
+            let last = index + 1 == ids.len();
+            PatchInfo { patch_id: id, next_entry_offset: if last { 0 } else { next }, ..Default::default() }.encode()
+        }).collect::<Vec<u8>>();
+
+        let ids = Entries::<PatchInfo>::new(chain(&[1, 2, 3], PatchInfo::SIZE as u64))
+                        .map(|entry| entry.unwrap().patch_id)
+                        .collect::<Vec<u64>>();
+
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(Entries::<PatchInfo>::new(vec![]).count(), 0);
+
+        // a link back into the record itself would loop forever
+        let mut entries = Entries::<PatchInfo>::new(chain(&[1, 2], 8));
+        match entries.next() {
+            Some(Err(WireError::Chain("PatchInfo", 0, 8))) => (),
+            other => panic!("expected a chain error, got {:?}", other),
+        }
+        assert!(entries.next().is_none());
+
+        // a link wrapping the offset around must not panic
+        let mut data = chain(&[1, 2], PatchInfo::SIZE as u64);
+        data[PatchInfo::SIZE + 8..][..8].copy_from_slice(&[0xFF; 8]);
+        let mut entries = Entries::<PatchInfo>::new(data);
+        assert!(entries.next().unwrap().is_ok());
+        match entries.next() {
+            Some(Err(WireError::Chain("PatchInfo", offset, next))) => {
+                assert_eq!((offset, next), (PatchInfo::SIZE, u64::max_value()))
+            },
+            other => panic!("expected a chain error, got {:?}", other),
+        }
+        assert!(entries.next().is_none());
+
+        // the last link points past the end of the buffer
+        let mut data = chain(&[1], 0);
+        data[8] = PatchInfo::SIZE as u8;
+        let entries = Entries::<PatchInfo>::new(data).collect::<Vec<_>>();
+        assert!(entries[0].is_ok());
+        assert!(entries[1].is_err());
+    }
+
+    #[test]
+    fn test_little_endian() {
+        let region = CreateRegion { partition_id: 0x0102, flags: 0x0304, ..Default::default() };
+        let data = region.encode();
+
+        assert_eq!(&data[..8], &[0x02, 0x01, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&data[24..28], &[0x04, 0x03, 0, 0]);
+    }
+}
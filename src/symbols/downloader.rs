@@ -93,7 +93,9 @@ impl PdbDownloader {
 mod tests {
     use super::PdbDownloader;
 
+    // reads the kernel image of the machine running the tests
     #[test]
+    #[cfg(windows)]
     fn test_nt_pdb_is_correct() {
         let pdb = PdbDownloader::new("c:\\windows\\system32\\ntoskrnl.exe".to_string());
         assert_eq!(pdb.generate_url(),
@@ -1,6 +1,8 @@
 use super::pdb::FallibleIterator;
 
 use super::pdb;
+use super::goblin;
+use super::error::PdbError;
 
 use std::fmt;
 use std::io::Write;
@@ -691,3 +693,37 @@ pub fn find_offset(filename: &str, name: &str) -> pdb::Result<u16> {
 
     find_struct_offset(filename, struct_name, field_name)
 }
+
+// segment and offset of a public symbol, e.g. a non exported kernel variable such as KiServiceTable
+pub fn find_public_symbol(filename: &str, name: &str) -> pdb::Result<Option<(u16, u32)>> {
+    let file = fs::File::open(filename)?;
+    let mut pdb = pdb::PDB::open(file)?;
+
+    let symbol_table = pdb.global_symbols()?;
+    let mut symbols = symbol_table.iter();
+
+    while let Some(symbol) = symbols.next()? {
+        if let Ok(pdb::SymbolData::PublicSymbol(public)) = symbol.parse() {
+            if symbol.name()?.as_bytes() == name.as_bytes() {
+                return Ok(Some((public.offset.section, public.offset.offset)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// turns a PDB segment:offset pair into a RVA through the section table of the image it describes
+pub fn section_rva(image: &str, segment: u16, offset: u32) -> Result<u32, PdbError> {
+    let buffer = fs::read(image).map_err(|err| PdbError::ParseError(format!("{}: {}", image, err)))?;
+
+    match goblin::Object::parse(&buffer) {
+        Ok(goblin::Object::PE(pe)) => {
+            // segments are numbered from 1
+            pe.sections.get((segment as usize).wrapping_sub(1))
+                       .map(|section| section.virtual_address + offset)
+                       .ok_or_else(|| PdbError::ParseError(format!("{} has no section {}", image, segment)))
+        },
+        _ => Err(PdbError::ParseError(format!("{} isn't a PE image", image))),
+    }
+}
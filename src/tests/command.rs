@@ -6,7 +6,7 @@ use std::{thread, time};
 use self::time::Duration;
 
 use super::failure::Error;
-use super::sentry::{io, search};
+use super::sentry::{io, search, SentrySession};
 use super::iochannel::{Device};
 use super::sentry::memguard::{ Partition};
 
@@ -136,7 +136,7 @@ fn consume_device(device: Device, messenger: &Sender<ShellMessage>) {
 }
 
 fn test_double_open(_matches: &ArgMatches,  messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-        let partition = Partition::root();
+        let partition = Partition::root()?;
         let device_one = Device::new(io::SE_NT_DEVICE_NAME).expect("Can't open sentry");
         // debug!(logger, "dropping: device_one");
         ShellMessage::send(messenger, format!("{}",style("Dropping device_one").yellow()), MessageType::Spinner, 0);
@@ -146,7 +146,7 @@ fn test_double_open(_matches: &ArgMatches,  messenger: &Sender<ShellMessage>) ->
         // debug!(logger, "creating a partition");
         ShellMessage::send(messenger, format!("{}",style("Creating partition").blue()), MessageType::Spinner, 0);
 
-        if io::delete_partition(&partition.device, partition.id).is_err() {
+        if io::delete_partition(&partition.session, partition.id).is_err() {
             ShellMessage::send(messenger, format!("{}",style("Test ended USUCCESSFULLY").red()), MessageType::Close, 0);
             // colorize::failed("TEST HAS FAILED");
         } else {
@@ -164,7 +164,7 @@ fn test_double_open(_matches: &ArgMatches,  messenger: &Sender<ShellMessage>) ->
 //
 
 fn test_search_pattern(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    let device = Device::new(io::SE_NT_DEVICE_NAME).expect("Can't open sentry");
+    let session = SentrySession::open().expect("Can't open sentry");
 
     let switch_context_pattern: Vec<u8> = vec![0x89, 0x60, 0x18, 0x4C,
                                                0x89, 0x68, 0x20, 0x4C,
@@ -174,10 +174,10 @@ fn test_search_pattern(_matches: &ArgMatches, messenger: &Sender<ShellMessage>)
                                                0x20, 0x00, 0x00, 0x00,
                                                0x48, 0x8B, 0xF9];
 
-    if let Some(offset) = search::pattern(&device,
+    if let Some(offset) = search::pattern(&session,
                                           "ntos",
                                           &switch_context_pattern,
-                                          Some("KeSynchronizeExecution")) {
+                                          Some("KeSynchronizeExecution"))? {
 
     ShellMessage::send(messenger, format!("Switch-content: {}", style(format!("0x{:016x}", offset)).cyan()), MessageType::Close, 0);
     }
@@ -3,7 +3,7 @@
 use super::clap::{App, ArgMatches, SubCommand};
 
 use super::failure::Error;
-use super::iochannel::{Device, IoCtl};
+use super::iochannel::{Device, IoCtl, Transport};
 use super::sentry::io::{IOCTL_SENTRY_TYPE, SE_NT_DEVICE_NAME};
 use std::ptr;
 
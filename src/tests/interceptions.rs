@@ -8,10 +8,9 @@ use std::time::Duration;
 
 use super::failure::Error;
 use super::common;
-use super::iochannel::Device;
-use super::sentry::{memory, search, io};
+use super::sentry::{memory, search, SentrySession};
 use super::sentry::memguard::{Response,
-                              Interception,
+                              InterceptionEvent,
                               Partition,
                               Region,
                               Guard,
@@ -76,27 +75,28 @@ impl fmt::Debug for ServiceTable {
 }
 
 fn find_ssdt_address(messenger: &Sender<ShellMessage>) -> ServiceTable {
-    let device = Device::new(io::SE_NT_DEVICE_NAME).expect("sentry device");
+    let session = SentrySession::open().expect("sentry device");
     let pattern = vec![0x48, 0x89, 0xA3, 0xD8,
                        0x01, 0x00, 0x00, 0x8B,
                        0xF8, 0xC1, 0xEF, 0x07,
                        0x83, 0xE7, 0x20, 0x25];
 
-    let address = search::pattern(&device,
+    let address = search::pattern(&session,
                                   "ntoskrnl",
                                   &pattern,
                                   Some("ZwCreateResourceManager"))
+                                  .expect("unable to search the SSDT pattern")
                                   .expect("unable to find SSDT pattern");
 
     let instruction = pattern.len() as u64 + 7;
 
-    let rva = memory::read_u32(&device, address + instruction).unwrap() as i32;
+    let rva = memory::read_u32(&session, address + instruction).unwrap() as i32;
 
     let ssdt_reference = address.wrapping_add(rva as u64) + instruction + 4;
 
     ShellMessage::send(messenger, format!("ref: {}",style(format!("0x{:016x}",ssdt_reference)).cyan()), MessageType::Spinner, 0);
     // println!("ref: 0x{:016x}", ssdt_reference);
-    let data = memory::read_virtual_memory(&device, ssdt_reference, mem::size_of::<ServiceTable>())
+    let data = memory::read_virtual_memory(&session, ssdt_reference, mem::size_of::<ServiceTable>())
                     .expect("error reading ServiceTable");
 
     let ssdt: ServiceTable = unsafe { mem::transmute_copy(&*data.as_ptr()) };
@@ -129,12 +129,12 @@ fn test_analysis_interception_messaged(show: Print, messenger: &Sender<ShellMess
 
     ShellMessage::send(messenger,format!("found at 0x{:16x}", address), MessageType::Spinner, 0);
 
-    let partition = Partition::root();
+    let partition = Partition::root()?;
 
-    let filter = Filter::process(&partition.device, "notepad", MatchType::EQUAL)
+    let filter = Filter::process(&partition.session, "notepad", MatchType::EQUAL)?
                             .expect("can't find notepad process");
 
-    let mut guard = Guard::new(&partition, Some(filter));
+    let mut guard = Guard::new(&partition, Some(filter))?;
 
     let region = Region::new(&partition, address,
                               ssdt.count as u64 * 4,
@@ -143,7 +143,7 @@ fn test_analysis_interception_messaged(show: Print, messenger: &Sender<ShellMess
                             .expect("can't create region");
 
     ShellMessage::send(messenger,format!("adding {} to {}", region, guard), MessageType::Spinner, 0);
-    guard.add(region);
+    guard.add(region)?;
 
     guard.set_callback(Box::new(move |interception| {
         let index = interception.address.wrapping_sub(address) / 4;
@@ -157,14 +157,16 @@ fn test_analysis_interception_messaged(show: Print, messenger: &Sender<ShellMess
     }));
 
     ShellMessage::send(messenger, "starting guard".to_string(), MessageType::Spinner, 0);
-    guard.start();
+    guard.start()?;
 
     let duration = Duration::from_secs(60);
     ShellMessage::send(messenger, format!("waiting {:?}", duration), MessageType::Spinner, 0);
     thread::sleep(duration);
 
     ShellMessage::send(messenger, "stoping guard".to_string(), MessageType::Spinner, 0);
-    guard.stop();
+    guard.stop()?;
+
+    ShellMessage::send(messenger, format!("callbacks: {}", guard.callback_stats()), MessageType::Close, 0);
     Ok(())
 }
 
@@ -173,25 +175,25 @@ fn test_analysis_interception_messaged(show: Print, messenger: &Sender<ShellMess
 //
 fn test_stealth_interception(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
 
-    let partition: Partition = Partition::root();
-    let mut guard = Guard::new(&partition, None);
+    let partition: Partition = Partition::root()?;
+    let mut guard = Guard::new(&partition, None)?;
 
     const POOL_SIZE: usize = 0x10;
 
-    let addr = memory::alloc_virtual_memory(&partition.device, POOL_SIZE).unwrap();
+    let addr = memory::alloc_virtual_memory(&partition.session, POOL_SIZE).unwrap();
     ShellMessage::send(messenger,format!("new pool: {} ({} bytes)",style(format!("0x{:016x}", addr)).cyan(), style(format!("{}", POOL_SIZE)).underlined().yellow()  ),MessageType::Spinner,0);
 
-    let bytes = memory::write_virtual_memory(&partition.device, addr, vec![0; POOL_SIZE]).unwrap();
+    let bytes = memory::write_virtual_memory(&partition.session, addr, vec![0; POOL_SIZE]).unwrap();
     ShellMessage::send(messenger,format!("zeroed {} bytes", style(format!("{}", bytes)).underlined().yellow()),MessageType::Spinner,0);
 
-    let v = memory::read_virtual_memory(&partition.device, addr, POOL_SIZE).unwrap();
+    let v = memory::read_virtual_memory(&partition.session, addr, POOL_SIZE).unwrap();
     let output = common::dump_vector(&v);
     ShellMessage::send(messenger,format!("dumping buffer {} \n{}", style(format!("0x{:016x}", addr)).cyan()  , output),MessageType::Spinner,0);
 
     let region = Region::new(&partition, addr, POOL_SIZE as u64, Some(Action::NOTIFY | Action::INSPECT), Access::WRITE).unwrap();
 
     ShellMessage::send(messenger,format!("adding {} to {}", style(format!("{}", region)).cyan() , style(format!("{}", guard)).blue()  ),MessageType::Spinner,0);
-    guard.add(region);
+    guard.add(region)?;
 
     guard.set_callback(Box::new(|interception| {
         // TODO:REVIEW: Here we remove return message to caller, to enable beautified print
@@ -204,15 +206,15 @@ fn test_stealth_interception(_matches: &ArgMatches, messenger: &Sender<ShellMess
 
     ShellMessage::send(messenger, format!("{}",style("Starting guard").blue()), MessageType::Spinner, 0);
     // ShellMessage::send(messenger,"starting guard".to_string(),MessageType::Spinner,0);
-    guard.start();
+    guard.start()?;
     ShellMessage::send(messenger,format!("accessing memory {}", style(format!("0x{:016x}",  addr)).cyan()  ),MessageType::Spinner,0);
 
     let v = common::dummy_vector(POOL_SIZE);
 
-    let bytes = memory::write_virtual_memory(&partition.device, addr, v).unwrap();
+    let bytes = memory::write_virtual_memory(&partition.session, addr, v).unwrap();
     ShellMessage::send(messenger,format!("{} bytes written", style(format!("{}", bytes)).underlined().yellow() ),MessageType::Spinner,0);
 
-    let v = memory::read_virtual_memory(&partition.device, addr, POOL_SIZE).unwrap();
+    let v = memory::read_virtual_memory(&partition.session, addr, POOL_SIZE).unwrap();
     if v.iter().any(|&b| b != 0x00) {
         // colorize::failed("STEALTH test result has FAILED.");
         ShellMessage::send(messenger, format!("{}",style("STEALTH test result has FAILED.").red())  , MessageType::Spinner, 0);
@@ -231,16 +233,16 @@ fn test_stealth_interception(_matches: &ArgMatches, messenger: &Sender<ShellMess
 
     ShellMessage::send(messenger,format!("{}",style("Stoping guard!").yellow()),MessageType::Spinner,0);
     // ShellMessage::send(messenger, "stoping guard".to_string(), MessageType::Spinner, 0);
-    guard.stop();
+    guard.stop()?;
 
-    memory::free_virtual_memory(&partition.device, addr).unwrap();
+    memory::free_virtual_memory(&partition.session, addr).unwrap();
     ShellMessage::send(messenger,format!("{}",style("Done!").green()),MessageType::Close, 0);
     Ok(())
 }
 
 // example of declared function as callback
 #[allow(dead_code)]
-fn callback_test(interception: Interception) -> Action {
+fn callback_test(interception: InterceptionEvent) -> Action {
     // println!(format!("The offensive address is {} (accessing: {})", style(format!("0x{:016x}",interception.address)).on_red(),
     //                                     style(format!("{:?}",interception.access)).underlined().cyan());
     println!("The offensive address is 0x{:016x} (accessing {:?})", interception.address, interception.access);
@@ -251,13 +253,13 @@ fn callback_test(interception: Interception) -> Action {
 }
 
 fn test_interception_callback(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    let partition: Partition = Partition::root();
-    let mut guard = Guard::new(&partition, None);
+    let partition: Partition = Partition::root()?;
+    let mut guard = Guard::new(&partition, None)?;
 
     const POOL_SIZE: usize = 0x100;
 
     ShellMessage::send(messenger, "Allocating pool".to_string(), MessageType::Spinner, 0);
-    let addr = memory::alloc_virtual_memory(&partition.device, POOL_SIZE).unwrap();
+    let addr = memory::alloc_virtual_memory(&partition.session, POOL_SIZE).unwrap();
 
     ShellMessage::send(messenger, format!("Addr: 0x{:016x}", addr), MessageType::Spinner, 0);
 
@@ -265,7 +267,7 @@ fn test_interception_callback(_matches: &ArgMatches, messenger: &Sender<ShellMes
 
     ShellMessage::send(messenger,
             format!("Adding {} to {}", style(format!("{}",region)).green(), style(format!("{}",guard)).blue()), MessageType::Spinner, 0);
-    guard.add(region);
+    guard.add(region)?;
 
     guard.set_callback(Box::new(|interception| {
         // let message = format!("The offensive address is 0x{:016X} (accessing: {:?})",interception.address,interception.access);
@@ -281,7 +283,7 @@ fn test_interception_callback(_matches: &ArgMatches, messenger: &Sender<ShellMes
     }));
 
     ShellMessage::send(messenger, format!("{}",style("Starting guard").blue()), MessageType::Spinner, 0);
-    guard.start();
+    guard.start()?;
 
     ShellMessage::send(
         messenger,
@@ -290,42 +292,42 @@ fn test_interception_callback(_matches: &ArgMatches, messenger: &Sender<ShellMes
         0
     );
 
-    let _ = memory::read_virtual_memory(&partition.device, addr, POOL_SIZE).unwrap();
+    let _ = memory::read_virtual_memory(&partition.session, addr, POOL_SIZE).unwrap();
     ShellMessage::send(messenger,format!("{}",style("Stoping guard...").yellow()),MessageType::Spinner, 0);
-    guard.stop();
+    guard.stop()?;
 
-    memory::free_virtual_memory(&partition.device, addr).unwrap();
+    memory::free_virtual_memory(&partition.session, addr).unwrap();
     ShellMessage::send(messenger,format!("{}",style("Done!").green()),MessageType::Close, 0);
     Ok(())
 }
 
 fn test_intercept_kernel_region(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    let partition: Partition = Partition::root();
-    let mut guard = Guard::new(&partition, None);
+    let partition: Partition = Partition::root()?;
+    let mut guard = Guard::new(&partition, None)?;
 
     const POOL_SIZE: usize = 0x100;
 
     ShellMessage::send(messenger,format!("{}",style("Allocating pool...").yellow()),MessageType::Spinner,0);
 
-    let addr = memory::alloc_virtual_memory(&partition.device, POOL_SIZE).unwrap();
+    let addr = memory::alloc_virtual_memory(&partition.session, POOL_SIZE).unwrap();
     ShellMessage::send(messenger,format!("Addr: {}",style(format!("0x{:016x}",addr)).cyan()),MessageType::Spinner,0);
 
     let region = Region::new(&partition, addr, POOL_SIZE as u64, None, Access::READ).unwrap();
 
     ShellMessage::send(messenger,format!("Adding {} to {}",style(format!("{}",region)).green() , style(format!("{}",guard)).blue()),MessageType::Spinner,0);
 
-    guard.add(region);
+    guard.add(region)?;
     ShellMessage::send(messenger,format!("{}",style("Starting guard!").on_blue()),MessageType::Spinner,0);
 
-    guard.start();
+    guard.start()?;
     ShellMessage::send(messenger,format!("Accesing memory {}",style(format!("0x{:016x}",addr)).cyan()),MessageType::Spinner,0);
 
-    let _ = memory::read_virtual_memory(&partition.device, addr, POOL_SIZE).unwrap();
+    let _ = memory::read_virtual_memory(&partition.session, addr, POOL_SIZE).unwrap();
     ShellMessage::send(messenger,format!("{}",style("Stoping guard!").yellow()),MessageType::Spinner,0);
 
-    guard.stop();
+    guard.stop()?;
 
-    memory::free_virtual_memory(&partition.device, addr).unwrap();
+    memory::free_virtual_memory(&partition.session, addr).unwrap();
     ShellMessage::send(messenger,format!("{}",style("Done!").green()),MessageType::Close,0);
 
     Ok(())
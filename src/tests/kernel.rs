@@ -2,7 +2,7 @@
 
 use super::clap::{App, Arg, ArgMatches, SubCommand};
 
-use super::iochannel::{IoCtl, Device};
+use super::iochannel::{IoCtl, Device, Transport};
 
 use std::sync::mpsc::Sender;
 
@@ -4,7 +4,7 @@ use super::clap::{App, ArgMatches, SubCommand};
 
 use std::{fmt};
 use super::common;
-use super::sentry::{io, memory};
+use super::sentry::{io, memory, SentrySession};
 use super::iochannel::{Device};
 use super::sentry::memguard::Filter;
 use super::sentry::memory::{Map, MapMode};
@@ -38,8 +38,8 @@ pub fn tests(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(
 
 #[allow(unused_variables)]
 fn test_fuzz_memory(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    let device = Device::new(io::SE_NT_DEVICE_NAME).expect("Can't open sentry");
-    let _filters: Vec<Filter> = (0..1000).map(|_| Filter::new(&device)).collect();
+    let session = SentrySession::open().expect("Can't open sentry");
+    let _filters: Vec<Filter> = (0..1000).map(|_| Filter::new(&session)).collect::<Result<_, _>>()?;
     // format!("{}", style("Done!").green());
 
     ShellMessage::send(messenger,format!("{}", style("Done!").green()),MessageType::Close,0);
@@ -47,7 +47,7 @@ fn test_fuzz_memory(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) ->
 }
 
 fn test_kernel_map(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    let device = Device::new(io::SE_NT_DEVICE_NAME).expect("Can't open sentry");
+    let session = SentrySession::open().expect("Can't open sentry");
 
     struct TestStruct {
         first:  u64,
@@ -60,7 +60,7 @@ fn test_kernel_map(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> R
         }
     }
 
-    let map = memory::KernelAlloc::<TestStruct>::new(&device);
+    let map = memory::KernelAlloc::<TestStruct>::new(&session)?;
 
     ShellMessage::send(messenger, format!("TestStruct: allocated {} bytes at:", format!("{}",style(map.size()).underlined().cyan())),MessageType::Close,0);
     ShellMessage::send(messenger, format!("\t\tkernel: {}",style(format!("0x{:016x}",map.kernel_ptr())).yellow()), MessageType::Close, 0);
@@ -75,7 +75,7 @@ fn test_kernel_map(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> R
     // debug!(logger, "reading kernel pointer 0x{:016x}", map.kernel_ptr());
     ShellMessage::send(messenger, format!("reading kernel pointer: {}", style(format!("0x{:016x}",map.kernel_ptr())).yellow()), MessageType::Spinner,1);
 
-    let v = memory::read_virtual_memory(&device, map.kernel_ptr(), map.size())
+    let v = memory::read_virtual_memory(&session, map.kernel_ptr(), map.size())
                             .expect("error reading memory");
 
 
@@ -172,14 +172,14 @@ fn test_memory_write(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) ->
 
 
 fn test_memory_map(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    let device = Device::new(io::SE_NT_DEVICE_NAME).expect("Can't open sentry");
+    let session = SentrySession::open().expect("Can't open sentry");
 
-    let addr = memory::alloc_virtual_memory(&device, 0x200).unwrap();
-    let map = Map::new(&device, addr, 0x200, Some(MapMode::UserMode));
+    let addr = memory::alloc_virtual_memory(&session, 0x200).unwrap();
+    let map = Map::new(&session, addr, 0x200, Some(MapMode::UserMode))?;
 
     // debug!(logger, "map: {:?}", map);
     ShellMessage::send(messenger, format!("[*] {}: {:?}",style("map").cyan(), map), MessageType::Close,0);
-    memory::free_virtual_memory(&device, addr).unwrap();
+    memory::free_virtual_memory(&session, addr).unwrap();
 
     Ok(())
 }
@@ -4,9 +4,8 @@ use std::thread;
 use std::time::Duration;
 
 use super::failure::Error;
-use super::iochannel::Device;
 use super::rand::Rng;
-use super::sentry::{io, misc};
+use super::sentry::{misc, SentrySession};
 
 use super::sentry::memguard::{
     Access, Action, Filter, Guard, MatchType, Partition, Region, Response,
@@ -77,8 +76,8 @@ fn test_fuzz_partition_process(messenger: &Sender<ShellMessage>) -> Result<(), E
         let elapse = rng.gen::<u8>();
         let duration = Duration::from_millis(u64::from(elapse));
         let _partition = Partition::root();
-        let _device = Device::new(io::SE_NT_DEVICE_NAME).expect("Can't open sentry");
-        if let Ok(process) = misc::Process::system() {
+        let session = SentrySession::open().expect("Can't open sentry");
+        if let Ok(process) = misc::Process::system(&session) {
             let _ = process.to_string();
             bar.inc(messenger, 1);
         }
@@ -122,9 +121,10 @@ fn create_multiple_partitions(messenger: &Sender<ShellMessage>) -> Result<(), Er
         0,
     );
     // debug!(logger, "creating 3 partitions");
-    let _partition1: Partition = Partition::new().unwrap();
-    let _partition2: Partition = Partition::new().unwrap();
-    let _partition3: Partition = Partition::new().unwrap();
+    let session = SentrySession::open()?;
+    let _partition1: Partition = Partition::with_session(&session)?;
+    let _partition2: Partition = Partition::with_session(&session)?;
+    let _partition3: Partition = Partition::with_session(&session)?;
     ShellMessage::send(
         messenger,
         format!("Waiting {} seconds...", style("5").underlined().yellow()),
@@ -145,7 +145,7 @@ fn create_multiple_partitions(messenger: &Sender<ShellMessage>) -> Result<(), Er
 }
 
 fn create_partition(messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    let partition: Partition = Partition::root();
+    let partition: Partition = Partition::root()?;
 
     ShellMessage::send(
         messenger,
@@ -191,17 +191,17 @@ fn test_guard_filters(
     _matches: &ArgMatches,
     messenger: &Sender<ShellMessage>,
 ) -> Result<(), Error> {
-    let partition = Partition::root();
-    let filter = Filter::process(&partition.device, "notepad", MatchType::EQUAL)
+    let partition = Partition::root()?;
+    let filter = Filter::process(&partition.session, "notepad", MatchType::EQUAL)?
         .expect("can't find \"notepad\" process");
 
     // // this is totally a non recommended way
     // let pid = filter.filter.Conditions[0].Value.Value;
 
-    let mut guard = Guard::new(&partition, Some(filter));
+    let mut guard = Guard::new(&partition, Some(filter))?;
 
     let addr =
-        misc::kernel_export_address(&partition.device, misc::get_kernel_base(), "ZwCreateKey")
+        misc::kernel_export_address(&partition.session, misc::get_kernel_base()?, "ZwCreateKey")
             .expect("can't find ZwCreateKey");
 
     let region = Region::new(
@@ -220,7 +220,7 @@ fn test_guard_filters(
         0,
     );
     // debug!(logger, "adding {} to {}", region, guard);
-    guard.add(region);
+    guard.add(region)?;
 
     guard.set_callback(Box::new(|interception| {
         let message = format!("executing 0x{:016x}", interception.address);
@@ -234,7 +234,7 @@ fn test_guard_filters(
         0,
     );
     // debug!(logger, "starting guard");
-    guard.start();
+    guard.start()?;
 
     ShellMessage::send(
         messenger,
@@ -252,7 +252,7 @@ fn test_guard_filters(
         MessageType::Spinner,
         0,
     );
-    guard.stop();
+    guard.stop()?;
     ShellMessage::send(
         messenger,
         "Guard Stopped.".to_string(),
@@ -277,7 +277,7 @@ fn start_guard_a_second(guard: &Guard, messenger: &Sender<ShellMessage>) -> Resu
         MessageType::Spinner,
         0,
     );
-    guard.start();
+    guard.start()?;
 
     let duration = Duration::from_secs(1);
     // debug!(logger, "waiting {:?}", duration);
@@ -296,13 +296,13 @@ fn start_guard_a_second(guard: &Guard, messenger: &Sender<ShellMessage>) -> Resu
         MessageType::Close,
         0,
     );
-    guard.stop();
+    guard.stop()?;
 
     Ok(())
 }
 fn start_a_guard(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    let partition: Partition = Partition::root();
-    let guard = Guard::new(&partition, None);
+    let partition: Partition = Partition::root()?;
+    let guard = Guard::new(&partition, None)?;
 
     start_guard_a_second(&guard, messenger)?;
 
@@ -313,10 +313,10 @@ fn create_multiple_guards(
     _matches: &ArgMatches,
     messenger: &Sender<ShellMessage>,
 ) -> Result<(), Error> {
-    let partition: Partition = Partition::root();
-    let _guard = Guard::new(&partition, None);
+    let partition: Partition = Partition::root()?;
+    let _guard = Guard::new(&partition, None)?;
 
-    let guards: Vec<Guard> = (0..10).map(|_| Guard::new(&partition, None)).collect();
+    let guards: Vec<Guard> = (0..10).map(|_| Guard::new(&partition, None)).collect::<Result<_, _>>()?;
 
     // debug!(logger, "guards-created: {}", guards.len());
     ShellMessage::send(
@@ -370,16 +370,39 @@ fn region_tests(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Resul
 
 fn test_enumerate_region(
     _matches: &ArgMatches,
-    _messenger: &Sender<ShellMessage>,
+    messenger: &Sender<ShellMessage>,
 ) -> Result<(), Error> {
-    unimplemented!()
+    let partition: Partition = Partition::root()?;
+    let mut guard: Guard = Guard::new(&partition, None)?;
+    // regions left out of the guard are deleted once dropped
+    let mut unguarded = vec![];
+
+    for index in 0..4 {
+        let region = Region::new(&partition, 0xCAFE_0000 + index * 0x1000, 0x1000, None, Access::READ)?;
+
+        if index % 2 == 0 {
+            guard.add(region)?;
+        } else {
+            unguarded.push(region);
+        }
+    }
+
+    for info in partition.regions()? {
+        ShellMessage::send(messenger, format!("{}", style(info?).blue().dim()), MessageType::Close, 0);
+    }
+
+    for info in guard.regions()? {
+        ShellMessage::send(messenger, format!("{} {}", style(&guard).magenta(), info?), MessageType::Close, 0);
+    }
+
+    Ok(())
 }
 
 fn test_create_multiple_regions(
     _matches: &ArgMatches,
     messenger: &Sender<ShellMessage>,
 ) -> Result<(), Error> {
-    let partition: Partition = Partition::root();
+    let partition: Partition = Partition::root()?;
     let _regions: Vec<Region> = (0..10)
         .map(|_| {
             let region = Region::new(&partition, 0xCAFE_BABE, 0x1000, None, Access::READ).unwrap();
@@ -401,9 +424,9 @@ fn test_regions_inside_guard(
     _matches: &ArgMatches,
     messenger: &Sender<ShellMessage>,
 ) -> Result<(), Error> {
-    let partition: Partition = Partition::root();
+    let partition: Partition = Partition::root()?;
 
-    let mut guard: Guard = Guard::new(&partition, None);
+    let mut guard: Guard = Guard::new(&partition, None)?;
 
     let regions: Vec<Region> = (0..10)
         .map(|_| {
@@ -422,7 +445,7 @@ fn test_regions_inside_guard(
         .collect();
 
     for region in regions {
-        guard.add(region);
+        guard.add(region)?;
     }
 
     start_guard_a_second(&guard, messenger)?;
@@ -434,7 +457,7 @@ fn test_create_region(
     _matches: &ArgMatches,
     messenger: &Sender<ShellMessage>,
 ) -> Result<(), Error> {
-    let partition: Partition = Partition::root();
+    let partition: Partition = Partition::root()?;
     let region = Region::new(&partition, 0xCAFE_BABE, 0x1000, None, Access::READ).unwrap();
     // debug!(logger, "{}", region);
     ShellMessage::send(
@@ -3,9 +3,10 @@
 use super::clap::{App, ArgMatches, SubCommand};
 
 use std::sync::mpsc::Sender;
-use super::cli::output::ShellMessage;
+use super::cli::output::{MessageType, ShellMessage};
 
 use super::failure::Error;
+use super::sentry::memguard::Instruction;
 
 pub fn bind() -> App<'static, 'static> {
     SubCommand::with_name("misc")
@@ -20,66 +21,24 @@ pub fn tests(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(
     }
 }
 
-// const CODE: &'static [u8] =
-//     b"\x55\x48\x8b\x05\xb8\x13\x00\x00\xe8\x4a\xed\xff\xff\xe9\x14\x9e\x08\x00\x45\x31\xe4";
+// a token swap as seen by a region over _EPROCESS.Token, then a few common writes
+const CODE: &[u8] =
+    b"\x65\x48\x8b\x04\x25\x88\x01\x00\x00\x48\x8b\x80\xb8\x00\x00\x00\x48\x8b\x88\x58\x03\x00\x00\
+      \x48\x89\x81\x58\x03\x00\x00\xf0\x48\x0f\xb1\x4c\xd3\xf8\xc6\x80\xca\x06\x00\x00\x00\xf3\x48\xab\xc3";
 
-// /// Print register names
-// fn reg_names<T, I>(cs: &Capstone, regs: T) -> String
-// where
-//     T: Iterator<Item = I>,
-//     I: Into<u64>,
-// {
-//     let names: Vec<String> = regs.map(|x| cs.reg_name(x.into()).unwrap()).collect();
-//     names.join(", ")
-// }
+fn test_disassembler(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    let mut offset = 0;
 
-// /// Print instruction group names
-// fn group_names<T, I>(cs: &Capstone, regs: T) -> String
-// where
-//     T: Iterator<Item = I>,
-//     I: Into<u64>,
-// {
-//     let names: Vec<String> = regs.map(|x| cs.group_name(x.into()).unwrap()).collect();
-//     names.join(", ")
-// }
+    while offset < CODE.len() {
+        let instruction = Instruction::decode(&CODE[offset..])?;
+        let bytes = CODE[offset..offset + instruction.length].iter()
+                                                             .map(|byte| format!("{:02x}", byte))
+                                                             .collect::<Vec<String>>();
 
-// fn example() -> CsResult<()> {
-//     let cs = Capstone::new()
-//         .x86()
-//         .mode(arch::x86::ArchMode::Mode64)
-//         .syntax(arch::x86::ArchSyntax::Att)
-//         .detail(true)
-//         .build()?;
+        ShellMessage::send(messenger, format!("{:08x}  {:<30} {}", offset, bytes.join(" "), instruction), MessageType::Close, 0);
 
-//     let insns = cs.disasm_all(CODE, 0x1000)?;
-//     println!("Found {} instructions", insns.len());
-//     for i in insns.iter() {
-//         println!("");
-//         println!("{}", i);
-//         let output: &[(&str, String)] =
-//             &[
-//                 (
-//                     "read regs:",
-//                     reg_names(&cs, cs.read_register_ids(&i)?.iter().map(|x| *x)),
-//                 ),
-//                 (
-//                     "write regs:",
-//                     reg_names(&cs, cs.write_register_ids(&i)?.iter().map(|x| *x)),
-//                 ),
-//                 (
-//                     "insn groups:",
-//                     group_names(&cs, cs.insn_group_ids(&i)?.iter().map(|x| *x)),
-//                 ),
-//             ];
-//         for &(ref name, ref message) in output.iter() {
-//             println!("    {:12} {}", name, message);
-//         }
-//     }
-//     Ok(())
-// }
+        offset += instruction.length;
+    }
 
-fn test_disassembler(_matches: &ArgMatches, _messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    // debug!(logger, "{}", ());
-    // example().unwrap();
-    unimplemented!()
+    Ok(())
 }
@@ -8,6 +8,7 @@ use std::sync::mpsc::Sender;
 use super::failure::Error;
 use super::cli::output::{ShellMessage, MessageType};
 use super::sentry::memguard::{ ObjectFilter };
+use super::sentry::SentrySession;
 
 pub fn bind() -> App<'static, 'static> {
     SubCommand::with_name("monitor")
@@ -32,7 +33,8 @@ pub fn monitor_tests(matches: &ArgMatches, messenger: &Sender<ShellMessage>) ->
 
     let term = Term::stdout();
 
-    let filter = ObjectFilter::new()
+    let session = SentrySession::open()?;
+    let filter = ObjectFilter::new(&session)
                 .expect("can't create object filter");
 
     ShellMessage::send(messenger, format!("[!] {}.", style("starting").magenta()), MessageType::Spinner,0);
@@ -10,6 +10,7 @@ use super::sentry::{memory, misc, io};
 use super::sentry::memguard::{Patch, Partition, Guard};
 
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 use super::cli::output::{ShellMessage, MessageType};
 
 pub fn bind() -> App<'static, 'static> {
@@ -31,7 +32,7 @@ fn test_patch_driver(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) ->
 
     ShellMessage::send(messenger, "scanning drivers:".to_string(), MessageType::Spinner, 0);
 
-    if let Some(driver) = misc::Drivers::iter()
+    if let Some(driver) = misc::Drivers::iter()?
                                     .inspect(|driver| {
                                         ShellMessage::send(messenger,
                                              format!("scanning drivers: {}", driver.name),
@@ -47,7 +48,7 @@ fn test_patch_driver(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) ->
 
         let new_code = memory::alloc_virtual_memory(&device, PAGE_SIZE).unwrap();
         {
-            let partition = Partition::root();
+            let partition = Partition::root()?;
 
             let patch_base = driver.base + PATCH_PAGE;
             let patch = vec![0x90; 6];
@@ -59,22 +60,21 @@ fn test_patch_driver(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) ->
             let patch = Patch::new(&partition, patch_base, new_code, PAGE_SIZE as u64).unwrap();
             ShellMessage::send(messenger, format!("{}", patch), MessageType::Spinner, 0);
 
-            let mut guard = Guard::new(&partition, None);
+            let mut guard = Guard::new(&partition, None)?;
 
             ShellMessage::send(messenger, format!("adding {} to {}", patch, guard),
                                 MessageType::Spinner,0);
-            guard.add(patch);
+            guard.add(patch)?;
 
             ShellMessage::send(messenger, format!("HEVD: {}", style("Applying patch.").green()),
                                 MessageType::Spinner, 0);
-            guard.start();
+            let supervision = guard.arm_for(Duration::from_secs(30))?;
             ShellMessage::send(messenger, format!("HEVD: {}", style("Patch applied.").green()),
                                 MessageType::Close, 0);
 
-            ShellMessage::sleep_bar(messenger, 30);
+            supervision.wait()?;
             ShellMessage::send(messenger, format!("HEVD: {}", style("Revoking patch").red()),
                                 MessageType::Spinner, 0);
-            guard.stop();
         }
 
         let _ = memory::free_virtual_memory(&device, new_code);
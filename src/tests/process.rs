@@ -3,7 +3,7 @@
 use super::clap::{App, ArgMatches, SubCommand};
 use super::failure::Error;
 
-use super::sentry::misc;
+use super::sentry::{misc, SentrySession};
 
 use std::sync::mpsc::Sender;
 use super::cli::output::{ShellMessage, MessageType};
@@ -35,15 +35,14 @@ fn test_list_drivers(_matches: &ArgMatches, _messenger: &Sender<ShellMessage>) -
     // misc::Drivers::iter().for_each(|driver|
     //     println!("{}", driver)
     // );
-    misc::list_kernel_drivers();
-    Ok(())
+    misc::list_kernel_drivers()
 }
 
 fn test_kernel_base(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
     // debug!(logger, "base: 0x{:016x}", misc::get_kernel_base());
     ShellMessage::send(
         messenger,
-        format!("base: {}", style(format!("0x{:016x}",misc::get_kernel_base())).yellow()),
+        format!("base: {}", style(format!("0x{:016x}",misc::get_kernel_base()?)).yellow()),
         MessageType::Close,
         0,
     );
@@ -51,7 +50,8 @@ fn test_kernel_base(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) ->
 }
 
 fn test_system_process(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    let system = misc::Process::system().expect("system process");
+    let session = SentrySession::open()?;
+    let system = misc::Process::system(&session).expect("system process");
     // debug!(logger, "system: 0x{:016x}", system.object());
     ShellMessage::send(
         messenger,
@@ -64,13 +64,14 @@ fn test_system_process(_matches: &ArgMatches, messenger: &Sender<ShellMessage>)
 }
 
 fn test_find_eprocess(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
+    let session = SentrySession::open()?;
+
     ShellMessage::send(
         messenger,
         format!(
                 "{}",
-            style(misc::WalkProcess::iter()
-                .find(|process| process.name().contains("svchost"))
-                .unwrap()).cyan()
+            style(misc::WalkProcess::by_name(&session, "svchost")?
+                .expect("no svchost process")).cyan()
         ),
         MessageType::Close,
         0,
@@ -81,22 +82,24 @@ fn test_find_eprocess(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -
 }
 
 fn test_walk_eprocess(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    misc::WalkProcess::iter().for_each(|process| {
+    let session = SentrySession::open()?;
+
+    for process in misc::WalkProcess::new(&session)? {
         // debug!(logger, "{}", process);
         ShellMessage::send(
             messenger,
-            format!("{}", process),
+            format!("{}", process?),
             MessageType::Close,
             0,
         );
-    });
+    }
     Ok(())
 }
 
 fn test_read_eprocess(_matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Result<(), Error> {
-    let current = misc::WalkProcess::iter()
-        .find(|process| process.name().contains("conveyor"))
-        .unwrap();
+    let session = SentrySession::open()?;
+    let current = misc::WalkProcess::by_name(&session, "conveyor")?
+        .expect("no conveyor process");
 
     // debug!(logger, "current-eprocess: 0x{:016x}", current.object());
     ShellMessage::send(
@@ -5,11 +5,12 @@ use super::clap::{App, Arg, ArgMatches, SubCommand};
 use super::failure::Error;
 
 use super::sentry::memguard::{Response, Partition, Region, Guard, Access, Action};
-use super::sentry::{misc, io, token};
+use super::sentry::{misc, io, token, SentrySession};
 use super::iochannel::{Device};
 
 
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 use super::cli::output::{ShellMessage, MessageType};
 use super::console::style;
 
@@ -47,7 +48,7 @@ fn duplicate_token(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Re
     let device = Device::new(io::SE_NT_DEVICE_NAME).expect("Can't open sentry");
     // debug!(logger, "elevating privilege of pid {}", pid);
         ShellMessage::send(messenger, format!("Elevating privilege of pid {}", style(pid).on_blue()), MessageType::Close,0);
-    token::steal_token(&device, 0, pid, token::TokenType::DuplicateSource);
+    token::steal_token(&device, 0, pid, token::TokenType::DuplicateSource)?;
     // debug!(logger, "success");
         ShellMessage::send(messenger, format!("{}",style("Success!").bold().green()), MessageType::Close,0);
     Ok(())
@@ -64,7 +65,7 @@ fn hijack_token(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Resul
     let device = Device::new(io::SE_NT_DEVICE_NAME).expect("Can't open sentry");
     // debug!(logger, "elevating privilege of pid {}", pid);
         ShellMessage::send(messenger, format!("Elevating privilege of pid {}", style(pid).on_blue()), MessageType::Close,0);
-    token::steal_token(&device, 0, pid, token::TokenType::HijackSystem);
+    token::steal_token(&device, 0, pid, token::TokenType::HijackSystem)?;
     // debug!(logger, "success");
         ShellMessage::send(messenger, format!("{}",style("Success!").bold().green()), MessageType::Close,0);
 
@@ -77,24 +78,24 @@ fn protect_token(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Resu
                      .parse()
                      .expect("error parsing pid");
 
-    let process = misc::WalkProcess::iter().find(|p| p.id() == pid)
-           .expect("can't find client pid");
+    let session = SentrySession::open()?;
+    let process = misc::WalkProcess::by_pid(&session, pid)?;
 
-    let token = process.token() & !0xF;
+    let token = process.token()? & !0xF;
     let token_offset = misc::get_offset("_EPROCESS.Token").expect("Token offset");
 
     ShellMessage::send(messenger, format!("Protecting target pid {} with token {}",
                         style(pid).blue(), style(format!("0x{:016x}",token)).cyan()), MessageType::Spinner,0);
 
-    let partition: Partition = Partition::root();
-    let mut guard = Guard::new(&partition, None);
+    let partition = Partition::with_session(&session)?;
+    let mut guard = Guard::new(&partition, None)?;
 
     // TODO: Do it in a stable way.
     // pointer to token (duplicateway)
     let token_region = Region::new(&partition, token, 8, None, Access::WRITE).unwrap();
-    guard.add(token_region);
+    guard.add(token_region)?;
     let pointer_region = Region::new(&partition, process.object() + u64::from(token_offset), 8, None, Access::WRITE).unwrap();
-    guard.add(pointer_region);
+    guard.add(pointer_region)?;
 
     guard.set_callback(Box::new(|interception| {
         let message = format!("0x{:016X} - IGNORING", interception.address);
@@ -102,9 +103,7 @@ fn protect_token(matches: &ArgMatches, messenger: &Sender<ShellMessage>) -> Resu
     }));
 
     ShellMessage::send(messenger, format!("Waiting {} seconds...",style("20").underlined().yellow()), MessageType::Spinner,0);
-    guard.start();
-    ShellMessage::sleep_bar(messenger,20);
-    guard.stop();
+    guard.arm_for(Duration::from_secs(20))?.wait()?;
     ShellMessage::send(messenger, format!("{}",style("Done!").green()), MessageType::Close,0);
     Ok(())
 }
@@ -1,3 +1,4 @@
+#![cfg(windows)]
 #![feature(plugin)]
 extern crate conveyor;
 extern crate winapi;
@@ -1,3 +1,4 @@
+#![cfg(windows)]
 extern crate conveyor;
 
 use conveyor::service::{WindowsService, ServiceError};